@@ -4,6 +4,12 @@ set -e
 rm -rf ./dist/*
 mkdir -p dist
 
+# Optionally embed a seed database into the module (see `wasm/src/seed.rs`).
+if [ -n "$SEED" ]; then
+  WASM_SQLITE_SEED="$(realpath "$SEED")"
+  FEATURES="${FEATURES:+$FEATURES,}seed"
+  export WASM_SQLITE_SEED FEATURES
+fi
 
 cd wasm
 make build
@@ -14,6 +20,14 @@ cd ..
 
 ./node_modules/.bin/tsc --emitDeclarationOnly
 
-wasm-opt -Os --asyncify --pass-arg asyncify-imports@env.put_page,env.get_page,env.del_page,env.conn_sleep \
-  wasm/target/wasm32-wasi/release/wasm_sqlite.wasm \
+WASM=wasm/target/wasm32-wasi/release/wasm_sqlite.wasm
+
+# Optionally snapshot SQLite's initialization into the module (see `wizer_initialize`).
+if [ -n "$WIZER" ]; then
+  wizer --allow-wasi --init-func wizer.initialize "$WASM" -o wasm/target/wasm_sqlite.wizer.wasm
+  WASM=wasm/target/wasm_sqlite.wizer.wasm
+fi
+
+wasm-opt -Os --asyncify --pass-arg asyncify-imports@env.put_page,env.get_page,env.get_pages,env.del_pages,env.storage_sync,env.conn_sleep,env.txn_begin,env.txn_commit,env.txn_rollback,env.emit_delta \
+  "$WASM" \
   -o dist/wasm_sqlite.wasm
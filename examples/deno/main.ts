@@ -0,0 +1,17 @@
+import {
+  ContentAddressedVfs,
+  HttpRangeVfs,
+  OverlayVfs,
+  Sqlite,
+  TransientError,
+} from "../../dist/wasm-sqlite.js";
+import { runFlows } from "../flows.mjs";
+
+await runFlows(
+  Sqlite,
+  "deno",
+  TransientError,
+  ContentAddressedVfs,
+  HttpRangeVfs,
+  OverlayVfs
+);
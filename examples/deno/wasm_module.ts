@@ -0,0 +1,6 @@
+// Deno doesn't support importing `.wasm` files as compiled `WebAssembly.Module`s (as e.g.
+// Cloudflare Workers do), which is what `dist/wasm-sqlite.js` expects. The import map redirects
+// the import to this module instead.
+
+const url = new URL("../../dist/wasm_sqlite.wasm", import.meta.url);
+export default await WebAssembly.compile(await Deno.readFile(url));
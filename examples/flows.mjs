@@ -0,0 +1,1512 @@
+// Flows shared by the JS example hosts. Each host only provides its specific way of loading the
+// wasm module and passes the `Sqlite`, `TransientError` and page store classes (e.g.
+// `ContentAddressedVfs`) in.
+
+export class MemoryVfs {
+  constructor(pages = []) {
+    // the pages of the main database (namespace `0`)
+    this.pages = pages;
+    // the pages of all other namespaces (e.g. spilled results)
+    this.namespaces = new Map();
+  }
+
+  pagesOf(namespace = 0) {
+    if (namespace === 0) {
+      return this.pages;
+    }
+    if (!this.namespaces.has(namespace)) {
+      this.namespaces.set(namespace, []);
+    }
+    return this.namespaces.get(namespace);
+  }
+
+  pageCount(namespace) {
+    return this.pagesOf(namespace).length;
+  }
+
+  async getPage(ix, namespace) {
+    return this.pagesOf(namespace)[ix] ?? null;
+  }
+
+  async putPage(ix, page, namespace) {
+    const pages = this.pagesOf(namespace);
+    while (pages.length <= ix) {
+      pages.push(new Uint8Array(page.length));
+    }
+    // the page is a view into the wasm memory, so it must be copied
+    pages[ix] = new Uint8Array(page);
+  }
+
+  async delPage(ix, namespace) {
+    const pages = this.pagesOf(namespace);
+    pages.length = Math.min(pages.length, ix);
+  }
+}
+
+export async function runFlows(
+  Sqlite,
+  host,
+  TransientError,
+  ContentAddressedVfs,
+  HttpRangeVfs,
+  OverlayVfs
+) {
+  // create, insert, select
+  const vfs = new MemoryVfs();
+  const sqlite = await Sqlite.instantiate(vfs);
+  const conn = await sqlite.connect();
+  await conn.execute(
+    "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL)"
+  );
+  await conn.execute("INSERT INTO users (name) VALUES (?)", ["Alice"]);
+  await conn.execute("INSERT INTO users (name) VALUES (?)", ["Bob"]);
+  assertEquals(await conn.query("SELECT id, name FROM users ORDER BY id"), [
+    { id: 1, name: "Alice" },
+    { id: 2, name: "Bob" },
+  ]);
+
+  // RETURNING rows via query, while execute discards them
+  assertEquals(
+    await conn.query(
+      "INSERT INTO users (name) VALUES (?) RETURNING id, name",
+      ["Mallory"]
+    ),
+    [{ id: 3, name: "Mallory" }]
+  );
+  await conn.execute("DELETE FROM users WHERE id = ? RETURNING id", [3]);
+  assertEquals(await countUsers(conn), 2);
+
+  // upsert
+  await conn.execute("CREATE TABLE kv (key TEXT PRIMARY KEY, value TEXT)");
+  await conn.upsert(
+    "kv",
+    [
+      { key: "a", value: "1" },
+      { key: "b", value: "2" },
+    ],
+    ["key"]
+  );
+  await conn.upsert("kv", [{ key: "a", value: "3" }], ["key"]);
+  assertEquals(await conn.query("SELECT key, value FROM kv ORDER BY key"), [
+    { key: "a", value: "3" },
+    { key: "b", value: "2" },
+  ]);
+
+  // key-value API
+  await conn.kvSet("user:1", { name: "Alice" });
+  await conn.kvSet("user:2", { name: "Bob" });
+  await conn.kvSet("session:1", "token");
+  assertEquals(await conn.kvGet("user:1"), { name: "Alice" });
+  assertEquals(await conn.kvGet("user:3"), undefined);
+  assertEquals(await conn.kvList({ prefix: "user:", limit: 1 }), [
+    { key: "user:1", value: { name: "Alice" } },
+  ]);
+  assert(await conn.kvDelete("user:1"), "existing key must be deleted");
+  assertEquals(
+    (await conn.kvList({ prefix: "user:" })).map((entry) => entry.key),
+    ["user:2"]
+  );
+
+  // counters and queues
+  assertEquals(await conn.counterIncr("visits"), 1);
+  assertEquals(await conn.counterIncr("visits", 5), 6);
+  assertEquals(await conn.queuePush("jobs", ["a", "b", "c"]), 3);
+  assertEquals(
+    (await conn.queuePopBatch("jobs", 2)).map((message) => message.payload),
+    ["a", "b"]
+  );
+  assertEquals(
+    (await conn.queuePopBatch("jobs", 2)).map((message) => message.payload),
+    ["c"]
+  );
+
+  // outbox: events are only dispatched if their transaction commits, and redelivered once their
+  // lease expires without an acknowledgement
+  await conn.execute("BEGIN");
+  await conn.outboxPush("users", [{ created: "Dave" }]);
+  await conn.execute("ROLLBACK");
+  await conn.execute("BEGIN");
+  await conn.outboxPush("users", [{ created: "Erin" }, { created: "Finn" }]);
+  await conn.execute("COMMIT");
+  const claimed = await conn.outboxClaim(10, 20);
+  assertEquals(
+    claimed.map((event) => event.payload),
+    [{ created: "Erin" }, { created: "Finn" }]
+  );
+  assertEquals(await conn.outboxClaim(10, 20), []);
+  assertEquals(await conn.outboxAck([claimed[0].id]), 1);
+  await sleep(30);
+  const redelivered = await conn.outboxClaim(10, 1000);
+  assertEquals(
+    redelivered.map((event) => [event.payload, event.attempts]),
+    [[{ created: "Finn" }, 2]]
+  );
+  assertEquals(await conn.outboxAck([claimed[0].id, redelivered[0].id]), 1);
+
+  // leader election
+  const follower = await sqlite.connect();
+  assertEquals((await conn.tryAcquireLeadership("a", 20)).leader, true);
+  const contender = await follower.tryAcquireLeadership("b", 20);
+  assertEquals([contender.leader, contender.holder], [false, "a"]);
+  assertEquals((await conn.tryAcquireLeadership("a", 20)).leader, true);
+  await sleep(30);
+  assertEquals((await follower.tryAcquireLeadership("b", 1000)).leader, true);
+  assertEquals(await conn.releaseLeadership("a"), false);
+  assertEquals(await follower.releaseLeadership("b"), true);
+  await follower.drop();
+
+  // schema versions
+  assertEquals(await conn.getVersion(), { user_version: 0, application_id: 0 });
+  assertEquals(
+    await conn.setVersion({ user_version: 1, application_id: 0x77617371 }),
+    { user_version: 1, application_id: 0x77617371 }
+  );
+  let outdated = false;
+  try {
+    await conn.setVersion({ user_version: 3, expected_user_version: 2 });
+  } catch (_err) {
+    outdated = true;
+  }
+  assert(outdated, "the expected user_version must match");
+  assertEquals(
+    (await conn.setVersion({ user_version: 2, expected_user_version: 1 }))
+      .user_version,
+    2
+  );
+
+  // declarative schema migrations
+  const schema = await Sqlite.instantiate(new MemoryVfs());
+  const catalog = await schema.connect();
+  await catalog.execute("CREATE TABLE products (id INTEGER PRIMARY KEY, name)");
+  await catalog.execute("INSERT INTO products (name) VALUES ('Lamp')");
+  await catalog.execute("CREATE TABLE legacy (id)");
+  const added = await catalog.schemaDiff(
+    "CREATE TABLE products (id INTEGER PRIMARY KEY, name, price DEFAULT 0)"
+  );
+  assertEquals(added, {
+    statements: ['ALTER TABLE "products" ADD COLUMN price DEFAULT 0'],
+    extra: ["legacy"],
+    applied: false,
+  });
+  const desired = `CREATE TABLE products (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+    CREATE INDEX products_name ON products (name);`;
+  const rebuilt = await catalog.schemaDiff(desired, {
+    apply: true,
+    drop: true,
+  });
+  assert(rebuilt.applied, "the migration must be applied");
+  assertEquals(rebuilt.statements[0], 'DROP TABLE "legacy"');
+  assertEquals(await catalog.query("SELECT id, name FROM products"), [
+    { id: 1, name: "Lamp" },
+  ]);
+  assertEquals(
+    await catalog.query(
+      "SELECT name FROM sqlite_master WHERE tbl_name IN (?, ?) ORDER BY name",
+      ["products", "legacy"]
+    ),
+    [{ name: "products" }, { name: "products_name" }]
+  );
+  assertEquals((await catalog.schemaDiff(desired)).statements, []);
+  await catalog.drop();
+
+  // scheduled jobs
+  const scheduled = await conn.jobSchedule("report", "30 9 * * MON", {
+    to: "team",
+  });
+  assert(scheduled.next_run > Date.now(), "next run must be in the future");
+  assertEquals(await conn.jobsDue(), {
+    jobs: [],
+    next_run: scheduled.next_run,
+  });
+  // missed runs are only returned once
+  const week = 7 * 24 * 60 * 60 * 1000;
+  const due = await conn.jobsDue(scheduled.next_run + week);
+  assertEquals(due.jobs, [
+    {
+      name: "report",
+      payload: { to: "team" },
+      scheduled_for: scheduled.next_run,
+    },
+  ]);
+  assertEquals(due.next_run, scheduled.next_run + 2 * week);
+  const dueAt = new Date(due.jobs[0].scheduled_for);
+  assertEquals([dueAt.getUTCDay(), dueAt.getUTCHours()], [1, 9]);
+  let neverRuns = false;
+  try {
+    await conn.jobSchedule("never", "0 0 31 2 *");
+  } catch (_err) {
+    neverRuns = true;
+  }
+  assert(neverRuns, "scheduling a job that never runs must fail");
+
+  // full-text search
+  await conn.execute(
+    "CREATE TABLE docs (id INTEGER PRIMARY KEY, title TEXT, body TEXT)"
+  );
+  await conn.execute("INSERT INTO docs (title, body) VALUES (?, ?)", [
+    "SQLite",
+    "an embedded database",
+  ]);
+  await conn.ftsCreateIndex("docs", ["title", "body"]);
+  await conn.execute("INSERT INTO docs (title, body) VALUES (?, ?)", [
+    "WASM",
+    "runs the embedded database in the browser",
+  ]);
+  assertEquals(
+    (await conn.ftsSearch("docs", "embedded")).map((doc) => doc.id).sort(),
+    [1, 2]
+  );
+  assertEquals(
+    (await conn.ftsSearch("docs", "browser")).map((doc) => doc.title),
+    ["WASM"]
+  );
+
+  // tables with a history can be queried as of an earlier time
+  await conn.execute(
+    "CREATE TABLE prices (sku TEXT PRIMARY KEY, cents INTEGER)"
+  );
+  await conn.execute("INSERT INTO prices (sku, cents) VALUES ('tea', 250)");
+  await conn.enableHistory("prices");
+  await sleep(5);
+  const beforeUpdate = Date.now();
+  await sleep(5);
+  await conn.execute("UPDATE prices SET cents = 300 WHERE sku = 'tea'");
+  await sleep(5);
+  const beforeDelete = Date.now();
+  await sleep(5);
+  await conn.execute("DELETE FROM prices WHERE sku = 'tea'");
+  const pricesSql = "SELECT sku, cents FROM prices";
+  assertEquals(await conn.query(pricesSql), []);
+  assertEquals(await conn.query(pricesSql, [], { as_of: beforeUpdate }), [
+    { sku: "tea", cents: 250 },
+  ]);
+  assertEquals(await conn.query(pricesSql, [], { as_of: beforeDelete }), [
+    { sku: "tea", cents: 300 },
+  ]);
+
+  // change data capture
+  await conn.execute("CREATE TABLE drafts (id INTEGER PRIMARY KEY, body TEXT)");
+  await conn.enableCdc("drafts");
+  await conn.execute("BEGIN");
+  await conn.execute("INSERT INTO drafts (id, body) VALUES (1, 'draft')");
+  await conn.execute("UPDATE drafts SET body = 'final' WHERE id = 1");
+  await conn.execute("COMMIT");
+  await conn.execute("DELETE FROM drafts WHERE id = 1");
+  const firstBatch = await conn.cdcPoll(0, 2);
+  const draft = { id: 1, body: "draft" };
+  assertEquals(
+    firstBatch.events.map(({ op, old, new: row }) => ({ op, old, new: row })),
+    [
+      { op: "insert", old: null, new: draft },
+      { op: "update", old: draft, new: { id: 1, body: "final" } },
+    ]
+  );
+  assertEquals(firstBatch.events[0].commit_id, firstBatch.events[1].commit_id);
+  const secondBatch = await conn.cdcPoll(firstBatch.cursor);
+  assertEquals(secondBatch.events.length, 1);
+  assertEquals(secondBatch.events[0].op, "delete");
+  assert(secondBatch.events[0].commit_id > firstBatch.events[1].commit_id);
+  assertEquals((await conn.cdcPoll(secondBatch.cursor)).events, []);
+  // columns added later are recorded too, and blobs are recorded as hex
+  await conn.execute("ALTER TABLE drafts ADD COLUMN attachment BLOB");
+  await conn.execute("BEGIN");
+  await conn.execute("SAVEPOINT undone");
+  await conn.execute("INSERT INTO drafts (id, body) VALUES (2, 'undone')");
+  await conn.execute("ROLLBACK TO undone");
+  await conn.execute("INSERT INTO drafts VALUES (3, 'scan', x'cafe')");
+  await conn.execute("COMMIT");
+  const thirdBatch = await conn.cdcPoll(secondBatch.cursor);
+  const [scan] = thirdBatch.events;
+  assertEquals(scan.new, { id: 3, body: "scan", attachment: { blob: "CAFE" } });
+  assertEquals(scan.commit_id, scan.seq);
+  // also when the table is altered via `query` instead of `execute`
+  await conn.query("ALTER TABLE drafts ADD COLUMN note TEXT");
+  await conn.execute("INSERT INTO drafts (id, note) VALUES (4, 'noted')");
+  assertEquals(
+    (await conn.cdcPoll(thirdBatch.cursor)).events.map((event) => event.new),
+    [{ id: 4, body: null, attachment: null, note: "noted" }]
+  );
+
+  // geospatial helpers
+  await conn.execute(
+    "CREATE VIRTUAL TABLE places USING rtree(id, min_lat, max_lat, min_lon, max_lon)"
+  );
+  await conn.execute(
+    "INSERT INTO places VALUES (1, 52.52, 52.52, 13.405, 13.405), (2, 48.137, 48.137, 11.575, 11.575)"
+  );
+  assertEquals(
+    (await conn.geoWithinRadius("places", 52.5, 13.4, 10000)).map(
+      (place) => place.id
+    ),
+    [1]
+  );
+  assertEquals(
+    await conn.query("SELECT geohash_encode(57.64911, 10.40744, 11) AS hash"),
+    [{ hash: "u4pruydqqvj" }]
+  );
+
+  // time-series downsampling
+  await conn.execute("CREATE TABLE metrics (ts INTEGER, name TEXT, value REAL)");
+  await conn.execute(
+    "INSERT INTO metrics VALUES (0, 'cpu', 1), (30, 'cpu', 3), (60, 'cpu', 5), (90, 'mem', 7)"
+  );
+  assertEquals(
+    await conn.tsDownsample({
+      table: "metrics",
+      time_column: "ts",
+      value_columns: ["value"],
+      group_columns: ["name"],
+      interval: 60,
+      before: 60,
+    }),
+    { deleted: 2, inserted: 1 }
+  );
+  assertEquals(
+    await conn.query(
+      "SELECT time_bucket('1m', ts) AS bucket, name, value FROM metrics ORDER BY ts"
+    ),
+    [
+      { bucket: 0, name: "cpu", value: 2 },
+      { bucket: 60, name: "cpu", value: 5 },
+      { bucket: 60, name: "mem", value: 7 },
+    ]
+  );
+
+  // JSON documents
+  await conn.execute(
+    "CREATE TABLE documents (id INTEGER PRIMARY KEY, body TEXT)"
+  );
+  await conn.execute(
+    `INSERT INTO documents VALUES (1, '{"name":"Alice","address":{"city":"Bonn"}}')`
+  );
+  await conn.execute(
+    `UPDATE documents SET body = json_pointer_set(
+      json_merge_patch(body, '{"name":null,"tags":["a"]}'),
+      '/address/zip', json_quote('53111')
+    ) WHERE id = 1`
+  );
+  assertEquals(
+    await conn.query(
+      "SELECT json_pointer_get(body, '/address/zip') AS zip, json_pointer_get(body, '/tags') AS tags, json_pointer_get(body, '/name') AS name FROM documents"
+    ),
+    [{ zip: "53111", tags: '["a"]', name: null }]
+  );
+
+  // transaction
+  await conn.execute("BEGIN");
+  await conn.execute("INSERT INTO users (name) VALUES (?)", ["Eve"]);
+  await conn.execute("ROLLBACK");
+  assertEquals(await countUsers(conn), 2);
+
+  await conn.execute("BEGIN");
+  await conn.execute("INSERT INTO users (name) VALUES (?)", ["Carol"]);
+  await conn.execute("COMMIT");
+  assertEquals(await countUsers(conn), 3);
+
+  let failed = false;
+  try {
+    await conn.execute("INSERT INTO missing VALUES (1)");
+  } catch (err) {
+    failed = true;
+    assert(String(err).includes("no such table"), `unexpected error: ${err}`);
+  }
+  assert(failed, "insert into missing table must fail");
+
+  failed = false;
+  try {
+    await conn.execute("DELETE FROM users", [], { readonly: true });
+  } catch (err) {
+    failed = true;
+    assertEquals(err.kind, "not_read_only");
+  }
+  assert(failed, "write in read-only query must fail");
+
+  // failing to open a connection throws instead of trapping the instance
+  failed = false;
+  try {
+    await sqlite.connect({ vfs: "missing" });
+  } catch (err) {
+    failed = true;
+    assert(String(err).includes("unknown vfs `missing`"), `unexpected error: ${err}`);
+  }
+  assert(failed, "connecting with an unknown vfs must fail");
+  assertEquals(await countUsers(conn), 3);
+
+  // errors of tagged connections carry the tag
+  await conn.setTag("tenant-1");
+  failed = false;
+  try {
+    await conn.execute("INSERT INTO missing VALUES (1)");
+  } catch (err) {
+    failed = true;
+    assertEquals(err.tag, "tenant-1");
+    assert(err.message.startsWith("[tenant-1] "), `unexpected error: ${err}`);
+  }
+  assert(failed, "insert into missing table must fail");
+  await conn.setTag(null);
+
+  // rows can be counted without transferring them
+  assertEquals(await conn.count("SELECT * FROM users"), await countUsers(conn));
+  assertEquals(
+    await conn.count("SELECT * FROM users WHERE name = ?", ["Nobody"]),
+    0
+  );
+
+  // large BLOBs can be written and read in chunks
+  await conn.execute("CREATE TABLE files (id INTEGER PRIMARY KEY, data BLOB)");
+  await conn.execute(
+    "INSERT INTO files (id, data) VALUES (1, zeroblob(10000))"
+  );
+  const writable = await conn.blobOpen({
+    table: "files",
+    column: "data",
+    rowid: 1,
+    writable: true,
+  });
+  assertEquals(await writable.size(), 10000);
+  const chunk = new Uint8Array(4096).fill(7);
+  for (let offset = 0; offset < 10000; offset += chunk.length) {
+    await writable.write(
+      offset,
+      chunk.subarray(0, Math.min(chunk.length, 10000 - offset))
+    );
+  }
+  await writable.close();
+  const readable = await conn.blobOpen({
+    table: "files",
+    column: "data",
+    rowid: 1,
+  });
+  const tail = await readable.read(9990, 10);
+  assert(tail.every((b) => b === 7), "blob must contain the written chunks");
+  await readable.close();
+
+  const opcodes = await conn.query(
+    "SELECT name FROM users WHERE id = ?",
+    [1],
+    { explain: "full" }
+  );
+  assert(
+    opcodes.some((op) => op.opcode === "OpenRead" && op.object === "users"),
+    "explain must list the opened table"
+  );
+
+  // connections can be restricted to registered queries
+  const restricted = await sqlite.connect();
+  await restricted.registerQuery(
+    "user_by_name",
+    "SELECT name FROM users WHERE name = ?"
+  );
+  await restricted.setSecurity({ registered_queries_only: true });
+  assertEquals(await restricted.run("user_by_name", ["Alice"]), [
+    { name: "Alice" },
+  ]);
+  // as are all other calls reading or changing rows, tables or the schema
+  for (const call of [
+    () => restricted.query("SELECT * FROM users"),
+    () => restricted.registerQuery("all_users", "SELECT * FROM users"),
+    () => restricted.setRowPolicy("users", "1"),
+    () => restricted.setVersion({ user_version: 9 }),
+    () => restricted.schemaDiff("CREATE TABLE users (id)", { apply: true }),
+    () => restricted.salvage(),
+    () => restricted.fork(17),
+    () => restricted.prewarm(["users"]),
+    () => restricted.copyTable(conn, "users"),
+    () => restricted.upsert("users", [{ name: "Eve" }], ["name"]),
+    () => restricted.kvGet("key"),
+    () => restricted.kvSet("key", 1),
+    () => restricted.kvDelete("key"),
+    () => restricted.kvList(),
+    () => restricted.counterIncr("visits"),
+    () => restricted.queuePush("mails", [{}]),
+    () => restricted.queuePopBatch("mails", 1),
+    () => restricted.tryAcquireLeadership("a", 1000),
+    () => restricted.releaseLeadership("a"),
+    () => restricted.jobSchedule("report", "@daily"),
+    () => restricted.jobsDue(),
+    () => restricted.outboxPush("events", [{}]),
+    () => restricted.outboxClaim(1, 1000),
+    () => restricted.outboxAck([1]),
+    () => restricted.ftsCreateIndex("users", ["name"]),
+    () => restricted.ftsSearch("users", "Alice"),
+    () => restricted.enableHistory("users"),
+    () => restricted.enableCdc("users"),
+    () => restricted.cdcPoll(),
+    () => restricted.geoWithinRadius("places", 52.5, 13.4, 10000),
+    () =>
+      restricted.tsDownsample({
+        table: "metrics",
+        time_column: "ts",
+        value_columns: ["value"],
+        interval: 60,
+        before: 60,
+      }),
+    () => restricted.blobOpen({ table: "files", column: "data", rowid: 1 }),
+    () => restricted.analyze(),
+    () => restricted.checkConstraints(),
+    () => restricted.selfTest(),
+    () => restricted.dumpPageMap(),
+    () => restricted.reclaimFreePages(),
+    () => sqlite.backup(restricted, conn),
+  ]) {
+    failed = false;
+    try {
+      await call();
+    } catch (err) {
+      failed = true;
+      assertEquals(err.kind, "not_registered");
+    }
+    assert(failed, "SQL must be rejected on a restricted connection");
+  }
+  failed = false;
+  try {
+    await restricted.run("all_users");
+  } catch (err) {
+    failed = true;
+    assertEquals(err.kind, "unknown_query");
+  }
+  assert(failed, "running an unknown query must fail");
+  await restricted.drop();
+
+  // row policies restrict all statements of a connection to the matching rows
+  await conn.execute(
+    "CREATE TABLE notes (id INTEGER PRIMARY KEY, tenant TEXT DEFAULT 'a', body TEXT)"
+  );
+  await conn.execute(
+    "INSERT INTO notes (tenant, body) VALUES ('a', 'mine'), ('b', 'theirs')"
+  );
+  const tenant = await sqlite.connect();
+  await tenant.setRowPolicy("notes", "tenant = 'a'");
+  await tenant.execute("INSERT INTO notes (body) VALUES ('defaulted')");
+  await tenant.execute("UPDATE notes SET body = upper(body)");
+  await tenant.execute("DELETE FROM notes WHERE body = 'DEFAULTED'");
+  assertEquals(await tenant.query("SELECT tenant, body FROM notes"), [
+    { tenant: "a", body: "MINE" },
+  ]);
+  for (const sql of [
+    "INSERT INTO notes (tenant, body) VALUES ('b', 'sneaky')",
+    "SELECT * FROM main.notes",
+    // the trigger would access the table under the name of the view
+    "CREATE TEMP TRIGGER NOTES INSTEAD OF DELETE ON notes " +
+      "BEGIN DELETE FROM main.notes; END",
+  ]) {
+    failed = false;
+    try {
+      await tenant.execute(sql);
+    } catch (_err) {
+      failed = true;
+    }
+    assert(failed, `row policy must reject: ${sql}`);
+  }
+  await tenant.setRowPolicy("notes", null);
+  assertEquals(await tenant.count("SELECT * FROM notes"), 2);
+  await tenant.drop();
+  await conn.execute("DROP TABLE notes");
+
+  // errors can be reported in the shape of D1's errors
+  await sqlite.setErrorStyle("d1");
+  failed = false;
+  try {
+    await conn.execute("INSERT INTO missing VALUES (1)");
+  } catch (err) {
+    failed = true;
+    assertEquals(err.message, "D1_ERROR: no such table: missing: SQLITE_ERROR");
+    assertEquals(err.cause.message, "no such table: missing: SQLITE_ERROR");
+    assertEquals(err.sql, "INSERT INTO missing VALUES (1)");
+  }
+  assert(failed, "insert into missing table must fail");
+  await sqlite.setErrorStyle("native");
+
+  // the host can rewrite SQL before it is prepared, e.g. to route it to a shadow table
+  await conn.execute("CREATE TABLE users_shadow (name TEXT)");
+  await conn.execute("INSERT INTO users_shadow (name) VALUES ('Shadow')");
+  vfs.rewriteQuery = (sql) =>
+    sql.startsWith("/* shadow */")
+      ? sql.replace("users", "users_shadow")
+      : undefined;
+  assertEquals(await conn.query("/* shadow */ SELECT name FROM users"), [
+    { name: "Shadow" },
+  ]);
+  delete vfs.rewriteQuery;
+
+  // SQL differing only in literals, comments and whitespace shares a fingerprint
+  const fingerprint = await sqlite.sqlFingerprint(
+    "SELECT * FROM users WHERE id IN (1, 2, 3) -- hot"
+  );
+  assertEquals(fingerprint.normalized, "select * from users where id in (?)");
+  assertEquals(
+    (await sqlite.sqlFingerprint("select *\n from USERS where id in (?)"))
+      .fingerprint,
+    fingerprint.fingerprint
+  );
+
+  // static database files can be queried via HTTP Range requests
+  const staticVfs = new MemoryVfs();
+  const staticDb = await (await Sqlite.instantiate(staticVfs)).connect();
+  await staticDb.execute("CREATE TABLE dataset (n INTEGER, padding BLOB)");
+  await staticDb.execute(
+    `WITH RECURSIVE s(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM s WHERE n < 100)
+     INSERT INTO dataset SELECT n, zeroblob(512) FROM s`
+  );
+  await staticDb.drop();
+  const file = new Uint8Array(staticVfs.pages.length * 4096);
+  staticVfs.pages.forEach((page, ix) => file.set(page, ix * 4096));
+  const ranges = [];
+  const fetchRange = async (_url, init) => {
+    const [start, end] = init.headers.Range.slice(6).split("-").map(Number);
+    ranges.push([start, end]);
+    return new Response(file.slice(start, end + 1), {
+      status: 206,
+      headers: { "Content-Range": `bytes ${start}-${end}/${file.length}` },
+    });
+  };
+  const httpVfs = await HttpRangeVfs.open("https://cdn.example/numbers.db", {
+    fetch: fetchRange,
+  });
+  const remote = await (await Sqlite.instantiate(httpVfs)).connect({
+    read_only: true,
+  });
+  assertEquals(await remote.query("SELECT sum(n) AS sum FROM dataset"), [
+    { sum: 5050 },
+  ]);
+  assert(
+    ranges.every(([start, end]) => end - start < file.length - 1),
+    "pages must be fetched via Range requests"
+  );
+  await remote.drop();
+
+  // an overlay stores the changes to a base image only, which stays untouched
+  const upperPages = new Map();
+  const pagesIn = (ns) => {
+    if (!upperPages.has(ns)) {
+      upperPages.set(ns, new Map());
+    }
+    return upperPages.get(ns);
+  };
+  // unlike `MemoryVfs`, pages that weren't written don't exist (instead of being empty)
+  const upper = {
+    pageCount: (ns) =>
+      Math.max(0, ...[...pagesIn(ns).keys()].map((ix) => ix + 1)),
+    getPage: async (ix, ns) => pagesIn(ns).get(ix) ?? null,
+    putPage: async (ix, page, ns) => {
+      pagesIn(ns).set(ix, new Uint8Array(page));
+    },
+    delPage: async (ix, ns) => {
+      pagesIn(ns).delete(ix);
+    },
+  };
+  const seeded = await (
+    await Sqlite.instantiate(new OverlayVfs(httpVfs, upper))
+  ).connect();
+  await seeded.execute("INSERT INTO dataset (n) VALUES (101)");
+  assertEquals(await seeded.query("SELECT sum(n) AS sum FROM dataset"), [
+    { sum: 5151 },
+  ]);
+  assert(
+    pagesIn(0).size < httpVfs.pageCount(0),
+    "only changed pages must be stored in the upper page store"
+  );
+  await seeded.drop();
+
+  // content-addressed page stores keep identical pages of all branches once, so forks are cheap
+  const blobs = new Map();
+  const pageMaps = new Map();
+  const contentStore = {
+    async getBlob(hash) {
+      return blobs.get(hash) ?? null;
+    },
+    async putBlob(hash, data) {
+      blobs.set(hash, data);
+    },
+    async getPageMap(branch) {
+      return pageMaps.get(branch) ?? null;
+    },
+    async putPageMap(branch, pageMap) {
+      pageMaps.set(branch, pageMap);
+    },
+  };
+  const mainBranch = await ContentAddressedVfs.open(contentStore, "main");
+  const mainSqlite = await Sqlite.instantiate(mainBranch);
+  const mainDb = await mainSqlite.connect();
+  await mainDb.execute("CREATE TABLE posts (title TEXT)");
+  await mainDb.execute("INSERT INTO posts (title) VALUES ('Hello')");
+  const previewBranch = await mainBranch.fork("preview");
+  const previewDb = await (await Sqlite.instantiate(previewBranch)).connect();
+  await previewDb.execute("INSERT INTO posts (title) VALUES ('Draft')");
+  assertEquals(await mainDb.query("SELECT title FROM posts"), [
+    { title: "Hello" },
+  ]);
+  const reopened = await (
+    await Sqlite.instantiate(await previewBranch.fork("preview-copy"))
+  ).connect();
+  assertEquals(await reopened.query("SELECT title FROM posts"), [
+    { title: "Hello" },
+    { title: "Draft" },
+  ]);
+  const branchPages = ["main", "preview"].map((b) => pageMaps.get(b)[0].length);
+  assert(
+    blobs.size < branchPages[0] + branchPages[1],
+    "pages of both branches must be deduplicated"
+  );
+  await Promise.all([previewDb.drop(), reopened.drop()]);
+
+  // a database can be forked into another namespace of the same page store, which shares the
+  // pages on a content-addressed page store
+  const blobCount = blobs.size;
+  const migration = await mainDb.fork(16);
+  assertEquals(migration.vfs, "cfdo-branch-16");
+  // only the header page differs (the copy has its own change counter)
+  assert(blobs.size <= blobCount + 1, "fork must share the pages of mainDb");
+  const migrationDb = await mainSqlite.connect({ vfs: migration.vfs });
+  await migrationDb.execute("ALTER TABLE posts ADD COLUMN slug TEXT");
+  assertEquals(await migrationDb.query("SELECT title, slug FROM posts"), [
+    { title: "Hello", slug: null },
+  ]);
+  assertEquals(await mainDb.query("SELECT * FROM posts"), [{ title: "Hello" }]);
+  await Promise.all([mainDb.drop(), migrationDb.drop()]);
+
+  // change tracking lists the pages changed since the last (incremental) backup
+  const backupVfs = new MemoryVfs();
+  const tracked = await Sqlite.instantiate(backupVfs);
+  await tracked.setChangeTracking(true);
+  const source = await tracked.connect();
+  await source.execute("CREATE TABLE events (body TEXT)");
+  const initial = await source.pagesChangedSince(0);
+  assert(initial.full, "changes since commit 0 must be unknown");
+  assertEquals(initial.pages.length, backupVfs.pageCount(0));
+  await source.execute(
+    `WITH RECURSIVE s(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM s WHERE n < 200)
+    INSERT INTO events (body) SELECT hex(randomblob(100)) FROM s`
+  );
+  const filled = await source.pagesChangedSince(initial.counter);
+  assert(!filled.full, "changes of tracked commits must be known");
+  assert(filled.counter > initial.counter);
+  await source.execute("INSERT INTO events (body) VALUES ('last')");
+  const incremental = await source.pagesChangedSince(filled.counter);
+  assert(!incremental.full, "changes of tracked commits must be known");
+  assert(incremental.pages.includes(0), "the header changes with every commit");
+  assert(incremental.pages.length < filled.pages.length);
+  await tracked.setChangeTracking(false);
+  await source.execute("DELETE FROM events WHERE body = 'last'");
+  await tracked.setChangeTracking(true);
+  await source.execute("INSERT INTO events (body) VALUES ('tracked again')");
+  const untracked = await source.pagesChangedSince(incremental.counter);
+  assert(untracked.full, "changes of untracked commits must be unknown");
+  await source.drop();
+
+  // the conformance test passes for a correct adapter and reports adapters keeping views into the
+  // wasm memory instead of copies of the pages
+  const conforming = await Sqlite.instantiate(new MemoryVfs());
+  const conformance = await conforming.runConformance();
+  assert(conformance.ok, JSON.stringify(conformance));
+  class ViewVfs extends MemoryVfs {
+    async putPage(ix, page, namespace) {
+      this.pagesOf(namespace)[ix] = page;
+    }
+  }
+  const viewing = await Sqlite.instantiate(new ViewVfs());
+  const broken = await viewing.runConformance();
+  assert(!broken.ok, "conformance test must fail for a broken adapter");
+  assertEquals(broken.checks[broken.checks.length - 1].name, "put_get");
+
+  // the statement journal keeps the last statements, with their bound values hashed
+  await sqlite.setStatementLog(2);
+  await conn.execute("INSERT INTO users (name) VALUES (?)", ["Journal"]);
+  await conn.query("SELECT count(*) FROM users");
+  await conn.execute("DELETE FROM users WHERE name = ?", ["Journal"]);
+  const journal = await sqlite.debugStatementLog();
+  assertEquals(
+    journal.map((entry) => entry.sql),
+    ["SELECT count(*) FROM users", "DELETE FROM users WHERE name = ?"]
+  );
+  assertEquals(journal[1].seq, journal[0].seq + 1);
+  assertEquals(journal[0].params_hash, null);
+  assertEquals(journal[1].params_hash.length, 16);
+  await sqlite.setStatementLog(0);
+  assertEquals(await sqlite.debugStatementLog(), []);
+
+  // writers wait for the write lock until their busy timeout expires, and the host is told once
+  // who they wait for
+  const blocked = [];
+  vfs.onLockBlocked = (lock) => blocked.push(lock);
+  const writer = await sqlite.connect({ busy_timeout_ms: 50 });
+  await writer.setTag("request-a");
+  await conn.setTag("long-txn");
+  await conn.execute("BEGIN IMMEDIATE");
+  const waitStarted = performance.now();
+  failed = false;
+  try {
+    await writer.execute("INSERT INTO users (name) VALUES (?)", ["Mallory"]);
+  } catch (err) {
+    failed = true;
+    assertEquals(err.code, 5); // SQLITE_BUSY
+  }
+  assert(failed, "write while another connection holds the write lock must fail");
+  assert(performance.now() - waitStarted >= 50, "writer must wait for its busy timeout");
+  assertEquals(blocked.length, 1);
+  assertEquals(blocked[0].kind, "Reserved");
+  assertEquals(blocked[0].tag, "request-a");
+  assertEquals(blocked[0].holder_tag, "long-txn");
+  assertEquals(blocked[0].holder_lock, "Reserved");
+  delete vfs.onLockBlocked;
+  await conn.execute("ROLLBACK");
+  await conn.setTag(null);
+  await writer.execute("BEGIN IMMEDIATE");
+  await writer.execute("ROLLBACK");
+  await writer.drop();
+
+  // oversized results fail, unless they are spilled and drained in chunks
+  await conn.execute("CREATE TABLE numbers (n INTEGER)");
+  await conn.execute(
+    `WITH RECURSIVE s(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM s WHERE n < 100)
+    INSERT INTO numbers SELECT n FROM s`
+  );
+  await conn.setLimits({ max_result_bytes: 256 });
+  failed = false;
+  try {
+    await conn.query("SELECT n FROM numbers");
+  } catch (err) {
+    failed = true;
+    assertEquals(err.kind, "result_size");
+  }
+  assert(failed, "oversized result must fail");
+  const numbers = await conn.query("SELECT n FROM numbers ORDER BY n", [], {
+    spill: true,
+  });
+  assertEquals(numbers.length, 100);
+  assertEquals(numbers[99], { n: 100 });
+  const spilled = JSON.parse(
+    await conn.queryRaw("SELECT n FROM numbers", [], { spill: true })
+  );
+  assertEquals(spilled.count, 100);
+  assertEquals((await conn.cursorNext(spilled.cursor, 2)).length, 2);
+  await conn.cursorClose(spilled.cursor);
+  assertEquals(await conn.cursorNext(spilled.cursor, 2), []);
+  await conn.setLimits({});
+  assertEquals(await countUsers(conn), 3);
+
+  // foreign keys are enforced, with details about deferred violations
+  await conn.execute(
+    "CREATE TABLE posts (id INTEGER PRIMARY KEY, user_id INTEGER REFERENCES users (id) DEFERRABLE INITIALLY DEFERRED)"
+  );
+  await conn.execute("BEGIN");
+  await conn.execute("INSERT INTO posts (user_id) VALUES (?)", [42]);
+  const report = await conn.checkConstraints(["posts"]);
+  assert(!report.ok, "constraint check must report the violation");
+  assertEquals(report.foreign_keys.length, 1);
+  failed = false;
+  try {
+    await conn.execute("COMMIT");
+  } catch (err) {
+    failed = true;
+    assert(err.rolledBack, "transaction must be rolled back");
+    assertEquals(err.foreignKeyViolations, [
+      { table: "posts", rowid: 1, parent: "users", fkid: 0 },
+    ]);
+  }
+  assert(failed, "commit with foreign key violation must fail");
+
+  // triggers writing through the page store (audit log), including rolled back writes
+  await conn.execute("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)");
+  await conn.execute("CREATE TABLE notes_audit (note_id INTEGER, action TEXT)");
+  await conn.execute(
+    "CREATE TRIGGER notes_insert AFTER INSERT ON notes BEGIN INSERT INTO notes_audit VALUES (new.id, 'insert'); END"
+  );
+  await conn.execute(
+    "CREATE TRIGGER notes_delete AFTER DELETE ON notes BEGIN INSERT INTO notes_audit VALUES (old.id, 'delete'); END"
+  );
+  await conn.execute("INSERT INTO notes (body) VALUES (?)", ["kept"]);
+  await conn.execute("BEGIN");
+  await conn.execute("INSERT INTO notes (body) VALUES (?)", ["rolled back"]);
+  await conn.execute("ROLLBACK");
+  await conn.execute("DELETE FROM notes");
+  assertEquals(
+    await conn.query("SELECT note_id, action FROM notes_audit ORDER BY rowid"),
+    [
+      { note_id: 1, action: "insert" },
+      { note_id: 1, action: "delete" },
+    ]
+  );
+
+  // recursive triggers are opt-in per connection
+  const recursive = await sqlite.connect({ recursive_triggers: true });
+  await recursive.execute("CREATE TABLE countdown (n INTEGER)");
+  await recursive.execute(
+    "CREATE TRIGGER countdown_next AFTER INSERT ON countdown WHEN new.n > 0 BEGIN INSERT INTO countdown VALUES (new.n - 1); END"
+  );
+  await recursive.execute("INSERT INTO countdown VALUES (3)");
+  assertEquals(await recursive.query("SELECT n FROM countdown ORDER BY n"), [
+    { n: 0 },
+    { n: 1 },
+    { n: 2 },
+    { n: 3 },
+  ]);
+  await recursive.drop();
+
+  // per-connection settings via on_open_sql
+  const tuned = await sqlite.connect({
+    on_open_sql: ["PRAGMA cache_size = -512", "PRAGMA temp_store = MEMORY"],
+  });
+  assertEquals(await tuned.query("PRAGMA cache_size"), [{ cache_size: -512 }]);
+  assertEquals(await tuned.query("PRAGMA temp_store"), [{ temp_store: 2 }]);
+  await tuned.drop();
+  await conn.execute("DELETE FROM countdown");
+  await conn.execute("INSERT INTO countdown VALUES (3)");
+  assertEquals(await conn.query("SELECT n FROM countdown ORDER BY n"), [
+    { n: 2 },
+    { n: 3 },
+  ]);
+
+  // strict tables: the JSON type follows each value, not the column declaration
+  await conn.execute(
+    "CREATE TABLE vals (id INTEGER PRIMARY KEY, v ANY, t TEXT AS (typeof(v))) STRICT"
+  );
+  await conn.execute(
+    "INSERT INTO vals (v) VALUES (?), (?), (?), (?), (?), (X'0102')",
+    [1, 1.5, "one", null, true]
+  );
+  assertEquals(await conn.query("SELECT v, t FROM vals ORDER BY id"), [
+    { v: 1, t: "integer" },
+    { v: 1.5, t: "real" },
+    { v: "one", t: "text" },
+    { v: null, t: "null" },
+    { v: 1, t: "integer" },
+    { v: [1, 2], t: "blob" },
+  ]);
+
+  // page map
+  const pageMap = await conn.dumpPageMap();
+  assertEquals(pageMap.pages.length, pageMap.page_count);
+  assertEquals(pageMap.pages[0], {
+    index: 0,
+    role: "header",
+    name: "sqlite_schema",
+  });
+  assert(
+    pageMap.objects.some((object) => object.name === "users"),
+    "page map must list the users table"
+  );
+
+  // page usage via the dbstat virtual table
+  const [usersStat] = await conn.query(
+    "SELECT name, pageno, pagetype FROM dbstat('main', 1) WHERE name = 'users'"
+  );
+  assertEquals(usersStat.name, "users");
+  assert(usersStat.pageno > 0, "dbstat must report the pages of the users table");
+
+  // raw page access via sqlite_dbpage is denied unless explicitly enabled
+  failed = false;
+  try {
+    await conn.query("SELECT data FROM sqlite_dbpage WHERE pgno = 1");
+  } catch (err) {
+    failed = true;
+    assert(String(err).includes("not authorized"), `unexpected error: ${err}`);
+  }
+  assert(failed, "raw page access must be denied by default");
+  const raw = await sqlite.connect({ dangerous_raw_pages: true });
+  assertEquals(
+    await raw.query("SELECT pgno, length(data) AS size FROM sqlite_dbpage WHERE pgno = 1"),
+    [{ pgno: 1, size: 4096 }]
+  );
+  await raw.drop();
+
+  // parameter introspection
+  const info = await conn.parameterInfo(
+    "SELECT id, name FROM users WHERE id = :id OR name = ?"
+  );
+  assertEquals(info.count, 2);
+  assertEquals(info.names, [":id", null]);
+  assertEquals(
+    info.columns.map((column) => [column.name, column.origin]),
+    [
+      ["id", "id"],
+      ["name", "name"],
+    ]
+  );
+  await conn.drop();
+
+  // backup: copy all pages into a fresh page store and open it in a new instance
+  const backup = new MemoryVfs(vfs.pages.map((page) => new Uint8Array(page)));
+  const restored = await (await Sqlite.instantiate(backup)).connect();
+  assertEquals(await countUsers(restored), 3);
+  await restored.drop();
+
+  // transient storage errors are retried
+  const flaky = new MemoryVfs(vfs.pages.map((page) => new Uint8Array(page)));
+  const flakyGetPage = flaky.getPage.bind(flaky);
+  let failures = 2;
+  flaky.getPage = async (ix, namespace) => {
+    if (ix > 0 && failures > 0) {
+      failures--;
+      throw new TransientError("rate limited");
+    }
+    return await flakyGetPage(ix, namespace);
+  };
+  const flakySqlite = await Sqlite.instantiate(flaky);
+  await flakySqlite.setRetryPolicy({ initial_backoff_ms: 1 });
+  const retried = await flakySqlite.connect();
+  assertEquals(await countUsers(retried), 3);
+  assertEquals(failures, 0);
+  await retried.drop();
+
+  // prewarming reads the root pages in a single batched read
+  const cold = new MemoryVfs(vfs.pages.map((page) => new Uint8Array(page)));
+  let batchedReads = 0;
+  cold.getPages = async (ixs, namespace) => {
+    batchedReads++;
+    return ixs.map((ix) => cold.pagesOf(namespace)[ix] ?? null);
+  };
+  const warmed = await (await Sqlite.instantiate(cold)).connect();
+  const prewarmed = await warmed.prewarm(["users"]);
+  assertEquals(batchedReads, 1);
+  assert(prewarmed.pages > 0, "prewarm must read the root page of users");
+  assertEquals(await countUsers(warmed), 3);
+  await warmed.drop();
+
+  // commits wait for the host to persist buffered page writes
+  const buffered = new MemoryVfs(vfs.pages.map((page) => new Uint8Array(page)));
+  let syncs = 0;
+  buffered.storageSync = async () => {
+    syncs++;
+  };
+  const synced = await (await Sqlite.instantiate(buffered)).connect();
+  await synced.execute("INSERT INTO users (name) VALUES (?)", ["Dave"]);
+  assert(syncs > 0, "commit must sync the page store");
+  buffered.storageSync = async () => {
+    throw new Error("sync failed");
+  };
+  failed = false;
+  try {
+    await synced.execute("INSERT INTO users (name) VALUES (?)", ["Eve"]);
+  } catch (err) {
+    failed = true;
+    assertEquals(err.hostError, "sync failed");
+  }
+  assert(failed, "commit must fail if the page store fails to sync");
+  await synced.drop();
+  const deferred = new MemoryVfs(vfs.pages.map((page) => new Uint8Array(page)));
+  syncs = 0;
+  deferred.storageSync = async () => {
+    syncs++;
+  };
+  const unsynced = await (await Sqlite.instantiate(deferred)).connect({
+    synchronous: "off",
+  });
+  assertEquals(await unsynced.query("PRAGMA synchronous"), [{ synchronous: 0 }]);
+  await unsynced.execute("INSERT INTO users (name) VALUES (?)", ["Frank"]);
+  assertEquals(syncs, 0);
+  await unsynced.drop();
+
+  // storage errors of the host surface as I/O errors carrying the host's message
+  const failing = new MemoryVfs(vfs.pages.map((page) => new Uint8Array(page)));
+  const getPage = failing.getPage.bind(failing);
+  failing.getPage = async (ix, namespace) => {
+    if (ix > 0) {
+      throw new Error("storage offline");
+    }
+    return await getPage(ix, namespace);
+  };
+  const offline = await (await Sqlite.instantiate(failing)).connect();
+  failed = false;
+  try {
+    await countUsers(offline);
+  } catch (err) {
+    failed = true;
+    assertEquals(err.hostError, "storage offline");
+  }
+  assert(failed, "query must fail if the host fails to read a page");
+  await offline.drop();
+
+  // salvage the rows of a corrupted database into the cfdo-salvage namespace
+  const damaged = new MemoryVfs();
+  const damagedSqlite = await Sqlite.instantiate(damaged);
+  const intact = await damagedSqlite.connect();
+  await intact.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, body TEXT)");
+  await intact.execute("CREATE INDEX items_body ON items (body)");
+  await intact.execute(
+    `WITH RECURSIVE s(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM s WHERE n < 500)
+    INSERT INTO items (body) SELECT hex(randomblob(100)) FROM s`
+  );
+  const leaves = (await intact.dumpPageMap()).pages.filter(
+    (page) => page.name === "items" && page.role === "leaf"
+  );
+  await intact.drop();
+  damaged.pages[leaves[Math.floor(leaves.length / 2)].index].fill(0xff);
+  const corrupted = await damagedSqlite.connect();
+  failed = false;
+  try {
+    await corrupted.query("SELECT count(*) AS count FROM items NOT INDEXED");
+  } catch (err) {
+    failed = true;
+    assert(String(err).includes("malformed"), `unexpected error: ${err}`);
+  }
+  assert(failed, "reading a corrupted page must fail");
+  const report = await corrupted.salvage();
+  await corrupted.drop();
+  assertEquals(report.failed, []);
+  const [items] = report.tables;
+  assertEquals(items.name, "items");
+  assert(items.corrupted, "salvage must report the corrupted rows");
+  assert(
+    items.rows > 400 && items.rows < 500,
+    `unexpected number of salvaged rows: ${items.rows}`
+  );
+  const salvaged = await damagedSqlite.connect({ vfs: "cfdo-salvage" });
+  assertEquals(await salvaged.query("SELECT count(*) AS count FROM items"), [
+    { count: items.rows },
+  ]);
+  assertEquals(await salvaged.query("PRAGMA integrity_check"), [
+    { integrity_check: "ok" },
+  ]);
+  await salvaged.drop();
+
+  // writes beyond the maximum database size fail, and the host is told how much storage each
+  // committed transaction consumed
+  const quotaVfs = new MemoryVfs();
+  let storedBytes = 0;
+  quotaVfs.onStorageDelta = (bytesDelta, ns) => {
+    if (ns === 0) storedBytes += bytesDelta;
+  };
+  const quota = await (await Sqlite.instantiate(quotaVfs)).connect();
+  await quota.setMaxDbSize(8 * 4096);
+  await quota.execute("CREATE TABLE blobs (data BLOB)");
+  failed = false;
+  try {
+    for (let i = 0; i < 10; i++) {
+      await quota.execute("INSERT INTO blobs (data) VALUES (zeroblob(4096))");
+    }
+  } catch (err) {
+    failed = true;
+    assertEquals(err.kind, "database_full");
+    assertEquals(err.code, 13); // SQLITE_FULL
+  }
+  assert(failed, "writes beyond the maximum database size must fail");
+  await quota.setMaxDbSize(null);
+  await quota.execute("INSERT INTO blobs (data) VALUES (zeroblob(4096))");
+  assertEquals(storedBytes, quotaVfs.pageCount(0) * 4096);
+
+  // truncating the database (e.g. by a VACUUM) deletes the dropped pages in a single call
+  const truncations = [];
+  quotaVfs.delPages = async (fromIx, toIx, namespace) => {
+    truncations.push([fromIx, toIx]);
+    quotaVfs.pagesOf(namespace).length = fromIx;
+  };
+  const untruncated = quotaVfs.pageCount(0);
+  await quota.execute("DELETE FROM blobs");
+  await quota.execute("VACUUM");
+  assertEquals(truncations, [[quotaVfs.pageCount(0), untruncated]]);
+  assertEquals(storedBytes, quotaVfs.pageCount(0) * 4096);
+  await quota.drop();
+
+  // freed pages are reused before the database grows, and can be reclaimed with incremental
+  // auto-vacuum
+  const freeVfs = new MemoryVfs();
+  const free = await (
+    await Sqlite.instantiate(freeVfs)
+  ).connect({ auto_vacuum: "incremental" });
+  const insertBlobs = async () => {
+    for (let i = 0; i < 8; i++) {
+      await free.execute("INSERT INTO blobs (data) VALUES (zeroblob(4000))");
+    }
+  };
+  await free.execute("CREATE TABLE blobs (data BLOB)");
+  await insertBlobs();
+  const grown = freeVfs.pageCount(0);
+  await free.execute("DELETE FROM blobs");
+  const freed = await free.freelistInfo();
+  assertEquals(freed.auto_vacuum, "incremental");
+  assertEquals(freed.page_count, grown);
+  assert(freed.freelist_count >= 8, "deleted blobs must free their pages");
+  await insertBlobs();
+  assertEquals(freeVfs.pageCount(0), grown);
+  assert((await free.freelistInfo()).freelist_count < freed.freelist_count);
+  await free.execute("DELETE FROM blobs");
+  const reclaimed = await free.reclaimFreePages();
+  assertEquals(reclaimed.freelist_count, 0);
+  assertEquals(reclaimed.page_count, freeVfs.pageCount(0));
+  assertEquals(reclaimed.reclaimed_pages, grown - freeVfs.pageCount(0));
+  await free.drop();
+
+  // with full auto-vacuum, each commit deletes the pages it freed from the page store
+  const fullVfs = new MemoryVfs();
+  const deletedPages = [];
+  fullVfs.delPages = async (fromIx, toIx, namespace) => {
+    deletedPages.push(toIx - fromIx);
+    fullVfs.pagesOf(namespace).length = fromIx;
+  };
+  const full = await (
+    await Sqlite.instantiate(fullVfs)
+  ).connect({ auto_vacuum: "full" });
+  await full.execute("CREATE TABLE blobs (data BLOB)");
+  await full.execute("INSERT INTO blobs (data) VALUES (zeroblob(20000))");
+  const withBlob = fullVfs.pageCount(0);
+  await full.execute("DELETE FROM blobs");
+  assertEquals(deletedPages.length, 1);
+  assertEquals(fullVfs.pageCount(0), withBlob - deletedPages[0]);
+  assertEquals((await full.freelistInfo()).freelist_count, 0);
+  await full.drop();
+
+  // auto-vacuum can only be set when the database is created
+  const existing = await (
+    await Sqlite.instantiate(fullVfs)
+  ).connect({ auto_vacuum: "none" });
+  assertEquals((await existing.freelistInfo()).auto_vacuum, "full");
+  await existing.drop();
+
+  // suspended connections are reopened on their next use, with their row policies, but cannot be
+  // suspended while a transaction or BLOB handle is open
+  const suspendVfs = new MemoryVfs();
+  const suspendSqlite = await Sqlite.instantiate(suspendVfs);
+  const suspending = await suspendSqlite.connect();
+  await suspending.execute("CREATE TABLE memos (owner TEXT, body TEXT)");
+  await suspending.execute("CREATE TABLE attachments (data BLOB)");
+  await suspending.execute(
+    "INSERT INTO memos (owner, body) VALUES ('a', 'mine'), ('b', 'theirs')"
+  );
+  await suspending.execute(
+    "INSERT INTO attachments (rowid, data) VALUES (1, zeroblob(16))"
+  );
+  await suspending.setRowPolicy("memos", "owner = 'a'");
+  await suspending.suspend();
+  assertEquals(await suspending.query("SELECT body FROM memos"), [
+    { body: "mine" },
+  ]);
+  const attachment = await suspending.blobOpen({
+    table: "attachments",
+    column: "data",
+    rowid: 1,
+  });
+  await suspending.execute("BEGIN");
+  for (const open of ["transaction", "BLOB"]) {
+    if (open === "BLOB") {
+      await suspending.execute("COMMIT");
+    }
+    failed = false;
+    try {
+      await suspending.suspend();
+    } catch (err) {
+      failed = true;
+      assert(String(err).includes(open), `unexpected error: ${err}`);
+    }
+    assert(failed, `suspending with an open ${open} must fail`);
+  }
+  // neither can it be dropped, which would leave the BLOB handle dangling
+  failed = false;
+  try {
+    await suspending.drop();
+  } catch (err) {
+    failed = true;
+    assert(String(err).includes("BLOB"), `unexpected error: ${err}`);
+  }
+  assert(failed, "dropping with an open BLOB must fail");
+  assertEquals(await attachment.size(), 16);
+  await attachment.close();
+  await suspending.suspend();
+  assertEquals(await suspending.query("SELECT body FROM memos"), [
+    { body: "mine" },
+  ]);
+
+  // the connection reports the rows changed so far and whether a transaction is left open
+  const changesBefore = await suspending.totalChanges();
+  assert(!(await suspending.inTransaction()), "no transaction must be open");
+  await suspending.execute("BEGIN");
+  assert(await suspending.inTransaction(), "BEGIN must leave it open");
+  await suspending.execute(
+    "INSERT INTO attachments (data) VALUES (zeroblob(8))"
+  );
+  await suspending.execute("UPDATE attachments SET data = zeroblob(4)");
+  assertEquals(await suspending.totalChanges(), changesBefore + 3);
+  await suspending.execute("COMMIT");
+  assert(!(await suspending.inTransaction()), "COMMIT must end it");
+
+  // pages beyond the database size from the header are reported as orphaned and deleted by
+  // reconciling the page store, while missing pages are reported and fail the reconciliation
+  const verified = await suspending.verifyPages();
+  const storedPages = suspendVfs.pageCount(0);
+  assertEquals(verified.page_count, storedPages);
+  assertEquals(verified.header_page_count, storedPages);
+  assertEquals([verified.missing, verified.orphaned], [[], []]);
+  suspendVfs.pages.push(new Uint8Array(4096), new Uint8Array(4096));
+  assertEquals((await suspending.verifyPages()).orphaned, [
+    storedPages,
+    storedPages + 1,
+  ]);
+  const reconciled = await suspending.reconcileStorage();
+  assertEquals(reconciled.header_page_count, storedPages);
+  assertEquals(reconciled.deleted, [storedPages, storedPages + 1]);
+  assertEquals(suspendVfs.pageCount(0), storedPages);
+  const lastPage = suspendVfs.pages.pop();
+  assertEquals((await suspending.verifyPages()).missing, [storedPages - 1]);
+  failed = false;
+  try {
+    await suspending.reconcileStorage();
+  } catch (err) {
+    failed = true;
+    assert(String(err).includes("missing pages"), `unexpected error: ${err}`);
+  }
+  assert(failed, "reconciling a page store with missing pages must fail");
+  suspendVfs.pages.push(lastPage);
+  assertEquals((await suspending.reconcileStorage()).deleted, []);
+
+  // the self-test exercises the page store and the SQLite features the module relies on
+  const selfTest = await suspending.selfTest();
+  assert(
+    selfTest.ok,
+    `self-test failed: ${JSON.stringify(selfTest.checks.filter((c) => !c.ok))}`
+  );
+  await suspending.drop();
+
+  // SQLite's memory usage is reported, and can be bounded by a soft heap limit
+  await suspendSqlite.setSoftHeapLimit(8 * 1024 * 1024);
+  const memory = await suspendSqlite.memoryStats();
+  assertEquals(memory.soft_heap_limit, 8 * 1024 * 1024);
+  assert(memory.sqlite_used > 0, "SQLite must report its memory usage");
+  assert(memory.sqlite_highwater >= memory.sqlite_used, "invalid highwater");
+  await suspendSqlite.setSoftHeapLimit(0);
+  assertEquals((await suspendSqlite.memoryStats()).soft_heap_limit, 0);
+
+  // with replication, the pages changed by each commit are emitted as a delta, which followers
+  // apply to their own page store (skipping deltas they are already past)
+  const deltas = [];
+  const primaryVfs = new MemoryVfs();
+  primaryVfs.emitDelta = async (delta) => {
+    deltas.push(delta);
+  };
+  const primarySqlite = await Sqlite.instantiate(primaryVfs);
+  await primarySqlite.setReplication(true);
+  const primary = await primarySqlite.connect();
+  await primary.execute("CREATE TABLE replicated (body TEXT)");
+  await primary.execute("INSERT INTO replicated (body) VALUES ('replicated')");
+  assert(deltas.length >= 2, "each commit must emit a delta");
+  const followerVfs = new MemoryVfs();
+  const followerSqlite = await Sqlite.instantiate(followerVfs);
+  for (const delta of deltas) {
+    assert(await followerSqlite.applyDelta(delta), "delta must be applied");
+  }
+  assertEquals(await followerSqlite.applyDelta(deltas[0]), false);
+  assertEquals(followerVfs.pageCount(0), primaryVfs.pageCount(0));
+  const follower = await followerSqlite.connect();
+  assertEquals(await follower.query("SELECT body FROM replicated"), [
+    { body: "replicated" },
+  ]);
+  await follower.drop();
+  await primarySqlite.setReplication(false);
+  await primary.drop();
+
+  // with write batching, page writes are kept in memory until they are flushed or discarded,
+  // while the connection already reads its own pending writes
+  const batchedVfs = new MemoryVfs();
+  const batchedSqlite = await Sqlite.instantiate(batchedVfs);
+  const batched = await batchedSqlite.connect();
+  const observer = await (await Sqlite.instantiate(batchedVfs)).connect();
+  const countBatched = async (conn) => {
+    const [{ n }] = await conn.query("SELECT COUNT(*) AS n FROM batched");
+    return n;
+  };
+  await batched.execute("CREATE TABLE batched (body TEXT)");
+  await batchedSqlite.setWriteBatching(true);
+  await batched.execute("INSERT INTO batched (body) VALUES ('flushed')");
+  assertEquals(await batched.query("SELECT body FROM batched"), [
+    { body: "flushed" },
+  ]);
+  const pending = await batchedSqlite.pendingWrites();
+  assertEquals(pending.length, 1);
+  assertEquals(pending[0].namespace, 0);
+  assert(pending[0].pages.length > 0, "the insert must leave pending pages");
+  assertEquals(await countBatched(observer), 0);
+  await batchedSqlite.flush();
+  assertEquals(await batchedSqlite.pendingWrites(), []);
+  assertEquals(await countBatched(observer), 1);
+
+  // discarding drops the pending writes, and the connection its stale page cache
+  await batched.execute("INSERT INTO batched (body) VALUES ('discarded')");
+  assertEquals(await countBatched(batched), 2);
+  await batchedSqlite.discard();
+  assertEquals(await batchedSqlite.pendingWrites(), []);
+  assertEquals(await batched.query("SELECT body FROM batched"), [
+    { body: "flushed" },
+  ]);
+
+  // a truncation and the writes growing the database again are flushed together
+  const insertBatched = async (n, size) => {
+    for (let i = 0; i < n; i++) {
+      await batched.execute("INSERT INTO batched (body) VALUES (zeroblob(?))", [
+        size,
+      ]);
+    }
+  };
+  await insertBatched(8, 4000);
+  await batchedSqlite.flush();
+  const batchedGrown = batchedVfs.pageCount(0);
+  await batched.execute("DELETE FROM batched WHERE body != 'flushed'");
+  await batched.execute("VACUUM");
+  const [truncated] = await batchedSqlite.pendingWrites();
+  assert(truncated.truncate !== null, "the VACUUM must truncate the database");
+  assert(truncated.truncate < batchedGrown, "the truncation must drop pages");
+  assertEquals(batchedVfs.pageCount(0), batchedGrown);
+  await insertBatched(4, 4000);
+  const [regrown] = await batchedSqlite.pendingWrites();
+  assertEquals(regrown.truncate, truncated.truncate);
+  assert(
+    regrown.pages.some((ix) => ix >= regrown.truncate),
+    "the inserts must grow the truncated database again"
+  );
+  await batchedSqlite.flush();
+  assertEquals(await batchedSqlite.pendingWrites(), []);
+  assertEquals(await countBatched(observer), 5);
+  assertEquals(await observer.query("PRAGMA page_count"), [
+    { page_count: batchedVfs.pageCount(0) },
+  ]);
+  await batchedSqlite.setWriteBatching(false);
+  await observer.drop();
+  await batched.drop();
+
+  // UTF-16 databases are rejected unless allowed, and converted to UTF-8 by salvaging them
+  const utf16 = new MemoryVfs();
+  const utf16Sqlite = await Sqlite.instantiate(utf16);
+  const legacy = await utf16Sqlite.connect({
+    on_open_sql: ["PRAGMA encoding = 'UTF-16le'"],
+  });
+  await legacy.execute("CREATE TABLE notes (body TEXT)");
+  await legacy.execute("INSERT INTO notes (body) VALUES (?)", ["grüße 👋"]);
+  await legacy.drop();
+  failed = false;
+  try {
+    await utf16Sqlite.connect();
+  } catch (err) {
+    failed = true;
+    assert(String(err).includes("UTF-16le"), `unexpected error: ${err}`);
+  }
+  assert(failed, "opening a UTF-16 database must fail");
+  const allowed = await utf16Sqlite.connect({ allow_utf16: true });
+  assertEquals(await allowed.encoding(), "UTF-16le");
+  await allowed.salvage();
+  await allowed.drop();
+  const converted = await utf16Sqlite.connect({ vfs: "cfdo-salvage" });
+  assertEquals(await converted.encoding(), "UTF-8");
+  assertEquals(await converted.query("SELECT body FROM notes"), [{ body: "grüße 👋" }]);
+  await converted.drop();
+
+  console.log(`${host}: all flows passed`);
+}
+
+async function countUsers(conn) {
+  const [{ n }] = await conn.query("SELECT COUNT(*) AS n FROM users");
+  return n;
+}
+
+function sleep(ms) {
+  return new Promise((resolve) => setTimeout(resolve, ms));
+}
+
+function assert(condition, message) {
+  if (!condition) {
+    throw new Error(message);
+  }
+}
+
+function assertEquals(actual, expected) {
+  const a = JSON.stringify(actual);
+  const b = JSON.stringify(expected);
+  assert(a === b, `expected ${b}, got ${a}`);
+}
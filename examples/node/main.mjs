@@ -0,0 +1,21 @@
+import { webcrypto } from "node:crypto";
+import {
+  ContentAddressedVfs,
+  HttpRangeVfs,
+  OverlayVfs,
+  Sqlite,
+  TransientError,
+} from "../../dist/wasm-sqlite.js";
+import { runFlows } from "../flows.mjs";
+
+// `crypto` is only a global starting with Node 19
+globalThis.crypto ??= webcrypto;
+
+await runFlows(
+  Sqlite,
+  "node",
+  TransientError,
+  ContentAddressedVfs,
+  HttpRangeVfs,
+  OverlayVfs
+);
@@ -0,0 +1,17 @@
+// Node doesn't support importing `.wasm` files as compiled `WebAssembly.Module`s (as e.g.
+// Cloudflare Workers do), which is what `dist/wasm-sqlite.js` expects. This loader fills that gap.
+
+export async function load(url, context, nextLoad) {
+  if (url.endsWith(".wasm")) {
+    return {
+      format: "module",
+      shortCircuit: true,
+      source: `
+        import { readFileSync } from "node:fs";
+        export default new WebAssembly.Module(readFileSync(new URL(${JSON.stringify(url)})));
+      `,
+    };
+  }
+
+  return nextLoad(url, context);
+}
@@ -0,0 +1,393 @@
+//! Runs the create/insert/select/transaction/strict/backup flows against the (not asyncified) wasm module
+//! using wasmtime with an in-memory page store. With `--bench`, measures inserts, point reads and
+//! scans instead.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use serde_json::{json, Value as JsonValue};
+use wasmtime::{Caller, Engine, Extern, Instance, Linker, Memory, Module, Store, TypedFunc};
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+
+const GET_PAGE_OK: u32 = 0;
+const GET_PAGE_NOT_FOUND: u32 = 1;
+const PUT_PAGE_OK: u32 = 0;
+const STORAGE_SYNC_OK: u32 = 0;
+
+struct Host {
+    wasi: WasiCtx,
+    /// The pages of each page store namespace.
+    pages: HashMap<u32, Vec<Vec<u8>>>,
+}
+
+struct Exports {
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+    dealloc: TypedFunc<u32, ()>,
+    conn_new: TypedFunc<(), u32>,
+    conn_execute: TypedFunc<(u32, u32, u32), i32>,
+    conn_query: TypedFunc<(u32, u32, u32), u32>,
+    conn_last_error: TypedFunc<u32, u32>,
+    conn_last_error_drop: TypedFunc<u32, ()>,
+    conn_drop: TypedFunc<u32, i32>,
+    query_result_drop: TypedFunc<u32, ()>,
+}
+
+struct Sqlite {
+    store: Store<Host>,
+    exports: Exports,
+}
+
+struct Connection<'a> {
+    sqlite: &'a mut Sqlite,
+    ptr: u32,
+}
+
+fn main() -> anyhow::Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .context("usage: wasm-sqlite-wasmtime-example <path/to/wasm_sqlite.wasm> [--bench]")?;
+
+    let engine = Engine::default();
+    let module = Module::from_file(&engine, &path)?;
+
+    if std::env::args().nth(2).as_deref() == Some("--bench") {
+        return bench(&engine, &module);
+    }
+
+    // create, insert, select, transaction
+    let mut sqlite = Sqlite::instantiate(&engine, &module, Vec::new())?;
+    let mut conn = sqlite.connect()?;
+    conn.execute(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL)",
+        json!([]),
+    )?;
+    conn.execute("INSERT INTO users (name) VALUES (?)", json!(["Alice"]))?;
+    conn.execute("INSERT INTO users (name) VALUES (?)", json!(["Bob"]))?;
+    let users = conn.query("SELECT id, name FROM users ORDER BY id", json!([]))?;
+    ensure!(
+        users == json!([{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]),
+        "unexpected select result: {users}"
+    );
+
+    conn.execute("BEGIN", json!([]))?;
+    conn.execute("INSERT INTO users (name) VALUES (?)", json!(["Eve"]))?;
+    conn.execute("ROLLBACK", json!([]))?;
+    ensure!(
+        count_users(&mut conn)? == 2,
+        "rollback did not discard insert"
+    );
+
+    conn.execute("BEGIN", json!([]))?;
+    conn.execute("INSERT INTO users (name) VALUES (?)", json!(["Carol"]))?;
+    conn.execute("COMMIT", json!([]))?;
+    ensure!(
+        count_users(&mut conn)? == 3,
+        "commit did not persist insert"
+    );
+
+    let err = conn
+        .execute("INSERT INTO missing VALUES (1)", json!([]))
+        .expect_err("insert into missing table must fail");
+    ensure!(
+        err.to_string().contains("no such table"),
+        "unexpected error: {err}"
+    );
+
+    // strict tables: the JSON type follows each value, not the column declaration
+    conn.execute(
+        "CREATE TABLE vals (id INTEGER PRIMARY KEY, v ANY, t TEXT AS (typeof(v))) STRICT",
+        json!([]),
+    )?;
+    conn.execute(
+        "INSERT INTO vals (v) VALUES (?), (?), (?), (?), (?), (X'0102')",
+        json!([1, 1.5, "one", null, true]),
+    )?;
+    let vals = conn.query("SELECT v, t FROM vals ORDER BY id", json!([]))?;
+    ensure!(
+        vals == json!([
+            {"v": 1, "t": "integer"},
+            {"v": 1.5, "t": "real"},
+            {"v": "one", "t": "text"},
+            {"v": null, "t": "null"},
+            {"v": 1, "t": "integer"},
+            {"v": [1, 2], "t": "blob"},
+        ]),
+        "unexpected strict table result: {vals}"
+    );
+    conn.drop()?;
+
+    // backup: copy all pages into a fresh page store and open it in a new instance
+    let backup = sqlite.store.data().pages[&0].clone();
+    let mut restored = Sqlite::instantiate(&engine, &module, backup)?;
+    let mut conn = restored.connect()?;
+    ensure!(count_users(&mut conn)? == 3, "backup is missing rows");
+    conn.drop()?;
+
+    eprintln!("wasmtime: all flows passed");
+    Ok(())
+}
+
+/// Measures the throughput through the wasm module, including the calls into the host.
+fn bench(engine: &Engine, module: &Module) -> anyhow::Result<()> {
+    const ROWS: i64 = 1000;
+    const SCANS: i64 = 100;
+
+    let mut sqlite = Sqlite::instantiate(engine, module, Vec::new())?;
+    let mut conn = sqlite.connect()?;
+    conn.execute(
+        "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT NOT NULL)",
+        json!([]),
+    )?;
+
+    let start = Instant::now();
+    conn.execute("BEGIN", json!([]))?;
+    for i in 0..ROWS {
+        conn.execute(
+            "INSERT INTO items (name) VALUES (?)",
+            json!([format!("item {i}")]),
+        )?;
+    }
+    conn.execute("COMMIT", json!([]))?;
+    report("inserts", ROWS, start.elapsed());
+
+    let start = Instant::now();
+    for id in 1..=ROWS {
+        conn.query("SELECT name FROM items WHERE id = ?", json!([id]))?;
+    }
+    report("point_reads", ROWS, start.elapsed());
+
+    let start = Instant::now();
+    for _ in 0..SCANS {
+        conn.query("SELECT SUM(LENGTH(name)) AS len FROM items", json!([]))?;
+    }
+    report("scans", SCANS, start.elapsed());
+
+    conn.drop()?;
+    Ok(())
+}
+
+fn report(name: &str, ops: i64, elapsed: Duration) {
+    eprintln!(
+        "{name}: {ops} ops in {elapsed:?} ({:.0} ops/s)",
+        ops as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn count_users(conn: &mut Connection<'_>) -> anyhow::Result<i64> {
+    let rows = conn.query("SELECT COUNT(*) AS n FROM users", json!([]))?;
+    rows[0]["n"]
+        .as_i64()
+        .ok_or_else(|| anyhow!("unexpected count result: {rows}"))
+}
+
+fn memory(caller: &mut Caller<'_, Host>) -> Memory {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .expect("module exports memory")
+}
+
+impl Sqlite {
+    fn instantiate(engine: &Engine, module: &Module, pages: Vec<Vec<u8>>) -> anyhow::Result<Self> {
+        let mut linker = Linker::new(engine);
+        wasmtime_wasi::add_to_linker(&mut linker, |host: &mut Host| &mut host.wasi)?;
+
+        linker.func_wrap("env", "page_count", |caller: Caller<'_, Host>, ns: u32| {
+            caller.data().pages.get(&ns).map_or(0, Vec::len) as u32
+        })?;
+        linker.func_wrap(
+            "env",
+            "get_page",
+            |mut caller: Caller<'_, Host>, ns: u32, ix: u32, ptr: u32, len: u32| {
+                let memory = memory(&mut caller);
+                let (data, host) = memory.data_and_store_mut(&mut caller);
+                let dst = &mut data[ptr as usize..(ptr + len) as usize];
+                match host.pages.get(&ns).and_then(|pages| pages.get(ix as usize)) {
+                    Some(page) => {
+                        dst.copy_from_slice(page);
+                        GET_PAGE_OK
+                    }
+                    None => GET_PAGE_NOT_FOUND,
+                }
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "get_pages",
+            |mut caller: Caller<'_, Host>,
+             ns: u32,
+             ixs: u32,
+             count: u32,
+             ptr: u32,
+             page_len: u32,
+             found: u32| {
+                let memory = memory(&mut caller);
+                let (data, host) = memory.data_and_store_mut(&mut caller);
+                let pages = host.pages.get(&ns);
+                for i in 0..count as usize {
+                    let at = ixs as usize + i * 4;
+                    let ix = u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+                    let page = pages.and_then(|pages| pages.get(ix as usize));
+                    data[found as usize + i] = u8::from(page.is_some());
+                    if let Some(page) = page {
+                        let dst = ptr as usize + i * page_len as usize;
+                        data[dst..dst + page_len as usize].copy_from_slice(page);
+                    }
+                }
+                GET_PAGE_OK
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "put_page",
+            |mut caller: Caller<'_, Host>, ns: u32, ix: u32, ptr: u32, len: u32| {
+                let memory = memory(&mut caller);
+                let (data, host) = memory.data_and_store_mut(&mut caller);
+                let pages = host.pages.entry(ns).or_default();
+                let ix = ix as usize;
+                if ix >= pages.len() {
+                    pages.resize(ix + 1, vec![0; len as usize]);
+                }
+                pages[ix].copy_from_slice(&data[ptr as usize..(ptr + len) as usize]);
+                PUT_PAGE_OK
+            },
+        )?;
+        linker.func_wrap(
+            "env",
+            "del_pages",
+            |mut caller: Caller<'_, Host>, ns: u32, from_ix: u32, _to_ix: u32| {
+                // The deleted pages are always the last ones.
+                if let Some(pages) = caller.data_mut().pages.get_mut(&ns) {
+                    pages.truncate(from_ix as usize);
+                }
+            },
+        )?;
+        // Pages are written to the in-memory page store right away.
+        linker.func_wrap("env", "storage_sync", |_ns: u32| STORAGE_SYNC_OK)?;
+        linker.func_wrap("env", "conn_sleep", |ms: u32| {
+            std::thread::sleep(Duration::from_millis(ms.into()));
+        })?;
+        // The in-memory page store has no transactions of its own.
+        linker.func_wrap("env", "txn_begin", || 0u32)?;
+        linker.func_wrap("env", "txn_commit", |_token: u32| {})?;
+        linker.func_wrap("env", "txn_rollback", |_token: u32| {})?;
+        // Replication is not enabled by the example.
+        linker.func_wrap("env", "emit_delta", |_ptr: u32, _len: u32| {})?;
+        linker.func_wrap("env", "export_span", |_ptr: u32, _len: u32| {})?;
+        linker.func_wrap("env", "slow_query", |_ptr: u32, _len: u32| {})?;
+        linker.func_wrap("env", "on_lock_blocked", |_ptr: u32, _len: u32| {})?;
+        linker.func_wrap("env", "on_storage_delta", |_ns: u32, _bytes_delta: f64| {})?;
+        // SQL is never rewritten.
+        linker.func_wrap("env", "rewrite_sql", |_ptr: u32, _len: u32| u32::MAX)?;
+        linker.func_wrap("env", "rewritten_sql", |_ptr: u32, _len: u32| {})?;
+        // The in-memory page store never fails.
+        linker.func_wrap("env", "host_error_message", |_ptr: u32, _len: u32| 0u32)?;
+        let start = Instant::now();
+        linker.func_wrap("env", "monotonic_millis", move || {
+            start.elapsed().as_secs_f64() * 1000.0
+        })?;
+
+        let wasi = WasiCtxBuilder::new().inherit_stderr().build();
+        let pages = HashMap::from([(0, pages)]);
+        let mut store = Store::new(engine, Host { wasi, pages });
+        let instance = linker.instantiate(&mut store, module)?;
+        let exports = Exports::new(&instance, &mut store)?;
+
+        Ok(Self { store, exports })
+    }
+
+    fn connect(&mut self) -> anyhow::Result<Connection<'_>> {
+        let ptr = self.exports.conn_new.call(&mut self.store, ())?;
+        ensure!(ptr != 0, "failed to open connection");
+        Ok(Connection { sqlite: self, ptr })
+    }
+}
+
+impl Exports {
+    fn new(instance: &Instance, store: &mut Store<Host>) -> anyhow::Result<Self> {
+        Ok(Self {
+            memory: instance
+                .get_memory(&mut *store, "memory")
+                .context("missing memory export")?,
+            alloc: instance.get_typed_func(&mut *store, "alloc")?,
+            dealloc: instance.get_typed_func(&mut *store, "dealloc")?,
+            conn_new: instance.get_typed_func(&mut *store, "conn_new")?,
+            conn_execute: instance.get_typed_func(&mut *store, "conn_execute")?,
+            conn_query: instance.get_typed_func(&mut *store, "conn_query")?,
+            conn_last_error: instance.get_typed_func(&mut *store, "conn_last_error")?,
+            conn_last_error_drop: instance.get_typed_func(&mut *store, "conn_last_error_drop")?,
+            conn_drop: instance.get_typed_func(&mut *store, "conn_drop")?,
+            query_result_drop: instance.get_typed_func(&mut *store, "query_result_drop")?,
+        })
+    }
+}
+
+impl<'a> Connection<'a> {
+    fn execute(&mut self, sql: &str, params: JsonValue) -> anyhow::Result<()> {
+        let (ptr, len) = self.write_query(sql, params)?;
+        let Sqlite { store, exports } = &mut *self.sqlite;
+        let ok = exports
+            .conn_execute
+            .call(&mut *store, (self.ptr, ptr, len))?;
+        exports.dealloc.call(&mut *store, ptr)?;
+        if ok == 0 {
+            return Err(self.last_error()?);
+        }
+        Ok(())
+    }
+
+    fn query(&mut self, sql: &str, params: JsonValue) -> anyhow::Result<JsonValue> {
+        let (ptr, len) = self.write_query(sql, params)?;
+        let Sqlite { store, exports } = &mut *self.sqlite;
+        let result = exports.conn_query.call(&mut *store, (self.ptr, ptr, len))?;
+        exports.dealloc.call(&mut *store, ptr)?;
+        if result == 0 {
+            return Err(self.last_error()?);
+        }
+
+        let data = exports.memory.data(&*store);
+        let word = |i: usize| {
+            let offset = result as usize + i * 4;
+            u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let (offset, len) = (word(0), word(1));
+        let json = serde_json::from_slice(&data[offset..offset + len])?;
+        exports.query_result_drop.call(&mut *store, result)?;
+
+        Ok(json)
+    }
+
+    fn drop(mut self) -> anyhow::Result<()> {
+        let Sqlite { store, exports } = &mut *self.sqlite;
+        if exports.conn_drop.call(&mut *store, self.ptr)? == 0 {
+            return Err(self.last_error()?);
+        }
+        Ok(())
+    }
+
+    fn write_query(&mut self, sql: &str, params: JsonValue) -> anyhow::Result<(u32, u32)> {
+        let query = serde_json::to_vec(&json!({ "v": 2, "sql": sql, "params": params }))?;
+        let len = query.len() as u32;
+        let Sqlite { store, exports } = &mut *self.sqlite;
+        let ptr = exports.alloc.call(&mut *store, len)?;
+        exports.memory.write(&mut *store, ptr as usize, &query)?;
+        Ok((ptr, len))
+    }
+
+    fn last_error(&mut self) -> anyhow::Result<anyhow::Error> {
+        let Sqlite { store, exports } = &mut *self.sqlite;
+        let ptr = exports.conn_last_error.call(&mut *store, self.ptr)?;
+        if ptr == 0 {
+            bail!("unknown error");
+        }
+
+        let data = &exports.memory.data(&*store)[ptr as usize..];
+        let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+        let message = String::from_utf8_lossy(&data[..len]).into_owned();
+        exports.conn_last_error_drop.call(&mut *store, ptr)?;
+
+        Ok(anyhow!(message))
+    }
+}
@@ -3,11 +3,125 @@ import module from "./wasm_sqlite.wasm";
 
 export type Param = string | number | boolean | null;
 
+// Each registered VFS (see `ConnectionOptions.vfs`) stores its pages in its own `namespace` (`0`
+// for the default `cfdo` VFS, `1` for `cfdo-cache`). Hosts that only use the default VFS can
+// ignore it, unless they enable `Sqlite.setChangeTracking`, which stores its records in namespace
+// `4`, or create branches (`16` and up, see `Connection.fork`).
 export interface Vfs {
-  pageCount(): number;
-  getPage(ix: number): Promise<Uint8Array>;
-  putPage(ix: number, page: Uint8Array): Promise<void>;
-  delPage(ix: number): Promise<void>;
+  pageCount(namespace: number): number;
+  // Resolves to `null` if the page does not exist. Rejects if the storage failed to read it, which
+  // fails the statement with an I/O error carrying the rejection's message (`SqliteError.hostError`).
+  // Rejections with a `TransientError` (of `getPage` and `putPage`) are retried first (see
+  // `Sqlite.setRetryPolicy`).
+  getPage(ix: number, namespace: number): Promise<Uint8Array | null>;
+  // Reads several pages at once (e.g. a single `storage.get(keys)` of a Durable Object), used by
+  // `Connection.prewarm`. Falls back to concurrent `getPage` calls if not implemented.
+  getPages?(
+    ixs: Array<number>,
+    namespace: number
+  ): Promise<Array<Uint8Array | null>>;
+  putPage(ix: number, page: Uint8Array, namespace: number): Promise<void>;
+  delPage(ix: number, namespace: number): Promise<void>;
+  // Deletes the pages `fromIx` (inclusive) to `toIx` (exclusive), which are always the last pages,
+  // e.g. when truncating the database after a `VACUUM`. Falls back to `delPage` calls (last page
+  // first) if not implemented.
+  delPages?(fromIx: number, toIx: number, namespace: number): Promise<void>;
+  // Called before SQLite considers a transaction durable, for stores that buffer `putPage` calls
+  // (e.g. batched Durable Object writes) to persist all pages written so far. Rejections fail the
+  // commit (`TransientError`s are retried first).
+  storageSync?(namespace: number): Promise<void>;
+
+  // Optional storage transaction around the page writes of each SQLite transaction. `txnBegin`
+  // returns a token that is passed to `txnCommit` or `txnRollback`.
+  txnBegin?(): Promise<number>;
+  txnCommit?(token: number): Promise<void>;
+  txnRollback?(token: number): Promise<void>;
+
+  // Receives the pages changed by each committed transaction while replication is enabled (see
+  // `Sqlite.setReplication`), to be passed to `Sqlite.applyDelta` of follower instances.
+  emitDelta?(delta: Uint8Array): Promise<void>;
+
+  // Receives closed spans while span export is enabled (see `Sqlite.setSpanExport`), e.g. to
+  // forward them to Workers Trace Events or an OpenTelemetry collector. Called synchronously.
+  exportSpan?(span: Span): void;
+
+  // Receives statements that exceeded the threshold set via `Sqlite.setSlowQueryThreshold`.
+  // Called synchronously.
+  slowQuery?(query: SlowQuery): void;
+
+  // Receives locks a connection failed to acquire because of another connection, e.g. to log
+  // which long-running transaction blocks a request. Retries of the same attempt (see the
+  // `busy_timeout_ms` option) are not reported again. Called synchronously.
+  onLockBlocked?(blocked: LockBlocked): void;
+
+  // Receives the bytes each committed storage transaction added to (positive) or removed from
+  // (negative) the page store of the namespace `ns`, e.g. to bill storage per tenant without
+  // scanning the stored pages. Called synchronously.
+  onStorageDelta?(bytesDelta: number, ns: number): void;
+
+  // Rewrites the SQL passed to `execute` and `query` (and their variants) before it is prepared,
+  // e.g. to route statements to shadow tables or add soft-delete filters. Returning `undefined`
+  // keeps the SQL as is. Called synchronously.
+  rewriteQuery?(sql: string): string | undefined;
+}
+
+export interface LockBlocked {
+  // The lock that couldn't be acquired, e.g. `Reserved` to start writing.
+  kind: string;
+  handle: number;
+  // The tag of the blocked connection (see `Connection.setTag`).
+  tag: string | null;
+  // The handle holding the write lock (or first in line for it); `null` if only readers are in
+  // the way.
+  holder: number | null;
+  holder_tag: string | null;
+  holder_lock: string | null;
+  // How long the holder has held its lock.
+  holder_held_ms: number | null;
+}
+
+export interface SlowQuery {
+  // The SQL with its placeholders; bound values are not included.
+  sql: string;
+  // The shape of the SQL, to aggregate slow queries by (see `Sqlite.sqlFingerprint`).
+  fingerprint: string;
+  duration_ms: number;
+  pages_read: number;
+  pages_written: number;
+  // The tag of the connection (see `Connection.setTag`).
+  tag: string | null;
+}
+
+export interface Fingerprint {
+  // E.g. `select * from users where id in (?)`.
+  normalized: string;
+  // A hash of `normalized` (16 hex digits).
+  fingerprint: string;
+}
+
+export interface StatementLogEntry {
+  // Increases with every statement; gaps reveal evicted entries.
+  seq: number;
+  // The SQL with its placeholders.
+  sql: string;
+  // A hash of the bound values keyed per module instance (16 hex digits), or `null` if there were
+  // none.
+  params_hash: string | null;
+  // When the statement finished, by the host's monotonic clock (`performance.now()`).
+  at_ms: number;
+  // The tag of the connection (see `Connection.setTag`).
+  tag: string | null;
+  error: string | null;
+}
+
+export interface Span {
+  name: string;
+  target: string;
+  parent: string | null;
+  duration_us: number;
+  // The tag of the connection the span belongs to (see `Connection.setTag`).
+  tag: string | null;
+  fields: Record<string, unknown>;
 }
 
 export class Sqlite {
@@ -21,6 +135,17 @@ export class Sqlite {
     let exports: Exports;
     const stdout = new Log(false);
     const stderr = new Log(true);
+    // the message of the last failed `getPage` or `putPage`, read by the module via
+    // `host_error_message`
+    let hostErrorMessage = new Uint8Array();
+    // the SQL returned by the last `rewriteQuery`, read by the module via `rewritten_sql`
+    let rewrittenSql = new Uint8Array();
+    const hostFailure = (err: unknown): number => {
+      hostErrorMessage = new TextEncoder().encode(
+        err instanceof Error ? err.message : String(err)
+      );
+      return err instanceof TransientError ? HOST_TRANSIENT_ERROR : HOST_ERROR;
+    };
     const instance = await Asyncify.instantiate(module, {
       wasi_snapshot_preview1: {
         // "wasi_snapshot_preview1"."random_get": [I32, I32] -> [I32]
@@ -132,25 +257,172 @@ export class Sqlite {
       },
 
       env: {
-        page_count(): number {
-          return vfs.pageCount();
+        page_count(ns: number): number {
+          return vfs.pageCount(ns);
         },
 
-        async get_page(ix: number, ptr: number) {
-          const page = await vfs.getPage(ix);
+        async get_page(
+          ns: number,
+          ix: number,
+          ptr: number,
+          len: number
+        ): Promise<number> {
+          let page: Uint8Array | null;
+          try {
+            page = await vfs.getPage(ix, ns);
+          } catch (err) {
+            return hostFailure(err);
+          }
+          if (!page) {
+            return GET_PAGE_NOT_FOUND;
+          }
           // console.log("got page:", ix, page);
           // console.log("write at", ptr, page.length);
-          const dst = new Uint8Array(exports.memory.buffer, ptr, 4096);
+          const dst = new Uint8Array(exports.memory.buffer, ptr, len);
           dst.set(page);
+          return GET_PAGE_OK;
+        },
+
+        async get_pages(
+          ns: number,
+          ixsPtr: number,
+          count: number,
+          ptr: number,
+          pageLen: number,
+          foundPtr: number
+        ): Promise<number> {
+          const ixs = Array.from(
+            new Uint32Array(exports.memory.buffer, ixsPtr, count)
+          );
+          let pages: Array<Uint8Array | null>;
+          try {
+            pages = await readPages(vfs, ixs, ns);
+          } catch (err) {
+            return hostFailure(err);
+          }
+          const found = new Uint8Array(exports.memory.buffer, foundPtr, count);
+          pages.forEach((page, i) => {
+            found[i] = page ? 1 : 0;
+            if (page) {
+              const dst = new Uint8Array(
+                exports.memory.buffer,
+                ptr + i * pageLen,
+                pageLen
+              );
+              dst.set(page);
+            }
+          });
+          return GET_PAGE_OK;
+        },
+
+        async put_page(
+          ns: number,
+          ix: number,
+          ptr: number,
+          len: number
+        ): Promise<number> {
+          const page = new Uint8Array(exports.memory.buffer, ptr, len);
+          try {
+            await vfs.putPage(ix, page, ns);
+          } catch (err) {
+            return hostFailure(err);
+          }
+          return PUT_PAGE_OK;
+        },
+
+        async del_pages(ns: number, fromIx: number, toIx: number) {
+          if (vfs.delPages) {
+            await vfs.delPages(fromIx, toIx, ns);
+            return;
+          }
+          for (let ix = toIx - 1; ix >= fromIx; ix--) {
+            await vfs.delPage(ix, ns);
+          }
+        },
+
+        async storage_sync(ns: number): Promise<number> {
+          try {
+            await vfs.storageSync?.(ns);
+          } catch (err) {
+            return hostFailure(err);
+          }
+          return STORAGE_SYNC_OK;
+        },
+
+        async txn_begin(): Promise<number> {
+          return (await vfs.txnBegin?.()) ?? 0;
+        },
+
+        async txn_commit(token: number) {
+          await vfs.txnCommit?.(token);
+        },
+
+        async txn_rollback(token: number) {
+          await vfs.txnRollback?.(token);
+        },
+
+        async emit_delta(ptr: number, len: number) {
+          // the delta is a view into the wasm memory, so it must be copied
+          const delta = new Uint8Array(exports.memory.buffer, ptr, len).slice();
+          await vfs.emitDelta?.(delta);
+        },
+
+        export_span(ptr: number, len: number) {
+          const json = new TextDecoder().decode(
+            new Uint8Array(exports.memory.buffer, ptr, len)
+          );
+          vfs.exportSpan?.(JSON.parse(json));
+        },
+
+        slow_query(ptr: number, len: number) {
+          const json = new TextDecoder().decode(
+            new Uint8Array(exports.memory.buffer, ptr, len)
+          );
+          vfs.slowQuery?.(JSON.parse(json));
+        },
+
+        on_lock_blocked(ptr: number, len: number) {
+          const json = new TextDecoder().decode(
+            new Uint8Array(exports.memory.buffer, ptr, len)
+          );
+          vfs.onLockBlocked?.(JSON.parse(json));
+        },
+
+        on_storage_delta(ns: number, bytesDelta: number) {
+          vfs.onStorageDelta?.(bytesDelta, ns);
+        },
+
+        rewrite_sql(ptr: number, len: number): number {
+          if (!vfs.rewriteQuery) {
+            return REWRITE_UNCHANGED;
+          }
+          const sql = new TextDecoder().decode(
+            new Uint8Array(exports.memory.buffer, ptr, len)
+          );
+          const rewritten = vfs.rewriteQuery(sql);
+          if (rewritten === undefined || rewritten === sql) {
+            return REWRITE_UNCHANGED;
+          }
+          rewrittenSql = new TextEncoder().encode(rewritten);
+          return rewrittenSql.length;
+        },
+
+        rewritten_sql(ptr: number, len: number) {
+          new Uint8Array(exports.memory.buffer, ptr, len).set(
+            rewrittenSql.subarray(0, len)
+          );
+          rewrittenSql = new Uint8Array();
         },
 
-        async put_page(ix: number, ptr: number) {
-          const page = new Uint8Array(exports.memory.buffer, ptr, 4096);
-          await vfs.putPage(ix, page);
+        monotonic_millis(): number {
+          return performance.now();
         },
 
-        async del_page(ix: number) {
-          await vfs.delPage(ix);
+        host_error_message(ptr: number, len: number): number {
+          new Uint8Array(exports.memory.buffer, ptr, len).set(
+            hostErrorMessage.subarray(0, len)
+          );
+          return hostErrorMessage.length;
         },
 
         async conn_sleep(ms: number) {
@@ -173,131 +445,2521 @@ export class Sqlite {
     return new Sqlite(exports);
   }
 
-  public async connect(): Promise<Connection> {
-    const ptr = await this.exports.conn_new();
+  public async connect(options?: ConnectionOptions): Promise<Connection> {
+    const ptr = options
+      ? await withJson(this.exports, options, (ptr, len) =>
+          this.exports.conn_new_with_options(ptr, len)
+        )
+      : await this.exports.conn_new();
+    if (!ptr) {
+      await this.throwInitError();
+    }
+    return new SqliteConnection(ptr, this.exports);
+  }
+
+  // Opens a scratch database that lives in the wasm memory only and never touches the page store.
+  // Page store related methods (`reconcileStorage`, `verifyPages`) fail on such connections.
+  public async connectMemory(): Promise<Connection> {
+    const ptr = await this.exports.conn_new_memory();
+    if (!ptr) {
+      await this.throwInitError();
+    }
     return new SqliteConnection(ptr, this.exports);
   }
-}
 
-export interface Connection {
-  execute(sql: string, params?: Array<Param>): Promise<void>;
-  query<T>(sql: string, params?: Array<Param>): Promise<Array<T>>;
-  queryRaw(sql: string, params?: Array<Param>): Promise<string>;
-  drop(): Promise<void>;
+  private async throwInitError(): Promise<void> {
+    const ptr = await this.exports.last_init_error();
+    if (ptr) {
+      const report = JSON.parse(await takeJsonString(this.exports, ptr));
+      throw new SqliteError(report);
+    } else {
+      throw new Error("failed to open connection");
+    }
+  }
+
+  // While enabled, page writes are kept in memory until `flush()` is called, which allows to
+  // integrate them into a transaction of the underlying storage. Disabling flushes pending writes.
+  public async setWriteBatching(enabled: boolean): Promise<void> {
+    await this.exports.set_write_batching(enabled ? 1 : 0);
+  }
+
+  // Lists the pending writes of each namespace that has any.
+  public async pendingWrites(): Promise<Array<PendingWrites>> {
+    const ptr = await this.exports.pending_writes();
+    return JSON.parse(await takeJsonString(this.exports, ptr));
+  }
+
+  // Must not be called while a transaction is in progress. If a page write fails, the writes stay
+  // pending and can be flushed again.
+  public async flush(): Promise<void> {
+    if (!(await this.exports.flush())) {
+      throw new Error("failed to flush pending writes");
+    }
+  }
+
+  // Sets how often and how fast `getPage` and `putPage` calls rejected with a `TransientError`
+  // are retried before the statement fails (4 attempts, starting with 10ms backoff, by default).
+  public async setRetryPolicy(policy: RetryPolicy): Promise<void> {
+    const ok = await withJson(this.exports, policy, (ptr, len) =>
+      this.exports.set_retry_policy(ptr, len)
+    );
+    if (!ok) {
+      throw new Error("invalid retry policy");
+    }
+  }
+
+  // With the `d1` style, errors are reported like Cloudflare D1 does: the message is prefixed with
+  // `D1_ERROR: ` and suffixed with the SQLite result code name, `cause` holds the chain of causes
+  // as nested errors, and `sql` the beginning of the failed statement. Defaults to `native`.
+  public async setErrorStyle(style: "native" | "d1"): Promise<void> {
+    const ok = await withJson(this.exports, style, (ptr, len) =>
+      this.exports.set_error_style(ptr, len)
+    );
+    if (!ok) {
+      throw new Error("invalid error style");
+    }
+  }
+
+  public async discard(): Promise<void> {
+    await this.exports.discard();
+  }
+
+  // Starts an online backup of the database of `src` into the database of `dst`, which is copied
+  // incrementally via `Backup.step()`, so writers to `src` are only blocked per step.
+  public async backup(src: Connection, dst: Connection): Promise<Backup> {
+    const target = dst as SqliteConnection;
+    const ptr = await this.exports.backup_start(
+      (src as SqliteConnection).ptr,
+      target.ptr
+    );
+    if (!ptr) {
+      await target.throwLastError();
+    }
+    return new Backup(ptr, target, this.exports);
+  }
+
+  // Reports the memory used by SQLite and the size of the module's memory, e.g. to monitor how
+  // close an isolate is to its memory limit.
+  public async memoryStats(): Promise<MemoryStats> {
+    const ptr = await this.exports.memory_stats();
+    return JSON.parse(await takeJsonString(this.exports, ptr));
+  }
+
+  // Releases module-wide memory not needed anymore, e.g. after a burst of large queries. See also
+  // `Connection.releaseMemory()`.
+  public async releaseMemory(): Promise<void> {
+    await this.exports.release_memory();
+  }
+
+  // Once SQLite's memory usage reaches `bytes`, it releases page cache memory before allocating
+  // more. `0` removes the limit.
+  public async setSoftHeapLimit(bytes: number): Promise<void> {
+    await this.exports.set_soft_heap_limit(bytes);
+  }
+
+  // While enabled, spans around open, lock, read, write and query are passed to `Vfs.exportSpan`.
+  public async setSpanExport(enabled: boolean): Promise<void> {
+    await this.exports.set_span_export(enabled ? 1 : 0);
+  }
+
+  // Statements running longer than `ms` milliseconds are passed to `Vfs.slowQuery`. `0` disables
+  // the slow-query log.
+  public async setSlowQueryThreshold(ms: number): Promise<void> {
+    await this.exports.set_slow_query_threshold(ms);
+  }
+
+  // While enabled, the pages of the `cfdo` database each commit changes are recorded in the page
+  // store (namespace `4`), so that `Connection.pagesChangedSince` can list them for incremental
+  // backups.
+  public async setChangeTracking(enabled: boolean): Promise<void> {
+    await this.exports.set_change_tracking(enabled ? 1 : 0);
+  }
+
+  // While enabled, the pages changed by each committed transaction are passed to `Vfs.emitDelta`.
+  public async setReplication(enabled: boolean): Promise<void> {
+    await this.exports.set_replication(enabled ? 1 : 0);
+  }
+
+  // Applies a delta emitted by a primary instance. Resolves to `false` if the delta was skipped,
+  // because the page store is already at the same or a later commit.
+  public async applyDelta(delta: Uint8Array): Promise<boolean> {
+    const ptr = await this.exports.alloc(delta.length);
+    new Uint8Array(this.exports.memory.buffer, ptr, delta.length).set(delta);
+    try {
+      const result = await this.exports.apply_delta(ptr, delta.length);
+      if (result < 0) {
+        throw new Error("invalid delta");
+      }
+      return result === 1;
+    } finally {
+      await this.exports.dealloc(ptr);
+    }
+  }
+
+  // Normalizes the SQL into its shape (literals and placeholders replaced by `?`, comments
+  // removed, ...) and hashes it, e.g. to aggregate metrics by query. Slow queries and the spans of
+  // statements carry the same fingerprint.
+  public async sqlFingerprint(sql: string): Promise<Fingerprint> {
+    const ptr = await withJson(this.exports, sql, (ptr, len) =>
+      this.exports.sql_fingerprint(ptr, len)
+    );
+    if (!ptr) {
+      throw new Error("invalid sql");
+    }
+    return JSON.parse(await takeJsonString(this.exports, ptr));
+  }
+
+  // Checks that the `Vfs` follows the contract of the page store (see the README), e.g. that
+  // `getPage` returns the content of the last `putPage`, against the `namespace`, which must be
+  // empty and not be used by any connection. The pages written are deleted again.
+  public async runConformance(namespace = 0): Promise<ConformanceReport> {
+    const ptr = await this.exports.run_conformance(namespace);
+    if (!ptr) {
+      throw new Error(`unknown namespace ${namespace}`);
+    }
+    return JSON.parse(await takeJsonString(this.exports, ptr));
+  }
+
+  // Keeps the last `capacity` statements of all connections in a journal (see
+  // `debugStatementLog`), e.g. to reconstruct the writes that led to a corrupted database. `0`
+  // disables the journal.
+  public async setStatementLog(capacity: number): Promise<void> {
+    await this.exports.set_statement_log(capacity);
+  }
+
+  // The statements in the journal, oldest first.
+  public async debugStatementLog(): Promise<Array<StatementLogEntry>> {
+    const ptr = await this.exports.debug_statement_log();
+    return JSON.parse(await takeJsonString(this.exports, ptr));
+  }
+
+  // Lists all database handles that currently hold or wait for a lock, e.g. to diagnose hanging
+  // queries.
+  public async locksDebug(): Promise<Array<LockDebug>> {
+    const ptr = await this.exports.locks_debug();
+    return JSON.parse(await takeJsonString(this.exports, ptr));
+  }
 }
 
-class SqliteConnection implements Connection {
+export class Backup {
   private readonly ptr: number;
+  private readonly dst: SqliteConnection;
   private readonly exports: Exports;
-  private readonly encoder = new TextEncoder();
-  private readonly decoder = new TextDecoder();
 
-  public constructor(ptr: number, exports: Exports) {
+  public constructor(ptr: number, dst: SqliteConnection, exports: Exports) {
     this.ptr = ptr;
+    this.dst = dst;
     this.exports = exports;
   }
 
-  private async throwLastError(): Promise<void> {
-    const m = await this.exports.conn_last_error(this.ptr);
-    if (m) {
-      const decoder = new TextDecoder();
+  // Copies up to `pages` pages (all remaining ones if omitted). Resolves to `true` once the
+  // backup is complete.
+  public async step(pages = -1): Promise<boolean> {
+    const result = await this.exports.backup_step(this.ptr, pages);
+    if (result < 0) {
+      await this.dst.throwLastError();
+    }
+    return result === 0;
+  }
 
-      let memory = new Uint8Array(this.exports.memory.buffer);
-      let len = 0;
-      for (; memory[len + m] != 0; len++) {}
+  public async progress(): Promise<BackupProgress> {
+    const ptr = await this.exports.backup_progress(this.ptr);
+    return JSON.parse(await takeJsonString(this.exports, ptr));
+  }
 
-      const data = new Uint8Array(this.exports.memory.buffer, m, len);
-      const err = decoder.decode(data);
-      await this.exports.conn_last_error_drop(m);
-      throw new Error(err);
-    } else {
-      throw new Error("unknown error");
+  // Must be called once done (or to abort the backup).
+  public async finish(): Promise<void> {
+    if (!(await this.exports.backup_finish(this.ptr))) {
+      await this.dst.throwLastError();
+    }
+  }
+}
+
+// A BLOB value opened for incremental I/O via `Connection.blobOpen`, to stream large values in
+// chunks instead of transferring them in a single JSON payload.
+export class BlobHandle {
+  private readonly ptr: number;
+  private readonly conn: SqliteConnection;
+  private readonly exports: Exports;
+
+  public constructor(ptr: number, conn: SqliteConnection, exports: Exports) {
+    this.ptr = ptr;
+    this.conn = conn;
+    this.exports = exports;
+  }
+
+  // The size of the BLOB in bytes, which can't be changed via the handle.
+  public async size(): Promise<number> {
+    return await this.exports.blob_size(this.ptr);
+  }
+
+  // Reads `length` bytes starting at `offset`.
+  public async read(offset: number, length: number): Promise<Uint8Array> {
+    const ptr = await this.exports.alloc(length);
+    try {
+      if (!(await this.exports.blob_read(this.ptr, offset, ptr, length))) {
+        await this.conn.throwLastError();
+      }
+      return new Uint8Array(this.exports.memory.buffer, ptr, length).slice();
+    } finally {
+      await this.exports.dealloc(ptr);
     }
   }
 
-  public async execute(sql: string, params?: Array<Param>): Promise<void> {
-    const query = JSON.stringify({
-      sql,
-      params: params ?? [],
-    });
+  // Overwrites the bytes starting at `offset`. Requires the BLOB to be opened as `writable`.
+  public async write(offset: number, data: Uint8Array): Promise<void> {
+    const ptr = await this.exports.alloc(data.length);
+    new Uint8Array(this.exports.memory.buffer, ptr, data.length).set(data);
+    try {
+      if (
+        !(await this.exports.blob_write(this.ptr, offset, ptr, data.length))
+      ) {
+        await this.conn.throwLastError();
+      }
+    } finally {
+      await this.exports.dealloc(ptr);
+    }
+  }
 
-    const queryOffset = await this.exports.alloc(query.length);
-    this.encoder.encodeInto(
-      query,
-      new Uint8Array(this.exports.memory.buffer, queryOffset, query.length)
-    );
-    const ok = await this.exports.conn_execute(
-      this.ptr,
-      queryOffset,
-      query.length
+  // Moves the handle to the value of the same column in another row.
+  public async reopen(rowid: number): Promise<void> {
+    const ok = await withJson(this.exports, rowid, (ptr, len) =>
+      this.exports.blob_reopen(this.ptr, ptr, len)
     );
-    await this.exports.dealloc(queryOffset, query.length);
     if (!ok) {
-      await this.throwLastError();
+      await this.conn.throwLastError();
     }
   }
 
-  public async query<T>(sql: string, params?: Array<Param>): Promise<Array<T>> {
-    return JSON.parse(await this.queryRaw(sql, params));
+  // Must be called once done, before the connection is dropped or suspended.
+  public async close(): Promise<void> {
+    if (!(await this.exports.blob_close(this.ptr))) {
+      await this.conn.throwLastError();
+    }
   }
+}
 
-  public async queryRaw(sql: string, params?: Array<Param>): Promise<string> {
-    const query = JSON.stringify({
-      sql,
-      params: params ?? [],
-    });
+export interface BlobTarget {
+  table: string;
+  column: string;
+  rowid: number;
+  // Open the BLOB for writing as well (default: `false`).
+  writable?: boolean;
+}
 
-    const queryOffset = await this.exports.alloc(query.length);
-    this.encoder.encodeInto(
-      query,
-      new Uint8Array(this.exports.memory.buffer, queryOffset, query.length)
-    );
-    const resultPtr = await this.exports.conn_query(
-      this.ptr,
-      queryOffset,
-      query.length
-    );
-    await this.exports.dealloc(queryOffset, query.length);
-    if (!resultPtr) {
-      await this.throwLastError();
+export interface MemoryStats {
+  sqlite_used: number;
+  sqlite_highwater: number;
+  page_cache: number;
+  heap_size: number | null;
+  soft_heap_limit: number;
+}
+
+export interface BackupProgress {
+  remaining: number;
+  page_count: number;
+}
+
+export interface LockDebug {
+  handle: number;
+  lock: string;
+  held_ms: number;
+  requested: string | null;
+  failed_attempts: number;
+  waiting_ms: number | null;
+  // The tag of the connection that last used the handle (see `Connection.setTag`).
+  tag: string | null;
+}
+
+export interface ConnectionOptions {
+  // Open the database read-only.
+  read_only?: boolean;
+  // Create the database if it doesn't exist yet (default: `true`).
+  create?: boolean;
+  // Open the database using an URI filename (e.g. `file:main.db?mode=ro`).
+  uri?: string;
+  // The registered VFS to open the database with: `cfdo` (default, 4096 byte pages),
+  // `cfdo-cache` (1024 byte pages) or `cfdo-salvage` (see `Connection.salvage`), each backed by
+  // its own page store namespace.
+  vfs?: string;
+  // Enforce foreign key constraints (`PRAGMA foreign_keys`, default: `true`).
+  foreign_keys?: boolean;
+  // Let triggers fire other triggers, including themselves (`PRAGMA recursive_triggers`,
+  // default: `false`).
+  recursive_triggers?: boolean;
+  // Allow reading and writing raw database pages through the `sqlite_dbpage` virtual table
+  // (default: `false`). Writes bypass all consistency checks and can corrupt the database; only
+  // meant for backup and repair tooling.
+  dangerous_raw_pages?: boolean;
+  // SQL executed after opening the database (and after reopening a suspended connection), e.g.
+  // `PRAGMA cache_size = -8000` or `PRAGMA temp_store = MEMORY`. Must not change the
+  // `journal_mode`.
+  on_open_sql?: Array<string>;
+  // Whether commits wait for `Vfs.storageSync` (`PRAGMA synchronous`, default: `full`). With
+  // `off`, `storageSync` is never called and the latest commits are only as durable as the
+  // host's buffering; `normal`, `full` and `extra` sync once per commit.
+  synchronous?: "off" | "normal" | "full" | "extra";
+  // How long writes wait for other connections to release the write lock, in milliseconds
+  // (default: `0`, failing with `SQLITE_BUSY` right away). Waiting writers get the lock in the
+  // order they asked for it.
+  busy_timeout_ms?: number;
+  // Allow opening databases with a UTF-16 text encoding (default: `false`), e.g. to convert them
+  // to UTF-8 via `Connection.salvage`.
+  allow_utf16?: boolean;
+  // Whether free pages are deleted from the page store (`PRAGMA auto_vacuum`, default: `none`):
+  // `full` at every commit, `incremental` via `Connection.reclaimFreePages`. Only applied when the
+  // database is created.
+  auto_vacuum?: "none" | "full" | "incremental";
+}
+
+export interface PendingWrites {
+  namespace: number;
+  pages: Array<number>;
+  truncate: number | null;
+}
+
+export interface Connection {
+  execute(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<void>;
+  query<T>(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<Array<T>>;
+  queryRaw(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<string>;
+  cursorNext<T>(cursor: number, maxRows: number): Promise<Array<T>>;
+  cursorClose(cursor: number): Promise<void>;
+  queryWithMeta<T>(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<QueryResult<T>>;
+  count(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<number>;
+  totalChanges(): Promise<number>;
+  inTransaction(): Promise<boolean>;
+  setLimits(limits: Limits): Promise<void>;
+  setMaxDbSize(bytes: number | null): Promise<void>;
+  setAutoRollback(enabled: boolean): Promise<void>;
+  setTag(tag: string | null): Promise<void>;
+  setSecurity(security: Security): Promise<void>;
+  setRowPolicy(table: string, predicate: string | null): Promise<void>;
+  registerQuery(name: string, sql: string): Promise<void>;
+  blobOpen(target: BlobTarget): Promise<BlobHandle>;
+  run<T>(name: string, params?: Array<Param>): Promise<Array<T>>;
+  tune(profile: TuningProfile): Promise<void>;
+  reconcileStorage(): Promise<Reconciliation>;
+  verifyPages(): Promise<PageVerification>;
+  pagesChangedSince(counter: number): Promise<ChangedPages>;
+  freelistInfo(): Promise<FreelistInfo>;
+  reclaimFreePages(maxPages?: number): Promise<ReclaimReport>;
+  dumpPageMap(): Promise<PageMap>;
+  prewarm(tables?: Array<string>): Promise<PrewarmReport>;
+  salvage(): Promise<SalvageReport>;
+  fork(namespace: number): Promise<ForkReport>;
+  encoding(): Promise<string>;
+  getVersion(): Promise<DbVersion>;
+  setVersion(version: SetDbVersion): Promise<DbVersion>;
+  schemaDiff(schema: string, options?: SchemaDiffOptions): Promise<SchemaDiff>;
+  selfTest(): Promise<SelfTestReport>;
+  analyze(options?: AnalyzeOptions): Promise<AnalyzeReport>;
+  parameterInfo(sql: string): Promise<ParameterInfo>;
+  checkConstraints(tables?: Array<string>): Promise<ConstraintReport>;
+  copyTable(dst: Connection, table: string): Promise<CopyReport>;
+  kvGet<T>(key: string): Promise<T | undefined>;
+  kvSet<T>(key: string, value: T): Promise<void>;
+  kvDelete(key: string): Promise<boolean>;
+  kvList<T>(options?: KvListOptions): Promise<Array<KvEntry<T>>>;
+  counterIncr(name: string, delta?: number): Promise<number>;
+  ftsCreateIndex(table: string, columns: Array<string>): Promise<void>;
+  enableHistory(table: string): Promise<void>;
+  enableCdc(table: string): Promise<void>;
+  cdcPoll<T = Record<string, unknown>>(
+    cursor?: number,
+    limit?: number
+  ): Promise<CdcBatch<T>>;
+  tsDownsample(options: DownsampleOptions): Promise<DownsampleReport>;
+  geoWithinRadius(
+    rtree: string,
+    lat: number,
+    lon: number,
+    radius: number,
+    limit?: number
+  ): Promise<Array<GeoMatch>>;
+  vecInsert(table: string, id: number, embedding: Array<number>): Promise<void>;
+  vecSearch(
+    table: string,
+    embedding: Array<number>,
+    k: number,
+    metric?: VectorMetric
+  ): Promise<Array<Neighbor>>;
+  ftsSearch<T>(
+    table: string,
+    query: string,
+    options?: FtsSearchOptions
+  ): Promise<Array<T & { rank: number }>>;
+  queuePush<T>(queue: string, payloads: Array<T>): Promise<number>;
+  tryAcquireLeadership(instanceId: string, ttlMs: number): Promise<Leadership>;
+  releaseLeadership(instanceId: string): Promise<boolean>;
+  jobSchedule<T>(
+    name: string,
+    cron: string,
+    payload?: T
+  ): Promise<ScheduledJob>;
+  jobsDue<T>(now?: number): Promise<DueJobs<T>>;
+  outboxPush<T>(topic: string, payloads: Array<T>): Promise<number>;
+  outboxClaim<T>(
+    batch: number,
+    leaseMs: number
+  ): Promise<Array<OutboxEvent<T>>>;
+  outboxAck(ids: Array<number>): Promise<number>;
+  queuePopBatch<T>(
+    queue: string,
+    limit: number
+  ): Promise<Array<QueueMessage<T>>>;
+  upsert(
+    table: string,
+    rows: Array<Record<string, Param>>,
+    conflictColumns: Array<string>
+  ): Promise<number>;
+  suspend(): Promise<void>;
+  releaseMemory(): Promise<void>;
+  drop(): Promise<void>;
+}
+
+export interface ErrorReport {
+  message: string;
+  causes: Array<string>;
+  code: number | null;
+  kind: string | null;
+  rolled_back: boolean;
+  foreign_key_violations: Array<ForeignKeyViolation>;
+  host_error: string | null;
+  tag: string | null;
+  d1: D1ErrorReport | null;
+}
+
+// An error in the shape of D1's errors (see `Sqlite.setErrorStyle`).
+export interface D1ErrorReport {
+  // E.g. `D1_ERROR: no such table: users: SQLITE_ERROR`.
+  message: string;
+  // The chain of causes, starting with the message without the `D1_ERROR: ` prefix.
+  causes: Array<string>;
+  // The beginning of the statement that failed, if any.
+  sql: string | null;
+}
+
+// A row violating a foreign key constraint (see `PRAGMA foreign_key_check`).
+export interface ForeignKeyViolation {
+  table: string;
+  // `null` for `WITHOUT ROWID` tables.
+  rowid: number | null;
+  parent: string;
+  fkid: number;
+}
+
+// Thrown by `Vfs.getPage` and `Vfs.putPage` for failures that might go away when retried (e.g.
+// rate limiting or network hiccups of the backing store).
+export class TransientError extends Error {
+  public constructor(message: string) {
+    super(message);
+    this.name = "TransientError";
+  }
+}
+
+// The storage of a `ContentAddressedVfs`, e.g. a key-value store.
+export interface ContentStore {
+  // Resolves to `null` if there is no blob with the hash.
+  getBlob(hash: string): Promise<Uint8Array | null>;
+  putBlob(hash: string, data: Uint8Array): Promise<void>;
+  // Resolves to `null` for branches that don't exist yet.
+  getPageMap(branch: string): Promise<ContentPageMap | null>;
+  putPageMap(branch: string, pageMap: ContentPageMap): Promise<void>;
+}
+
+// The hex-encoded SHA-256 hash of the content of each page, by namespace and page index.
+export type ContentPageMap = Record<number, Array<string>>;
+
+// A `Vfs` storing each page as a blob under the hash of its content, plus a page map (the hash of
+// each page) per branch. Identical pages are stored once, across all branches, and forking a
+// branch only copies its page map, e.g. to give each preview deployment its own copy-on-write
+// database. Blobs are never deleted; blobs not referenced by the page map of any branch can be
+// garbage-collected by the host.
+export class ContentAddressedVfs implements Vfs {
+  private readonly store: ContentStore;
+  public readonly branch: string;
+  private pageMap: ContentPageMap;
+  // The page map at the start of the current storage transaction, restored on rollback.
+  private committed: ContentPageMap;
+  private dirty = false;
+  // Hashes of blobs known to exist in the store, which don't have to be written again.
+  private readonly blobs = new Set<string>();
+
+  private constructor(
+    store: ContentStore,
+    branch: string,
+    pageMap: ContentPageMap
+  ) {
+    this.store = store;
+    this.branch = branch;
+    this.pageMap = pageMap;
+    this.committed = copyPageMap(pageMap);
+    for (const hashes of Object.values(pageMap)) {
+      hashes.forEach((hash) => this.blobs.add(hash));
     }
+  }
 
-    const [resultOffset, resultLength] = new Uint32Array(
-      this.exports.memory.buffer,
-      resultPtr,
-      2
-    );
-    const result = this.decoder.decode(
-      new Uint8Array(this.exports.memory.buffer, resultOffset, resultLength)
-    );
-    await this.exports.query_result_drop(resultPtr);
+  // Opens the branch, which is empty if it doesn't exist yet.
+  public static async open(
+    store: ContentStore,
+    branch: string
+  ): Promise<ContentAddressedVfs> {
+    const pageMap = (await store.getPageMap(branch)) ?? {};
+    return new ContentAddressedVfs(store, branch, pageMap);
+  }
 
-    return result;
+  // Creates (or replaces) the branch `branch` with the content of this branch as of its last
+  // commit, by copying the page map only.
+  public async fork(branch: string): Promise<ContentAddressedVfs> {
+    await this.store.putPageMap(branch, copyPageMap(this.committed));
+    return ContentAddressedVfs.open(this.store, branch);
   }
 
-  public async drop(): Promise<void> {
-    await this.exports.conn_drop(this.ptr);
+  public pageCount(namespace: number): number {
+    return this.pageMap[namespace]?.length ?? 0;
+  }
+
+  public async getPage(
+    ix: number,
+    namespace: number
+  ): Promise<Uint8Array | null> {
+    const hash = this.pageMap[namespace]?.[ix];
+    if (!hash) {
+      return null;
+    }
+    const page = await this.store.getBlob(hash);
+    if (!page) {
+      throw new Error(`blob ${hash} of page ${ix} is missing`);
+    }
+    return page;
+  }
+
+  public async putPage(
+    ix: number,
+    page: Uint8Array,
+    namespace: number
+  ): Promise<void> {
+    // the page is a view into the wasm memory, so it must be copied
+    const data = new Uint8Array(page);
+    const digest = new Uint8Array(await crypto.subtle.digest("SHA-256", data));
+    const hash = Array.from(digest)
+      .map((b) => b.toString(16).padStart(2, "0"))
+      .join("");
+    if (!this.blobs.has(hash)) {
+      await this.store.putBlob(hash, data);
+      this.blobs.add(hash);
+    }
+    const hashes = (this.pageMap[namespace] ??= []);
+    // pages skipped over don't exist (an empty hash)
+    while (hashes.length < ix) {
+      hashes.push("");
+    }
+    hashes[ix] = hash;
+    this.dirty = true;
+  }
+
+  public async delPage(ix: number, namespace: number): Promise<void> {
+    await this.delPages(ix, ix + 1, namespace);
+  }
+
+  public async delPages(
+    fromIx: number,
+    _toIx: number,
+    namespace: number
+  ): Promise<void> {
+    const hashes = this.pageMap[namespace];
+    if (hashes && hashes.length > fromIx) {
+      hashes.length = fromIx;
+      this.dirty = true;
+    }
+  }
+
+  public async storageSync(): Promise<void> {
+    await this.persist();
+  }
+
+  public async txnBegin(): Promise<number> {
+    this.committed = copyPageMap(this.pageMap);
+    return 0;
+  }
+
+  public async txnCommit(): Promise<void> {
+    await this.persist();
+  }
+
+  public async txnRollback(): Promise<void> {
+    this.pageMap = copyPageMap(this.committed);
+    this.dirty = false;
+  }
+
+  // Writes the page map, once all blobs it references are written.
+  private async persist(): Promise<void> {
+    if (this.dirty) {
+      await this.store.putPageMap(this.branch, copyPageMap(this.pageMap));
+      this.dirty = false;
+    }
+    this.committed = copyPageMap(this.pageMap);
   }
 }
 
-const ERRNO_SUCCESS = 0;
-const ERRNO_BADF = 8;
-const ERRNO_INVAL = 28;
-const ERRNO_NOSYS = 52;
+function copyPageMap(pageMap: ContentPageMap): ContentPageMap {
+  return Object.fromEntries(
+    Object.entries(pageMap).map(([namespace, hashes]) => [
+      namespace,
+      [...hashes],
+    ])
+  );
+}
 
-interface Exports {
-  readonly memory: WebAssembly.Memory;
-  alloc(size: number): Promise<number>;
-  dealloc(size: number, len: number): Promise<void>;
+export interface HttpRangeVfsOptions {
+  // Defaults to the global `fetch`, e.g. to add authentication headers.
+  fetch?: (url: string, init: RequestInit) => Promise<Response>;
+}
 
-  conn_new(): Promise<number>;
-  conn_execute(conn: number, ptr: number, len: number): Promise<number>;
-  conn_query(conn: number, ptr: number, len: number): Promise<number>;
-  conn_drop(conn: number): Promise<void>;
-  conn_last_error(conn: number): Promise<number>;
-  conn_last_error_drop(err: number): Promise<void>;
+// The page size of the main page store namespace (`cfdo`).
+const HTTP_PAGE_SIZE = 4096;
 
-  query_result_drop(ptr: number): Promise<void>;
+// A read-only `Vfs` serving the database (namespace `0`) from a static SQLite file via HTTP Range
+// requests, e.g. to query a large immutable dataset on a CDN without downloading it first. The
+// file must have a page size of 4096 bytes (`PRAGMA page_size = 4096; VACUUM;`). Pages of other
+// namespaces (e.g. spilled results) are kept in memory. Connect with `read_only: true`.
+export class HttpRangeVfs implements Vfs {
+  public readonly url: string;
+  // The size of the file in bytes.
+  public readonly size: number;
+  private readonly fetch: (url: string, init: RequestInit) => Promise<Response>;
+  private readonly namespaces = new Map<number, Array<Uint8Array>>();
+
+  private constructor(
+    url: string,
+    size: number,
+    fetchRange: (url: string, init: RequestInit) => Promise<Response>
+  ) {
+    this.url = url;
+    this.size = size;
+    this.fetch = fetchRange;
+  }
+
+  // Determines the size of the file, which also makes sure that the server supports Range
+  // requests.
+  public static async open(
+    url: string,
+    options: HttpRangeVfsOptions = {}
+  ): Promise<HttpRangeVfs> {
+    const fetchRange = options.fetch ?? ((input, init) => fetch(input, init));
+    const res = await fetchRange(url, { headers: { Range: "bytes=0-0" } });
+    await res.arrayBuffer();
+    checkRangeResponse(url, res);
+    const size = Number(res.headers.get("Content-Range")?.split("/")[1]);
+    if (!Number.isSafeInteger(size)) {
+      throw new Error(`${url} responded without the size of the file`);
+    }
+    return new HttpRangeVfs(url, size, fetchRange);
+  }
+
+  public pageCount(namespace: number): number {
+    if (namespace === 0) {
+      return Math.ceil(this.size / HTTP_PAGE_SIZE);
+    }
+    return this.namespaces.get(namespace)?.length ?? 0;
+  }
+
+  public async getPage(
+    ix: number,
+    namespace: number
+  ): Promise<Uint8Array | null> {
+    if (namespace !== 0) {
+      return this.namespaces.get(namespace)?.[ix] ?? null;
+    }
+    if (ix >= this.pageCount(0)) {
+      return null;
+    }
+    const [page] = await this.fetchPages(ix, ix + 1);
+    return page;
+  }
+
+  // Fetches each run of consecutive pages with a single request.
+  public async getPages(
+    ixs: Array<number>,
+    namespace: number
+  ): Promise<Array<Uint8Array | null>> {
+    if (namespace !== 0) {
+      return Promise.all(ixs.map((ix) => this.getPage(ix, namespace)));
+    }
+    const sorted = [...new Set(ixs)]
+      .filter((ix) => ix < this.pageCount(0))
+      .sort((a, b) => a - b);
+    const runs: Array<[number, number]> = [];
+    for (const ix of sorted) {
+      const run = runs[runs.length - 1];
+      if (run && run[1] === ix) {
+        run[1] = ix + 1;
+      } else {
+        runs.push([ix, ix + 1]);
+      }
+    }
+    const pages = new Map<number, Uint8Array>();
+    await Promise.all(
+      runs.map(async ([from, to]) => {
+        const fetched = await this.fetchPages(from, to);
+        fetched.forEach((page, i) => pages.set(from + i, page));
+      })
+    );
+    return ixs.map((ix) => pages.get(ix) ?? null);
+  }
+
+  public async putPage(
+    ix: number,
+    page: Uint8Array,
+    namespace: number
+  ): Promise<void> {
+    const pages = this.writablePages(namespace);
+    while (pages.length <= ix) {
+      pages.push(new Uint8Array(page.length));
+    }
+    // the page is a view into the wasm memory, so it must be copied
+    pages[ix] = new Uint8Array(page);
+  }
+
+  public async delPage(ix: number, namespace: number): Promise<void> {
+    await this.delPages(ix, ix + 1, namespace);
+  }
+
+  public async delPages(
+    fromIx: number,
+    _toIx: number,
+    namespace: number
+  ): Promise<void> {
+    const pages = this.writablePages(namespace);
+    pages.length = Math.min(pages.length, fromIx);
+  }
+
+  private writablePages(namespace: number): Array<Uint8Array> {
+    if (namespace === 0) {
+      throw new Error(`${this.url} is read-only`);
+    }
+    let pages = this.namespaces.get(namespace);
+    if (!pages) {
+      pages = [];
+      this.namespaces.set(namespace, pages);
+    }
+    return pages;
+  }
+
+  // Fetches the pages `fromIx` (inclusive) to `toIx` (exclusive). The last page of the file is
+  // padded with zeros if the file size isn't a multiple of the page size.
+  private async fetchPages(
+    fromIx: number,
+    toIx: number
+  ): Promise<Array<Uint8Array>> {
+    const start = fromIx * HTTP_PAGE_SIZE;
+    const end = Math.min(toIx * HTTP_PAGE_SIZE, this.size) - 1;
+    const res = await this.fetch(this.url, {
+      headers: { Range: `bytes=${start}-${end}` },
+    });
+    const data = new Uint8Array(await res.arrayBuffer());
+    checkRangeResponse(this.url, res);
+    const expected = end - start + 1;
+    if (data.length !== expected) {
+      throw new Error(
+        `${this.url} responded with ${data.length} instead of ${expected} bytes`
+      );
+    }
+
+    const pages: Array<Uint8Array> = [];
+    for (let offset = 0; offset < data.length; offset += HTTP_PAGE_SIZE) {
+      const page = new Uint8Array(HTTP_PAGE_SIZE);
+      page.set(data.subarray(offset, offset + HTTP_PAGE_SIZE));
+      pages.push(page);
+    }
+    return pages;
+  }
+}
+
+// Throws if the server didn't respond with the requested range, with a `TransientError` for
+// failures that might go away when retried (see `Sqlite.setRetryPolicy`).
+function checkRangeResponse(url: string, res: Response) {
+  if (res.status === 206) {
+    return;
+  }
+  const message = `${url} responded with status ${res.status} to a Range request`;
+  if (res.status === 429 || res.status >= 500) {
+    throw new TransientError(message);
+  }
+  throw new Error(res.ok ? `${url} does not support Range requests` : message);
+}
+
+// A `Vfs` layering a mutable page store (`upper`) over a read-only base image (`base`, e.g. an
+// `HttpRangeVfs` of a dataset on a CDN), for seeded databases that users modify incrementally:
+// pages are read from the upper store and fall back to the base image if absent there, and all
+// writes go to the upper store, which therefore must resolve `getPage` to `null` for pages it
+// doesn't have (instead of e.g. empty pages). Only the database (namespace `0`) is layered, the
+// other namespaces are stored in the upper store only. Deleting all pages of the database reverts
+// it to the base image. Callbacks (e.g. `emitDelta`) of the upper store aren't forwarded.
+export class OverlayVfs implements Vfs {
+  public readonly base: Vfs;
+  public readonly upper: Vfs;
+  // Pages of the base image at and after this index were truncated by this instance and are
+  // therefore not read anymore. Once the upper store has pages, it determines the page count.
+  private baseLimit = Infinity;
+
+  public constructor(base: Vfs, upper: Vfs) {
+    this.base = base;
+    this.upper = upper;
+  }
+
+  public pageCount(namespace: number): number {
+    const upperCount = this.upper.pageCount(namespace);
+    if (namespace !== 0 || upperCount > 0) {
+      return upperCount;
+    }
+    return this.basePageCount();
+  }
+
+  public async getPage(
+    ix: number,
+    namespace: number
+  ): Promise<Uint8Array | null> {
+    const page = await this.upper.getPage(ix, namespace);
+    if (page || namespace !== 0 || ix >= this.basePageCount()) {
+      return page;
+    }
+    return this.base.getPage(ix, 0);
+  }
+
+  public async getPages(
+    ixs: Array<number>,
+    namespace: number
+  ): Promise<Array<Uint8Array | null>> {
+    const pages = await readPages(this.upper, ixs, namespace);
+    if (namespace !== 0) {
+      return pages;
+    }
+    const missing = ixs.filter(
+      (ix, i) => !pages[i] && ix < this.basePageCount()
+    );
+    if (missing.length > 0) {
+      const basePages = await readPages(this.base, missing, 0);
+      const byIx = new Map(missing.map((ix, i) => [ix, basePages[i]]));
+      return pages.map((page, i) => page ?? byIx.get(ixs[i]) ?? null);
+    }
+    return pages;
+  }
+
+  public async putPage(
+    ix: number,
+    page: Uint8Array,
+    namespace: number
+  ): Promise<void> {
+    const baseCount = this.basePageCount();
+    const isFirstWrite = namespace === 0 && this.upper.pageCount(0) === 0;
+    if (isFirstWrite && ix < baseCount - 1) {
+      // The upper store determines the page count once it has pages, so the last page of the
+      // base image is copied up first.
+      const last = await this.base.getPage(baseCount - 1, 0);
+      if (last) {
+        await this.upper.putPage(baseCount - 1, last, 0);
+      }
+    }
+    await this.upper.putPage(ix, page, namespace);
+  }
+
+  public async delPage(ix: number, namespace: number): Promise<void> {
+    await this.delPages(ix, ix + 1, namespace);
+  }
+
+  public async delPages(
+    fromIx: number,
+    toIx: number,
+    namespace: number
+  ): Promise<void> {
+    if (namespace === 0) {
+      this.baseLimit = Math.min(this.baseLimit, fromIx);
+    }
+    if (this.upper.delPages) {
+      await this.upper.delPages(fromIx, toIx, namespace);
+    } else {
+      for (let ix = toIx - 1; ix >= fromIx; ix--) {
+        await this.upper.delPage(ix, namespace);
+      }
+    }
+  }
+
+  public async storageSync(namespace: number): Promise<void> {
+    await this.upper.storageSync?.(namespace);
+  }
+
+  public async txnBegin(): Promise<number> {
+    return (await this.upper.txnBegin?.()) ?? 0;
+  }
+
+  public async txnCommit(token: number): Promise<void> {
+    await this.upper.txnCommit?.(token);
+  }
+
+  public async txnRollback(token: number): Promise<void> {
+    await this.upper.txnRollback?.(token);
+  }
+
+  private basePageCount(): number {
+    return Math.min(this.base.pageCount(0), this.baseLimit);
+  }
+}
+
+// Reads several pages of `vfs`, at once if it supports it.
+function readPages(
+  vfs: Vfs,
+  ixs: Array<number>,
+  namespace: number
+): Promise<Array<Uint8Array | null>> {
+  return vfs.getPages
+    ? vfs.getPages(ixs, namespace)
+    : Promise.all(ixs.map((ix) => vfs.getPage(ix, namespace)));
+}
+
+export interface RetryPolicy {
+  // The maximum number of calls, including the first one (`1` disables retries).
+  max_attempts?: number;
+  // The delay before the first retry, doubled for each further retry.
+  initial_backoff_ms?: number;
+  max_backoff_ms?: number;
+}
+
+export class SqliteError extends Error {
+  // The extended SQLite result code, if the error originated from SQLite.
+  public readonly code: number | null;
+  // Identifies errors raised by the module instead of SQLite (e.g. `not_read_only`).
+  public readonly kind: string | null;
+  public readonly causes: Array<string>;
+  // Whether the open transaction was automatically rolled back because of the error.
+  public readonly rolledBack: boolean;
+  // The rows violating deferred foreign key constraints if the error is a failed foreign key
+  // constraint on `COMMIT`.
+  public readonly foreignKeyViolations: Array<ForeignKeyViolation>;
+  // The message of the failed `Vfs.getPage` if the error was caused by the storage.
+  public readonly hostError: string | null;
+  // The tag of the connection (see `Connection.setTag`).
+  public readonly tag: string | null;
+  // With the `d1` error style: the chain of causes as nested errors, like D1 reports them.
+  public readonly cause: Error | undefined;
+  // With the `d1` error style: the beginning of the statement that failed, if any.
+  public readonly sql: string | null;
+
+  public constructor(report: ErrorReport) {
+    let message =
+      report.tag !== null ? `[${report.tag}] ${report.message}` : report.message;
+    if (report.host_error !== null) {
+      message += ` (host: ${report.host_error})`;
+    }
+    if (report.d1 !== null) {
+      message = report.d1.message;
+    } else if (report.causes.length > 0) {
+      message +=
+        "\n\nCaused by:\n" +
+        report.causes
+          .map((cause, i) => `${i.toString().padStart(4)}: ${cause}`)
+          .join("\n");
+    }
+
+    super(message);
+    this.name = "SqliteError";
+    this.code = report.code;
+    this.kind = report.kind;
+    this.causes = report.causes;
+    this.rolledBack = report.rolled_back;
+    this.foreignKeyViolations = report.foreign_key_violations;
+    this.hostError = report.host_error;
+    this.tag = report.tag;
+    this.cause = report.d1 !== null ? causeChain(report.d1.causes) : undefined;
+    this.sql = report.d1?.sql ?? null;
+  }
+}
+
+// Nests the messages into errors, each being the `cause` of the one before.
+function causeChain(messages: Array<string>): Error | undefined {
+  let cause: Error | undefined;
+  for (const message of messages.slice().reverse()) {
+    const err: Error & { cause?: Error } = new Error(message);
+    err.cause = cause;
+    cause = err;
+  }
+  return cause;
+}
+
+export interface QueryOptions {
+  // Require the number of params to match the number of placeholders, and reject string literals
+  // in the SQL if params are provided.
+  strict?: boolean;
+  // Return the rows together with metadata (see `queryWithMeta`).
+  meta?: boolean;
+  // Reject statements that could modify the database (fails with kind `not_read_only`).
+  readonly?: boolean;
+  // Interrupts the query once aborted (e.g. when the request that issued it is aborted). Only
+  // supported for queries, not for `execute`.
+  signal?: AbortSignal;
+  // Spill results exceeding `Limits.max_result_bytes` into the `cfdo-spill` page store namespace
+  // (`2`) instead of failing. `query` and `queryWithMeta` drain spilled results transparently,
+  // `queryRaw` returns a `SpilledResult` to drain via `cursorNext`.
+  spill?: boolean;
+  // Resolve to the opcodes of the statement's VDBE program (`EXPLAIN`, as `Array<Opcode>`)
+  // instead of running it.
+  explain?: "full";
+  // Read tables with a history (see `Connection.enableHistory`) as they were at this time (unix
+  // epoch milliseconds, e.g. `Date.now()`) instead of their current rows.
+  as_of?: number;
+}
+
+// An instruction of a VDBE program, see https://www.sqlite.org/opcode.html.
+export interface Opcode {
+  addr: number;
+  opcode: string;
+  p1: number;
+  p2: number;
+  p3: number;
+  p4: string | null;
+  p5: number;
+  comment: string | null;
+  // The table or index whose B-tree is opened (for `OpenRead` and `OpenWrite`).
+  object: string | null;
+}
+
+export interface SpilledResult {
+  cursor: number;
+  count: number;
+}
+
+// The number of rows fetched per call when draining a spilled result.
+const CURSOR_CHUNK_ROWS = 1000;
+
+export interface QueryMeta {
+  columns: Array<ColumnMeta>;
+  status: StatementStatus;
+}
+
+// Counters of the executed statement (`sqlite3_stmt_status`), e.g. to alert on queries that start
+// doing full table scans as the data grows.
+export interface StatementStatus {
+  fullscan_step: number;
+  sort: number;
+  autoindex: number;
+  vm_step: number;
+}
+
+// `table` and `origin` are `null` for expressions that don't directly refer to a table column.
+export interface ColumnMeta {
+  name: string;
+  database: string | null;
+  table: string | null;
+  origin: string | null;
+  decltype: string | null;
+}
+
+export interface ConstraintReport {
+  ok: boolean;
+  foreign_keys: Array<ForeignKeyViolation>;
+  // The problems found by `PRAGMA integrity_check` (e.g. `NOT NULL` or `CHECK` violations).
+  integrity: Array<string>;
+}
+
+export interface ParameterInfo {
+  count: number;
+  // The name of each placeholder including its prefix (e.g. `:id`), `null` for anonymous (`?`)
+  // placeholders.
+  names: Array<string | null>;
+  columns: Array<ColumnMeta>;
+}
+
+export interface QueryResult<T> {
+  meta: QueryMeta;
+  rows: Array<T>;
+}
+
+export interface Limits {
+  // The maximum number of VM steps (opcodes) a single statement may execute (enforced with a
+  // granularity of 1000 steps).
+  max_vm_steps?: number | null;
+  // The maximum size of a query result (in bytes of JSON). Larger results fail with kind
+  // `result_size`, unless the query enables `spill`.
+  max_result_bytes?: number | null;
+}
+
+export interface Reconciliation {
+  page_count: number;
+  header_page_count: number | null;
+  deleted: Array<number>;
+}
+
+export interface SelfTestReport {
+  ok: boolean;
+  checks: Array<{ name: string; ok: boolean; error: string | null }>;
+}
+
+export interface ConformanceReport {
+  ok: boolean;
+  // Stops at the first failing check, whose `error` describes the violated contract.
+  checks: Array<{ name: string; ok: boolean; error: string | null }>;
+}
+
+export type TuningProfile = "low-memory" | "read-heavy" | "write-heavy";
+
+export interface AnalyzeOptions {
+  // The approximate number of rows to scan per index (`PRAGMA analysis_limit`).
+  analysis_limit?: number;
+  // Analyze all tables (`ANALYZE`) instead of running `PRAGMA optimize`.
+  full?: boolean;
+}
+
+export interface AnalyzeReport {
+  // The number of rows in each statistics table (`null` if it doesn't exist).
+  stat_rows: Record<string, number | null>;
+}
+
+export interface Security {
+  // Whether functions and virtual tables used by the schema (views, triggers, ...) are trusted.
+  trusted_schema?: boolean;
+  // Enables `SQLITE_DBCONFIG_DEFENSIVE`, which prevents deliberately corrupting the database.
+  defensive?: boolean;
+  // Only allow running queries registered via `Connection.registerQuery` (through
+  // `Connection.run`); passing SQL directly, or any other call reading or changing rows, tables or
+  // the schema (e.g. `Connection.upsert` or `Connection.kvSet`), fails with kind `not_registered`.
+  // Can't be disabled again, and no more queries can be registered once enabled.
+  registered_queries_only?: boolean;
+}
+
+export interface KvListOptions {
+  // Only list keys starting with this prefix.
+  prefix?: string;
+  limit?: number;
+}
+
+export interface KvEntry<T> {
+  key: string;
+  value: T;
+}
+
+export interface DownsampleOptions {
+  table: string;
+  // The column containing the timestamp (unix epoch seconds).
+  time_column: string;
+  value_columns: Array<string>;
+  // Columns identifying a series (e.g. a metric name), aggregated separately.
+  group_columns?: Array<string>;
+  aggregate?: "avg" | "sum" | "min" | "max";
+  // The bucket size in seconds.
+  interval: number;
+  // Only rows older than this timestamp are compacted.
+  before: number;
+}
+
+export interface DownsampleReport {
+  deleted: number;
+  inserted: number;
+}
+
+export interface GeoMatch {
+  id: number;
+  // The distance in meters.
+  distance: number;
+}
+
+export type VectorMetric = "cosine" | "l2";
+
+export interface Neighbor {
+  id: number;
+  distance: number;
+}
+
+export interface ChangeEvent<T = Record<string, unknown>> {
+  // Increases with every recorded change (across all tables).
+  seq: number;
+  // Shared by all changes committed together.
+  commit_id: number;
+  table: string;
+  op: "insert" | "update" | "delete";
+  // The row before (`null` for inserts) and after the change (`null` for deletes). Blobs are
+  // recorded as `{ blob: "<hex>" }`.
+  old: T | null;
+  new: T | null;
+}
+
+export interface CdcBatch<T = Record<string, unknown>> {
+  // The `seq` of the last event of the batch, to pass to the next `cdcPoll`.
+  cursor: number;
+  events: Array<ChangeEvent<T>>;
+}
+
+export interface FtsSearchOptions {
+  limit?: number;
+  offset?: number;
+}
+
+export interface QueueMessage<T> {
+  // Increases with every pushed message (across all queues).
+  id: number;
+  payload: T;
+}
+
+export interface Leadership {
+  // Whether the instance is the leader.
+  leader: boolean;
+  // The instance holding the lease.
+  holder: string;
+  // When the lease expires unless renewed (unix epoch milliseconds).
+  expires_at: number;
+}
+
+export interface ScheduledJob {
+  name: string;
+  // The next time the job is due (unix epoch milliseconds).
+  next_run: number;
+}
+
+export interface DueJobs<T> {
+  jobs: Array<{ name: string; payload: T; scheduled_for: number }>;
+  // The next time any job is due (e.g. to set the next alarm to), `null` without jobs.
+  next_run: number | null;
+}
+
+export interface OutboxEvent<T> {
+  // Increases with every written event.
+  id: number;
+  topic: string;
+  payload: T;
+  // How often the event has been claimed, including this time.
+  attempts: number;
+}
+
+export interface CopyReport {
+  rows: number;
+  // Whether the table did not exist in the destination database and was created.
+  created: boolean;
+}
+
+export interface PageVerification {
+  page_count: number;
+  header_page_count: number | null;
+  freelist_count: number;
+  missing: Array<number>;
+  missing_free: Array<number>;
+  orphaned: Array<number>;
+}
+
+export interface ChangedPages {
+  // The commit the database is at, to pass to the next `pagesChangedSince` call.
+  counter: number;
+  // Whether the changes are unknown (e.g. change tracking was disabled for some commits), in
+  // which case `pages` lists all pages.
+  full: boolean;
+  pages: Array<number>;
+}
+
+export interface FreelistInfo {
+  page_count: number;
+  // Pages freed by deletes, which are reused before the database grows.
+  freelist_count: number;
+  auto_vacuum: "none" | "full" | "incremental";
+}
+
+export interface ReclaimReport extends FreelistInfo {
+  // The number of pages deleted from the page store.
+  reclaimed_pages: number;
+}
+
+export interface PageMap {
+  page_count: number;
+  // Ordered by page index (as passed to the page callbacks).
+  pages: Array<PageMapEntry>;
+  // The number of pages per table or index, largest first.
+  objects: Array<{ name: string; pages: number }>;
+}
+
+export interface PageMapEntry {
+  index: number;
+  // `free` for pages not part of any B-tree (e.g. on the freelist).
+  role: "header" | "root" | "interior" | "leaf" | "overflow" | "free";
+  // The table or index the page belongs to (`null` for free pages).
+  name: string | null;
+}
+
+export interface PrewarmReport {
+  // The tables and indexes whose root pages were loaded.
+  objects: number;
+  // The pages read from the host in the batched read.
+  pages: number;
+}
+
+export interface DbVersion {
+  // `PRAGMA user_version`, e.g. the number of the last applied schema migration.
+  user_version: number;
+  // `PRAGMA application_id`, identifying the application the database belongs to.
+  application_id: number;
+}
+
+export interface SetDbVersion {
+  user_version?: number;
+  application_id?: number;
+  // Only set the versions if the current `user_version` is this one (otherwise rejects), e.g. so
+  // that only one of several instances records a migration it ran.
+  expected_user_version?: number;
+}
+
+export interface SchemaDiffOptions {
+  // Apply the statements in a single transaction (which fails on foreign key violations).
+  apply?: boolean;
+  // Drop the objects that only exist in the live schema as well.
+  drop?: boolean;
+}
+
+export interface SchemaDiff {
+  // The statements migrating the live schema to the desired one, in order.
+  statements: Array<string>;
+  // The tables, indexes, views and triggers that only exist in the live schema.
+  extra: Array<string>;
+  applied: boolean;
+}
+
+export interface ForkReport {
+  // The VFS to open the branch with, e.g. `sqlite.connect({ vfs: "cfdo-branch-16" })`.
+  vfs: string;
+  namespace: number;
+  page_count: number;
+}
+
+export interface SalvageReport {
+  tables: Array<{
+    name: string;
+    // The number of rows copied into the salvaged database.
+    rows: number;
+    // Whether rows had to be skipped because of corrupted pages.
+    corrupted: boolean;
+  }>;
+  // Schema objects that could not be recreated in the salvaged database.
+  failed: Array<{ name: string; error: string }>;
+}
+
+class SqliteConnection implements Connection {
+  public readonly ptr: number;
+  private readonly exports: Exports;
+
+  public constructor(ptr: number, exports: Exports) {
+    this.ptr = ptr;
+    this.exports = exports;
+  }
+
+  public async throwLastError(): Promise<void> {
+    const ptr = await this.exports.conn_last_error_json(this.ptr);
+    if (ptr) {
+      const report = JSON.parse(await takeJsonString(this.exports, ptr));
+      throw new SqliteError(report);
+    } else {
+      throw new Error("unknown error");
+    }
+  }
+
+  public async execute(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<void> {
+    const { signal: _, ...queryOptions } = options ?? {};
+    const query = {
+      v: QUERY_PAYLOAD_VERSION,
+      ...queryOptions,
+      sql,
+      params: params ?? [],
+    };
+    const ok = await withJson(this.exports, query, (ptr, len) =>
+      this.exports.conn_execute(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  public async query<T>(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<Array<T>> {
+    const rows = JSON.parse(await this.queryRaw(sql, params, options));
+    return await this.drain(rows);
+  }
+
+  public async queryWithMeta<T>(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<QueryResult<T>> {
+    const result = JSON.parse(
+      await this.queryRaw(sql, params, { ...options, meta: true })
+    );
+    result.rows = await this.drain(result.rows);
+    return result;
+  }
+
+  // Resolves to the number of rows the statement returns, without transferring them (e.g. for
+  // existence or size checks). `meta` and `spill` are ignored.
+  public async count(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<number> {
+    const countOptions = { ...options, count_only: true };
+    return JSON.parse(await this.queryRaw(sql, params, countOptions));
+  }
+
+  // Reads all rows of a result that might have been spilled (see `QueryOptions.spill`).
+  private async drain<T>(rows: Array<T> | SpilledResult): Promise<Array<T>> {
+    if (Array.isArray(rows)) {
+      return rows;
+    }
+
+    const all: Array<T> = [];
+    for (;;) {
+      const chunk = await this.cursorNext<T>(rows.cursor, CURSOR_CHUNK_ROWS);
+      if (chunk.length === 0) {
+        return all;
+      }
+      all.push(...chunk);
+    }
+  }
+
+  // Removes and returns the next rows of a spilled result. Returns an empty array once drained.
+  public async cursorNext<T>(
+    cursor: number,
+    maxRows: number
+  ): Promise<Array<T>> {
+    const resultPtr = await this.exports.conn_cursor_next(
+      this.ptr,
+      cursor,
+      maxRows
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Discards the remaining rows of a spilled result.
+  public async cursorClose(cursor: number): Promise<void> {
+    const ok = await this.exports.conn_cursor_close(this.ptr, cursor);
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  public async queryRaw(
+    sql: string,
+    params?: Array<Param>,
+    options?: QueryOptions
+  ): Promise<string> {
+    const { signal, ...queryOptions } = options ?? {};
+    const query = {
+      v: QUERY_PAYLOAD_VERSION,
+      ...queryOptions,
+      sql,
+      params: params ?? [],
+    };
+
+    let resultPtr: number;
+    if (signal) {
+      const token = nextCancelToken++;
+      const onAbort = () => {
+        this.exports.cancel(token);
+      };
+      signal.addEventListener("abort", onAbort);
+      try {
+        resultPtr = await withJson(this.exports, query, (ptr, len) =>
+          this.exports.conn_query_with_token(this.ptr, ptr, len, token)
+        );
+      } finally {
+        signal.removeEventListener("abort", onAbort);
+      }
+    } else {
+      resultPtr = await withJson(this.exports, query, (ptr, len) =>
+        this.exports.conn_query(this.ptr, ptr, len)
+      );
+    }
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return await takeJsonString(this.exports, resultPtr);
+  }
+
+  // The number of rows modified by all statements since the connection was opened.
+  public async totalChanges(): Promise<number> {
+    return await this.exports.conn_total_changes(this.ptr);
+  }
+
+  // Whether a previous call left an open transaction that must be committed or rolled back.
+  public async inTransaction(): Promise<boolean> {
+    return (await this.exports.conn_in_transaction(this.ptr)) !== 0;
+  }
+
+  public async setLimits(limits: Limits): Promise<void> {
+    const ok = await withJson(this.exports, limits, (ptr, len) =>
+      this.exports.conn_set_limits_json(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Hardens the connection, e.g. before executing user-supplied SQL. Options that are not set keep
+  // their current value.
+  public async setSecurity(security: Security): Promise<void> {
+    const ok = await withJson(this.exports, security, (ptr, len) =>
+      this.exports.conn_set_security(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Restricts all statements of the connection to the rows of `table` matching the SQL
+  // `predicate` (e.g. `tenant_id = 42`), so that a missing `WHERE` clause can't leak or modify
+  // rows of other tenants. Writes of rows not matching it fail. `null` removes the policy.
+  public async setRowPolicy(
+    table: string,
+    predicate: string | null
+  ): Promise<void> {
+    const ok = await withJson(this.exports, { table, predicate }, (ptr, len) =>
+      this.exports.conn_set_row_policy(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Opens a BLOB value for reading (and writing) it in chunks. To store a large value, insert a
+  // `zeroblob(size)` first and then fill it via `BlobHandle.write`.
+  public async blobOpen(target: BlobTarget): Promise<BlobHandle> {
+    const ptr = await withJson(this.exports, target, (ptr, len) =>
+      this.exports.blob_open(this.ptr, ptr, len)
+    );
+    if (!ptr) {
+      await this.throwLastError();
+    }
+    return new BlobHandle(ptr, this, this.exports);
+  }
+
+  // Limits the size of the database (rounded down to whole pages), e.g. to enforce per-tenant
+  // quotas. Writes growing the database beyond it fail with kind `database_full`. `null` removes
+  // the limit.
+  public async setMaxDbSize(bytes: number | null): Promise<void> {
+    const ok = await withJson(this.exports, bytes, (ptr, len) =>
+      this.exports.conn_set_max_db_size(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Registers a named query to run via `run`, e.g. before restricting the connection to
+  // registered queries (`Security.registered_queries_only`).
+  public async registerQuery(name: string, sql: string): Promise<void> {
+    const ok = await withJson(this.exports, { name, sql }, (ptr, len) =>
+      this.exports.conn_register_query(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Runs the query registered as `name` and resolves to its rows.
+  public async run<T>(name: string, params?: Array<Param>): Promise<Array<T>> {
+    const resultPtr = await withJson(
+      this.exports,
+      { name, params: params ?? [] },
+      (ptr, len) => this.exports.conn_run(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Applies curated pragmas (cache size, ...) for a typical workload (see the README).
+  public async tune(profile: TuningProfile): Promise<void> {
+    const ok = await withJson(this.exports, profile, (ptr, len) =>
+      this.exports.conn_tune(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Whether a failing call that leaves an explicit transaction open automatically rolls it back
+  // (enabled by default).
+  public async setAutoRollback(enabled: boolean): Promise<void> {
+    await this.exports.conn_set_auto_rollback(this.ptr, enabled ? 1 : 0);
+  }
+
+  // Identifies the caller of the connection (e.g. a tenant) in exported spans, slow queries,
+  // `Sqlite.locksDebug` and errors. `null` removes the tag.
+  public async setTag(tag: string | null): Promise<void> {
+    const ok = await withJson(this.exports, tag, (ptr, len) =>
+      this.exports.conn_set_tag(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  public async reconcileStorage(): Promise<Reconciliation> {
+    const resultPtr = await this.exports.conn_reconcile_storage(this.ptr);
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  public async verifyPages(): Promise<PageVerification> {
+    const resultPtr = await this.exports.conn_verify_pages(this.ptr);
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Lists the pages changed after the commit `counter` (as returned by the previous call, `0` for
+  // all pages), so that backups only need to copy those. Requires `Sqlite.setChangeTracking`.
+  public async pagesChangedSince(counter: number): Promise<ChangedPages> {
+    const resultPtr = await this.exports.conn_pages_changed_since(
+      this.ptr,
+      counter
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  public async freelistInfo(): Promise<FreelistInfo> {
+    const resultPtr = await this.exports.conn_freelist_info(this.ptr);
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Deletes up to `maxPages` (default: all) free pages from the page store. Only databases with
+  // `auto_vacuum = INCREMENTAL` keep free pages that can be reclaimed this way.
+  public async reclaimFreePages(maxPages?: number): Promise<ReclaimReport> {
+    const options = { max_pages: maxPages ?? null };
+    const resultPtr = await withJson(this.exports, options, (ptr, len) =>
+      this.exports.conn_reclaim_free_pages(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Lists which table or index each page of the database belongs to, e.g. to find out which
+  // tables dominate the storage or why a page is written frequently.
+  public async dumpPageMap(): Promise<PageMap> {
+    const resultPtr = await this.exports.conn_dump_page_map(this.ptr);
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Loads the schema and the root pages of the given tables (all tables by default) and their
+  // indexes in a single batched read (`Vfs.getPages`), e.g. right after a cold start, so that the
+  // first queries don't need a round trip to the host for each of them.
+  public async prewarm(tables?: Array<string>): Promise<PrewarmReport> {
+    const resultPtr = await withJson(this.exports, tables ?? [], (ptr, len) =>
+      this.exports.conn_prewarm(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Copies as much as possible of a corrupted database ("database disk image is malformed") into
+  // the empty `cfdo-salvage` namespace (`3`), skipping the rows on corrupted pages. The result can
+  // then be opened via `sqlite.connect({ vfs: "cfdo-salvage" })`.
+  public async salvage(): Promise<SalvageReport> {
+    const resultPtr = await this.exports.conn_salvage(this.ptr);
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Copies the database into the empty page store `namespace` (`16` or higher), e.g. to test a
+  // migration against it. The branch can then be opened via the `vfs` of the report. On a
+  // content-addressed page store (see `ContentAddressedVfs`), the copy shares its pages.
+  public async fork(namespace: number): Promise<ForkReport> {
+    const resultPtr = await this.exports.conn_fork(this.ptr, namespace);
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Resolves to the text encoding of the database (`UTF-8`, `UTF-16le` or `UTF-16be`).
+  public async encoding(): Promise<string> {
+    const resultPtr = await this.exports.conn_encoding(this.ptr);
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Resolves to the schema version (`PRAGMA user_version`) and application id of the database.
+  public async getVersion(): Promise<DbVersion> {
+    const resultPtr = await this.exports.conn_get_version(this.ptr);
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Sets the given versions in a single savepoint and resolves to the versions afterwards.
+  public async setVersion(version: SetDbVersion): Promise<DbVersion> {
+    const resultPtr = await withJson(this.exports, version, (ptr, len) =>
+      this.exports.conn_set_version(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Compares the live schema with the desired one (`CREATE` statements) and resolves to the
+  // statements migrating it: added columns are appended via `ALTER TABLE ... ADD COLUMN`, other
+  // changed tables are rebuilt (keeping the rows of their common columns), and changed indexes,
+  // views and triggers are recreated. Only applies them with `apply`, and only drops objects
+  // missing from the desired schema with `drop`.
+  public async schemaDiff(
+    schema: string,
+    options?: SchemaDiffOptions
+  ): Promise<SchemaDiff> {
+    const resultPtr = await withJson(
+      this.exports,
+      { schema, ...options },
+      (ptr, len) => this.exports.conn_schema_diff(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Gathers statistics for the query planner, which are persisted in the page store. Only
+  // analyzes tables whose statistics are missing or outdated unless `full` is set.
+  public async analyze(options?: AnalyzeOptions): Promise<AnalyzeReport> {
+    const resultPtr = await withJson(this.exports, options ?? {}, (ptr, len) =>
+      this.exports.conn_analyze(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Checks foreign key constraints and the integrity of `tables` (default: all tables) without
+  // modifying anything, e.g. before committing a large imported batch.
+  public async checkConstraints(
+    tables?: Array<string>
+  ): Promise<ConstraintReport> {
+    const resultPtr = await withJson(
+      this.exports,
+      { tables: tables ?? [] },
+      (ptr, len) => this.exports.conn_check_constraints(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Describes the placeholders and result columns of `sql` without executing it, e.g. to validate
+  // calls of generated query functions.
+  public async parameterInfo(sql: string): Promise<ParameterInfo> {
+    const resultPtr = await withJson(this.exports, sql, (ptr, len) =>
+      this.exports.conn_parameter_info(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  public async selfTest(): Promise<SelfTestReport> {
+    const resultPtr = await this.exports.conn_self_test(this.ptr);
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Inserts each row, or updates the existing row with the same values in `conflictColumns`
+  // (`INSERT ... ON CONFLICT DO UPDATE`), all in a single savepoint. Resolves to the number of
+  // rows written.
+  public async upsert(
+    table: string,
+    rows: Array<Record<string, Param>>,
+    conflictColumns: Array<string>
+  ): Promise<number> {
+    const upsert = { table, rows, conflict_columns: conflictColumns };
+    const resultPtr = await withJson(this.exports, upsert, (ptr, len) =>
+      this.exports.conn_upsert(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    const report = JSON.parse(await takeJsonString(this.exports, resultPtr));
+    return report.rows;
+  }
+
+  // Key-value API on top of an automatically created table (`_wasm_sqlite_kv`). Values are stored
+  // as JSON.
+  public async kvGet<T>(key: string): Promise<T | undefined> {
+    const resultPtr = await withJson(this.exports, key, (ptr, len) =>
+      this.exports.conn_kv_get(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    const entry: KvEntry<T> | null = JSON.parse(
+      await takeJsonString(this.exports, resultPtr)
+    );
+    return entry?.value;
+  }
+
+  public async kvSet<T>(key: string, value: T): Promise<void> {
+    const ok = await withJson(this.exports, { key, value }, (ptr, len) =>
+      this.exports.conn_kv_set(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Resolves to whether the key existed.
+  public async kvDelete(key: string): Promise<boolean> {
+    const resultPtr = await withJson(this.exports, key, (ptr, len) =>
+      this.exports.conn_kv_delete(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Lists the entries ordered by key.
+  public async kvList<T>(options?: KvListOptions): Promise<Array<KvEntry<T>>> {
+    const resultPtr = await withJson(this.exports, options ?? {}, (ptr, len) =>
+      this.exports.conn_kv_list(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Records every version of the rows of `table` in `<table>_history` (via triggers), so that
+  // queries can read the table as of an earlier time (`QueryOptions.as_of`). Does nothing if the
+  // history already exists.
+  public async enableHistory(table: string): Promise<void> {
+    const ok = await withJson(this.exports, { table }, (ptr, len) =>
+      this.exports.conn_enable_history(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Records every insert, update and delete of `table` as a change event in `_wasm_sqlite_cdc`
+  // (via triggers), to tail them via `cdcPoll`. Does nothing if the changes are already recorded.
+  public async enableCdc(table: string): Promise<void> {
+    const ok = await withJson(this.exports, { table }, (ptr, len) =>
+      this.exports.conn_enable_cdc(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Resolves to up to `limit` of the committed change events after `cursor` (the `cursor` of the
+  // previous batch), oldest first. Events aren't removed by polling.
+  public async cdcPoll<T = Record<string, unknown>>(
+    cursor = 0,
+    limit?: number
+  ): Promise<CdcBatch<T>> {
+    const resultPtr = await withJson(
+      this.exports,
+      { cursor, limit },
+      (ptr, len) => this.exports.conn_cdc_poll(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Creates a full-text index (`<table>_fts`, an external-content FTS5 table) over `columns` of
+  // `table`, kept in sync via triggers. Does nothing if the index already exists.
+  public async ftsCreateIndex(
+    table: string,
+    columns: Array<string>
+  ): Promise<void> {
+    const ok = await withJson(this.exports, { table, columns }, (ptr, len) =>
+      this.exports.conn_fts_create_index(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Resolves to the rows of `table` matching the FTS5 `query`, best matches (by bm25) first. Each
+  // row has an additional `rank` (lower is better).
+  public async ftsSearch<T>(
+    table: string,
+    query: string,
+    options?: FtsSearchOptions
+  ): Promise<Array<T & { rank: number }>> {
+    const search = { ...options, table, query };
+    const resultPtr = await withJson(this.exports, search, (ptr, len) =>
+      this.exports.conn_fts_search(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Replaces the rows older than `options.before` with one aggregated row per time bucket (and
+  // series) in a single savepoint.
+  public async tsDownsample(
+    options: DownsampleOptions
+  ): Promise<DownsampleReport> {
+    const resultPtr = await withJson(this.exports, options, (ptr, len) =>
+      this.exports.conn_ts_downsample(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Resolves to the entries of the R-tree `rtree` (with the columns `id, min_lat, max_lat, min_lon,
+  // max_lon`) within `radius` meters of the point, nearest first.
+  public async geoWithinRadius(
+    rtree: string,
+    lat: number,
+    lon: number,
+    radius: number,
+    limit?: number
+  ): Promise<Array<GeoMatch>> {
+    const search = { rtree, lat, lon, radius, limit };
+    const resultPtr = await withJson(this.exports, search, (ptr, len) =>
+      this.exports.conn_geo_within_radius(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Stores the embedding with the given `id` in `table` (created if it doesn't exist yet). Requires
+  // the module to be built with the `vector` feature.
+  public async vecInsert(
+    table: string,
+    id: number,
+    embedding: Array<number>
+  ): Promise<void> {
+    const vecInsert = this.exports.conn_vec_insert;
+    if (!vecInsert) {
+      throw new Error("wasm module was built without the `vector` feature");
+    }
+
+    const ok = await withJson(
+      this.exports,
+      { table, id, embedding },
+      (ptr, len) => vecInsert(this.ptr, ptr, len)
+    );
+    if (!ok) {
+      await this.throwLastError();
+    }
+  }
+
+  // Resolves to the `k` nearest neighbors of `embedding` in `table`, nearest first (brute force,
+  // suited for small data sets). Requires the module to be built with the `vector` feature.
+  public async vecSearch(
+    table: string,
+    embedding: Array<number>,
+    k: number,
+    metric?: VectorMetric
+  ): Promise<Array<Neighbor>> {
+    const vecSearch = this.exports.conn_vec_search;
+    if (!vecSearch) {
+      throw new Error("wasm module was built without the `vector` feature");
+    }
+
+    const search = { table, embedding, k, metric: metric ?? "cosine" };
+    const resultPtr = await withJson(this.exports, search, (ptr, len) =>
+      vecSearch(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Atomically adds `delta` (default: `1`) to the counter `name` (starting at `0`) and resolves to
+  // its new value.
+  public async counterIncr(name: string, delta?: number): Promise<number> {
+    const incr = { name, delta: delta ?? 1 };
+    const resultPtr = await withJson(this.exports, incr, (ptr, len) =>
+      this.exports.conn_counter_incr(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Appends the payloads to the persistent queue in a single savepoint.
+  public async queuePush<T>(queue: string, payloads: Array<T>): Promise<number> {
+    const resultPtr = await withJson(
+      this.exports,
+      { queue, payloads },
+      (ptr, len) => this.exports.conn_queue_push(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Removes and resolves to up to `limit` of the oldest messages of the queue.
+  public async queuePopBatch<T>(
+    queue: string,
+    limit: number
+  ): Promise<Array<QueueMessage<T>>> {
+    const resultPtr = await withJson(
+      this.exports,
+      { queue, limit },
+      (ptr, len) => this.exports.conn_queue_pop_batch(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Acquires or renews the leadership lease (in `_wasm_sqlite_leases`) for `ttlMs` milliseconds,
+  // unless another instance holds an unexpired lease. Leaders are expected to renew the lease well
+  // before it expires, and to stop acting as the leader once a renewal fails.
+  public async tryAcquireLeadership(
+    instanceId: string,
+    ttlMs: number
+  ): Promise<Leadership> {
+    const resultPtr = await withJson(
+      this.exports,
+      { instance_id: instanceId, ttl_ms: ttlMs },
+      (ptr, len) =>
+        this.exports.conn_try_acquire_leadership(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Gives up the leadership lease, if the instance holds it, and resolves to whether it did.
+  public async releaseLeadership(instanceId: string): Promise<boolean> {
+    const resultPtr = await withJson(
+      this.exports,
+      { instance_id: instanceId },
+      (ptr, len) => this.exports.conn_release_leadership(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Creates or replaces the job `name` (in `_wasm_sqlite_jobs`), which is due whenever the cron
+  // expression (`minute hour day-of-month month day-of-week`, in UTC) matches.
+  public async jobSchedule<T>(
+    name: string,
+    cron: string,
+    payload?: T
+  ): Promise<ScheduledJob> {
+    const resultPtr = await withJson(
+      this.exports,
+      { name, cron, payload },
+      (ptr, len) => this.exports.conn_job_schedule(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Resolves to the jobs due at `now` (once per job, even if it missed several runs) and moves them
+  // to their next run, and to the next time any job is due.
+  public async jobsDue<T>(now = Date.now()): Promise<DueJobs<T>> {
+    const resultPtr = await withJson(this.exports, { now }, (ptr, len) =>
+      this.exports.conn_jobs_due(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Writes the payloads to the outbox (`_wasm_sqlite_outbox`) as part of the current transaction,
+  // so that they are only dispatched if the transaction commits.
+  public async outboxPush<T>(
+    topic: string,
+    payloads: Array<T>
+  ): Promise<number> {
+    const resultPtr = await withJson(
+      this.exports,
+      { topic, payloads },
+      (ptr, len) => this.exports.conn_outbox_push(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Claims and resolves to up to `batch` of the oldest events for `leaseMs` milliseconds. Events
+  // that aren't acknowledged via `outboxAck` before their lease expires are claimed again.
+  public async outboxClaim<T>(
+    batch: number,
+    leaseMs: number
+  ): Promise<Array<OutboxEvent<T>>> {
+    const resultPtr = await withJson(
+      this.exports,
+      { batch, lease_ms: leaseMs },
+      (ptr, len) => this.exports.conn_outbox_claim(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Removes the dispatched events from the outbox and resolves to the number of removed events.
+  public async outboxAck(ids: Array<number>): Promise<number> {
+    const resultPtr = await withJson(this.exports, { ids }, (ptr, len) =>
+      this.exports.conn_outbox_ack(this.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await this.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Copies all rows of `table` into `dst` (creating the table there if necessary) without passing
+  // them through JS, e.g. to move a staging table from an in-memory database into the paged one.
+  public async copyTable(dst: Connection, table: string): Promise<CopyReport> {
+    const target = dst as SqliteConnection;
+    const resultPtr = await withJson(this.exports, table, (ptr, len) =>
+      this.exports.conn_copy_table(this.ptr, target.ptr, ptr, len)
+    );
+    if (!resultPtr) {
+      await target.throwLastError();
+    }
+
+    return JSON.parse(await takeJsonString(this.exports, resultPtr));
+  }
+
+  // Closes the underlying database connection to free its memory (e.g. while idle), but keeps
+  // this connection usable; it is reopened transparently on its next use.
+  public async suspend(): Promise<void> {
+    if (!(await this.exports.conn_suspend(this.ptr))) {
+      await this.throwLastError();
+    }
+  }
+
+  // Releases unused page cache memory and cached statements of the connection.
+  public async releaseMemory(): Promise<void> {
+    await this.exports.conn_release_memory(this.ptr);
+  }
+
+  // Fails (leaving the connection usable) while `Blob` handles of the connection are open.
+  public async drop(): Promise<void> {
+    if (!(await this.exports.conn_drop(this.ptr))) {
+      await this.throwLastError();
+    }
+  }
+}
+
+// Writes `value` as JSON into the wasm memory, calls `f` with its location, and frees it afterwards.
+async function withJson<R>(
+  exports: Exports,
+  value: unknown,
+  f: (ptr: number, len: number) => Promise<R>
+): Promise<R> {
+  const json = new TextEncoder().encode(JSON.stringify(value));
+
+  const ptr = await exports.alloc(json.length);
+  new Uint8Array(exports.memory.buffer, ptr, json.length).set(json);
+  try {
+    return await f(ptr, json.length);
+  } finally {
+    await exports.dealloc(ptr);
+  }
+}
+
+// Reads and frees a `JsonString` returned by the wasm module.
+async function takeJsonString(exports: Exports, ptr: number): Promise<string> {
+  const [offset, length] = new Uint32Array(exports.memory.buffer, ptr, 2);
+  const result = new TextDecoder().decode(
+    new Uint8Array(exports.memory.buffer, offset, length)
+  );
+  await exports.query_result_drop(ptr);
+
+  return result;
+}
+
+const GET_PAGE_OK = 0;
+const GET_PAGE_NOT_FOUND = 1;
+const PUT_PAGE_OK = 0;
+const STORAGE_SYNC_OK = 0;
+const REWRITE_UNCHANGED = 0xffffffff;
+const HOST_ERROR = 2;
+const HOST_TRANSIENT_ERROR = 3;
+
+const ERRNO_SUCCESS = 0;
+const ERRNO_BADF = 8;
+const ERRNO_INVAL = 28;
+const ERRNO_NOSYS = 52;
+
+// The version of the query payload passed to `conn_execute` and `conn_query`.
+const QUERY_PAYLOAD_VERSION = 2;
+
+// Identifies queries that can be cancelled via an `AbortSignal` (see `QueryOptions.signal`).
+let nextCancelToken = 1;
+
+interface Exports {
+  readonly memory: WebAssembly.Memory;
+  alloc(size: number): Promise<number>;
+  dealloc(ptr: number): Promise<void>;
+
+  conn_new(): Promise<number>;
+  conn_new_with_options(ptr: number, len: number): Promise<number>;
+  conn_new_memory(): Promise<number>;
+  last_init_error(): Promise<number>;
+  conn_suspend(conn: number): Promise<number>;
+  conn_release_memory(conn: number): Promise<void>;
+  conn_execute(conn: number, ptr: number, len: number): Promise<number>;
+  conn_query(conn: number, ptr: number, len: number): Promise<number>;
+  conn_query_with_token(
+    conn: number,
+    ptr: number,
+    len: number,
+    token: number
+  ): Promise<number>;
+  cancel(token: number): Promise<number>;
+  conn_cursor_next(
+    conn: number,
+    cursor: number,
+    max_rows: number
+  ): Promise<number>;
+  conn_cursor_close(conn: number, cursor: number): Promise<number>;
+  conn_total_changes(conn: number): Promise<number>;
+  conn_in_transaction(conn: number): Promise<number>;
+  conn_set_limits_json(conn: number, ptr: number, len: number): Promise<number>;
+  conn_reconcile_storage(conn: number): Promise<number>;
+  conn_verify_pages(conn: number): Promise<number>;
+  conn_pages_changed_since(conn: number, counter: number): Promise<number>;
+  conn_freelist_info(conn: number): Promise<number>;
+  conn_reclaim_free_pages(
+    conn: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  conn_dump_page_map(conn: number): Promise<number>;
+  conn_salvage(conn: number): Promise<number>;
+  conn_fork(conn: number, target: number): Promise<number>;
+  conn_encoding(conn: number): Promise<number>;
+  conn_get_version(conn: number): Promise<number>;
+  conn_set_version(conn: number, ptr: number, len: number): Promise<number>;
+  conn_schema_diff(conn: number, ptr: number, len: number): Promise<number>;
+  conn_prewarm(conn: number, ptr: number, len: number): Promise<number>;
+  conn_self_test(conn: number): Promise<number>;
+  conn_analyze(conn: number, ptr: number, len: number): Promise<number>;
+  conn_parameter_info(conn: number, ptr: number, len: number): Promise<number>;
+  conn_check_constraints(
+    conn: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  backup_start(src: number, dst: number): Promise<number>;
+  backup_step(backup: number, pages: number): Promise<number>;
+  backup_progress(backup: number): Promise<number>;
+  backup_finish(backup: number): Promise<number>;
+  blob_open(conn: number, ptr: number, len: number): Promise<number>;
+  blob_reopen(blob: number, ptr: number, len: number): Promise<number>;
+  blob_size(blob: number): Promise<number>;
+  blob_read(
+    blob: number,
+    offset: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  blob_write(
+    blob: number,
+    offset: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  blob_close(blob: number): Promise<number>;
+  set_span_export(enabled: number): Promise<void>;
+  set_slow_query_threshold(ms: number): Promise<void>;
+  set_replication(enabled: number): Promise<void>;
+  set_change_tracking(enabled: number): Promise<void>;
+  memory_stats(): Promise<number>;
+  release_memory(): Promise<void>;
+  set_soft_heap_limit(bytes: number): Promise<void>;
+  apply_delta(ptr: number, len: number): Promise<number>;
+  conn_upsert(conn: number, ptr: number, len: number): Promise<number>;
+  conn_kv_get(conn: number, ptr: number, len: number): Promise<number>;
+  conn_kv_set(conn: number, ptr: number, len: number): Promise<number>;
+  conn_kv_delete(conn: number, ptr: number, len: number): Promise<number>;
+  conn_kv_list(conn: number, ptr: number, len: number): Promise<number>;
+  conn_counter_incr(conn: number, ptr: number, len: number): Promise<number>;
+  conn_fts_create_index(
+    conn: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  conn_fts_search(conn: number, ptr: number, len: number): Promise<number>;
+  conn_enable_history(conn: number, ptr: number, len: number): Promise<number>;
+  conn_enable_cdc(conn: number, ptr: number, len: number): Promise<number>;
+  conn_cdc_poll(conn: number, ptr: number, len: number): Promise<number>;
+  conn_ts_downsample(conn: number, ptr: number, len: number): Promise<number>;
+  conn_geo_within_radius(
+    conn: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  // only available with the `vector` feature
+  conn_vec_insert?(conn: number, ptr: number, len: number): Promise<number>;
+  conn_vec_search?(conn: number, ptr: number, len: number): Promise<number>;
+  conn_queue_push(conn: number, ptr: number, len: number): Promise<number>;
+  conn_queue_pop_batch(
+    conn: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  conn_try_acquire_leadership(
+    conn: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  conn_release_leadership(
+    conn: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  conn_job_schedule(conn: number, ptr: number, len: number): Promise<number>;
+  conn_jobs_due(conn: number, ptr: number, len: number): Promise<number>;
+  conn_outbox_push(conn: number, ptr: number, len: number): Promise<number>;
+  conn_outbox_claim(conn: number, ptr: number, len: number): Promise<number>;
+  conn_outbox_ack(conn: number, ptr: number, len: number): Promise<number>;
+  conn_copy_table(
+    src: number,
+    dst: number,
+    ptr: number,
+    len: number
+  ): Promise<number>;
+  conn_drop(conn: number): Promise<number>;
+  conn_last_error(conn: number): Promise<number>;
+  conn_last_error_drop(err: number): Promise<void>;
+  conn_last_error_json(conn: number): Promise<number>;
+  conn_set_auto_rollback(conn: number, enabled: number): Promise<void>;
+  conn_set_tag(conn: number, ptr: number, len: number): Promise<number>;
+  conn_set_security(conn: number, ptr: number, len: number): Promise<number>;
+  conn_set_row_policy(conn: number, ptr: number, len: number): Promise<number>;
+  conn_set_max_db_size(conn: number, ptr: number, len: number): Promise<number>;
+  conn_register_query(conn: number, ptr: number, len: number): Promise<number>;
+  conn_run(conn: number, ptr: number, len: number): Promise<number>;
+  conn_tune(conn: number, ptr: number, len: number): Promise<number>;
+
+  query_result_drop(ptr: number): Promise<void>;
+
+  set_write_batching(enabled: number): Promise<void>;
+  pending_writes(): Promise<number>;
+  flush(): Promise<number>;
+  set_retry_policy(ptr: number, len: number): Promise<number>;
+  set_error_style(ptr: number, len: number): Promise<number>;
+  discard(): Promise<void>;
+  locks_debug(): Promise<number>;
+  sql_fingerprint(ptr: number, len: number): Promise<number>;
+  set_statement_log(capacity: number): Promise<void>;
+  debug_statement_log(): Promise<number>;
+  run_conformance(ns: number): Promise<number>;
 }
 
 // A wrapper for console.{log,error} that tries to prevent adding unnecessary new lines.
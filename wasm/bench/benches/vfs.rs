@@ -0,0 +1,88 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rusqlite::{params, Connection};
+use wasm_sqlite_bench::{flush_writes, open, Settings};
+
+const ROWS: i64 = 1000;
+
+fn seed(conn: &Connection) {
+    conn.execute_batch("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT NOT NULL)")
+        .unwrap();
+    insert(conn);
+}
+
+fn insert(conn: &Connection) {
+    conn.execute_batch("BEGIN").unwrap();
+    let mut stmt = conn
+        .prepare_cached("INSERT INTO items (name) VALUES (?)")
+        .unwrap();
+    for i in 0..ROWS {
+        stmt.execute(params![format!("item {i}")]).unwrap();
+    }
+    conn.execute_batch("COMMIT").unwrap();
+    flush_writes();
+}
+
+fn inserts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inserts");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    for settings in Settings::ALL {
+        group.bench_function(BenchmarkId::from_parameter(settings.name()), |b| {
+            b.iter_batched(
+                || {
+                    let conn = open(settings);
+                    conn.execute_batch(
+                        "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT NOT NULL)",
+                    )
+                    .unwrap();
+                    conn
+                },
+                |conn| insert(&conn),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn point_reads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("point_reads");
+    group.throughput(Throughput::Elements(1));
+    for settings in Settings::ALL {
+        let conn = open(settings);
+        seed(&conn);
+        let mut id = 0;
+        group.bench_function(BenchmarkId::from_parameter(settings.name()), |b| {
+            b.iter(|| {
+                id = id % ROWS + 1;
+                let name: String = conn
+                    .prepare_cached("SELECT name FROM items WHERE id = ?")
+                    .unwrap()
+                    .query_row([id], |row| row.get(0))
+                    .unwrap();
+                name
+            })
+        });
+    }
+    group.finish();
+}
+
+fn scans(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scans");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    for settings in Settings::ALL {
+        let conn = open(settings);
+        seed(&conn);
+        group.bench_function(BenchmarkId::from_parameter(settings.name()), |b| {
+            b.iter(|| {
+                let len: i64 = conn
+                    .query_row("SELECT SUM(LENGTH(name)) FROM items", [], |row| row.get(0))
+                    .unwrap();
+                len
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, inserts, point_reads, scans);
+criterion_main!(benches);
@@ -0,0 +1,198 @@
+//! A native in-memory page store providing the host imports of `wasm-sqlite`, so that the VFS can
+//! be benchmarked without a wasm runtime.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, Once};
+use std::time::Instant;
+
+use rusqlite::{Connection, OpenFlags};
+use wasm_sqlite::PagesVfs;
+
+static PAGES: Mutex<Option<HashMap<u32, Vec<Vec<u8>>>>> = Mutex::new(None);
+
+extern "C" {
+    fn set_write_batching(enabled: i32);
+    fn flush() -> i32;
+}
+
+/// VFS settings a benchmark runs with.
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// `PRAGMA cache_size` (negative values are KiB).
+    pub cache_size: i32,
+    pub write_batching: bool,
+}
+
+impl Settings {
+    pub const ALL: [Settings; 4] = [
+        Settings {
+            cache_size: -64,
+            write_batching: false,
+        },
+        Settings {
+            cache_size: -2000,
+            write_batching: false,
+        },
+        Settings {
+            cache_size: -16384,
+            write_batching: false,
+        },
+        Settings {
+            cache_size: -2000,
+            write_batching: true,
+        },
+    ];
+
+    pub fn name(&self) -> String {
+        format!(
+            "cache={}KiB{}",
+            -self.cache_size,
+            if self.write_batching { ",batching" } else { "" }
+        )
+    }
+}
+
+/// Clears the page store and opens a new connection to it with the given settings.
+pub fn open(settings: Settings) -> Connection {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        sqlite_vfs::register("cfdo", PagesVfs::<4096>::default(), true).unwrap();
+    });
+
+    *PAGES.lock().unwrap() = Some(HashMap::new());
+    unsafe { set_write_batching(i32::from(settings.write_batching)) };
+
+    let conn = Connection::open_with_flags_and_vfs(
+        "main.db",
+        OpenFlags::SQLITE_OPEN_READ_WRITE
+            | OpenFlags::SQLITE_OPEN_CREATE
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        "cfdo",
+    )
+    .unwrap();
+    conn.execute_batch(&format!(
+        "PRAGMA page_size = 4096; PRAGMA journal_mode = MEMORY; PRAGMA cache_size = {};",
+        settings.cache_size
+    ))
+    .unwrap();
+    conn
+}
+
+/// Writes pending pages to the page store (only relevant with write batching enabled).
+pub fn flush_writes() {
+    assert_eq!(unsafe { flush() }, 1, "flush failed");
+}
+
+fn with_pages<T>(ns: u32, f: impl FnOnce(&mut Vec<Vec<u8>>) -> T) -> T {
+    let mut pages = PAGES.lock().unwrap();
+    f(pages
+        .get_or_insert_with(HashMap::new)
+        .entry(ns)
+        .or_default())
+}
+
+#[no_mangle]
+extern "C" fn page_count(ns: u32) -> u32 {
+    with_pages(ns, |pages| pages.len() as u32)
+}
+
+#[no_mangle]
+unsafe extern "C" fn get_page(ns: u32, ix: u32, ptr: *mut u8, len: u32) -> u32 {
+    with_pages(ns, |pages| match pages.get(ix as usize) {
+        Some(page) => {
+            std::ptr::copy_nonoverlapping(page.as_ptr(), ptr, len as usize);
+            0
+        }
+        None => 1,
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn get_pages(
+    ns: u32,
+    ixs: *const u32,
+    count: u32,
+    ptr: *mut u8,
+    page_len: u32,
+    found: *mut u8,
+) -> u32 {
+    for i in 0..count as usize {
+        let status = get_page(ns, *ixs.add(i), ptr.add(i * page_len as usize), page_len);
+        *found.add(i) = u8::from(status == 0);
+    }
+    0
+}
+
+#[no_mangle]
+unsafe extern "C" fn put_page(ns: u32, ix: u32, ptr: *const u8, len: u32) -> u32 {
+    let data = std::slice::from_raw_parts(ptr, len as usize);
+    with_pages(ns, |pages| {
+        let ix = ix as usize;
+        if ix >= pages.len() {
+            pages.resize(ix + 1, vec![0; len as usize]);
+        }
+        pages[ix].copy_from_slice(data);
+    });
+    0
+}
+
+#[no_mangle]
+extern "C" fn storage_sync(_ns: u32) -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn del_pages(ns: u32, from_ix: u32, _to_ix: u32) {
+    with_pages(ns, |pages| pages.truncate(from_ix as usize))
+}
+
+#[no_mangle]
+extern "C" fn conn_sleep(ms: u32) {
+    std::thread::sleep(std::time::Duration::from_millis(ms.into()));
+}
+
+#[no_mangle]
+extern "C" fn txn_begin() -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn txn_commit(_token: u32) {}
+
+#[no_mangle]
+extern "C" fn txn_rollback(_token: u32) {}
+
+#[no_mangle]
+extern "C" fn emit_delta(_ptr: *const u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn export_span(_ptr: *const u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn slow_query(_ptr: *const u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn on_lock_blocked(_ptr: *const u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn on_storage_delta(_ns: u32, _bytes_delta: f64) {}
+
+#[no_mangle]
+extern "C" fn rewrite_sql(_ptr: *const u8, _len: u32) -> u32 {
+    u32::MAX
+}
+
+#[no_mangle]
+extern "C" fn rewritten_sql(_ptr: *mut u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn host_error_message(_ptr: *mut u8, _len: u32) -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn monotonic_millis() -> f64 {
+    static START: Mutex<Option<Instant>> = Mutex::new(None);
+    let start = *START.lock().unwrap().get_or_insert_with(Instant::now);
+    start.elapsed().as_secs_f64() * 1000.0
+}
@@ -0,0 +1,48 @@
+//! Exercises the ownership flows of the memory shared with the host, the way the JS hosts use
+//! them. Meant to be run under Miri (`cargo xtask miri`), which detects leaks, double frees and
+//! out-of-bounds accesses.
+
+use std::ffi::CString;
+
+use wasm_sqlite::ffi::{self, JsonString};
+
+fn main() {
+    unsafe {
+        // the host allocates memory for a payload, writes into it, and releases it after the call
+        let json = br#"{"sql":"SELECT 1","params":[]}"#;
+        let ptr = ffi::alloc(json.len());
+        std::ptr::copy_nonoverlapping(json.as_ptr(), ptr, json.len());
+        assert_eq!(ffi::payload(ptr, json.len()), json);
+        ffi::dealloc(ptr);
+        let released = ptr;
+
+        // empty payloads
+        let ptr = ffi::alloc(0);
+        assert!(ffi::payload(ptr, 0).is_empty());
+        assert!(ffi::payload(std::ptr::null(), 0).is_empty());
+        ffi::dealloc(ptr);
+
+        // results are read via the `#[repr(C)]` layout and released by the host
+        for json in [String::new(), String::from(r#"[{"1":1}]"#)] {
+            let result = JsonString::new(json.clone()).into_raw();
+            let [ptr, len, _cap] = *(result as *const [usize; 3]);
+            let bytes = std::slice::from_raw_parts(ptr as *const u8, len);
+            assert_eq!(bytes, json.as_bytes());
+            ffi::query_result_drop(result);
+        }
+
+        // error messages
+        ffi::conn_last_error_drop(CString::new("error").unwrap().into_raw());
+        ffi::conn_last_error_drop(std::ptr::null_mut());
+
+        // unknown pointers are ignored (in release builds)
+        if !cfg!(debug_assertions) {
+            ffi::dealloc(released);
+        }
+
+        // connection handles
+        let handle = Box::into_raw(Box::new(42u64));
+        *ffi::as_mut(handle) += 1;
+        assert_eq!(*ffi::take(handle), 43);
+    }
+}
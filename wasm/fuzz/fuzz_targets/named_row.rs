@@ -0,0 +1,32 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rusqlite::types::Value;
+use wasm_sqlite_fuzz as _;
+
+#[derive(Debug, Arbitrary)]
+enum Column {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+fuzz_target!(|columns: Vec<(String, Column)>| {
+    let columns = columns
+        .into_iter()
+        .map(|(name, column)| {
+            let value = match column {
+                Column::Null => Value::Null,
+                Column::Integer(v) => Value::Integer(v),
+                Column::Real(v) => Value::Real(v),
+                Column::Text(v) => Value::Text(v),
+                Column::Blob(v) => Value::Blob(v),
+            };
+            (name, value)
+        })
+        .collect::<Vec<_>>();
+    wasm_sqlite::fuzz::named_row(&columns);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wasm_sqlite_fuzz as _;
+
+fuzz_target!(|data: &[u8]| {
+    wasm_sqlite::fuzz::query_payload(data);
+});
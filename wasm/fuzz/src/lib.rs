@@ -0,0 +1,83 @@
+//! No-op host imports of `wasm-sqlite` for the fuzz targets, which only use in-memory databases.
+
+#[no_mangle]
+extern "C" fn page_count(_ns: u32) -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn get_page(_ns: u32, _ix: u32, _ptr: *mut u8, _len: u32) -> u32 {
+    1
+}
+
+#[no_mangle]
+extern "C" fn get_pages(
+    _ns: u32,
+    _ixs: *const u32,
+    _count: u32,
+    _ptr: *mut u8,
+    _page_len: u32,
+    _found: *mut u8,
+) -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn put_page(_ns: u32, _ix: u32, _ptr: *const u8, _len: u32) -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn storage_sync(_ns: u32) -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn del_pages(_ns: u32, _from_ix: u32, _to_ix: u32) {}
+
+#[no_mangle]
+extern "C" fn conn_sleep(_ms: u32) {}
+
+#[no_mangle]
+extern "C" fn txn_begin() -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn txn_commit(_token: u32) {}
+
+#[no_mangle]
+extern "C" fn txn_rollback(_token: u32) {}
+
+#[no_mangle]
+extern "C" fn emit_delta(_ptr: *const u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn export_span(_ptr: *const u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn slow_query(_ptr: *const u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn on_lock_blocked(_ptr: *const u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn on_storage_delta(_ns: u32, _bytes_delta: f64) {}
+
+#[no_mangle]
+extern "C" fn rewrite_sql(_ptr: *const u8, _len: u32) -> u32 {
+    u32::MAX
+}
+
+#[no_mangle]
+extern "C" fn rewritten_sql(_ptr: *mut u8, _len: u32) {}
+
+#[no_mangle]
+extern "C" fn host_error_message(_ptr: *mut u8, _len: u32) -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "C" fn monotonic_millis() -> f64 {
+    0.0
+}
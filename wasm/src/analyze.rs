@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+
+/// The tables in which `ANALYZE` stores its statistics (`sqlite_stat4` requires SQLite to be
+/// compiled with `SQLITE_ENABLE_STAT4`).
+const STAT_TABLES: [&str; 2] = ["sqlite_stat1", "sqlite_stat4"];
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalyzeOptions {
+    /// The approximate number of rows to scan per index (`PRAGMA analysis_limit`), which bounds
+    /// the time spent analyzing large tables. `None` or `0` scans all rows.
+    pub analysis_limit: Option<u32>,
+    /// Analyze all tables (`ANALYZE`) instead of only those whose statistics are missing or
+    /// outdated (`PRAGMA optimize`).
+    pub full: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnalyzeReport {
+    /// The number of rows in each statistics table after analyzing (`None` if the table doesn't
+    /// exist).
+    pub stat_rows: BTreeMap<&'static str, Option<i64>>,
+}
+
+/// Gathers statistics for the query planner. The statistics are stored in regular tables, so
+/// they are persisted in the page store like any other data.
+pub fn analyze(
+    conn: &rusqlite::Connection,
+    options: &AnalyzeOptions,
+) -> Result<AnalyzeReport, rusqlite::Error> {
+    let previous_limit: i64 = conn.query_row("PRAGMA analysis_limit", [], |row| row.get(0))?;
+    conn.execute_batch(&format!(
+        "PRAGMA analysis_limit = {}",
+        options.analysis_limit.unwrap_or(0)
+    ))?;
+
+    let result = if options.full {
+        conn.execute_batch("ANALYZE")
+    } else {
+        conn.execute_batch("PRAGMA optimize")
+    };
+    conn.execute_batch(&format!("PRAGMA analysis_limit = {previous_limit}"))?;
+    result?;
+
+    let mut report = AnalyzeReport {
+        stat_rows: BTreeMap::new(),
+    };
+    for table in STAT_TABLES {
+        let exists = conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?",
+                [table],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        let rows = if exists {
+            Some(
+                conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })?,
+            )
+        } else {
+            None
+        };
+        report.stat_rows.insert(table, rows);
+    }
+
+    Ok(report)
+}
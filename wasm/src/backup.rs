@@ -0,0 +1,86 @@
+use std::ffi::CStr;
+use std::os::raw::c_int;
+
+use rusqlite::ffi;
+use serde::Serialize;
+
+use crate::Connection;
+
+/// An incremental copy of the database of one connection into the database of another, using
+/// SQLite's online backup API. Between steps, the source database is not locked, so writers are
+/// only blocked for the duration of a single step.
+pub struct Backup {
+    backup: *mut ffi::sqlite3_backup,
+    pub dst: *mut Connection,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupProgress {
+    pub remaining: i32,
+    pub page_count: i32,
+}
+
+/// The result of a [Backup::step].
+pub enum StepResult {
+    Done,
+    More,
+}
+
+impl Backup {
+    pub fn start(src: &Connection, dst: *mut Connection) -> Result<Self, rusqlite::Error> {
+        let dst_handle = unsafe { (*dst).conn().handle() };
+        let main = b"main\0".as_ptr() as *const _;
+        let backup =
+            unsafe { ffi::sqlite3_backup_init(dst_handle, main, src.conn().handle(), main) };
+        if backup.is_null() {
+            return Err(error(dst_handle, unsafe {
+                ffi::sqlite3_errcode(dst_handle)
+            }));
+        }
+
+        Ok(Self { backup, dst })
+    }
+
+    /// Copies up to `pages` pages (all remaining ones if negative). Busy or locked databases are
+    /// not an error; the step simply has to be retried later.
+    pub fn step(&mut self, pages: i32) -> Result<StepResult, rusqlite::Error> {
+        match unsafe { ffi::sqlite3_backup_step(self.backup, pages) } {
+            ffi::SQLITE_DONE => Ok(StepResult::Done),
+            ffi::SQLITE_OK | ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED => Ok(StepResult::More),
+            code => Err(error(self.dst_handle(), code)),
+        }
+    }
+
+    pub fn progress(&self) -> BackupProgress {
+        unsafe {
+            BackupProgress {
+                remaining: ffi::sqlite3_backup_remaining(self.backup),
+                page_count: ffi::sqlite3_backup_pagecount(self.backup),
+            }
+        }
+    }
+
+    /// Releases the backup. Returns the error of a failed step, if any.
+    pub fn finish(self) -> Result<(), rusqlite::Error> {
+        let code = unsafe { ffi::sqlite3_backup_finish(self.backup) };
+        match code {
+            ffi::SQLITE_OK => Ok(()),
+            code => Err(error(self.dst_handle(), code)),
+        }
+    }
+
+    pub fn dst(&mut self) -> &mut Connection {
+        unsafe { crate::ffi::as_mut(self.dst) }
+    }
+
+    fn dst_handle(&self) -> *mut ffi::sqlite3 {
+        unsafe { (*self.dst).conn().handle() }
+    }
+}
+
+fn error(handle: *mut ffi::sqlite3, code: c_int) -> rusqlite::Error {
+    let message = unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(handle)) }
+        .to_string_lossy()
+        .into_owned();
+    rusqlite::Error::SqliteFailure(ffi::Error::new(code), Some(message))
+}
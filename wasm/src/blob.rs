@@ -0,0 +1,127 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::c_int;
+
+use rusqlite::ffi;
+
+use crate::Connection;
+
+/// Incremental I/O of a single BLOB value using SQLite's `sqlite3_blob_*` API, so that large
+/// values (images, model weights, ...) can be streamed in chunks instead of being materialized in
+/// a single JSON payload. The size of a BLOB can't be changed this way; write a `zeroblob(n)` of
+/// the final size first and then fill it in chunks.
+pub struct Blob {
+    blob: *mut ffi::sqlite3_blob,
+    pub conn: *mut Connection,
+}
+
+/// The value to open, as passed to `blob_open` (JSON).
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlobTarget {
+    pub table: String,
+    pub column: String,
+    pub rowid: i64,
+    /// Open the BLOB for writing as well.
+    #[serde(default)]
+    pub writable: bool,
+}
+
+impl Blob {
+    pub fn open(conn: *mut Connection, target: &BlobTarget) -> Result<Self, rusqlite::Error> {
+        let handle = unsafe { (*conn).conn().handle() };
+        let table = cstring(&target.table)?;
+        let column = cstring(&target.column)?;
+        let mut blob = std::ptr::null_mut();
+        let code = unsafe {
+            ffi::sqlite3_blob_open(
+                handle,
+                b"main\0".as_ptr() as *const _,
+                table.as_ptr(),
+                column.as_ptr(),
+                target.rowid,
+                c_int::from(target.writable),
+                &mut blob,
+            )
+        };
+        if code != ffi::SQLITE_OK {
+            // A handle is returned even on failure (unless out of memory), which must be closed.
+            unsafe { ffi::sqlite3_blob_close(blob) };
+            return Err(error(handle, code));
+        }
+
+        Ok(Self { blob, conn })
+    }
+
+    /// Points the handle to the value of the same column in another row.
+    pub fn reopen(&mut self, rowid: i64) -> Result<(), rusqlite::Error> {
+        match unsafe { ffi::sqlite3_blob_reopen(self.blob, rowid) } {
+            ffi::SQLITE_OK => Ok(()),
+            code => Err(error(self.handle(), code)),
+        }
+    }
+
+    /// The size of the BLOB in bytes.
+    pub fn size(&self) -> u32 {
+        unsafe { ffi::sqlite3_blob_bytes(self.blob) as u32 }
+    }
+
+    /// Fills `buf` with the bytes starting at `offset`. Fails if the range exceeds the BLOB.
+    pub fn read(&self, offset: u32, buf: &mut [u8]) -> Result<(), rusqlite::Error> {
+        let code = unsafe {
+            ffi::sqlite3_blob_read(
+                self.blob,
+                buf.as_mut_ptr() as *mut _,
+                buf.len() as c_int,
+                offset as c_int,
+            )
+        };
+        match code {
+            ffi::SQLITE_OK => Ok(()),
+            code => Err(error(self.handle(), code)),
+        }
+    }
+
+    /// Overwrites the bytes starting at `offset` with `data`. Fails if the range exceeds the BLOB.
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), rusqlite::Error> {
+        let code = unsafe {
+            ffi::sqlite3_blob_write(
+                self.blob,
+                data.as_ptr() as *const _,
+                data.len() as c_int,
+                offset as c_int,
+            )
+        };
+        match code {
+            ffi::SQLITE_OK => Ok(()),
+            code => Err(error(self.handle(), code)),
+        }
+    }
+
+    /// Releases the handle, which commits pending writes if not inside an explicit transaction.
+    pub fn close(self) -> Result<(), rusqlite::Error> {
+        let handle = self.handle();
+        match unsafe { ffi::sqlite3_blob_close(self.blob) } {
+            ffi::SQLITE_OK => Ok(()),
+            code => Err(error(handle, code)),
+        }
+    }
+
+    pub fn conn(&mut self) -> &mut Connection {
+        unsafe { crate::ffi::as_mut(self.conn) }
+    }
+
+    fn handle(&self) -> *mut ffi::sqlite3 {
+        unsafe { (*self.conn).conn().handle() }
+    }
+}
+
+fn cstring(s: &str) -> Result<CString, rusqlite::Error> {
+    CString::new(s).map_err(|_| rusqlite::Error::InvalidParameterName(s.to_string()))
+}
+
+fn error(handle: *mut ffi::sqlite3, code: c_int) -> rusqlite::Error {
+    let message = unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(handle)) }
+        .to_string_lossy()
+        .into_owned();
+    rusqlite::Error::SqliteFailure(ffi::Error::new(code), Some(message))
+}
@@ -0,0 +1,58 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use rusqlite::ffi;
+
+thread_local! {
+    /// The database handles of the statements currently running with a cancellation token.
+    static RUNNING: RefCell<HashMap<u32, *mut ffi::sqlite3>> = RefCell::new(HashMap::new());
+
+    /// The tokens that were cancelled while their statement was running.
+    static CANCELLED: RefCell<HashSet<u32>> = RefCell::new(HashSet::new());
+}
+
+#[derive(Debug)]
+pub struct Cancelled {
+    pub token: u32,
+}
+
+/// Runs `f` (which runs a statement on `db`) so that it can be interrupted by calling [cancel]
+/// with `token`, e.g. from a host callback while the statement waits for a page.
+pub fn with_token<T>(
+    token: u32,
+    db: *mut ffi::sqlite3,
+    f: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    RUNNING.with(|running| running.borrow_mut().insert(token, db));
+    let result = f();
+    RUNNING.with(|running| running.borrow_mut().remove(&token));
+
+    let cancelled = CANCELLED.with(|cancelled| cancelled.borrow_mut().remove(&token));
+    match result {
+        Err(_) if cancelled => Err(Box::new(Cancelled { token })),
+        result => result,
+    }
+}
+
+/// Interrupts the statement running with `token`. Returns `false` if no such statement is running
+/// (anymore).
+pub fn cancel(token: u32) -> bool {
+    let db = match RUNNING.with(|running| running.borrow().get(&token).copied()) {
+        Some(db) => db,
+        None => return false,
+    };
+
+    tracing::debug!(token, "cancel");
+    CANCELLED.with(|cancelled| cancelled.borrow_mut().insert(token));
+    unsafe { ffi::sqlite3_interrupt(db) };
+    true
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "query cancelled (token {})", self.token)
+    }
+}
+
+impl std::error::Error for Cancelled {}
@@ -0,0 +1,268 @@
+//! Change data capture: triggers record every insert, update and delete of the enabled tables as
+//! a JSON change event in `_wasm_sqlite_cdc`, which downstream systems (e.g. search indexes or
+//! analytics) tail via [poll]. Unlike buffering the changes reported by the update hook (or a
+//! session), the triggers write the events as part of the transaction making the changes, so
+//! events are committed (and rolled back) atomically with them and survive the instance.
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use crate::copy::quote;
+use crate::history::columns;
+
+/// The table the change events of all tables are recorded in. Old and new values are stored as
+/// JSON text.
+const TABLE: &str = "_wasm_sqlite_cdc";
+
+/// The commit id of the change, as SQL: the `seq` the first event of the transaction got. Unlike
+/// `max(seq)`, `sqlite_sequence` doesn't go back once the log is trimmed.
+const COMMIT_ID: &str = "cdc_commit_id(
+  (SELECT coalesce((SELECT seq FROM sqlite_sequence WHERE name = '_wasm_sqlite_cdc'), 0) + 1)
+)";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnableCdc {
+    /// The table to record the changes of.
+    pub table: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CdcPoll {
+    /// The `cursor` of the previous batch, `0` to start with the oldest event.
+    #[serde(default)]
+    pub cursor: i64,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangeEvent {
+    /// Increases with every recorded change (across all tables).
+    pub seq: i64,
+    /// Shared by all changes committed together.
+    pub commit_id: i64,
+    pub table: String,
+    pub op: Op,
+    /// The row before the change (`None` for inserts).
+    pub old: Option<JsonValue>,
+    /// The row after the change (`None` for deletes).
+    pub new: Option<JsonValue>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CdcBatch {
+    /// The `seq` of the last returned event, to pass to the next poll.
+    pub cursor: i64,
+    pub events: Vec<ChangeEvent>,
+}
+
+/// Registers the `cdc_commit_id(next)` function used by the triggers, which returns the same id
+/// for all changes of a transaction: `next` when first called in a transaction, and the id of the
+/// transaction until it is committed or rolled back.
+pub fn register(conn: &Connection) -> Result<(), rusqlite::Error> {
+    // `0` while the current transaction hasn't recorded a change yet.
+    let current = Arc::new(AtomicI64::new(0));
+
+    conn.create_scalar_function("cdc_commit_id", 1, FunctionFlags::SQLITE_UTF8, {
+        let current = current.clone();
+        move |ctx| {
+            let next = ctx.get::<i64>(0)?;
+            // The `seq`s only go back (to the id or before) if the first event of the transaction
+            // was undone by `ROLLBACK TO`, after which the next event is the first one.
+            let id = current.load(Ordering::Relaxed);
+            if id == 0 || id >= next {
+                current.store(next, Ordering::Relaxed);
+                return Ok(next);
+            }
+            Ok(id)
+        }
+    })?;
+    conn.commit_hook(Some({
+        let current = current.clone();
+        move || {
+            current.store(0, Ordering::Relaxed);
+            false
+        }
+    }));
+    conn.rollback_hook(Some(move || current.store(0, Ordering::Relaxed)));
+    Ok(())
+}
+
+/// Creates the triggers recording the changes of `table` (and the log table, if it doesn't exist
+/// yet). Existing rows aren't recorded. Blobs are recorded as `{"blob": "<hex>"}`, as JSON cannot
+/// hold them. Does nothing if the changes of the table are already recorded.
+pub fn enable(conn: &Connection, enable: &EnableCdc) -> Result<(), Box<dyn std::error::Error>> {
+    let names = columns(conn, &enable.table)?;
+    if names.is_empty() {
+        return Err(format!("no such table: {}", enable.table).into());
+    }
+
+    let exists = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'trigger' AND name = ?")?
+        .exists([format!("{}_cdc_ai", enable.table)])?;
+    if exists {
+        return Ok(());
+    }
+
+    conn.execute_batch(&format!(
+        "SAVEPOINT enable_cdc;
+         CREATE TABLE IF NOT EXISTS {TABLE} (
+           seq INTEGER PRIMARY KEY AUTOINCREMENT, commit_id INTEGER NOT NULL, tbl TEXT NOT NULL,
+           op TEXT NOT NULL, old TEXT, new TEXT
+         );
+         {triggers}
+         RELEASE enable_cdc;",
+        triggers = triggers(&enable.table, &names),
+    ))
+    .map_err(|err| {
+        conn.execute_batch("ROLLBACK TO enable_cdc; RELEASE enable_cdc")
+            .ok();
+        err
+    })?;
+
+    Ok(())
+}
+
+/// Recreates the triggers of all tables whose changes are recorded, so that they record the
+/// columns (and the name) of each table after an `ALTER TABLE`. Dropping a column of such a table
+/// fails, as its triggers use the column.
+pub fn refresh(conn: &Connection) -> Result<(), Box<dyn std::error::Error>> {
+    let enabled = conn
+        .prepare(
+            "SELECT name, tbl_name FROM sqlite_master
+             WHERE type = 'trigger' AND name LIKE '%\\_cdc\\_ai' ESCAPE '\\'",
+        )?
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    if enabled.is_empty() {
+        return Ok(());
+    }
+
+    let mut sql = String::from("SAVEPOINT refresh_cdc;");
+    for (trigger, table) in enabled {
+        let prefix = &trigger[..trigger.len() - "_cdc_ai".len()];
+        for suffix in ["ai", "au", "ad"] {
+            let name = quote(&format!("{prefix}_cdc_{suffix}"));
+            sql.push_str(&format!("DROP TRIGGER IF EXISTS {name};"));
+        }
+        sql.push_str(&triggers(&table, &columns(conn, &table)?));
+    }
+    sql.push_str("RELEASE refresh_cdc;");
+    conn.execute_batch(&sql).map_err(|err| {
+        conn.execute_batch("ROLLBACK TO refresh_cdc; RELEASE refresh_cdc")
+            .ok();
+        err
+    })?;
+
+    Ok(())
+}
+
+/// The statements creating the triggers recording the changes of `table` with the columns `names`.
+fn triggers(table: &str, names: &[String]) -> String {
+    let name = |suffix: &str| quote(&format!("{table}_cdc_{suffix}"));
+    let row = |alias: &str| {
+        let values = names
+            .iter()
+            .map(|column| {
+                let value = format!("{alias}.{}", quote(column));
+                format!(
+                    "'{}', CASE typeof({value}) WHEN 'blob' THEN json_object('blob', hex({value})) \
+                     ELSE {value} END",
+                    column.replace('\'', "''")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("json_object({values})")
+    };
+    let record = |op: &str, old: &str, new: &str| {
+        format!(
+            "INSERT INTO {TABLE} (commit_id, tbl, op, old, new)
+               VALUES ({COMMIT_ID}, '{}', '{op}', {old}, {new});",
+            table.replace('\'', "''")
+        )
+    };
+
+    format!(
+        "CREATE TRIGGER {ai} AFTER INSERT ON {table} BEGIN
+           {insert}
+         END;
+         CREATE TRIGGER {au} AFTER UPDATE ON {table} BEGIN
+           {update}
+         END;
+         CREATE TRIGGER {ad} AFTER DELETE ON {table} BEGIN
+           {delete}
+         END;",
+        ai = name("ai"),
+        au = name("au"),
+        ad = name("ad"),
+        table = quote(table),
+        insert = record("insert", "NULL", &row("new")),
+        update = record("update", &row("old"), &row("new")),
+        delete = record("delete", &row("old"), "NULL"),
+    )
+}
+
+/// Returns up to `limit` of the committed change events after `cursor`, oldest first. A batch may
+/// end within a commit; the rest of it is returned by the next poll. Events aren't removed, the
+/// log can be trimmed via `DELETE FROM _wasm_sqlite_cdc WHERE seq <= ?` once processed.
+pub fn poll(conn: &Connection, poll: &CdcPoll) -> Result<CdcBatch, Box<dyn std::error::Error>> {
+    let mut batch = CdcBatch {
+        cursor: poll.cursor,
+        events: Vec::new(),
+    };
+
+    let exists = conn
+        .prepare_cached("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?")?
+        .exists([TABLE])?;
+    if !exists {
+        return Ok(batch);
+    }
+
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT seq, commit_id, tbl, op, old, new FROM {TABLE} WHERE seq > ?1 ORDER BY seq LIMIT ?2"
+    ))?;
+    let limit = poll.limit.map_or(-1, i64::from);
+    let mut rows = stmt.query(params![poll.cursor, limit])?;
+    while let Some(row) = rows.next()? {
+        let op = match row.get_ref(3)?.as_str()? {
+            "insert" => Op::Insert,
+            "update" => Op::Update,
+            "delete" => Op::Delete,
+            op => return Err(format!("unknown change op: {op}").into()),
+        };
+        let json = |ix: usize| -> Result<Option<JsonValue>, Box<dyn std::error::Error>> {
+            match row.get::<_, Option<String>>(ix)? {
+                Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+                None => Ok(None),
+            }
+        };
+        let event = ChangeEvent {
+            seq: row.get(0)?,
+            commit_id: row.get(1)?,
+            table: row.get(2)?,
+            op,
+            old: json(4)?,
+            new: json(5)?,
+        };
+        batch.cursor = event.seq;
+        batch.events.push(event);
+    }
+    Ok(batch)
+}
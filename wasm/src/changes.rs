@@ -0,0 +1,161 @@
+//! Change tracking of the durable database for incremental backups (see `set_change_tracking`):
+//! for each page, the commit (SQLite's file change counter) that last changed it is stored in a
+//! separate namespace of the page store, so that backup tooling can copy only the pages changed
+//! since its last backup (see [changed_since]) instead of the whole page store.
+//!
+//! Page `0` of the namespace is a header, page `1 + n` holds the commit counters (u32, little
+//! endian) of the database pages `n * 1024..(n + 1) * 1024`. `0` means unchanged since tracking
+//! started.
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use serde::Serialize;
+
+use crate::namespace::Namespace;
+use crate::vfs;
+
+/// The namespace of the page store holding the change map. It doesn't hold a database, so it
+/// isn't one of the registered VFSs.
+pub const NAMESPACE: u32 = 4;
+pub const PAGE_SIZE: usize = 4096;
+const ENTRIES_PER_PAGE: u32 = (PAGE_SIZE / 4) as u32;
+const MAGIC: &[u8; 4] = b"chg1";
+
+thread_local! {
+    static ENABLED: Cell<bool> = Cell::new(false);
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.with(|e| e.set(enabled));
+}
+
+/// Whether the pages written to `namespace` are tracked.
+pub fn is_tracked(namespace: u32) -> bool {
+    ENABLED.with(Cell::get) && namespace == Namespace::main().id
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangedPages {
+    /// The commit the database is at, to be passed to the next [changed_since] call once the
+    /// pages are copied.
+    pub counter: u32,
+    /// Whether the changes since the requested commit are unknown (e.g. because tracking was
+    /// disabled for some commits), in which case `pages` lists all pages.
+    pub full: bool,
+    pub pages: Vec<u32>,
+}
+
+struct Header {
+    /// The commit tracking started at; changes of earlier commits are unknown.
+    tracked_since: u32,
+    /// The last tracked commit.
+    last_counter: u32,
+}
+
+impl Header {
+    fn read() -> Result<Option<Self>, io::Error> {
+        let page = match vfs::get_meta_page(NAMESPACE, 0)? {
+            Some(page) if &page[..4] == MAGIC => page,
+            _ => return Ok(None),
+        };
+        Ok(Some(Self {
+            tracked_since: read_u32(&page, 4),
+            last_counter: read_u32(&page, 8),
+        }))
+    }
+
+    fn write(&self) -> Result<(), io::Error> {
+        let mut page = [0u8; PAGE_SIZE];
+        page[..4].copy_from_slice(MAGIC);
+        page[4..8].copy_from_slice(&self.tracked_since.to_le_bytes());
+        page[8..12].copy_from_slice(&self.last_counter.to_le_bytes());
+        vfs::put_meta_page(NAMESPACE, 0, &page)
+    }
+}
+
+/// Records that the commit `counter` changed `pages` of the durable database. Called as part of
+/// the commit's storage transaction.
+pub fn record(counter: u32, pages: &BTreeSet<u32>) -> Result<(), io::Error> {
+    let previous = counter.wrapping_sub(1);
+    let mut header = match Header::read()? {
+        Some(header) if header.last_counter == previous => header,
+        // Tracking starts now, either for the first time, or again after commits that weren't
+        // tracked. Entries of earlier commits are all older than `tracked_since`, so they can be
+        // kept.
+        _ => Header {
+            tracked_since: previous,
+            last_counter: previous,
+        },
+    };
+
+    let mut by_page = BTreeMap::<u32, Vec<u32>>::new();
+    for ix in pages {
+        by_page
+            .entry(1 + ix / ENTRIES_PER_PAGE)
+            .or_default()
+            .push(*ix);
+    }
+    for (meta_ix, ixs) in by_page {
+        let mut page = vfs::get_meta_page(NAMESPACE, meta_ix)?.unwrap_or([0u8; PAGE_SIZE]);
+        for ix in ixs {
+            let offset = (ix % ENTRIES_PER_PAGE) as usize * 4;
+            page[offset..offset + 4].copy_from_slice(&counter.to_le_bytes());
+        }
+        vfs::put_meta_page(NAMESPACE, meta_ix, &page)?;
+    }
+
+    header.last_counter = counter;
+    header.write()
+}
+
+/// Lists the pages of the durable database changed after the commit `since` (as returned by a
+/// previous call, or `0` for all pages).
+pub fn changed_since(since: u32) -> Result<ChangedPages, io::Error> {
+    let main = Namespace::main();
+    let page_count = main.page_count() as u32;
+    let counter = main.commit_counter()?.unwrap_or(0);
+
+    let known = match Header::read()? {
+        Some(header) => {
+            since != 0
+                && header.last_counter == counter
+                && header.tracked_since <= since
+                && since <= counter
+        }
+        None => false,
+    };
+    if !known {
+        return Ok(ChangedPages {
+            counter,
+            full: true,
+            pages: (0..page_count).collect(),
+        });
+    }
+
+    let mut pages = Vec::new();
+    let meta_pages = (page_count + ENTRIES_PER_PAGE - 1) / ENTRIES_PER_PAGE;
+    for meta_ix in 1..=meta_pages {
+        let page = match vfs::get_meta_page(NAMESPACE, meta_ix)? {
+            Some(page) => page,
+            None => continue,
+        };
+        let first = (meta_ix - 1) * ENTRIES_PER_PAGE;
+        for (i, entry) in page.chunks_exact(4).enumerate() {
+            let ix = first + i as u32;
+            if ix < page_count && read_u32(entry, 0) > since {
+                pages.push(ix);
+            }
+        }
+    }
+    Ok(ChangedPages {
+        counter,
+        full: false,
+        pages,
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+/// A point in time of the host's monotonic clock (`monotonic_millis` import), used instead of
+/// [std::time::Instant], which is unreliable or unavailable on some wasm targets.
+#[derive(Debug, Clone, Copy)]
+pub struct Instant {
+    millis: f64,
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        Self {
+            millis: unsafe { crate::monotonic_millis() },
+        }
+    }
+
+    /// The time passed since this instant. Zero if the host clock went backwards.
+    pub fn elapsed(&self) -> Duration {
+        let millis = Self::now().millis - self.millis;
+        if millis.is_finite() && millis > 0.0 {
+            Duration::from_secs_f64(millis / 1000.0)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! A conformance test of the host's page store (see `run_conformance`), so that broken adapters
+//! (e.g. ones keeping a view into the wasm memory instead of a copy of a page, or not awaiting
+//! writes) fail with an explicit contract violation instead of silently corrupting databases.
+//!
+//! The contract: `get_page` returns the content of the last `put_page` of the same index (or
+//! `NOT_FOUND` if there was none, or it was deleted since), `page_count` is the index of the last
+//! page plus one, `del_pages` is only called for the last pages and removes all of them, and
+//! `get_pages` reads the requested indices in the requested order. Each call must see the effects
+//! of all previous calls, even if the host completes them asynchronously.
+
+use serde::Serialize;
+
+use crate::namespace::Namespace;
+use crate::retry;
+use crate::vfs::{host_error, GET_PAGE_NOT_FOUND, GET_PAGE_OK, PUT_PAGE_OK, STORAGE_SYNC_OK};
+
+/// The number of pages written by the test.
+const PAGES: u32 = 3;
+
+#[derive(Debug, Serialize)]
+pub struct ConformanceReport {
+    pub ok: bool,
+    pub checks: Vec<ConformanceCheck>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConformanceCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    /// The violated part of the contract.
+    pub error: Option<String>,
+}
+
+/// Runs the conformance test against the namespace, which must be empty and not be used by any
+/// connection in the meantime. Stops at the first failing check. All pages written are deleted
+/// again afterwards (as far as the page store allows).
+pub fn run(ns: &Namespace) -> ConformanceReport {
+    let store = Store {
+        ns: ns.id,
+        page_size: ns.page_size,
+    };
+    let mut report = ConformanceReport {
+        ok: true,
+        checks: Vec::new(),
+    };
+
+    let checks: [(&'static str, &dyn Fn() -> Result<(), String>); 7] = [
+        ("empty", &|| {
+            store.expect_page_count(0)?;
+            store.expect_page(0, None)
+        }),
+        ("put_get", &|| {
+            // All pages are written from the same buffer, so hosts keeping a view into the wasm
+            // memory (instead of a copy) end up with the content of the last page everywhere.
+            let mut buf = vec![0u8; store.page_size];
+            for ix in 0..PAGES {
+                buf.copy_from_slice(&store.page(ix, 0));
+                store.put(ix, &buf)?;
+                store.expect_page(ix, Some(&store.page(ix, 0)))?;
+            }
+            for ix in 0..PAGES {
+                store.expect_page(ix, Some(&store.page(ix, 0)))?;
+            }
+            store.sync()
+        }),
+        ("page_count", &|| {
+            store.expect_page_count(PAGES)?;
+            store.put(1, &store.page(1, 1))?;
+            store.expect_page_count(PAGES)
+        }),
+        ("overwrite", &|| {
+            // Writes without reads in between, which must still be applied in order.
+            for version in 2..=8 {
+                store.put(1, &store.page(1, version))?;
+            }
+            store.expect_page(1, Some(&store.page(1, 8)))?;
+            store.expect_page(0, Some(&store.page(0, 0)))
+        }),
+        ("get_pages", &|| {
+            let ixs = [2, 0, PAGES + 1, 1];
+            let expected = [
+                Some(store.page(2, 0)),
+                Some(store.page(0, 0)),
+                None,
+                Some(store.page(1, 8)),
+            ];
+            let pages = store.get_pages(&ixs)?;
+            for ((ix, page), expected) in ixs.iter().zip(pages).zip(expected) {
+                if page != expected {
+                    return Err(format!(
+                        "get_pages returned {} for page {ix}, expected {}",
+                        store.describe(page.as_deref()),
+                        store.describe(expected.as_deref()),
+                    ));
+                }
+            }
+            Ok(())
+        }),
+        ("del_pages", &|| {
+            let last = PAGES - 1;
+            store.del(last, PAGES);
+            store.expect_page_count(last)?;
+            store.expect_page(last, None)?;
+            store.expect_page(0, Some(&store.page(0, 0)))?;
+            // A page written right after its deletion must exist again.
+            store.put(last, &store.page(last, 9))?;
+            store.expect_page_count(PAGES)?;
+            store.expect_page(last, Some(&store.page(last, 9)))
+        }),
+        ("cleanup", &|| {
+            store.del(0, PAGES);
+            store.expect_page_count(0)?;
+            for ix in 0..PAGES {
+                store.expect_page(ix, None)?;
+            }
+            store.sync()
+        }),
+    ];
+
+    for (name, check) in checks {
+        let result = check();
+        let ok = result.is_ok();
+        report.checks.push(ConformanceCheck {
+            name,
+            ok,
+            error: result.err(),
+        });
+        if !ok {
+            report.ok = false;
+            // Only clean up pages the test wrote itself.
+            if name != "empty" && name != "cleanup" {
+                store.del(0, PAGES);
+            }
+            break;
+        }
+    }
+
+    report
+}
+
+struct Store {
+    ns: u32,
+    page_size: usize,
+}
+
+impl Store {
+    fn get(&self, ix: u32) -> Result<Option<Vec<u8>>, String> {
+        let mut data = vec![0u8; self.page_size];
+        let status = retry::with_retry("get_page", || unsafe {
+            crate::get_page(self.ns, ix, data.as_mut_ptr(), data.len() as u32)
+        });
+        match status {
+            GET_PAGE_OK => Ok(Some(data)),
+            GET_PAGE_NOT_FOUND => Ok(None),
+            status => Err(self.failed(format!("get_page({ix}) returned status {status}"))),
+        }
+    }
+
+    fn get_pages(&self, ixs: &[u32]) -> Result<Vec<Option<Vec<u8>>>, String> {
+        let mut data = vec![0u8; ixs.len() * self.page_size];
+        let mut found = vec![0u8; ixs.len()];
+        let status = retry::with_retry("get_pages", || unsafe {
+            crate::get_pages(
+                self.ns,
+                ixs.as_ptr(),
+                ixs.len() as u32,
+                data.as_mut_ptr(),
+                self.page_size as u32,
+                found.as_mut_ptr(),
+            )
+        });
+        if status != GET_PAGE_OK {
+            return Err(self.failed(format!("get_pages returned status {status}")));
+        }
+        Ok(data
+            .chunks_exact(self.page_size)
+            .zip(found)
+            .map(|(page, found)| (found != 0).then(|| page.to_vec()))
+            .collect())
+    }
+
+    fn put(&self, ix: u32, data: &[u8]) -> Result<(), String> {
+        let status = retry::with_retry("put_page", || unsafe {
+            crate::put_page(self.ns, ix, data.as_ptr(), data.len() as u32)
+        });
+        if status != PUT_PAGE_OK {
+            return Err(self.failed(format!("put_page({ix}) returned status {status}")));
+        }
+        Ok(())
+    }
+
+    fn del(&self, from_ix: u32, to_ix: u32) {
+        unsafe { crate::del_pages(self.ns, from_ix, to_ix) };
+    }
+
+    fn sync(&self) -> Result<(), String> {
+        let status = retry::with_retry("storage_sync", || unsafe { crate::storage_sync(self.ns) });
+        if status != STORAGE_SYNC_OK {
+            return Err(self.failed(format!("storage_sync returned status {status}")));
+        }
+        Ok(())
+    }
+
+    fn expect_page(&self, ix: u32, expected: Option<&[u8]>) -> Result<(), String> {
+        let page = self.get(ix)?;
+        if page.as_deref() != expected {
+            return Err(format!(
+                "get_page({ix}) returned {}, expected {}",
+                self.describe(page.as_deref()),
+                self.describe(expected),
+            ));
+        }
+        Ok(())
+    }
+
+    fn expect_page_count(&self, expected: u32) -> Result<(), String> {
+        let count = unsafe { crate::page_count(self.ns) };
+        if count != expected {
+            return Err(format!("page_count returned {count}, expected {expected}"));
+        }
+        Ok(())
+    }
+
+    /// The content of version `version` of page `ix`, which differs from all other versions and
+    /// pages. Starts with the index and version, so that wrong pages can be identified.
+    fn page(&self, ix: u32, version: u32) -> Vec<u8> {
+        let mut data = (0..self.page_size)
+            .map(|i| (i as u32 * 31 + ix * 7 + version * 13) as u8)
+            .collect::<Vec<_>>();
+        data[..4].copy_from_slice(&ix.to_le_bytes());
+        data[4..8].copy_from_slice(&version.to_le_bytes());
+        data
+    }
+
+    fn describe(&self, page: Option<&[u8]>) -> String {
+        let page = match page {
+            Some(page) => page,
+            None => return "no page".to_string(),
+        };
+        if page.len() >= 8 {
+            let ix = u32::from_le_bytes(page[..4].try_into().unwrap());
+            let version = u32::from_le_bytes(page[4..8].try_into().unwrap());
+            if page == self.page(ix, version) {
+                return format!("version {version} of page {ix}");
+            }
+        }
+        format!("{} bytes of unknown content", page.len())
+    }
+
+    /// Turns a failed call into an error including the host's message.
+    fn failed(&self, context: String) -> String {
+        host_error(format!("{context} (namespace {})", self.ns)).to_string()
+    }
+}
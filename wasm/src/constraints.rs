@@ -0,0 +1,63 @@
+use serde::Serialize;
+
+use crate::copy::quote;
+use crate::error::{foreign_key_violations, ForeignKeyViolation};
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckOptions {
+    /// The tables to check. Checks all tables if empty.
+    pub tables: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConstraintReport {
+    pub ok: bool,
+    /// The rows violating foreign key constraints (`PRAGMA foreign_key_check`).
+    pub foreign_keys: Vec<ForeignKeyViolation>,
+    /// The problems found by `PRAGMA integrity_check` (e.g. `NOT NULL` or `CHECK` constraint
+    /// violations and index inconsistencies).
+    pub integrity: Vec<String>,
+}
+
+/// Checks the constraints of the given tables without modifying anything, e.g. before committing
+/// a large imported batch with deferred foreign keys.
+pub fn check_constraints(
+    conn: &rusqlite::Connection,
+    options: &CheckOptions,
+) -> Result<ConstraintReport, rusqlite::Error> {
+    let mut report = ConstraintReport {
+        ok: true,
+        foreign_keys: Vec::new(),
+        integrity: Vec::new(),
+    };
+
+    if options.tables.is_empty() {
+        report.foreign_keys = foreign_key_violations(conn, None)?;
+        report.integrity = integrity_check(conn, None)?;
+    } else {
+        for table in &options.tables {
+            report
+                .foreign_keys
+                .extend(foreign_key_violations(conn, Some(table))?);
+            report.integrity.extend(integrity_check(conn, Some(table))?);
+        }
+    }
+
+    report.ok = report.foreign_keys.is_empty() && report.integrity.is_empty();
+    Ok(report)
+}
+
+fn integrity_check(
+    conn: &rusqlite::Connection,
+    table: Option<&str>,
+) -> Result<Vec<String>, rusqlite::Error> {
+    let sql = match table {
+        Some(table) => format!("PRAGMA integrity_check({})", quote(table)),
+        None => "PRAGMA integrity_check".to_string(),
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    rows.filter(|row| !matches!(row.as_deref(), Ok("ok")))
+        .collect()
+}
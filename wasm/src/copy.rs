@@ -0,0 +1,88 @@
+use std::error::Error;
+
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, OptionalExtension};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct CopyReport {
+    pub rows: usize,
+    /// Whether the table did not exist in the destination database and was created.
+    pub created: bool,
+}
+
+/// Copies all rows of `table` from `src` into the table of the same name in `dst`, creating it
+/// (using the schema from `src`) if it doesn't exist yet. The rows are streamed from one database
+/// to the other, and inserted inside a savepoint so that a failure leaves `dst` untouched.
+pub fn copy_table(
+    src: &Connection,
+    dst: &Connection,
+    table: &str,
+) -> Result<CopyReport, Box<dyn Error>> {
+    dst.execute_batch("SAVEPOINT copy_table")?;
+    match copy_rows(src, dst, table) {
+        Ok(report) => {
+            dst.execute_batch("RELEASE copy_table")?;
+            Ok(report)
+        }
+        Err(err) => {
+            dst.execute_batch("ROLLBACK TO copy_table; RELEASE copy_table")
+                .ok();
+            Err(err)
+        }
+    }
+}
+
+fn copy_rows(
+    src: &Connection,
+    dst: &Connection,
+    table: &str,
+) -> Result<CopyReport, Box<dyn Error>> {
+    let mut report = CopyReport {
+        rows: 0,
+        created: false,
+    };
+
+    if table_sql(dst, table)?.is_none() {
+        let sql = table_sql(src, table)?.ok_or_else(|| format!("no such table: {table}"))?;
+        dst.execute_batch(&sql)?;
+        report.created = true;
+    }
+
+    let mut select = src.prepare(&format!("SELECT * FROM {}", quote(table)))?;
+    let columns = select
+        .column_names()
+        .into_iter()
+        .map(quote)
+        .collect::<Vec<_>>();
+    let mut insert = dst.prepare(&format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        quote(table),
+        columns.join(", "),
+        vec!["?"; columns.len()].join(", ")
+    ))?;
+
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let values = (0..columns.len())
+            .map(|i| row.get::<_, Value>(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        insert.execute(params_from_iter(values))?;
+        report.rows += 1;
+    }
+
+    Ok(report)
+}
+
+fn table_sql(conn: &Connection, table: &str) -> Result<Option<String>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?",
+        [table],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn quote(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
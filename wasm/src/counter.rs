@@ -0,0 +1,32 @@
+use rusqlite::{params, Connection};
+
+/// The table backing the counters.
+const TABLE: &str = "_wasm_sqlite_counters";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CounterIncr {
+    pub name: String,
+    /// Defaults to `1`. Can be negative to decrement.
+    #[serde(default = "one")]
+    pub delta: i64,
+}
+
+fn one() -> i64 {
+    1
+}
+
+/// Atomically adds the delta to the counter (starting at `0`) and returns its new value.
+pub fn incr(conn: &Connection, incr: &CounterIncr) -> Result<i64, rusqlite::Error> {
+    conn.prepare_cached(&format!(
+        "CREATE TABLE IF NOT EXISTS {TABLE} (name TEXT PRIMARY KEY, value INTEGER NOT NULL) \
+         WITHOUT ROWID"
+    ))?
+    .execute([])?;
+    conn.prepare_cached(&format!(
+        "INSERT INTO {TABLE} (name, value) VALUES (?, ?) \
+         ON CONFLICT (name) DO UPDATE SET value = value + excluded.value \
+         RETURNING value"
+    ))?
+    .query_row(params![incr.name, incr.delta], |row| row.get(0))
+}
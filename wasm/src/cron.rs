@@ -0,0 +1,160 @@
+//! Cron expressions (`minute hour day-of-month month day-of-week`, in UTC), as understood by Vixie
+//! cron: fields can be `*`, values, ranges (`1-5`), lists (`1,15`) and steps (`*/15`, `0-30/10`),
+//! months and weekdays can be names (`JAN`, `MON`), and weekday `7` is Sunday as well. If both
+//! the day of month and the day of week are restricted, a day matching either one matches.
+
+use std::error::Error;
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// How far ahead to look for the next run. Feb 29 can be eight years apart (e.g. 2096 and 2104).
+const MAX_DAYS: i64 = 8 * 366 + 1;
+
+const MINUTE_MS: i64 = 60 * 1000;
+const DAY_MINUTES: i64 = 24 * 60;
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// Bit sets of the matching values of each field.
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// Whether the day fields are restricted (not starting with `*`).
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Schedule {
+    pub fn parse(expr: &str) -> Result<Self, Box<dyn Error>> {
+        let expr = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expr => expr,
+        };
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        let (minutes, hours, days, months, weekdays) = match fields[..] {
+            [minutes, hours, days, months, weekdays] => (minutes, hours, days, months, weekdays),
+            _ => return Err(format!("expected 5 fields, got {}", fields.len()).into()),
+        };
+
+        let weekdays_bits = parse_field(weekdays, 0, 7, &WEEKDAYS)?;
+        Ok(Schedule {
+            minutes: parse_field(minutes, 0, 59, &[])?,
+            hours: parse_field(hours, 0, 23, &[])? as u32,
+            days: parse_field(days, 1, 31, &[])? as u32,
+            months: parse_field(months, 1, 12, &MONTHS)? as u16,
+            // Fold Sunday as `7` into `0`.
+            weekdays: ((weekdays_bits | weekdays_bits >> 7) & 0x7f) as u8,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+
+    /// The first time (unix epoch milliseconds) strictly after `after` the schedule matches.
+    /// Returns `None` if it doesn't match within the next eight years (e.g. `0 0 30 2 *`).
+    pub fn next_after(&self, after: i64) -> Option<i64> {
+        let start = after.div_euclid(MINUTE_MS) + 1;
+        let first_day = start.div_euclid(DAY_MINUTES);
+        for day in first_day..first_day + MAX_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+
+            let from = if day == first_day {
+                start.rem_euclid(DAY_MINUTES)
+            } else {
+                0
+            };
+            for minute_of_day in from..DAY_MINUTES {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if self.hours & (1 << hour) != 0 && self.minutes & (1 << minute) != 0 {
+                    return Some((day * DAY_MINUTES + minute_of_day) * MINUTE_MS);
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether the schedule matches the day (days since the unix epoch).
+    fn matches_day(&self, day: i64) -> bool {
+        let (month, day_of_month) = civil_from_days(day);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+
+        // 1970-01-01 was a Thursday.
+        let weekday = (day + 4).rem_euclid(7);
+        let day_matches = self.days & (1 << day_of_month) != 0;
+        let weekday_matches = self.weekdays & (1 << weekday) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day_matches || weekday_matches,
+            (true, false) => day_matches,
+            (false, true) => weekday_matches,
+            (false, false) => true,
+        }
+    }
+}
+
+/// Parses a field into a bit set of its values, each of which must be within `min..=max`.
+/// `names` are alternatives for the values starting at `min`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, Box<dyn Error>> {
+    let value = |s: &str| -> Result<u32, Box<dyn Error>> {
+        let value = match names.iter().position(|name| name.eq_ignore_ascii_case(s)) {
+            Some(ix) => min + ix as u32,
+            None => s
+                .parse::<u32>()
+                .map_err(|_| format!("invalid value `{s}` in `{field}`"))?,
+        };
+        if !(min..=max).contains(&value) {
+            return Err(format!("value `{s}` in `{field}` is out of range {min}-{max}").into());
+        }
+        Ok(value)
+    };
+
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(format!("invalid step `{step}` in `{field}`").into()),
+            },
+            None => (part, None),
+        };
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (value(from)?, value(to)?),
+            // `5/15` is short for `5-<max>/15`.
+            None if step.is_some() => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if from > to {
+            return Err(format!("invalid range `{range}` in `{field}`").into());
+        }
+        for value in (from..=to).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// The month (1-12) and day of month (1-31) of the day (days since the unix epoch), see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+fn civil_from_days(day: i64) -> (u32, u32) {
+    let z = day + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day_of_month = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (month as u32, day_of_month as u32)
+}
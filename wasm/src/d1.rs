@@ -0,0 +1,105 @@
+//! Translation of errors into the shape of Cloudflare D1's errors, for hosts replacing D1 with
+//! this module without changing how callers match on error messages (see `set_error_style`).
+
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ErrorReport;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorStyle {
+    /// The module's own error reports.
+    Native,
+    /// Error reports additionally carry a [D1Error].
+    D1,
+}
+
+thread_local! {
+    static STYLE: Cell<ErrorStyle> = Cell::new(ErrorStyle::Native);
+}
+
+pub fn set_style(style: ErrorStyle) {
+    STYLE.with(|current| current.set(style));
+}
+
+/// Statements are cut off after this many characters in [D1Error::sql].
+const SQL_SNIPPET_CHARS: usize = 100;
+
+/// An error as D1 reports it, e.g. `D1_ERROR: no such table: users: SQLITE_ERROR`.
+#[derive(Debug, Serialize)]
+pub struct D1Error {
+    pub message: String,
+    /// The chain of causes, starting with the message without the `D1_ERROR: ` prefix.
+    pub causes: Vec<String>,
+    /// The beginning of the statement that failed, if the error was caused by one.
+    pub sql: Option<String>,
+}
+
+/// Translates the `report` into a [D1Error] if enabled via [set_style]. `sql` is the statement
+/// that failed, if any.
+pub fn translate(report: &ErrorReport, sql: Option<&str>) -> Option<D1Error> {
+    if STYLE.with(Cell::get) != ErrorStyle::D1 {
+        return None;
+    }
+
+    let cause = match report.code {
+        Some(code) => format!("{}: {}", report.message, code_name(code)),
+        None => report.message.clone(),
+    };
+    Some(D1Error {
+        message: format!("D1_ERROR: {cause}"),
+        causes: std::iter::once(cause)
+            .chain(report.causes.iter().cloned())
+            .collect(),
+        sql: sql.map(snippet),
+    })
+}
+
+/// The name of the primary result code of the (extended) SQLite result `code`.
+fn code_name(code: i32) -> &'static str {
+    const NAMES: [&str; 29] = [
+        "SQLITE_OK",
+        "SQLITE_ERROR",
+        "SQLITE_INTERNAL",
+        "SQLITE_PERM",
+        "SQLITE_ABORT",
+        "SQLITE_BUSY",
+        "SQLITE_LOCKED",
+        "SQLITE_NOMEM",
+        "SQLITE_READONLY",
+        "SQLITE_INTERRUPT",
+        "SQLITE_IOERR",
+        "SQLITE_CORRUPT",
+        "SQLITE_NOTFOUND",
+        "SQLITE_FULL",
+        "SQLITE_CANTOPEN",
+        "SQLITE_PROTOCOL",
+        "SQLITE_EMPTY",
+        "SQLITE_SCHEMA",
+        "SQLITE_TOOBIG",
+        "SQLITE_CONSTRAINT",
+        "SQLITE_MISMATCH",
+        "SQLITE_MISUSE",
+        "SQLITE_NOLFS",
+        "SQLITE_AUTH",
+        "SQLITE_FORMAT",
+        "SQLITE_RANGE",
+        "SQLITE_NOTADB",
+        "SQLITE_NOTICE",
+        "SQLITE_WARNING",
+    ];
+    NAMES
+        .get((code & 0xff) as usize)
+        .copied()
+        .unwrap_or("SQLITE_ERROR")
+}
+
+fn snippet(sql: &str) -> String {
+    let sql = sql.trim();
+    match sql.char_indices().nth(SQL_SNIPPET_CHARS) {
+        Some((end, _)) => format!("{}...", &sql[..end]),
+        None => sql.to_string(),
+    }
+}
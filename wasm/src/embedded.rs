@@ -0,0 +1,181 @@
+//! Helpers for embedding the crate natively, i.e. using [crate::PagesVfs] through rusqlite
+//! directly instead of through the wasm exports.
+
+use std::cell::RefCell;
+use std::path::Path;
+
+use rusqlite::Params;
+use serde::de::DeserializeOwned;
+
+use crate::NamedRows;
+
+/// Runs `sql` and deserializes each row into a `T`, with the column names as field names. Values
+/// are mapped the same way as for the JSON results of `conn_query` (e.g. blobs become arrays of
+/// bytes).
+pub fn query_as<T: DeserializeOwned>(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: impl Params,
+) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare(sql)?;
+    let names = stmt
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let rows = NamedRows {
+        names,
+        rows: RefCell::new(stmt.query(params)?),
+    };
+
+    Ok(serde_json::from_value(serde_json::to_value(&rows)?)?)
+}
+
+/// Returns the schema of the database as `CREATE` statements, e.g. to commit a snapshot of the
+/// production schema next to the code for [check_queries].
+pub fn schema_snapshot(conn: &rusqlite::Connection) -> Result<String, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT sql FROM sqlite_master
+         WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\'
+         ORDER BY rowid",
+    )?;
+    let mut snapshot = String::new();
+    for sql in stmt.query_map([], |row| row.get::<_, String>(0))? {
+        snapshot.push_str(&sql?);
+        snapshot.push_str(";\n");
+    }
+    Ok(snapshot)
+}
+
+/// Checks that each query is valid against a schema snapshot (see [schema_snapshot]) by preparing
+/// it on an in-memory database with that schema, without access to the actual database. Meant to
+/// be called from a build script or test (like sqlx's offline mode), so that typos in tables or
+/// columns fail the build instead of the query at the edge. Reports all invalid queries at once.
+pub fn check_queries<'a>(
+    schema: &str,
+    queries: impl IntoIterator<Item = &'a str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = rusqlite::Connection::open_in_memory()?;
+    conn.execute_batch(schema)
+        .map_err(|err| format!("invalid schema snapshot: {err}"))?;
+
+    let errors = queries
+        .into_iter()
+        .filter_map(|sql| match conn.prepare(sql) {
+            Ok(_) => None,
+            Err(err) => Some(format!("`{sql}`: {err}")),
+        })
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("invalid queries:\n{}", errors.join("\n")).into())
+    }
+}
+
+/// Checks the queries of all [query!](crate::query!) invocations in the given Rust source files against a schema
+/// snapshot (see [check_queries]). Meant to be called from the build script of the crate using
+/// [query!](crate::query!), so that invalid queries fail its build:
+///
+/// ```ignore
+/// // build.rs
+/// fn main() {
+///     println!("cargo:rerun-if-changed=schema.sql");
+///     println!("cargo:rerun-if-changed=src/db.rs");
+///     let schema = std::fs::read_to_string("schema.sql").unwrap();
+///     wasm_sqlite::embedded::check_sources(&schema, ["src/db.rs"]).unwrap();
+/// }
+/// ```
+pub fn check_sources<P: AsRef<Path>>(
+    schema: &str,
+    paths: impl IntoIterator<Item = P>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut queries = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+        let literals =
+            query_literals(&source).map_err(|err| format!("{}: {err}", path.display()))?;
+        queries.extend(literals);
+    }
+    check_queries(schema, queries.iter().map(String::as_str))
+}
+
+/// The string literals passed to [query!](crate::query!) in the Rust source.
+fn query_literals(source: &str) -> Result<Vec<String>, String> {
+    let mut queries = Vec::new();
+    let mut rest = source;
+    while let Some(ix) = rest.find("query!(") {
+        // Skip other macros merely ending in `query`, e.g. `my_query!`.
+        let is_other_macro = rest[..ix]
+            .chars()
+            .next_back()
+            .map_or(false, |c| c.is_alphanumeric() || c == '_');
+        rest = &rest[ix + "query!(".len()..];
+        if is_other_macro {
+            continue;
+        }
+
+        let (sql, after) = string_literal(rest.trim_start())
+            .ok_or_else(|| "`query!` expects a string literal".to_string())?;
+        queries.push(sql);
+        rest = after;
+    }
+    Ok(queries)
+}
+
+/// Parses the Rust string literal (`"..."` or `r#"..."#`) at the start of `s` into its value and
+/// the rest of `s`.
+fn string_literal(s: &str) -> Option<(String, &str)> {
+    if let Some(raw) = s.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let body = raw[hashes..].strip_prefix('"')?;
+        let end = format!("\"{}", "#".repeat(hashes));
+        let ix = body.find(&end)?;
+        return Some((body[..ix].to_string(), &body[ix + end.len()..]));
+    }
+
+    let body = s.strip_prefix('"')?;
+    let mut value = String::new();
+    let mut chars = body.char_indices().peekable();
+    while let Some((ix, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &body[ix + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                '0' => value.push('\0'),
+                'x' => {
+                    let hex = chars.by_ref().take(2).map(|(_, c)| c).collect::<String>();
+                    value.push(char::from(u8::from_str_radix(&hex, 16).ok()?));
+                }
+                'u' => {
+                    let hex = chars
+                        .by_ref()
+                        .map(|(_, c)| c)
+                        .skip(1)
+                        .take_while(|c| *c != '}')
+                        .collect::<String>();
+                    value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                // A line continuation skips the line break and the leading whitespace.
+                '\n' => while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {},
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Expands to the SQL string literal and marks it as a query to check against the schema snapshot
+/// at build time (see [embedded::check_sources](crate::embedded::check_sources)), e.g.
+/// `conn.prepare(wasm_sqlite::query!("SELECT name FROM users"))`.
+#[macro_export]
+macro_rules! query {
+    ($sql:literal) => {
+        $sql
+    };
+}
@@ -0,0 +1,217 @@
+use std::error::Error;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::copy::quote;
+use crate::d1::D1Error;
+use crate::limits::LimitError;
+use crate::query::QueryError;
+
+/// Wraps the error of a call that failed while an explicit transaction was open, which was thus
+/// rolled back automatically.
+#[derive(Debug)]
+pub struct TransactionRolledBack {
+    pub error: Box<dyn Error>,
+}
+
+/// Wraps a failed foreign key constraint with the rows violating it.
+#[derive(Debug)]
+pub struct ForeignKeyViolations {
+    pub error: Box<dyn Error>,
+    pub violations: Vec<ForeignKeyViolation>,
+}
+
+/// A row of `PRAGMA foreign_key_check`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ForeignKeyViolation {
+    /// The table containing the row that violates the constraint.
+    pub table: String,
+    /// `None` for `WITHOUT ROWID` tables.
+    pub rowid: Option<i64>,
+    /// The table the row refers to.
+    pub parent: String,
+    /// The index of the violated constraint in `PRAGMA foreign_key_list(table)`.
+    pub fkid: i64,
+}
+
+/// An I/O error of SQLite caused by a failed page store call, together with the host's message.
+#[derive(Debug)]
+pub struct HostError {
+    pub error: Box<dyn Error>,
+    pub message: String,
+}
+
+/// Returned by page store related calls on in-memory connections.
+#[derive(Debug)]
+pub struct NoPageStore;
+
+/// A serializable representation of an error and its causes.
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    pub message: String,
+    pub causes: Vec<String>,
+    /// The extended SQLite result code, if the error originated from SQLite.
+    pub code: Option<i32>,
+    /// Identifies errors raised by this module instead of SQLite (e.g. `not_read_only`).
+    pub kind: Option<&'static str>,
+    /// Whether an open transaction was rolled back because of the error.
+    pub rolled_back: bool,
+    /// The rows violating foreign key constraints if the error is a failed foreign key
+    /// constraint. Only available for deferred constraints (failing on `COMMIT`), as the
+    /// changes of a statement failing an immediate constraint are undone.
+    pub foreign_key_violations: Vec<ForeignKeyViolation>,
+    /// The host's message if the error was caused by a failed page store call.
+    pub host_error: Option<String>,
+    /// The tag of the connection (see `conn_set_tag`).
+    pub tag: Option<String>,
+    /// The error in the shape of D1's errors, if enabled via `set_error_style`.
+    pub d1: Option<D1Error>,
+}
+
+impl ErrorReport {
+    pub fn new(err: &(dyn Error + 'static)) -> Self {
+        let (err, rolled_back) = match err.downcast_ref::<TransactionRolledBack>() {
+            Some(rolled_back) => (rolled_back.error.as_ref(), true),
+            None => (err, false),
+        };
+        let (err, host_error) = match err.downcast_ref::<HostError>() {
+            Some(host) => (host.error.as_ref(), Some(host.message.clone())),
+            None => (err, None),
+        };
+        let (err, foreign_key_violations) = match err.downcast_ref::<ForeignKeyViolations>() {
+            Some(fk) => (fk.error.as_ref(), fk.violations.clone()),
+            None => (err, Vec::new()),
+        };
+
+        let mut report = ErrorReport {
+            message: err.to_string(),
+            causes: Vec::new(),
+            code: None,
+            kind: None,
+            rolled_back,
+            foreign_key_violations,
+            host_error,
+            tag: None,
+            d1: None,
+        };
+
+        let mut current = Some(err);
+        while let Some(err) = current {
+            if let Some(rusqlite::Error::SqliteFailure(failure, _)) =
+                err.downcast_ref::<rusqlite::Error>()
+            {
+                report.code.get_or_insert(failure.extended_code);
+            }
+            if let Some(err) = err.downcast_ref::<QueryError>() {
+                report.kind.get_or_insert(err.kind());
+            }
+            if let Some(err) = err.downcast_ref::<LimitError>() {
+                report.kind.get_or_insert(err.kind());
+            }
+            current = err.source();
+            if let Some(source) = current {
+                report.causes.push(source.to_string());
+            }
+        }
+
+        report
+    }
+}
+
+impl fmt::Display for TransactionRolledBack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (transaction rolled back)", self.error)
+    }
+}
+
+impl Error for TransactionRolledBack {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Whether `err` (or one of its causes) is a failed foreign key constraint.
+pub fn is_foreign_key_error(err: &(dyn Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(rusqlite::Error::SqliteFailure(failure, _)) =
+            err.downcast_ref::<rusqlite::Error>()
+        {
+            if failure.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY {
+                return true;
+            }
+        }
+        current = err.source();
+    }
+    false
+}
+
+/// Whether `err` (or one of its causes) is an I/O error of SQLite.
+pub fn is_io_error(err: &(dyn Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(rusqlite::Error::SqliteFailure(failure, _)) =
+            err.downcast_ref::<rusqlite::Error>()
+        {
+            if failure.code == rusqlite::ErrorCode::SystemIoFailure {
+                return true;
+            }
+        }
+        current = err.source();
+    }
+    false
+}
+
+/// Lists the rows currently violating foreign key constraints, optionally only of `table`.
+pub fn foreign_key_violations(
+    conn: &rusqlite::Connection,
+    table: Option<&str>,
+) -> Result<Vec<ForeignKeyViolation>, rusqlite::Error> {
+    let sql = match table {
+        Some(table) => format!("PRAGMA foreign_key_check({})", quote(table)),
+        None => "PRAGMA foreign_key_check".to_string(),
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    let violations = stmt.query_map([], |row| {
+        Ok(ForeignKeyViolation {
+            table: row.get(0)?,
+            rowid: row.get(1)?,
+            parent: row.get(2)?,
+            fkid: row.get(3)?,
+        })
+    })?;
+    violations.collect()
+}
+
+impl fmt::Display for ForeignKeyViolations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Error for ForeignKeyViolations {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (host: {})", self.error, self.message)
+    }
+}
+
+impl Error for HostError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+impl fmt::Display for NoPageStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("in-memory connection has no page store")
+    }
+}
+
+impl Error for NoPageStore {}
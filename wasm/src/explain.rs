@@ -0,0 +1,78 @@
+//! Opcode-level `EXPLAIN` output of statements (see `Query::explain`), e.g. to debug which
+//! B-trees (and thus pages) a statement opens.
+
+use std::collections::HashMap;
+
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Explain {
+    /// The VDBE program of the statement, one entry per opcode (`EXPLAIN`).
+    Full,
+}
+
+/// An instruction of the VDBE program, see <https://www.sqlite.org/opcode.html>.
+#[derive(Debug, Serialize)]
+pub struct Opcode {
+    pub addr: i64,
+    pub opcode: String,
+    pub p1: i64,
+    pub p2: i64,
+    pub p3: i64,
+    pub p4: Option<String>,
+    pub p5: i64,
+    /// Only available if SQLite was compiled with `SQLITE_ENABLE_EXPLAIN_COMMENTS`.
+    pub comment: Option<String>,
+    /// The table or index whose B-tree the opcode opens (the root page in `p2` of `OpenRead` and
+    /// `OpenWrite` on the main database).
+    pub object: Option<String>,
+}
+
+/// Explains the statement `sql` without running it. The `params` are bound, but only matter
+/// for the program if they are used in e.g. `LIMIT`.
+pub fn explain(
+    conn: &Connection,
+    sql: &str,
+    params: &[JsonValue],
+) -> Result<Vec<Opcode>, rusqlite::Error> {
+    let roots = {
+        let mut stmt =
+            conn.prepare("SELECT rootpage, name FROM sqlite_schema WHERE rootpage > 0")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get(1)?)))?;
+        rows.collect::<Result<HashMap<_, String>, _>>()?
+    };
+
+    let mut stmt = conn.prepare(&format!("EXPLAIN {sql}"))?;
+    let rows = stmt.query_map(params_from_iter(params), |row| {
+        let opcode: String = row.get("opcode")?;
+        let p2 = row.get("p2")?;
+        let p3 = row.get("p3")?;
+        let opens_btree = matches!(opcode.as_str(), "OpenRead" | "OpenWrite" | "ReopenIdx");
+        Ok(Opcode {
+            addr: row.get("addr")?,
+            object: if opens_btree && p3 == 0 {
+                roots.get(&p2).cloned()
+            } else {
+                None
+            },
+            opcode,
+            p1: row.get("p1")?,
+            p2,
+            p3,
+            p4: match row.get::<_, Value>("p4")? {
+                Value::Null => None,
+                Value::Integer(i) => Some(i.to_string()),
+                Value::Real(f) => Some(f.to_string()),
+                Value::Text(s) => Some(s),
+                Value::Blob(b) => Some(String::from_utf8_lossy(&b).into_owned()),
+            },
+            p5: row.get("p5")?,
+            comment: row.get("comment")?,
+        })
+    })?;
+    rows.collect()
+}
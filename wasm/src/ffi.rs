@@ -0,0 +1,164 @@
+//! All raw pointer handling of the exports: turning the pointers and lengths passed by the host
+//! into references, and the memory the host allocates or receives ownership of. Debug builds
+//! validate the pointers before dereferencing them, to turn host bugs into panics instead of
+//! memory corruption.
+
+use std::alloc::Layout;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::ptr::NonNull;
+
+/// Upper bound for payload lengths passed by the host. Larger lengths are most likely garbage
+/// (e.g. a pointer passed as length).
+const MAX_PAYLOAD_LEN: usize = 1 << 30;
+
+thread_local! {
+    /// The layouts of the allocations made via [alloc] that have not been released yet, by
+    /// address. Keeps [dealloc] from relying on the host to pass the layout back correctly.
+    static ALLOCATIONS: RefCell<HashMap<usize, Layout>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the payload of `len` bytes at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must point to `len` initialized bytes that stay alive and unmodified for `'a`.
+pub unsafe fn payload<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        // `ptr` may be null or dangling for empty payloads
+        return &[];
+    }
+    debug_assert!(!ptr.is_null(), "payload pointer is null");
+    debug_assert!(
+        len <= MAX_PAYLOAD_LEN,
+        "payload length {len} exceeds {MAX_PAYLOAD_LEN}"
+    );
+    std::slice::from_raw_parts(ptr, len)
+}
+
+/// Returns the object `ptr` points to (e.g. a connection handle). Panics if `ptr` is null.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [Box::into_raw] and not been freed, and no other reference to
+/// the object must be alive for `'a`.
+pub unsafe fn as_mut<'a, T>(ptr: *mut T) -> &'a mut T {
+    debug_assert!(
+        ptr as usize % std::mem::align_of::<T>() == 0,
+        "misaligned pointer {ptr:p}"
+    );
+    ptr.as_mut().expect("null pointer")
+}
+
+/// Takes back ownership of an object previously handed to the host via [Box::into_raw].
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [Box::into_raw] and not been freed.
+pub unsafe fn take<T>(ptr: *mut T) -> Box<T> {
+    debug_assert!(!ptr.is_null(), "null pointer");
+    debug_assert!(
+        ptr as usize % std::mem::align_of::<T>() == 0,
+        "misaligned pointer {ptr:p}"
+    );
+    Box::from_raw(ptr)
+}
+
+/// A string handed to the host, which reads it via `ptr` and `len` and releases it via
+/// [query_result_drop].
+#[repr(C)]
+pub struct JsonString {
+    ptr: NonNull<u8>,
+    len: usize,
+    cap: usize,
+}
+
+impl JsonString {
+    pub fn new(json: String) -> Self {
+        let mut v = std::mem::ManuallyDrop::new(json);
+        Self {
+            // a `String`'s pointer is never null, even if it didn't allocate
+            ptr: unsafe { NonNull::new_unchecked(v.as_mut_ptr()) },
+            len: v.len(),
+            cap: v.capacity(),
+        }
+    }
+
+    pub fn into_raw(self) -> *mut Self {
+        Box::into_raw(Box::new(self))
+    }
+}
+
+impl Drop for JsonString {
+    fn drop(&mut self) {
+        unsafe {
+            String::from_raw_parts(self.ptr.as_ptr(), self.len, self.cap);
+        }
+    }
+}
+
+/// Allocates a buffer of `size` bytes for the host to write a payload into. Empty allocations
+/// return a dangling pointer, as allocating zero bytes is undefined behaviour. Returns null if the
+/// allocation failed.
+///
+/// # Safety
+///
+/// The memory must be released via [dealloc].
+#[no_mangle]
+pub unsafe fn alloc(size: usize) -> *mut u8 {
+    let layout = match Layout::array::<u8>(size) {
+        Ok(layout) if size > 0 => layout,
+        Ok(_) => return NonNull::dangling().as_ptr(),
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let ptr = std::alloc::alloc(layout);
+    if !ptr.is_null() {
+        ALLOCATIONS.with(|allocations| allocations.borrow_mut().insert(ptr as usize, layout));
+    }
+    ptr
+}
+
+/// Releases memory allocated via [alloc]. Pointers that are unknown (e.g. already released) are
+/// ignored in release builds, and panic in debug builds.
+///
+/// # Safety
+///
+/// `ptr` must not be used anymore afterwards.
+#[no_mangle]
+pub unsafe fn dealloc(ptr: *mut u8) {
+    if ptr == NonNull::dangling().as_ptr() {
+        return;
+    }
+    match ALLOCATIONS.with(|allocations| allocations.borrow_mut().remove(&(ptr as usize))) {
+        Some(layout) => std::alloc::dealloc(ptr, layout),
+        None => {
+            debug_assert!(false, "dealloc of unknown pointer {ptr:p}");
+            tracing::error!(ptr = ?ptr, "dealloc of unknown pointer");
+        }
+    }
+}
+
+/// Releases a [JsonString] returned by any of the exports.
+///
+/// # Safety
+///
+/// `json` must have been returned by an export and not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn query_result_drop(json: *mut JsonString) {
+    drop(take(json));
+}
+
+/// Releases an error message returned by `conn_last_error`. Does nothing if `s` is null.
+///
+/// # Safety
+///
+/// `s` must be null or have been returned by `conn_last_error` and not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn conn_last_error_drop(s: *mut c_char) {
+    if s.is_null() {
+        return;
+    }
+    drop(CString::from_raw(s));
+}
@@ -0,0 +1,266 @@
+//! Normalization of SQL into its shape, so that metrics (slow queries, spans) can be aggregated by
+//! query instead of by literal text. Literals and placeholders are replaced by `?`, comments are
+//! removed, keywords and unquoted identifiers are lowercased and whitespace is normalized, e.g.
+//! `SELECT * FROM users WHERE id IN (1, 2, 3) -- hot` becomes
+//! `select * from users where id in (?)`.
+
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Fingerprint {
+    pub normalized: String,
+    /// A hash of the normalized SQL (16 hex digits), stable across versions of the module.
+    pub fingerprint: String,
+}
+
+impl Fingerprint {
+    pub fn of(sql: &str) -> Self {
+        let normalized = normalize(sql);
+        Self {
+            fingerprint: format!("{:016x}", fnv1a(normalized.as_bytes())),
+            normalized,
+        }
+    }
+}
+
+/// The fingerprint of `sql` (see [Fingerprint::fingerprint]).
+pub fn fingerprint(sql: &str) -> String {
+    Fingerprint::of(sql).fingerprint
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Word,
+    Literal,
+    Operator,
+    Open,
+    Close,
+    Comma,
+    Dot,
+}
+
+pub fn normalize(sql: &str) -> String {
+    let chars = sql.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(sql.len());
+    let mut last: Option<Token> = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let start = i;
+        let token = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '-' if next == Some('-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if next == Some('*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 2;
+                continue;
+            }
+            ';' => {
+                i += 1;
+                continue;
+            }
+            '\'' => {
+                i = skip_quoted(&chars, i, '\'');
+                Token::Literal
+            }
+            'x' | 'X' if next == Some('\'') => {
+                i = skip_quoted(&chars, i + 1, '\'');
+                Token::Literal
+            }
+            '"' | '`' => {
+                i = skip_quoted(&chars, i, c);
+                separate(&mut out, last, Token::Word);
+                out.extend(&chars[start..i.min(chars.len())]);
+                last = Some(Token::Word);
+                continue;
+            }
+            '[' => {
+                while i < chars.len() && chars[i] != ']' {
+                    i += 1;
+                }
+                i += 1;
+                separate(&mut out, last, Token::Word);
+                out.extend(&chars[start..i.min(chars.len())]);
+                last = Some(Token::Word);
+                continue;
+            }
+            '0'..='9' => {
+                i = skip_number(&chars, i);
+                Token::Literal
+            }
+            '.' if next.map_or(false, |c| c.is_ascii_digit()) => {
+                i = skip_number(&chars, i);
+                Token::Literal
+            }
+            // A sign directly in front of a number is part of the literal, unless it is a binary
+            // operator (i.e. follows an operand).
+            '-' | '+'
+                if next.map_or(false, |c| c.is_ascii_digit() || c == '.')
+                    && !matches!(last, Some(Token::Word | Token::Literal | Token::Close)) =>
+            {
+                i = skip_number(&chars, i + 1);
+                Token::Literal
+            }
+            '?' => {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                Token::Literal
+            }
+            ':' | '@' | '$' if next.map_or(false, is_word_char) => {
+                i += 1;
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                Token::Literal
+            }
+            _ if is_word_char(c) => {
+                while i < chars.len() && (is_word_char(chars[i]) || chars[i] == '$') {
+                    i += 1;
+                }
+                separate(&mut out, last, Token::Word);
+                out.extend(chars[start..i].iter().flat_map(|c| c.to_lowercase()));
+                last = Some(Token::Word);
+                continue;
+            }
+            '(' => {
+                i += 1;
+                Token::Open
+            }
+            ')' => {
+                i += 1;
+                Token::Close
+            }
+            ',' => {
+                i += 1;
+                Token::Comma
+            }
+            '.' => {
+                i += 1;
+                Token::Dot
+            }
+            '<' | '>' | '=' | '!' | '|' => {
+                while i < chars.len() && matches!(chars[i], '<' | '>' | '=' | '!' | '|') {
+                    i += 1;
+                }
+                Token::Operator
+            }
+            '-' if next == Some('>') => {
+                i += 2;
+                if chars.get(i) == Some(&'>') {
+                    i += 1;
+                }
+                Token::Operator
+            }
+            _ => {
+                i += 1;
+                Token::Operator
+            }
+        };
+
+        separate(&mut out, last, token);
+        if token == Token::Literal {
+            out.push('?');
+        } else {
+            out.extend(&chars[start..i]);
+        }
+        last = Some(token);
+    }
+
+    collapse_lists(&out)
+}
+
+/// Appends the separator between the `last` token and the next one.
+fn separate(out: &mut String, last: Option<Token>, next: Token) {
+    let last = match last {
+        Some(last) => last,
+        None => return,
+    };
+    let glued = matches!(last, Token::Open | Token::Dot)
+        || matches!(next, Token::Close | Token::Comma | Token::Dot);
+    if !glued {
+        out.push(' ');
+    }
+}
+
+/// Collapses lists of placeholders, e.g. `in (?, ?, ?)` into `in (?)`, so that lists of
+/// different lengths share a fingerprint.
+fn collapse_lists(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut rest = sql;
+    while let Some(pos) = rest.find("(?, ?") {
+        out.push_str(&rest[..pos + 2]);
+        rest = &rest[pos + 2..];
+        let mut end = 0;
+        while rest[end..].starts_with(", ?") {
+            end += 3;
+        }
+        if rest[end..].starts_with(')') {
+            rest = &rest[end..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || !c.is_ascii()
+}
+
+/// The index after the string or identifier quoted with `quote` starting at `i`. Doubled quotes
+/// are escapes.
+fn skip_quoted(chars: &[char], mut i: usize, quote: char) -> usize {
+    i += 1;
+    while i < chars.len() {
+        if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// The index after the numeric literal starting at `i` (including hex literals and exponents).
+fn skip_number(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() {
+        let c = chars[i];
+        if (c == 'e' || c == 'E')
+            && matches!(chars.get(i + 1), Some('+' | '-'))
+            && chars
+                .get(i - 1)
+                .map_or(false, |c| c.is_ascii_digit() || *c == '.')
+        {
+            i += 2;
+        } else if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+/// The 64-bit FNV-1a hash, which (unlike `DefaultHasher`) is stable across Rust versions.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
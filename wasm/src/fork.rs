@@ -0,0 +1,48 @@
+//! Branches of a database (see `conn_fork`), e.g. for preview environments or to test a migration
+//! against a copy of the production data. A branch is a copy of the database in its own page
+//! store namespace, made with SQLite's backup API. On a content-addressed page store (e.g.
+//! `ContentAddressedVfs` of the JS package), the copy only writes the page map of the namespace,
+//! as all pages already exist, so branches are copy-on-write.
+
+use serde::Serialize;
+
+use crate::backup::{Backup, StepResult};
+use crate::namespace::Namespace;
+use crate::options::ConnectionOptions;
+use crate::Connection;
+
+#[derive(Debug, Serialize)]
+pub struct ForkReport {
+    /// The VFS to open the branch with (`ConnectionOptions::vfs`).
+    pub vfs: &'static str,
+    pub namespace: u32,
+    pub page_count: usize,
+}
+
+/// Copies the database of `src` into the (empty) branch namespace `target`.
+pub fn fork(src: &Connection, target: u32) -> Result<ForkReport, Box<dyn std::error::Error>> {
+    let namespace = Namespace::branch(target)?;
+    let _span = tracing::info_span!("fork", vfs = namespace.vfs).entered();
+    if namespace.page_count() > 0 {
+        return Err(format!(
+            "the page store namespace of vfs `{}` must be empty to fork into it",
+            namespace.vfs
+        )
+        .into());
+    }
+
+    let dst = crate::open(namespace, &ConnectionOptions::default())?;
+    let mut dst = Connection::new(dst, Some(namespace));
+    let mut backup = Backup::start(src, &mut dst)?;
+    let result = backup.step(-1);
+    backup.finish()?;
+    if let StepResult::More = result? {
+        return Err("the database is locked by another connection".into());
+    }
+
+    Ok(ForkReport {
+        vfs: namespace.vfs,
+        namespace: namespace.id,
+        page_count: namespace.page_count(),
+    })
+}
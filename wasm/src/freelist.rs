@@ -0,0 +1,88 @@
+//! Reporting and reclaiming of free pages. SQLite keeps the pages freed by deletes on its
+//! freelist and reuses them for new data before growing the database, so the host's page store
+//! only grows once the freelist is empty. Free pages are only deleted from the page store by a
+//! `VACUUM`, at every commit with `auto_vacuum = FULL`, or by [reclaim] with
+//! `auto_vacuum = INCREMENTAL`.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoVacuum {
+    None,
+    Full,
+    Incremental,
+}
+
+impl AutoVacuum {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AutoVacuum::None => "NONE",
+            AutoVacuum::Full => "FULL",
+            AutoVacuum::Incremental => "INCREMENTAL",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FreelistInfo {
+    pub page_count: u32,
+    /// The number of pages on the freelist, which are reused before the database grows.
+    pub freelist_count: u32,
+    pub auto_vacuum: AutoVacuum,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReclaimOptions {
+    /// The maximum number of pages to reclaim. `None` (or `0`) reclaims all free pages.
+    pub max_pages: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReclaimReport {
+    /// The number of pages deleted from the page store.
+    pub reclaimed_pages: u32,
+    #[serde(flatten)]
+    pub info: FreelistInfo,
+}
+
+pub fn info(conn: &rusqlite::Connection) -> Result<FreelistInfo, rusqlite::Error> {
+    let pragma = |name: &str| conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get(0));
+    let auto_vacuum = match pragma("auto_vacuum")? {
+        1 => AutoVacuum::Full,
+        2 => AutoVacuum::Incremental,
+        _ => AutoVacuum::None,
+    };
+    Ok(FreelistInfo {
+        page_count: pragma("page_count")?,
+        freelist_count: pragma("freelist_count")?,
+        auto_vacuum,
+    })
+}
+
+/// Moves free pages to the end of the database and deletes them from the page store
+/// (`PRAGMA incremental_vacuum`). Only databases with `auto_vacuum = INCREMENTAL` keep free pages
+/// around that can be reclaimed this way: with `FULL`, they are already reclaimed at every commit,
+/// and with `NONE`, only a `VACUUM` (which rewrites the whole database) can reclaim them.
+pub fn reclaim(
+    conn: &rusqlite::Connection,
+    options: &ReclaimOptions,
+) -> Result<ReclaimReport, rusqlite::Error> {
+    let before = info(conn)?;
+    if before.auto_vacuum == AutoVacuum::Incremental && before.freelist_count > 0 {
+        // The pragma frees one page per step, so it has to be stepped until it is done.
+        let mut stmt = conn.prepare(&format!(
+            "PRAGMA incremental_vacuum({})",
+            options.max_pages.unwrap_or(0)
+        ))?;
+        let mut rows = stmt.query([])?;
+        while rows.next()?.is_some() {}
+    }
+
+    let info = info(conn)?;
+    Ok(ReclaimReport {
+        reclaimed_pages: before.page_count.saturating_sub(info.page_count),
+        info,
+    })
+}
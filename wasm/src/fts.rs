@@ -0,0 +1,124 @@
+use rusqlite::Connection;
+use serde_json::Value as JsonValue;
+
+use crate::copy::quote;
+use crate::query::Query;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FtsIndex {
+    /// The (rowid) table to index.
+    pub table: String,
+    /// The text columns to index.
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FtsSearch {
+    pub table: String,
+    /// An FTS5 query, e.g. `sqlite AND (vfs OR wasm)`.
+    pub query: String,
+    #[serde(default)]
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+/// The name of the full-text index of `table`.
+fn index_name(table: &str) -> String {
+    format!("{table}_fts")
+}
+
+/// Creates an external-content FTS5 index over the columns of `table` (named `<table>_fts`), the
+/// triggers keeping it in sync with the table, and indexes the existing rows. Does nothing if
+/// the index already exists.
+pub fn create_index(conn: &Connection, index: &FtsIndex) -> Result<(), Box<dyn std::error::Error>> {
+    if index.columns.is_empty() {
+        return Err("full-text index requires at least one column".into());
+    }
+
+    let table = quote(&index.table);
+    let fts = quote(&index_name(&index.table));
+    let columns = index
+        .columns
+        .iter()
+        .map(|column| quote(column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let values = |prefix: &str| {
+        index
+            .columns
+            .iter()
+            .map(|column| format!("{prefix}.{}", quote(column)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let (new, old) = (values("new"), values("old"));
+    let trigger = |suffix: &str| quote(&format!("{}_{suffix}", index_name(&index.table)));
+
+    let exists = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?")?
+        .exists([index_name(&index.table)])?;
+    if exists {
+        return Ok(());
+    }
+
+    conn.execute_batch(&format!(
+        "SAVEPOINT fts_create_index;
+         CREATE VIRTUAL TABLE {fts} USING fts5({columns}, content={content});
+         CREATE TRIGGER {ai} AFTER INSERT ON {table} BEGIN
+           INSERT INTO {fts} (rowid, {columns}) VALUES (new.rowid, {new});
+         END;
+         CREATE TRIGGER {ad} AFTER DELETE ON {table} BEGIN
+           INSERT INTO {fts} ({fts}, rowid, {columns}) VALUES ('delete', old.rowid, {old});
+         END;
+         CREATE TRIGGER {au} AFTER UPDATE ON {table} BEGIN
+           INSERT INTO {fts} ({fts}, rowid, {columns}) VALUES ('delete', old.rowid, {old});
+           INSERT INTO {fts} (rowid, {columns}) VALUES (new.rowid, {new});
+         END;
+         INSERT INTO {fts} ({fts}) VALUES ('rebuild');
+         RELEASE fts_create_index;",
+        content = literal(&index.table),
+        ai = trigger("ai"),
+        ad = trigger("ad"),
+        au = trigger("au"),
+    ))
+    .map_err(|err| {
+        conn.execute_batch("ROLLBACK TO fts_create_index; RELEASE fts_create_index")
+            .ok();
+        err
+    })?;
+
+    Ok(())
+}
+
+/// The query returning the rows of the table matching the search, best matches (by bm25) first.
+/// Each row has an additional `rank` column (lower is better).
+pub fn search_query(search: &FtsSearch) -> Query {
+    let table = quote(&search.table);
+    let fts = quote(&index_name(&search.table));
+    Query {
+        sql: format!(
+            "SELECT {table}.*, bm25({fts}) AS rank FROM {fts} \
+             JOIN {table} ON {table}.rowid = {fts}.rowid \
+             WHERE {fts} MATCH ?1 ORDER BY rank LIMIT ?2 OFFSET ?3"
+        ),
+        params: vec![
+            JsonValue::from(search.query.as_str()),
+            JsonValue::from(search.limit.map_or(-1, i64::from)),
+            JsonValue::from(search.offset),
+        ],
+        strict: false,
+        meta: false,
+        readonly: true,
+        spill: false,
+        count_only: false,
+        explain: None,
+        as_of: None,
+    }
+}
+
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
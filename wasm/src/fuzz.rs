@@ -0,0 +1,81 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`, which exercise the JSON boundary of the
+//! exports natively (the host imports are provided by the fuzz crate). Only compiled when fuzzing
+//! (`cargo fuzz` sets `--cfg fuzzing`).
+
+use std::cell::RefCell;
+
+use rusqlite::params_from_iter;
+use rusqlite::types::Value;
+
+use crate::copy::quote;
+use crate::query::Query;
+use crate::{open_memory, Connection, NamedRows};
+
+/// Opens a fresh in-memory connection, which cannot attach (and thereby create) database files.
+fn open() -> Connection {
+    let conn = Connection::new(open_memory().expect("open in-memory connection"), None);
+    unsafe {
+        rusqlite::ffi::sqlite3_limit(
+            conn.conn().handle(),
+            rusqlite::ffi::SQLITE_LIMIT_ATTACHED,
+            0,
+        )
+    };
+    conn
+}
+
+/// Feeds `data` as the payload of `conn_execute` and `conn_query`. Errors are expected for most
+/// inputs, only panics and crashes are findings.
+pub fn query_payload(data: &[u8]) {
+    let query = match Query::from_json(data) {
+        Ok(query) => query,
+        Err(_) => return,
+    };
+
+    let mut conn = open();
+    conn.execute(&query).ok();
+    if let Ok(json) = conn.query(&query) {
+        serde_json::from_str::<serde_json::Value>(&json).expect("query result is not valid JSON");
+    }
+}
+
+/// Serializes a single row with the given column names and values the way query results are
+/// serialized, and checks that the result is valid JSON.
+pub fn named_row(columns: &[(String, Value)]) {
+    if columns.is_empty() || columns.len() > 100 {
+        return;
+    }
+
+    let conn = open();
+    let sql = format!(
+        "SELECT {}",
+        columns
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| format!("?{} AS {}", i + 1, quote(name)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    // names containing NUL bytes cannot be prepared
+    let mut stmt = match conn.conn().prepare(&sql) {
+        Ok(stmt) => stmt,
+        Err(_) => return,
+    };
+    let names = stmt
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let rows = stmt
+        .query(params_from_iter(columns.iter().map(|(_, value)| value)))
+        .expect("failed to bind values");
+    let rows = NamedRows {
+        names,
+        rows: RefCell::new(rows),
+    };
+
+    let json = serde_json::to_string(&rows).expect("failed to serialize row");
+    let rows = serde_json::from_str::<Vec<serde_json::Map<String, serde_json::Value>>>(&json)
+        .expect("row is not a valid JSON object");
+    assert_eq!(rows.len(), 1);
+}
@@ -0,0 +1,168 @@
+//! Geospatial SQL functions and a radius search on top of R-tree indexes.
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+use crate::copy::quote;
+
+/// The mean earth radius in meters.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WithinRadius {
+    /// An R-tree table with the columns `(id, min_lat, max_lat, min_lon, max_lon)`. Points are
+    /// stored with the same min and max values.
+    pub rtree: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// The radius in meters.
+    pub radius: f64,
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeoMatch {
+    pub id: i64,
+    /// The distance in meters (to the center of the entry's bounding box).
+    pub distance: f64,
+}
+
+/// Registers the `haversine(lat1, lon1, lat2, lon2)`, `geohash_encode(lat, lon, precision)` and
+/// `geohash_decode(hash)` SQL functions.
+pub fn register(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("haversine", 4, flags, |ctx| {
+        Ok(haversine(
+            ctx.get(0)?,
+            ctx.get(1)?,
+            ctx.get(2)?,
+            ctx.get(3)?,
+        ))
+    })?;
+    conn.create_scalar_function("geohash_encode", 3, flags, |ctx| {
+        let precision: u32 = ctx.get(2)?;
+        if !(1..=12).contains(&precision) {
+            return Err(rusqlite::Error::UserFunctionError(
+                "geohash precision must be between 1 and 12".into(),
+            ));
+        }
+        Ok(geohash_encode(ctx.get(0)?, ctx.get(1)?, precision as usize))
+    })?;
+    // Returns the center of the geohash cell as a JSON array `[lat, lon]`.
+    conn.create_scalar_function("geohash_decode", 1, flags, |ctx| {
+        let hash: String = ctx.get(0)?;
+        let (lat, lon) = geohash_decode(&hash).ok_or_else(|| {
+            rusqlite::Error::UserFunctionError(format!("invalid geohash `{hash}`").into())
+        })?;
+        Ok(format!("[{lat},{lon}]"))
+    })?;
+    Ok(())
+}
+
+/// Finds the entries of the R-tree within the radius, nearest first. The R-tree narrows the
+/// search down to the bounding box of the circle (not wrapping around the antimeridian), the
+/// exact distance is then computed with [haversine].
+pub fn within_radius(
+    conn: &Connection,
+    search: &WithinRadius,
+) -> Result<Vec<GeoMatch>, rusqlite::Error> {
+    let dlat = (search.radius / EARTH_RADIUS).to_degrees();
+    let dlon = (dlat / search.lat.to_radians().cos().max(f64::EPSILON)).min(180.0);
+
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT id, haversine(?1, ?2, (min_lat + max_lat) / 2, (min_lon + max_lon) / 2) AS distance
+         FROM {}
+         WHERE max_lat >= ?3 AND min_lat <= ?4 AND max_lon >= ?5 AND min_lon <= ?6
+           AND distance <= ?7
+         ORDER BY distance LIMIT ?8",
+        quote(&search.rtree)
+    ))?;
+    let matches = stmt.query_map(
+        params![
+            search.lat,
+            search.lon,
+            search.lat - dlat,
+            search.lat + dlat,
+            search.lon - dlon,
+            search.lon + dlon,
+            search.radius,
+            search.limit.map_or(-1, i64::from),
+        ],
+        |row| {
+            Ok(GeoMatch {
+                id: row.get(0)?,
+                distance: row.get(1)?,
+            })
+        },
+    )?;
+    matches.collect()
+}
+
+/// The great-circle distance between two points in meters.
+pub fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+fn geohash_encode(lat: f64, lon: f64, precision: usize) -> String {
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+    while hash.len() < precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let (range, value) = if even {
+                (&mut lon_range, lon)
+            } else {
+                (&mut lat_range, lat)
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+        hash.push(GEOHASH_ALPHABET[index] as char);
+    }
+    hash
+}
+
+fn geohash_decode(hash: &str) -> Option<(f64, f64)> {
+    if hash.is_empty() {
+        return None;
+    }
+
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut even = true;
+    for c in hash.bytes() {
+        let index = GEOHASH_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_lowercase())?;
+        for bit in (0..5).rev() {
+            let range: &mut (f64, f64) = if even { &mut lon_range } else { &mut lat_range };
+            let mid = (range.0 + range.1) / 2.0;
+            if index & (1 << bit) != 0 {
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+    }
+    Some((
+        (lat_range.0 + lat_range.1) / 2.0,
+        (lon_range.0 + lon_range.1) / 2.0,
+    ))
+}
@@ -0,0 +1,145 @@
+//! Opt-in row history of tables ("time travel"): every version of a row is kept in a shadow table,
+//! so that deleted or overwritten rows can still be queried as of an earlier time (see
+//! `Query::as_of`), e.g. for audit logs or undo. Timestamps are unix epoch milliseconds.
+
+use rusqlite::Connection;
+
+use crate::copy::quote;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnableHistory {
+    /// The (rowid) table to record the history of.
+    pub table: String,
+}
+
+/// The current time in unix epoch milliseconds, as SQL. `'now'` is the same for all changes made
+/// by a single statement.
+pub const NOW: &str = "CAST(round((julianday('now') - 2440587.5) * 86400000) AS INTEGER)";
+
+/// The columns of the history table that aren't columns of the table itself.
+const HISTORY_COLUMNS: [&str; 3] = ["_rowid", "_valid_from", "_valid_to"];
+
+/// The name of the history table of `table`.
+fn history_name(table: &str) -> String {
+    format!("{table}_history")
+}
+
+/// Creates the history table of `table` (named `<table>_history`) and the triggers recording
+/// each change to it. Besides the columns of the table, each version has the rowid of its row
+/// (`_rowid`) and the time range it was current in (`_valid_from` inclusive, `_valid_to`
+/// exclusive, `NULL` while it still is). Existing rows are recorded as current since now. Does
+/// nothing if the history already exists; columns added to the table afterwards aren't recorded.
+pub fn enable(conn: &Connection, enable: &EnableHistory) -> Result<(), Box<dyn std::error::Error>> {
+    let names = columns(conn, &enable.table)?;
+    if names.is_empty() {
+        return Err(format!("no such table: {}", enable.table).into());
+    }
+
+    let table = quote(&enable.table);
+    let history = quote(&history_name(&enable.table));
+    let columns = names
+        .iter()
+        .map(|column| quote(column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let new = names
+        .iter()
+        .map(|column| format!("new.{}", quote(column)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let name = |suffix: &str| quote(&format!("{}_{suffix}", history_name(&enable.table)));
+
+    let exists = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?")?
+        .exists([history_name(&enable.table)])?;
+    if exists {
+        return Ok(());
+    }
+
+    // The insert trigger closes the current version of the rowid as well, as rows replaced by
+    // `INSERT OR REPLACE` don't fire the delete trigger (unless `recursive_triggers` is enabled).
+    conn.execute_batch(&format!(
+        "SAVEPOINT enable_history;
+         CREATE TABLE {history} (
+           _rowid INTEGER NOT NULL, _valid_from INTEGER NOT NULL, _valid_to INTEGER, {columns}
+         );
+         CREATE INDEX {index} ON {history} (_rowid, _valid_to);
+         CREATE TRIGGER {ai} AFTER INSERT ON {table} BEGIN
+           UPDATE {history} SET _valid_to = {NOW} WHERE _rowid = new.rowid AND _valid_to IS NULL;
+           INSERT INTO {history} (_rowid, _valid_from, {columns})
+             VALUES (new.rowid, {NOW}, {new});
+         END;
+         CREATE TRIGGER {au} AFTER UPDATE ON {table} BEGIN
+           UPDATE {history} SET _valid_to = {NOW} WHERE _rowid = old.rowid AND _valid_to IS NULL;
+           INSERT INTO {history} (_rowid, _valid_from, {columns})
+             VALUES (new.rowid, {NOW}, {new});
+         END;
+         CREATE TRIGGER {ad} AFTER DELETE ON {table} BEGIN
+           UPDATE {history} SET _valid_to = {NOW} WHERE _rowid = old.rowid AND _valid_to IS NULL;
+         END;
+         INSERT INTO {history} (_rowid, _valid_from, {columns})
+           SELECT rowid, {NOW}, {columns} FROM {table};
+         RELEASE enable_history;",
+        index = name("rowid"),
+        ai = name("ai"),
+        au = name("au"),
+        ad = name("ad"),
+    ))
+    .map_err(|err| {
+        conn.execute_batch("ROLLBACK TO enable_history; RELEASE enable_history")
+            .ok();
+        err
+    })?;
+
+    Ok(())
+}
+
+/// Shadows each table with a history by a temporary view (of the same name) of its rows as of
+/// `time`, so that unqualified references to the table read the past. Returns the names of the
+/// views, to be passed to [drop_views] once done.
+pub fn create_views(conn: &Connection, time: i64) -> Result<Vec<String>, rusqlite::Error> {
+    let tables = conn
+        .prepare(
+            "SELECT tbl_name FROM sqlite_master
+             WHERE type = 'trigger' AND name = tbl_name || '_history_ai'",
+        )?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut views = Vec::with_capacity(tables.len());
+    for table in tables {
+        let history = history_name(&table);
+        let columns = columns(conn, &history)?
+            .iter()
+            .filter(|column| !HISTORY_COLUMNS.contains(&column.as_str()))
+            .map(|column| quote(column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let created = conn.execute_batch(&format!(
+            "CREATE TEMP VIEW {} AS SELECT {columns} FROM main.{}
+             WHERE _valid_from <= {time} AND (_valid_to IS NULL OR _valid_to > {time})",
+            quote(&table),
+            quote(&history),
+        ));
+        if let Err(err) = created {
+            drop_views(conn, &views).ok();
+            return Err(err);
+        }
+        views.push(table);
+    }
+    Ok(views)
+}
+
+pub fn drop_views(conn: &Connection, views: &[String]) -> Result<(), rusqlite::Error> {
+    for view in views {
+        conn.execute_batch(&format!("DROP VIEW IF EXISTS temp.{}", quote(view)))?;
+    }
+    Ok(())
+}
+
+pub fn columns(conn: &Connection, table: &str) -> Result<Vec<String>, rusqlite::Error> {
+    conn.prepare("SELECT name FROM pragma_table_info(?)")?
+        .query_map([table], |row| row.get(0))?
+        .collect()
+}
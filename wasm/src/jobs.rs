@@ -0,0 +1,152 @@
+//! Scheduled jobs: named cron schedules (see [crate::cron]) with a payload, stored in
+//! `_wasm_sqlite_jobs`, so that alarm handlers can ask the database which jobs are due (and when
+//! to set the next alarm) instead of evaluating cron expressions themselves.
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use crate::cron::Schedule;
+use crate::history::NOW;
+
+/// The table backing the scheduled jobs. Payloads are stored as JSON text.
+const TABLE: &str = "_wasm_sqlite_jobs";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobSchedule {
+    pub name: String,
+    pub cron: String,
+    #[serde(default)]
+    pub payload: JsonValue,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobsDue {
+    /// The current time in unix epoch milliseconds.
+    pub now: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledJob {
+    pub name: String,
+    /// The next time the job is due, in unix epoch milliseconds.
+    pub next_run: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DueJob {
+    pub name: String,
+    pub payload: JsonValue,
+    /// The time the job was due (the first one, if it missed several runs).
+    pub scheduled_for: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DueJobs {
+    pub jobs: Vec<DueJob>,
+    /// The next time any job is due (e.g. to set the next alarm to), `None` without jobs.
+    pub next_run: Option<i64>,
+}
+
+/// Creates or replaces the job `name`, due next at the first time the cron expression matches
+/// after now.
+pub fn schedule(
+    conn: &Connection,
+    job: &JobSchedule,
+) -> Result<ScheduledJob, Box<dyn std::error::Error>> {
+    let schedule = Schedule::parse(&job.cron)
+        .map_err(|err| format!("invalid cron expression `{}`: {err}", job.cron))?;
+    let now: i64 = conn.query_row(&format!("SELECT {NOW}"), [], |row| row.get(0))?;
+    let next_run = schedule
+        .next_after(now)
+        .ok_or_else(|| format!("cron expression `{}` never matches", job.cron))?;
+
+    create_table(conn)?;
+    conn.prepare_cached(&format!(
+        "INSERT OR REPLACE INTO {TABLE} (name, cron, payload, next_run) VALUES (?, ?, ?, ?)"
+    ))?
+    .execute(params![
+        job.name,
+        job.cron,
+        serde_json::to_string(&job.payload)?,
+        next_run
+    ])?;
+
+    Ok(ScheduledJob {
+        name: job.name.clone(),
+        next_run,
+    })
+}
+
+/// Returns the jobs due at `now` and moves each of them to its next run after `now` in a single
+/// savepoint, so that a due job is returned once, even if it missed several runs.
+pub fn due(conn: &Connection, due: &JobsDue) -> Result<DueJobs, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    conn.execute_batch("SAVEPOINT jobs_due")?;
+    match take_due(conn, due.now) {
+        Ok(jobs) => {
+            conn.execute_batch("RELEASE jobs_due")?;
+            Ok(jobs)
+        }
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TO jobs_due; RELEASE jobs_due")
+                .ok();
+            Err(err)
+        }
+    }
+}
+
+fn take_due(conn: &Connection, now: i64) -> Result<DueJobs, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT name, cron, payload, next_run FROM {TABLE}
+         WHERE next_run <= ? ORDER BY next_run, name"
+    ))?;
+    let mut rows = stmt.query([now])?;
+
+    let mut jobs = Vec::new();
+    let mut next_runs = Vec::new();
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(0)?;
+        let cron: String = row.get(1)?;
+        let payload: String = row.get(2)?;
+        let next_run = Schedule::parse(&cron)
+            .map_err(|err| format!("invalid cron expression of job `{name}`: {err}"))?
+            .next_after(now);
+        next_runs.push((name.clone(), next_run));
+        jobs.push(DueJob {
+            name,
+            payload: serde_json::from_str(&payload)?,
+            scheduled_for: row.get(3)?,
+        });
+    }
+    drop(rows);
+
+    let mut update =
+        conn.prepare_cached(&format!("UPDATE {TABLE} SET next_run = ? WHERE name = ?"))?;
+    let mut delete = conn.prepare_cached(&format!("DELETE FROM {TABLE} WHERE name = ?"))?;
+    for (name, next_run) in next_runs {
+        match next_run {
+            Some(next_run) => update.execute(params![next_run, name])?,
+            // An expression that matched once matches again within eight years, but don't keep a
+            // job that wouldn't.
+            None => delete.execute([name])?,
+        };
+    }
+
+    let next_run = conn
+        .prepare_cached(&format!("SELECT min(next_run) FROM {TABLE}"))?
+        .query_row([], |row| row.get(0))?;
+    Ok(DueJobs { jobs, next_run })
+}
+
+fn create_table(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.prepare_cached(&format!(
+        "CREATE TABLE IF NOT EXISTS {TABLE} \
+         (name TEXT PRIMARY KEY, cron TEXT NOT NULL, payload TEXT NOT NULL, \
+          next_run INTEGER NOT NULL)"
+    ))?
+    .execute([])?;
+    Ok(())
+}
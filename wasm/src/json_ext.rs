@@ -0,0 +1,133 @@
+//! JSON SQL functions complementing the built-in JSON1 functions, for updating nested fields of
+//! documents with a single `UPDATE`.
+
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::{ToSqlOutput, Value};
+use serde_json::{Map, Value as JsonValue};
+
+/// Registers `json_merge_patch(target, patch)`, `json_pointer_get(doc, pointer)` and
+/// `json_pointer_set(doc, pointer, value)`, with `pointer` being a JSON pointer (RFC 6901, e.g.
+/// `/address/city`) and `value` JSON text (e.g. `json_quote('Berlin')` or `'{"a":1}'`).
+pub fn register(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("json_merge_patch", 2, flags, |ctx| {
+        let mut target = json_arg(ctx, 0)?;
+        merge_patch(&mut target, json_arg(ctx, 1)?);
+        Ok(target.to_string())
+    })?;
+    conn.create_scalar_function("json_pointer_get", 2, flags, |ctx| {
+        let doc = json_arg(ctx, 0)?;
+        let pointer: String = ctx.get(1)?;
+        Ok(doc
+            .pointer(&pointer)
+            .map(to_sql)
+            .unwrap_or(ToSqlOutput::Owned(Value::Null)))
+    })?;
+    conn.create_scalar_function("json_pointer_set", 3, flags, |ctx| {
+        let mut doc = json_arg(ctx, 0)?;
+        let pointer: String = ctx.get(1)?;
+        set_pointer(&mut doc, &pointer, json_arg(ctx, 2)?).map_err(|err| {
+            rusqlite::Error::UserFunctionError(format!("json_pointer_set: {err}").into())
+        })?;
+        Ok(doc.to_string())
+    })?;
+    Ok(())
+}
+
+fn json_arg(ctx: &Context<'_>, i: usize) -> Result<JsonValue, rusqlite::Error> {
+    match ctx.get::<Option<String>>(i)? {
+        Some(json) => serde_json::from_str(&json)
+            .map_err(|err| rusqlite::Error::UserFunctionError(Box::new(err))),
+        None => Ok(JsonValue::Null),
+    }
+}
+
+/// Maps a JSON value to an SQL value the same way `json_extract` does.
+fn to_sql(value: &JsonValue) -> ToSqlOutput<'static> {
+    ToSqlOutput::Owned(match value {
+        JsonValue::Null => Value::Null,
+        JsonValue::Bool(b) => Value::Integer(i64::from(*b)),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(n) => Value::Integer(n),
+            None => Value::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        JsonValue::String(s) => Value::Text(s.clone()),
+        JsonValue::Array(_) | JsonValue::Object(_) => Value::Text(value.to_string()),
+    })
+}
+
+/// Applies a JSON merge patch (RFC 7396).
+fn merge_patch(target: &mut JsonValue, patch: JsonValue) {
+    let patch = match patch {
+        JsonValue::Object(patch) => patch,
+        patch => {
+            *target = patch;
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = JsonValue::Object(Map::new());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(&key);
+        } else {
+            merge_patch(target.entry(key).or_insert(JsonValue::Null), value);
+        }
+    }
+}
+
+/// Sets the value at the JSON pointer, creating missing objects along the way. Array elements are
+/// addressed by index, `-` appends.
+fn set_pointer(doc: &mut JsonValue, pointer: &str, value: JsonValue) -> Result<(), String> {
+    if pointer.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let pointer = pointer
+        .strip_prefix('/')
+        .ok_or_else(|| format!("invalid JSON pointer `{pointer}`"))?;
+
+    let mut current = doc;
+    let tokens = pointer
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect::<Vec<_>>();
+    for (i, token) in tokens.iter().enumerate() {
+        let last = i + 1 == tokens.len();
+        if current.is_null() {
+            *current = JsonValue::Object(Map::new());
+        }
+        current = match current {
+            JsonValue::Object(map) => {
+                if last {
+                    map.insert(token.clone(), value);
+                    return Ok(());
+                }
+                map.entry(token.clone()).or_insert(JsonValue::Null)
+            }
+            JsonValue::Array(items) => {
+                let index = if token == "-" {
+                    items.len()
+                } else {
+                    token
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|index| *index <= items.len())
+                        .ok_or_else(|| format!("invalid array index `{token}`"))?
+                };
+                if index == items.len() {
+                    items.push(JsonValue::Null);
+                }
+                if last {
+                    items[index] = value;
+                    return Ok(());
+                }
+                &mut items[index]
+            }
+            _ => return Err(format!("cannot set `{token}` of a scalar value")),
+        };
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+/// The table backing the key-value API. Values are stored as JSON text.
+const TABLE: &str = "_wasm_sqlite_kv";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KvSet {
+    pub key: String,
+    pub value: JsonValue,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KvList {
+    /// Only list keys starting with this prefix.
+    pub prefix: String,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KvEntry {
+    pub key: String,
+    pub value: JsonValue,
+}
+
+pub fn get(conn: &Connection, key: &str) -> Result<Option<KvEntry>, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    let value = conn
+        .prepare_cached(&format!("SELECT value FROM {TABLE} WHERE key = ?"))?
+        .query_row([key], |row| row.get::<_, String>(0))
+        .optional()?;
+    match value {
+        Some(value) => Ok(Some(KvEntry {
+            key: key.to_string(),
+            value: serde_json::from_str(&value)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+pub fn set(conn: &Connection, set: &KvSet) -> Result<(), Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    conn.prepare_cached(&format!(
+        "INSERT INTO {TABLE} (key, value) VALUES (?, ?) \
+         ON CONFLICT (key) DO UPDATE SET value = excluded.value"
+    ))?
+    .execute(params![set.key, serde_json::to_string(&set.value)?])?;
+    Ok(())
+}
+
+/// Returns whether the key existed.
+pub fn delete(conn: &Connection, key: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    let deleted = conn
+        .prepare_cached(&format!("DELETE FROM {TABLE} WHERE key = ?"))?
+        .execute([key])?;
+    Ok(deleted > 0)
+}
+
+/// Lists the entries whose key starts with the prefix, ordered by key.
+pub fn list(conn: &Connection, list: &KvList) -> Result<Vec<KvEntry>, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    // The prefix is turned into a key range, so that the lookup can use the primary key index.
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT key, value FROM {TABLE} WHERE key >= ?1 AND (?2 IS NULL OR key < ?2) \
+         ORDER BY key LIMIT ?3"
+    ))?;
+    let limit = list.limit.map_or(-1, i64::from);
+    let mut rows = stmt.query(params![list.prefix, prefix_end(&list.prefix), limit])?;
+
+    let mut entries = Vec::new();
+    while let Some(row) = rows.next()? {
+        let value: String = row.get(1)?;
+        entries.push(KvEntry {
+            key: row.get(0)?,
+            value: serde_json::from_str(&value)?,
+        });
+    }
+    Ok(entries)
+}
+
+fn create_table(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.prepare_cached(&format!(
+        "CREATE TABLE IF NOT EXISTS {TABLE} (key TEXT PRIMARY KEY, value TEXT NOT NULL) \
+         WITHOUT ROWID"
+    ))?
+    .execute([])?;
+    Ok(())
+}
+
+/// The smallest string greater than all strings starting with `prefix`, or `None` if there is no
+/// such string (an empty prefix or one consisting of `char::MAX` only).
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut end = prefix.to_string();
+    while let Some(last) = end.pop() {
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            end.push(next);
+            return Some(end);
+        }
+    }
+    None
+}
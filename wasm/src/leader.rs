@@ -0,0 +1,101 @@
+//! Leader election between instances sharing a page store: the leader holds a lease in
+//! `_wasm_sqlite_leases` until it expires, and keeps it by renewing it before then.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::history::NOW;
+
+/// The table backing the leases.
+const TABLE: &str = "_wasm_sqlite_leases";
+
+/// The name of the leadership lease.
+const LEADER: &str = "leader";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AcquireLeadership {
+    pub instance_id: String,
+    /// For how long the lease is held unless renewed, in milliseconds.
+    pub ttl_ms: u32,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReleaseLeadership {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Leadership {
+    /// Whether the instance is the leader (now).
+    pub leader: bool,
+    /// The instance holding the lease.
+    pub holder: String,
+    /// When the lease expires, in unix epoch milliseconds.
+    pub expires_at: i64,
+}
+
+/// Acquires or renews the leadership lease for the instance, unless another instance holds an
+/// unexpired lease. Acquiring happens in a single statement, so two instances never both succeed.
+pub fn try_acquire(
+    conn: &Connection,
+    acquire: &AcquireLeadership,
+) -> Result<Leadership, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    let acquired = conn
+        .prepare_cached(&format!(
+            "INSERT INTO {TABLE} (name, holder, expires_at) VALUES (?1, ?2, {NOW} + ?3)
+             ON CONFLICT (name) DO UPDATE
+             SET holder = excluded.holder, expires_at = excluded.expires_at
+             WHERE holder = excluded.holder OR expires_at <= {NOW}
+             RETURNING expires_at"
+        ))?
+        .query_row(
+            params![LEADER, acquire.instance_id, acquire.ttl_ms],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(expires_at) = acquired {
+        return Ok(Leadership {
+            leader: true,
+            holder: acquire.instance_id.clone(),
+            expires_at,
+        });
+    }
+
+    let (holder, expires_at) = conn
+        .prepare_cached(&format!(
+            "SELECT holder, expires_at FROM {TABLE} WHERE name = ?"
+        ))?
+        .query_row([LEADER], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(Leadership {
+        leader: false,
+        holder,
+        expires_at,
+    })
+}
+
+/// Gives up the leadership lease, if the instance holds it, so that another instance can acquire
+/// it without waiting for it to expire. Returns whether the instance held the lease.
+pub fn release(
+    conn: &Connection,
+    release: &ReleaseLeadership,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    let released = conn
+        .prepare_cached(&format!(
+            "DELETE FROM {TABLE} WHERE name = ? AND holder = ? AND expires_at > {NOW}"
+        ))?
+        .execute(params![LEADER, release.instance_id])?;
+    Ok(released > 0)
+}
+
+fn create_table(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.prepare_cached(&format!(
+        "CREATE TABLE IF NOT EXISTS {TABLE} \
+         (name TEXT PRIMARY KEY, holder TEXT NOT NULL, expires_at INTEGER NOT NULL)"
+    ))?
+    .execute([])?;
+    Ok(())
+}
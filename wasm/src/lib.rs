@@ -1,24 +1,157 @@
 use std::cell::RefCell;
 use std::ffi::CString;
 use std::os::raw::c_char;
-use std::ptr::NonNull;
+use std::rc::Rc;
+use std::time::Duration;
 
 use rusqlite::{params_from_iter, OpenFlags, Row, Rows};
 use serde::ser::Serializer;
 use serde::Serialize;
 use serde_json::Value as JsonValue;
-use sqlite_vfs::{register, RegisterError};
+use sqlite_vfs::RegisterError;
 
+use crate::backup::{Backup, StepResult};
+use crate::blob::{Blob, BlobTarget};
+use crate::error::{
+    is_foreign_key_error, is_io_error, ErrorReport, ForeignKeyViolations, HostError, NoPageStore,
+    TransactionRolledBack,
+};
+use crate::explain::Explain;
+use crate::ffi::JsonString;
+use crate::limits::{Limits, StepBudget};
+use crate::namespace::{Namespace, NAMESPACES};
+use crate::options::ConnectionOptions;
+use crate::policy::{RowPolicies, RowPolicy};
+use crate::query::{Query, QueryError};
+use crate::registry::{Registration, Registry, Run};
+use crate::security::Security;
+use crate::tune::Profile;
 pub use crate::vfs::PagesVfs;
 
+mod analyze;
+mod backup;
+mod blob;
+mod cancel;
+mod cdc;
+mod changes;
+mod clock;
+mod conformance;
+mod constraints;
+mod copy;
+mod counter;
+mod cron;
+mod d1;
+pub mod embedded;
+mod error;
+mod explain;
+pub mod ffi;
+mod fingerprint;
+mod fork;
+mod freelist;
+mod fts;
+#[cfg(fuzzing)]
+pub mod fuzz;
+mod geo;
+mod history;
+mod jobs;
+mod json_ext;
+mod kv;
+mod leader;
+mod limits;
+mod memory;
+mod meta;
+mod namespace;
+mod options;
+mod outbox;
+mod page_map;
+mod policy;
+mod prewarm;
+mod query;
+mod queue;
+mod registry;
+mod replication;
+mod retry;
+mod rewrite;
+mod salvage;
+mod schema_diff;
+mod security;
+#[cfg(feature = "seed")]
+mod seed;
+mod self_test;
+mod slow_query;
+mod spill;
+mod statement_log;
+mod tag;
+mod timeseries;
+mod trace;
+mod tune;
+mod upsert;
+#[cfg(feature = "vector")]
+mod vector;
+mod version;
 mod vfs;
 
 extern "C" {
-    pub fn page_count() -> u32;
-    pub fn get_page(ix: u32, ptr: *mut u8);
-    pub fn put_page(ix: u32, ptr: *const u8);
-    pub fn del_page(ix: u32);
+    pub fn page_count(ns: u32) -> u32;
+    pub fn get_page(ns: u32, ix: u32, ptr: *mut u8, len: u32) -> u32;
+    /// Reads `count` pages (indices at `ixs`) at once into consecutive `page_len` byte slots at
+    /// `ptr`, setting `found[i]` to `1` for each existing page. Returns a status like `get_page`
+    /// (`NOT_FOUND` is not used).
+    pub fn get_pages(
+        ns: u32,
+        ixs: *const u32,
+        count: u32,
+        ptr: *mut u8,
+        page_len: u32,
+        found: *mut u8,
+    ) -> u32;
+    pub fn put_page(ns: u32, ix: u32, ptr: *const u8, len: u32) -> u32;
+    /// Deletes the pages `from_ix..to_ix` (exclusive), which are always the last pages of the
+    /// namespace, e.g. when truncating the database after a `VACUUM`.
+    pub fn del_pages(ns: u32, from_ix: u32, to_ix: u32);
+    /// Persists all pages written to the namespace so far (for hosts buffering `put_page`), called
+    /// before SQLite considers a transaction durable. Returns a status like `put_page`.
+    pub fn storage_sync(ns: u32) -> u32;
     pub fn conn_sleep(ms: u32);
+    pub fn txn_begin() -> u32;
+    pub fn txn_commit(token: u32);
+    pub fn txn_rollback(token: u32);
+    pub fn emit_delta(ptr: *const u8, len: u32);
+    pub fn export_span(ptr: *const u8, len: u32);
+    pub fn slow_query(ptr: *const u8, len: u32);
+    /// Receives a JSON description of a lock a connection failed to acquire because of another
+    /// connection (e.g. a long-running write transaction).
+    pub fn on_lock_blocked(ptr: *const u8, len: u32);
+    /// Receives the bytes a committed storage transaction added to (positive) or removed from
+    /// (negative) the namespace's page store, e.g. to account storage per tenant.
+    pub fn on_storage_delta(ns: u32, bytes_delta: f64);
+    /// Passes the SQL of a statement about to be prepared to the host for rewriting. Returns the
+    /// length of the rewritten SQL, to be read via `rewritten_sql`, or `u32::MAX` to use the SQL
+    /// as is.
+    pub fn rewrite_sql(ptr: *const u8, len: u32) -> u32;
+    /// Writes the SQL of the last `rewrite_sql` call into `ptr` (at most `len` bytes).
+    pub fn rewritten_sql(ptr: *mut u8, len: u32);
+    /// Writes the message of the last failed page store call into `ptr` (at most `len` bytes) and
+    /// returns its full length.
+    pub fn host_error_message(ptr: *mut u8, len: u32) -> u32;
+    /// Milliseconds (with fractions) of a monotonic clock of the host, e.g. `performance.now()`.
+    pub fn monotonic_millis() -> f64;
+}
+
+/// Pre-initializes the module for [Wizer](https://github.com/bytecodealliance/wizer) (see
+/// `WIZER=1 ./build.sh`): SQLite's initialization, including the registration of the VFSs and the
+/// tracing subscriber, runs at build time and is snapshotted into the module's memory instead of
+/// running on every cold start. Connections (and thus their page cache) can't be snapshotted, as
+/// the database lives in the host's page store, which isn't available at build time and changes
+/// after it; use `conn_prewarm` after opening them instead.
+#[export_name = "wizer.initialize"]
+extern "C" fn wizer_initialize() {
+    let code = unsafe { rusqlite::ffi::sqlite3_initialize() };
+    assert_eq!(
+        code,
+        rusqlite::ffi::SQLITE_OK,
+        "failed to initialize SQLite"
+    );
 }
 
 // TODO: is there any way to provide this method for SQLite, but not export it as part of the WASM
@@ -32,8 +165,13 @@ extern "C" fn sqlite3_os_init() -> i32 {
         .filter(Some("sqlite_vfs"), log::LevelFilter::Debug)
         .try_init()
         .ok();
+    trace::init();
 
-    match register("cfdo", PagesVfs::<4096>::default(), true) {
+    let result = NAMESPACES
+        .iter()
+        .enumerate()
+        .try_for_each(|(i, ns)| ns.register(i == 0));
+    match result {
         Ok(_) => SQLITE_OK,
         Err(RegisterError::Nul(_)) => SQLITE_ERROR,
         Err(RegisterError::Register(code)) => code,
@@ -41,47 +179,241 @@ extern "C" fn sqlite3_os_init() -> i32 {
 }
 
 pub struct Connection {
-    conn: rusqlite::Connection,
+    /// `None` while the connection is suspended (see [conn_suspend]).
+    conn: Option<rusqlite::Connection>,
     last_error: Option<Box<dyn std::error::Error>>,
+    step_budget: StepBudget,
+    /// The limits to apply again when reopening a suspended connection.
+    limits: Limits,
+    /// The security options to apply again when reopening a suspended connection.
+    security: Security,
+    /// The tuning profile to apply again when reopening a suspended connection.
+    profile: Option<Profile>,
+    auto_rollback: bool,
+    /// The page store namespace the database is stored in. `None` for in-memory databases.
+    namespace: Option<&'static Namespace>,
+    /// The options to reopen a suspended connection with.
+    options: ConnectionOptions,
+    /// Identifies the caller of the connection in spans, slow queries, lock diagnostics and errors
+    /// (see [conn_set_tag]).
+    tag: Option<Rc<str>>,
+    /// The SQL of the statement run by the current call, if any, for error reports.
+    statement: Option<String>,
+    /// The named queries registered via [conn_register_query].
+    queries: Registry,
+    /// The number of BLOB handles opened via [blob_open] and not closed yet.
+    open_blobs: usize,
+    /// The maximum size of the database in bytes (see [conn_set_max_db_size]).
+    max_db_size: Option<u64>,
+    /// The row policies to apply again when reopening a suspended connection (see
+    /// [conn_set_row_policy]). Boxed, as the authorizer keeps a pointer to them.
+    row_policies: Box<RowPolicies>,
+}
+
+thread_local! {
+    /// The error of the last connection that failed to open, see [last_init_error].
+    static INIT_ERROR: RefCell<Option<Box<dyn std::error::Error>>> = RefCell::new(None);
 }
 
+/// Opens a connection with the default [ConnectionOptions]. Returns null if the database could
+/// not be opened, see [last_init_error].
 #[no_mangle]
 pub unsafe extern "C" fn conn_new() -> *mut Connection {
-    let is_new = page_count() == 0;
+    init_result(conn_open(ConnectionOptions::default()))
+}
+
+/// Opens a connection according to the [ConnectionOptions] (JSON). Returns null if the options are
+/// invalid or the database could not be opened, see [last_init_error].
+#[no_mangle]
+pub unsafe extern "C" fn conn_new_with_options(ptr: *const u8, len: usize) -> *mut Connection {
+    let options = unsafe { ffi::payload(ptr, len) };
+    let result = serde_json::from_slice::<ConnectionOptions>(options)
+        .map_err(Box::from)
+        .and_then(conn_open);
+    init_result(result)
+}
+
+fn conn_open(options: ConnectionOptions) -> Result<Connection, Box<dyn std::error::Error>> {
+    let namespace = match &options.vfs {
+        Some(vfs) => Namespace::find(vfs).ok_or_else(|| format!("unknown vfs `{vfs}`"))?,
+        None => Namespace::main(),
+    };
+    let conn = open(namespace, &options)?;
+
+    let mut conn = Connection::new(conn, Some(namespace));
+    conn.options = options;
+    Ok(conn)
+}
+
+/// Returns the opened connection, or stores the error for [last_init_error] and returns null.
+fn init_result(result: Result<Connection, Box<dyn std::error::Error>>) -> *mut Connection {
+    match result {
+        Ok(conn) => conn.into_raw(),
+        Err(mut err) => {
+            tracing::error!(error = %err, "failed to open connection");
+            if let Some(message) = vfs::take_host_error() {
+                if is_io_error(err.as_ref()) {
+                    err = Box::new(HostError {
+                        error: err,
+                        message,
+                    });
+                }
+            }
+            INIT_ERROR.with(|init_error| *init_error.borrow_mut() = Some(err));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// The error (see [ErrorReport], JSON) of the last `conn_new*` call that returned null. Null if
+/// there is none. The error is cleared once retrieved.
+#[no_mangle]
+extern "C" fn last_init_error() -> *const JsonString {
+    match INIT_ERROR.with(|init_error| init_error.borrow_mut().take()) {
+        Some(err) => {
+            let mut report = ErrorReport::new(err.as_ref());
+            report.d1 = d1::translate(&report, None);
+            let json = serde_json::to_string(&report).expect("serialize error report");
+            JsonString::new(json).into_raw()
+        }
+        None => std::ptr::null(),
+    }
+}
+
+/// Opens the database stored in the page store of the given namespace.
+fn open(
+    namespace: &Namespace,
+    options: &ConnectionOptions,
+) -> Result<rusqlite::Connection, Box<dyn std::error::Error>> {
+    let _span = tracing::debug_span!("open", vfs = namespace.vfs).entered();
+    #[cfg(feature = "seed")]
+    if !options.read_only {
+        seed::write_if_empty(namespace)?;
+    }
+    let is_new = namespace.page_count() == 0;
+
+    if let Some(page_size) = namespace.stored_page_size()? {
+        if page_size != namespace.page_size {
+            return Err(format!(
+                "database in page store has a page size of {page_size} bytes, but the page store \
+                 of vfs `{}` only supports a page size of {} bytes",
+                namespace.vfs, namespace.page_size
+            )
+            .into());
+        }
+    }
 
     let conn = rusqlite::Connection::open_with_flags_and_vfs(
-        "main.db",
-        OpenFlags::SQLITE_OPEN_READ_WRITE
-            | OpenFlags::SQLITE_OPEN_CREATE
-            | OpenFlags::SQLITE_OPEN_NO_MUTEX,
-        "cfdo",
-    )
-    .expect("open connection");
+        options.uri.as_deref().unwrap_or("main.db"),
+        options.open_flags(),
+        namespace.vfs,
+    )?;
+
+    if is_new && !options.read_only {
+        conn.execute(&format!("PRAGMA page_size = {};", namespace.page_size), [])?;
+        conn.execute_batch("PRAGMA encoding = 'UTF-8'")?;
+        // Must be set before the first table is created.
+        if let Some(auto_vacuum) = options.auto_vacuum {
+            conn.execute_batch(&format!("PRAGMA auto_vacuum = {}", auto_vacuum.as_str()))?;
+        }
+    }
+    let encoding = encoding(&conn)?;
+    if encoding != "UTF-8" && !options.allow_utf16 {
+        return Err(format!(
+            "database uses the {encoding} text encoding, but only UTF-8 is supported (open it \
+             with `allow_utf16` and convert it via `salvage`)"
+        )
+        .into());
+    }
+
+    conn.pragma_update(None, "foreign_keys", &options.foreign_keys)?;
+    conn.pragma_update(None, "recursive_triggers", &options.recursive_triggers)?;
+    conn.execute_batch(&format!(
+        "PRAGMA synchronous = {}",
+        options.synchronous.as_str()
+    ))?;
+    conn.busy_timeout(Duration::from_millis(options.busy_timeout_ms.into()))?;
+    if !options.dangerous_raw_pages {
+        security::deny_raw_pages(&conn)?;
+    }
+    geo::register(&conn)?;
+    timeseries::register(&conn)?;
+    json_ext::register(&conn)?;
+    cdc::register(&conn)?;
+    #[cfg(feature = "vector")]
+    vector::register(&conn)?;
+
+    let journal_mode: String =
+        conn.query_row("PRAGMA journal_mode = MEMORY", [], |row| row.get(0))?;
+    if journal_mode != "memory" {
+        return Err(format!("failed to set journal_mode = MEMORY (got {journal_mode})").into());
+    }
+
+    for sql in &options.on_open_sql {
+        tracing::debug!(sql, "on open");
+        conn.execute_batch(sql)?;
+    }
+
+    Ok(conn)
+}
+
+/// The text encoding of the database (`UTF-8`, `UTF-16le` or `UTF-16be`).
+fn encoding(conn: &rusqlite::Connection) -> Result<String, rusqlite::Error> {
+    conn.query_row("PRAGMA encoding", [], |row| row.get(0))
+}
 
-    if is_new {
-        conn.execute("PRAGMA page_size = 4096;", [])
-            .expect("set page_size = 4096");
+/// Releases all locks and the memory of the connection (including its page cache) by closing the
+/// underlying database connection, while keeping the handle valid. The connection is reopened on
+/// its next use. Fails for connections inside a transaction and for in-memory connections.
+#[no_mangle]
+extern "C" fn conn_suspend(conn: *mut Connection) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    match conn.suspend() {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(err);
+            0
+        }
     }
+}
+
+/// Opens a scratch database that lives in memory only, without touching the page store. Supports
+/// the same exports as connections created via [conn_new], except for the page store related ones.
+/// Returns null on failure, see [last_init_error].
+#[no_mangle]
+pub unsafe extern "C" fn conn_new_memory() -> *mut Connection {
+    init_result(open_memory().map(|conn| Connection::new(conn, None)))
+}
 
-    let journal_mode: String = conn
-        .query_row("PRAGMA journal_mode = MEMORY", [], |row| row.get(0))
-        .expect("set journal_mode = MEMORY");
-    assert_eq!(journal_mode, "memory");
+fn open_memory() -> Result<rusqlite::Connection, Box<dyn std::error::Error>> {
+    let conn = rusqlite::Connection::open_in_memory_with_flags(
+        OpenFlags::SQLITE_OPEN_READ_WRITE
+            | OpenFlags::SQLITE_OPEN_CREATE
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    conn.pragma_update(None, "foreign_keys", &true)?;
+    security::deny_raw_pages(&conn)?;
+    geo::register(&conn)?;
+    timeseries::register(&conn)?;
+    json_ext::register(&conn)?;
+    cdc::register(&conn)?;
+    #[cfg(feature = "vector")]
+    vector::register(&conn)?;
 
-    Box::into_raw(Box::new(Connection {
-        conn,
-        last_error: None,
-    }))
+    Ok(conn)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn conn_last_error(conn: *mut Connection) -> *mut c_char {
     use std::fmt::Write;
 
-    let conn: &mut Connection = unsafe { conn.as_mut().unwrap() };
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
 
     if let Some(err) = conn.last_error.take() {
-        let mut message = err.to_string();
+        let mut message = match &conn.tag {
+            Some(tag) => format!("[{tag}] {err}"),
+            None => err.to_string(),
+        };
 
         let mut source = std::error::Error::source(err.as_ref());
         let mut i = 0;
@@ -105,203 +437,1833 @@ pub unsafe extern "C" fn conn_last_error(conn: *mut Connection) -> *mut c_char {
     }
 }
 
+/// Like [conn_last_error], but returns the error as JSON (see [ErrorReport]).
 #[no_mangle]
-pub unsafe extern "C" fn conn_last_error_drop(s: *mut c_char) {
-    if s.is_null() {
-        return;
+extern "C" fn conn_last_error_json(conn: *mut Connection) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    if let Some(err) = conn.last_error.take() {
+        let mut report = ErrorReport::new(err.as_ref());
+        report.tag = conn.tag.as_deref().map(String::from);
+        report.d1 = d1::translate(&report, conn.statement.as_deref());
+        let json = serde_json::to_string(&report).expect("serialize error report");
+        JsonString::new(json).into_raw()
+    } else {
+        std::ptr::null()
     }
-    let _ = CString::from_raw(s);
 }
 
+/// Sets the tag (JSON string, or `null` to remove it) attached to the spans, slow queries, lock
+/// diagnostics and errors of the connection. Returns `1` on success and `0` on failure.
 #[no_mangle]
-pub unsafe extern "C" fn conn_drop(conn: *mut Connection) {
-    drop(Box::from_raw(conn));
+extern "C" fn conn_set_tag(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let tag = unsafe { ffi::payload(ptr, len) };
+    match serde_json::from_slice::<Option<String>>(tag) {
+        Ok(tag) => {
+            conn.tag = tag.map(Rc::from);
+            tag::set_current(conn.tag.clone());
+            1
+        }
+        Err(err) => {
+            conn.fail(err.into());
+            0
+        }
+    }
 }
 
-#[derive(serde::Deserialize)]
-struct Query {
-    sql: String,
-    params: Vec<JsonValue>,
+/// Closes the connection. Returns `0` if BLOB handles opened via [blob_open] are still open, in
+/// which case the connection stays usable and the error is stored as its last error, `1` otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn conn_drop(conn: *mut Connection) -> i32 {
+    let c: &mut Connection = ffi::as_mut(conn);
+    if c.open_blobs > 0 {
+        c.fail("cannot drop a connection with open BLOB handles".into());
+        return 0;
+    }
+    drop(ffi::take(conn));
+    1
 }
 
 #[no_mangle]
 extern "C" fn conn_execute(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
-    let conn: &mut Connection = unsafe { conn.as_mut().unwrap() };
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
 
-    let query = unsafe { std::slice::from_raw_parts::<'_, u8>(ptr, len) };
-    let query: Query = match serde_json::from_slice(query) {
-        Ok(query) => query,
+    let query = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| Query::from_json(query))
+        .and_then(rewrite::rewrite)
+        .and_then(|query| slow_query::measure(&query.sql, || conn.execute(&query)));
+    match result {
+        Ok(()) => 1,
         Err(err) => {
-            conn.last_error = Some(Box::new(err));
-            return 0;
+            conn.fail(err);
+            0
         }
-    };
+    }
+}
 
-    if let Err(err) = conn
-        .conn
-        .execute(&query.sql, params_from_iter(&query.params))
-    {
-        conn.last_error = Some(Box::new(err));
-        0
-    } else {
-        1
+#[no_mangle]
+extern "C" fn conn_query(conn: *mut Connection, ptr: *const u8, len: usize) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let query = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| Query::from_json(query))
+        .and_then(rewrite::rewrite)
+        .and_then(|query| slow_query::measure(&query.sql, || conn.query(&query)));
+
+    match result {
+        Ok(json) => JsonString::new(json).into_raw(),
+        Err(err) => {
+            conn.fail(err);
+            std::ptr::null()
+        }
+    }
+}
+
+/// Registers a named query (`{"name": ..., "sql": ...}`) to run via [conn_run]. The SQL is
+/// prepared to validate it. Fails once the connection is restricted to registered queries.
+#[no_mangle]
+extern "C" fn conn_register_query(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let registration = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<Registration>(registration).map_err(Box::from))
+        .and_then(|registration| {
+            conn.conn().prepare(&registration.sql)?;
+            conn.queries.register(registration);
+            Ok(())
+        });
+    match result {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(err);
+            0
+        }
+    }
+}
+
+/// Runs the registered query (`{"name": ..., "params": [...]}`, see [conn_register_query]) and
+/// returns its rows like [conn_query].
+#[no_mangle]
+extern "C" fn conn_run(conn: *mut Connection, ptr: *const u8, len: usize) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let run = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| serde_json::from_slice::<Run>(run).map_err(Box::from))
+        .and_then(|run| Ok(conn.queries.query(run)?))
+        .and_then(|query| slow_query::measure(&query.sql, || conn.query(&query)));
+    match result {
+        Ok(json) => JsonString::new(json).into_raw(),
+        Err(err) => {
+            conn.fail(err);
+            std::ptr::null()
+        }
     }
 }
 
-#[repr(C)]
-pub struct JsonString {
-    ptr: NonNull<u8>,
+/// Like [conn_query], but the query can be interrupted by calling [cancel()] with `token` while it
+/// runs (e.g. from a host callback when the request that issued the query is aborted).
+#[no_mangle]
+extern "C" fn conn_query_with_token(
+    conn: *mut Connection,
+    ptr: *const u8,
     len: usize,
-    cap: usize,
+    token: u32,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let query = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| Query::from_json(query))
+        .and_then(rewrite::rewrite)
+        .and_then(|query| {
+            let db = unsafe { conn.conn().handle() };
+            cancel::with_token(token, db, || {
+                slow_query::measure(&query.sql, || conn.query(&query))
+            })
+        });
+    match result {
+        Ok(json) => JsonString::new(json).into_raw(),
+        Err(err) => {
+            conn.fail(err);
+            std::ptr::null()
+        }
+    }
 }
 
-impl JsonString {
-    fn new(json: String) -> Self {
-        let mut v = std::mem::ManuallyDrop::new(json);
-        Self {
-            ptr: unsafe { NonNull::new_unchecked(v.as_mut_ptr()) },
-            len: v.len(),
-            cap: v.capacity(),
+/// Returns the next `max_rows` rows (JSON array) of a result spilled by [conn_query] (see
+/// [spill]). Returns an empty array once all rows were returned.
+#[no_mangle]
+extern "C" fn conn_cursor_next(
+    conn: *mut Connection,
+    cursor: u32,
+    max_rows: u32,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    match spill::next(cursor, max_rows) {
+        Ok(json) => JsonString::new(json).into_raw(),
+        Err(err) => {
+            conn.fail(err);
+            std::ptr::null()
         }
     }
+}
 
-    fn into_raw(self) -> *mut Self {
-        Box::into_raw(Box::new(self))
+/// Discards the remaining rows of a spilled result. Returns `1` on success, `0` on failure.
+#[no_mangle]
+extern "C" fn conn_cursor_close(conn: *mut Connection, cursor: u32) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    match spill::close(cursor) {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(err);
+            0
+        }
     }
 }
 
+/// Interrupts the query running with `token` (see [conn_query_with_token]). Returns `1` if the
+/// query was interrupted and `0` if no query with this token is running.
 #[no_mangle]
-extern "C" fn conn_query(conn: *mut Connection, ptr: *const u8, len: usize) -> *const JsonString {
-    let conn: &mut Connection = unsafe { conn.as_mut().unwrap() };
+extern "C" fn cancel(token: u32) -> i32 {
+    i32::from(cancel::cancel(token))
+}
+
+/// The number of rows modified by all statements since the connection was opened.
+#[no_mangle]
+extern "C" fn conn_total_changes(conn: *mut Connection) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    match &conn.conn {
+        Some(c) => unsafe { rusqlite::ffi::sqlite3_total_changes(c.handle()) },
+        None => 0,
+    }
+}
+
+/// Whether the connection is inside an explicit transaction (which must be committed or rolled
+/// back), i.e. not in autocommit mode.
+#[no_mangle]
+extern "C" fn conn_in_transaction(conn: *mut Connection) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    i32::from(conn.in_transaction())
+}
+
+#[no_mangle]
+extern "C" fn conn_set_limits_json(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
 
-    let query = unsafe { std::slice::from_raw_parts::<'_, u8>(ptr, len) };
-    let query: Query = match serde_json::from_slice(query) {
-        Ok(query) => query,
+    let limits = unsafe { ffi::payload(ptr, len) };
+    let limits: Limits = match serde_json::from_slice(limits) {
+        Ok(limits) => limits,
         Err(err) => {
-            conn.last_error = Some(Box::new(err));
-            return std::ptr::null();
+            conn.fail(Box::new(err));
+            return 0;
         }
     };
 
-    let mut stmt = match conn.conn.prepare(&query.sql) {
-        Ok(stmt) => stmt,
+    // Suspended connections apply the limits once they are reopened.
+    conn.limits = limits;
+    if let Some(c) = &conn.conn {
+        conn.step_budget.apply(c, &conn.limits);
+    }
+    1
+}
+
+/// Limits the size of the database to the given number of bytes (JSON number, or `null` to remove
+/// the limit), e.g. to enforce per-tenant quotas. Writes that would grow the database beyond it
+/// fail with kind `database_full`. Returns `1` on success and `0` on failure.
+#[no_mangle]
+extern "C" fn conn_set_max_db_size(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let max = unsafe { ffi::payload(ptr, len) };
+    let result = serde_json::from_slice::<Option<u64>>(max)
+        .map_err(Box::from)
+        .and_then(|max| {
+            // Suspended connections apply the limit once they are reopened.
+            if let Some(c) = &conn.conn {
+                limits::apply_max_db_size(c, max)?;
+            }
+            conn.max_db_size = max;
+            Ok(())
+        });
+    match result {
+        Ok(()) => 1,
         Err(err) => {
-            conn.last_error = Some(Box::new(err));
-            return std::ptr::null();
+            conn.fail(err);
+            0
         }
-    };
-    let names = stmt
-        .column_names()
-        .into_iter()
-        .map(String::from)
-        .collect::<Vec<_>>();
-    let rows = match stmt.query(params_from_iter(&query.params)) {
-        Ok(rows) => rows,
+    }
+}
+
+/// Applies the [Security] options (JSON) to the connection, e.g. to harden it before executing
+/// user-supplied SQL.
+#[no_mangle]
+extern "C" fn conn_set_security(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let security = unsafe { ffi::payload(ptr, len) };
+    let result = serde_json::from_slice::<Security>(security)
+        .map_err(Box::from)
+        .and_then(|security| {
+            if conn.security.registered_queries_only()
+                && security.registered_queries_only == Some(false)
+            {
+                return Err("registered_queries_only can't be disabled once enabled".into());
+            }
+            // Suspended connections apply the options once they are reopened.
+            if let Some(c) = &conn.conn {
+                security.apply(c)?;
+            }
+            conn.security.merge(security);
+            Ok(())
+        });
+    match result {
+        Ok(()) => 1,
         Err(err) => {
-            conn.last_error = Some(Box::new(err));
-            return std::ptr::null();
+            conn.fail(err);
+            0
         }
-    };
-    let rows = NamedRows {
-        names,
-        rows: RefCell::new(rows),
-    };
+    }
+}
+
+/// Sets (or removes) the [RowPolicy] (JSON) of a table: all statements of the connection only see
+/// and write the rows matching its predicate, e.g. the rows of a single tenant. Returns `1` on
+/// success and `0` on failure.
+#[no_mangle]
+extern "C" fn conn_set_row_policy(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
 
-    let result = match serde_json::to_string(&rows) {
-        Ok(result) => result,
+    let policy = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<RowPolicy>(policy).map_err(Box::from))
+        .and_then(|policy| conn.row_policies.set(conn.conn(), policy));
+    match result {
+        Ok(()) => 1,
         Err(err) => {
-            conn.last_error = Some(Box::new(err));
-            return std::ptr::null();
+            conn.fail(err);
+            0
         }
-    };
-    JsonString::new(result).into_raw()
+    }
 }
 
-struct NamedRows<'a> {
-    names: Vec<String>,
-    rows: RefCell<Rows<'a>>,
+/// Applies a tuning [Profile] (passed as JSON string, e.g. `"read-heavy"`) to the connection.
+#[no_mangle]
+extern "C" fn conn_tune(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let profile = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| serde_json::from_slice::<Profile>(profile).map_err(Box::from))
+        .and_then(|profile| {
+            profile.apply(conn.conn())?;
+            conn.profile = Some(profile);
+            Ok(())
+        });
+    match result {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(err);
+            0
+        }
+    }
 }
 
-impl<'a> Serialize for NamedRows<'a> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        use serde::ser::SerializeSeq;
+/// Configures whether a failing call that leaves an explicit transaction open automatically rolls
+/// it back (enabled by default), so that it can't block all subsequent writes.
+#[no_mangle]
+extern "C" fn conn_set_auto_rollback(conn: *mut Connection, enabled: i32) {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    conn.auto_rollback = enabled != 0;
+}
 
-        let mut rows = self.rows.borrow_mut();
-        let mut seq = serializer.serialize_seq(None)?;
-        while let Some(row) = rows
-            .next()
-            .map_err(|err| serde::ser::Error::custom(format!("failed to get next row: {err}")))?
-        {
-            let row = NamedRow {
-                names: &self.names,
-                row,
-            };
-            seq.serialize_element(&row)?;
+#[no_mangle]
+extern "C" fn conn_reconcile_storage(conn: *mut Connection) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn
+        .namespace()
+        .map_err(Box::from)
+        .and_then(|namespace| conn.with_write_lock(|| namespace.reconcile_storage()));
+    conn.json_result(result)
+}
+
+#[no_mangle]
+extern "C" fn conn_verify_pages(conn: *mut Connection) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn
+        .namespace()
+        .map_err(Box::from)
+        .and_then(|namespace| conn.with_write_lock(|| namespace.verify_pages()));
+    conn.json_result(result)
+}
+
+/// Returns the [changes::ChangedPages] of the durable database since the commit `counter` (JSON),
+/// e.g. for incremental backups. Requires change tracking (see [set_change_tracking]).
+#[no_mangle]
+extern "C" fn conn_pages_changed_since(conn: *mut Connection, counter: u32) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn.namespace().map_err(Box::from).and_then(|namespace| {
+        if namespace.id != Namespace::main().id {
+            return Err(format!(
+                "changes are only tracked for the database of vfs `{}`",
+                Namespace::main().vfs
+            )
+            .into());
         }
-        seq.end()
+        conn.with_write_lock(|| changes::changed_since(counter))
+    });
+    conn.json_result(result)
+}
+
+/// Returns the [freelist::FreelistInfo] of the database (JSON).
+#[no_mangle]
+extern "C" fn conn_freelist_info(conn: *mut Connection) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn
+        .resume()
+        .and_then(|()| Ok(freelist::info(conn.conn())?));
+    conn.json_result(result)
+}
+
+/// Deletes free pages from the page store according to the [freelist::ReclaimOptions] (JSON).
+/// Returns a [freelist::ReclaimReport] (JSON).
+#[no_mangle]
+extern "C" fn conn_reclaim_free_pages(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let options = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| {
+            serde_json::from_slice::<freelist::ReclaimOptions>(options).map_err(Box::from)
+        })
+        .and_then(|options| Ok(freelist::reclaim(conn.conn(), &options)?));
+    conn.json_result(result)
+}
+
+/// Loads the schema and the root pages of the tables (JSON array of names, all tables if empty) and
+/// their indexes in a single batched host read. Returns a [prewarm::PrewarmReport] (JSON).
+#[no_mangle]
+extern "C" fn conn_prewarm(conn: *mut Connection, ptr: *const u8, len: usize) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let tables = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| Ok(conn.namespace()?))
+        .and_then(|namespace| {
+            let tables = serde_json::from_slice::<Vec<String>>(tables)?;
+            prewarm::prewarm(conn.conn(), namespace, &tables)
+        });
+    conn.json_result(result)
+}
+
+/// Lists the role of each page of the database (header, B-tree root, interior, leaf or overflow
+/// page of which table or index, or free) as a [page_map::PageMap] (JSON).
+#[no_mangle]
+extern "C" fn conn_dump_page_map(conn: *mut Connection) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| Ok(page_map::page_map(conn.conn())?));
+    conn.json_result(result)
+}
+
+/// Returns the text encoding of the database (JSON string, e.g. `"UTF-8"`).
+#[no_mangle]
+extern "C" fn conn_encoding(conn: *mut Connection) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn.resume().and_then(|()| Ok(encoding(conn.conn())?));
+    conn.json_result(result)
+}
+
+/// Returns the [version::DbVersion] of the database (JSON).
+#[no_mangle]
+extern "C" fn conn_get_version(conn: *mut Connection) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn.resume().and_then(|()| Ok(version::get(conn.conn())?));
+    conn.json_result(result)
+}
+
+/// Sets the versions of the database according to the [version::SetDbVersion] (JSON). Returns the
+/// [version::DbVersion] afterwards.
+#[no_mangle]
+extern "C" fn conn_set_version(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let set = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<version::SetDbVersion>(set).map_err(Box::from))
+        .and_then(|set| version::set(conn.conn(), &set));
+    conn.json_result(result)
+}
+
+/// Compares the live schema with the desired one according to the [schema_diff::SchemaDiffOptions]
+/// (JSON), and returns the statements migrating it as [schema_diff::SchemaDiff] (JSON).
+#[no_mangle]
+extern "C" fn conn_schema_diff(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let options = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| {
+            serde_json::from_slice::<schema_diff::SchemaDiffOptions>(options).map_err(Box::from)
+        })
+        .and_then(|options| schema_diff::diff(conn.conn(), &options));
+    conn.json_result(result)
+}
+
+/// Copies as much as possible of the (corrupted) database of the connection into the empty
+/// `cfdo-salvage` namespace, skipping corrupted pages. Returns a [salvage::SalvageReport] (JSON).
+#[no_mangle]
+extern "C" fn conn_salvage(conn: *mut Connection) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| salvage::salvage(conn.conn()));
+    conn.json_result(result)
+}
+
+/// Creates a branch of the database of the connection in the empty page store namespace `target`
+/// (at least [namespace::FIRST_BRANCH]). Returns a [fork::ForkReport] (JSON).
+#[no_mangle]
+extern "C" fn conn_fork(conn: *mut Connection, target: u32) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| fork::fork(conn, target));
+    conn.json_result(result)
+}
+
+/// Copies all rows of a table (name passed as JSON string) from `src` into `dst` (e.g. from an
+/// in-memory scratch database into the paged database), without serializing them to the host.
+/// Errors are stored as the last error of `dst`.
+#[no_mangle]
+extern "C" fn conn_copy_table(
+    src: *mut Connection,
+    dst: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let table = unsafe { ffi::payload(ptr, len) };
+    if src == dst {
+        let dst: &mut Connection = unsafe { ffi::as_mut(dst) };
+        return dst.json_result::<()>(Err("cannot copy a table into the same connection".into()));
     }
+
+    let src: &mut Connection = unsafe { ffi::as_mut(src) };
+    let dst: &mut Connection = unsafe { ffi::as_mut(dst) };
+    let result = src
+        .resume()
+        .and_then(|()| src.allow_sql())
+        .and_then(|()| dst.resume())
+        .and_then(|()| dst.allow_sql())
+        .and_then(|()| serde_json::from_slice::<String>(table).map_err(Box::from))
+        .and_then(|table| copy::copy_table(src.conn(), dst.conn(), &table));
+    dst.json_result(result)
 }
 
-struct NamedRow<'a> {
-    names: &'a [String],
-    row: &'a Row<'a>,
+/// Inserts or updates the rows of an [upsert::Upsert] (JSON) in a single savepoint.
+#[no_mangle]
+extern "C" fn conn_upsert(conn: *mut Connection, ptr: *const u8, len: usize) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let upsert = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<upsert::Upsert>(upsert).map_err(Box::from))
+        .and_then(|upsert| upsert::upsert(conn.conn(), &upsert));
+    conn.json_result(result)
 }
 
-impl<'a> Serialize for NamedRow<'a> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        use rusqlite::types::ValueRef;
-        use serde::ser::SerializeMap;
+/// Returns the entry of the key (a JSON string) of the key-value table as `{"key", "value"}`, or
+/// `null` if the key doesn't exist.
+#[no_mangle]
+extern "C" fn conn_kv_get(conn: *mut Connection, ptr: *const u8, len: usize) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
 
-        let mut map = serializer.serialize_map(Some(self.names.len()))?;
-        for i in 0..self.names.len() {
-            let val = self.row.get_ref_unwrap(i);
-            match val {
-                ValueRef::Null => map.serialize_entry(&self.names[i], &JsonValue::Null)?,
-                ValueRef::Integer(v) => map.serialize_entry(&self.names[i], &v)?,
-                ValueRef::Real(v) => map.serialize_entry(&self.names[i], &v)?,
-                ValueRef::Text(v) => {
-                    let s = String::from_utf8_lossy(v);
-                    map.serialize_entry(&self.names[i], &s)?
-                }
-                ValueRef::Blob(v) => map.serialize_entry(&self.names[i], &v)?,
-            }
+    let key = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<String>(key).map_err(Box::from))
+        .and_then(|key| kv::get(conn.conn(), &key));
+    conn.json_result(result)
+}
+
+/// Sets the value of a key in the key-value table according to the [kv::KvSet] (JSON).
+#[no_mangle]
+extern "C" fn conn_kv_set(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let set = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<kv::KvSet>(set).map_err(Box::from))
+        .and_then(|set| kv::set(conn.conn(), &set));
+    match result {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(err);
+            0
         }
-        map.end()
     }
 }
 
+/// Deletes the key (a JSON string) from the key-value table. Returns whether it existed.
 #[no_mangle]
-unsafe fn alloc(size: usize) -> *mut u8 {
-    use std::alloc::{alloc, Layout};
+extern "C" fn conn_kv_delete(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
 
-    let align = std::mem::align_of::<usize>();
-    let layout = Layout::from_size_align_unchecked(size, align);
-    alloc(layout)
+    let key = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<String>(key).map_err(Box::from))
+        .and_then(|key| kv::delete(conn.conn(), &key));
+    conn.json_result(result)
 }
 
+/// Lists the entries of the key-value table according to the [kv::KvList] (JSON).
 #[no_mangle]
-unsafe fn dealloc(ptr: *mut u8, size: usize) {
-    use std::alloc::{dealloc, Layout};
-    let align = std::mem::align_of::<usize>();
-    let layout = Layout::from_size_align_unchecked(size, align);
-    dealloc(ptr, layout);
+extern "C" fn conn_kv_list(conn: *mut Connection, ptr: *const u8, len: usize) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let list = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<kv::KvList>(list).map_err(Box::from))
+        .and_then(|list| kv::list(conn.conn(), &list));
+    conn.json_result(result)
 }
 
+/// Atomically adds to a counter according to the [counter::CounterIncr] (JSON). Returns the new
+/// value.
 #[no_mangle]
-unsafe extern "C" fn query_result_drop(json: *mut JsonString) {
-    drop(Box::from_raw(json));
+extern "C" fn conn_counter_incr(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let incr = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<counter::CounterIncr>(incr).map_err(Box::from))
+        .and_then(|incr| Ok(counter::incr(conn.conn(), &incr)?));
+    conn.json_result(result)
 }
 
-impl Drop for JsonString {
-    fn drop(&mut self) {
-        unsafe {
-            String::from_raw_parts(self.ptr.as_ptr(), self.len, self.cap);
-        }
+/// Appends messages to a queue according to the [queue::QueuePush] (JSON). Returns the number of
+/// pushed messages.
+#[no_mangle]
+extern "C" fn conn_queue_push(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let push = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<queue::QueuePush>(push).map_err(Box::from))
+        .and_then(|push| queue::push(conn.conn(), &push));
+    conn.json_result(result)
+}
+
+/// Removes and returns the oldest messages of a queue according to the [queue::QueuePop] (JSON).
+#[no_mangle]
+extern "C" fn conn_queue_pop_batch(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let pop = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<queue::QueuePop>(pop).map_err(Box::from))
+        .and_then(|pop| queue::pop_batch(conn.conn(), &pop));
+    conn.json_result(result)
+}
+
+/// Acquires or renews the leadership lease according to the [leader::AcquireLeadership] (JSON).
+/// Returns the [leader::Leadership].
+#[no_mangle]
+extern "C" fn conn_try_acquire_leadership(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let acquire = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| {
+            serde_json::from_slice::<leader::AcquireLeadership>(acquire).map_err(Box::from)
+        })
+        .and_then(|acquire| leader::try_acquire(conn.conn(), &acquire));
+    conn.json_result(result)
+}
+
+/// Gives up the leadership lease according to the [leader::ReleaseLeadership] (JSON). Returns
+/// whether the instance held it.
+#[no_mangle]
+extern "C" fn conn_release_leadership(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let release = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| {
+            serde_json::from_slice::<leader::ReleaseLeadership>(release).map_err(Box::from)
+        })
+        .and_then(|release| leader::release(conn.conn(), &release));
+    conn.json_result(result)
+}
+
+/// Creates or replaces a scheduled job according to the [jobs::JobSchedule] (JSON). Returns the
+/// [jobs::ScheduledJob].
+#[no_mangle]
+extern "C" fn conn_job_schedule(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let job = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<jobs::JobSchedule>(job).map_err(Box::from))
+        .and_then(|job| jobs::schedule(conn.conn(), &job));
+    conn.json_result(result)
+}
+
+/// Returns the [jobs::DueJobs] at the time of the [jobs::JobsDue] (JSON) and moves them to their
+/// next run.
+#[no_mangle]
+extern "C" fn conn_jobs_due(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let due = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<jobs::JobsDue>(due).map_err(Box::from))
+        .and_then(|due| jobs::due(conn.conn(), &due));
+    conn.json_result(result)
+}
+
+/// Writes events to the outbox according to the [outbox::OutboxPush] (JSON), as part of the current
+/// transaction. Returns the number of written events.
+#[no_mangle]
+extern "C" fn conn_outbox_push(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let push = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<outbox::OutboxPush>(push).map_err(Box::from))
+        .and_then(|push| outbox::push(conn.conn(), &push));
+    conn.json_result(result)
+}
+
+/// Claims and returns the oldest undispatched events according to the [outbox::OutboxClaim]
+/// (JSON).
+#[no_mangle]
+extern "C" fn conn_outbox_claim(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let claim = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<outbox::OutboxClaim>(claim).map_err(Box::from))
+        .and_then(|claim| outbox::claim(conn.conn(), &claim));
+    conn.json_result(result)
+}
+
+/// Removes dispatched events from the outbox according to the [outbox::OutboxAck] (JSON). Returns
+/// the number of removed events.
+#[no_mangle]
+extern "C" fn conn_outbox_ack(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let ack = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<outbox::OutboxAck>(ack).map_err(Box::from))
+        .and_then(|ack| outbox::ack(conn.conn(), &ack));
+    conn.json_result(result)
+}
+
+/// Creates a full-text index according to the [fts::FtsIndex] (JSON), which is kept in sync with
+/// the table via triggers.
+#[no_mangle]
+extern "C" fn conn_fts_create_index(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let index = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<fts::FtsIndex>(index).map_err(Box::from))
+        .and_then(|index| fts::create_index(conn.conn(), &index));
+    match result {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(err);
+            0
+        }
+    }
+}
+
+/// Starts recording the history of a table according to the [history::EnableHistory] (JSON), to
+/// query it via `Query::as_of`.
+#[no_mangle]
+extern "C" fn conn_enable_history(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let enable = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<history::EnableHistory>(enable).map_err(Box::from))
+        .and_then(|enable| history::enable(conn.conn(), &enable));
+    match result {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(err);
+            0
+        }
+    }
+}
+
+/// Starts recording the changes of a table according to the [cdc::EnableCdc] (JSON), to tail them
+/// via [conn_cdc_poll].
+#[no_mangle]
+extern "C" fn conn_enable_cdc(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let enable = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<cdc::EnableCdc>(enable).map_err(Box::from))
+        .and_then(|enable| cdc::enable(conn.conn(), &enable));
+    match result {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(err);
+            0
+        }
+    }
+}
+
+/// Returns the [cdc::CdcBatch] of change events after the cursor of the [cdc::CdcPoll] (JSON).
+#[no_mangle]
+extern "C" fn conn_cdc_poll(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let poll = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<cdc::CdcPoll>(poll).map_err(Box::from))
+        .and_then(|poll| cdc::poll(conn.conn(), &poll));
+    conn.json_result(result)
+}
+
+/// Returns the rows matching the [fts::FtsSearch] (JSON), best matches first.
+#[no_mangle]
+extern "C" fn conn_fts_search(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let search = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<fts::FtsSearch>(search).map_err(Box::from))
+        .and_then(|search| conn.query(&fts::search_query(&search)));
+    match result {
+        Ok(json) => JsonString::new(json).into_raw(),
+        Err(err) => {
+            conn.fail(err);
+            std::ptr::null()
+        }
+    }
+}
+
+/// Stores an embedding according to the [vector::VecInsert] (JSON).
+#[cfg(feature = "vector")]
+#[no_mangle]
+extern "C" fn conn_vec_insert(conn: *mut Connection, ptr: *const u8, len: usize) -> i32 {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let insert = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<vector::VecInsert>(insert).map_err(Box::from))
+        .and_then(|insert| Ok(vector::insert(conn.conn(), &insert)?));
+    match result {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(err);
+            0
+        }
+    }
+}
+
+/// Returns the nearest neighbors according to the [vector::VecSearch] (JSON).
+#[cfg(feature = "vector")]
+#[no_mangle]
+extern "C" fn conn_vec_search(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let search = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<vector::VecSearch>(search).map_err(Box::from))
+        .and_then(|search| Ok(vector::search(conn.conn(), &search)?));
+    conn.json_result(result)
+}
+
+/// Returns the entries of an R-tree within a radius according to the [geo::WithinRadius] (JSON),
+/// nearest first.
+#[no_mangle]
+extern "C" fn conn_geo_within_radius(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let search = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<geo::WithinRadius>(search).map_err(Box::from))
+        .and_then(|search| Ok(geo::within_radius(conn.conn(), &search)?));
+    conn.json_result(result)
+}
+
+/// Compacts old rows into aggregates according to the [timeseries::Downsample] (JSON).
+#[no_mangle]
+extern "C" fn conn_ts_downsample(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let downsample = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| {
+            serde_json::from_slice::<timeseries::Downsample>(downsample).map_err(Box::from)
+        })
+        .and_then(|downsample| timeseries::downsample(conn.conn(), &downsample));
+    conn.json_result(result)
+}
+
+/// Starts an online backup of the database of `src` into the database of `dst` (e.g. a connection
+/// to a fresh page namespace). Returns null on failure; the error is stored as the last error of
+/// `dst`.
+#[no_mangle]
+extern "C" fn backup_start(src: *mut Connection, dst: *mut Connection) -> *mut Backup {
+    let src: &mut Connection = unsafe { ffi::as_mut(src) };
+    let result = src
+        .resume()
+        .and_then(|()| src.allow_sql())
+        .and_then(|()| unsafe { ffi::as_mut(dst) }.resume())
+        .and_then(|()| unsafe { ffi::as_mut(dst) }.allow_sql())
+        .and_then(|()| Backup::start(src, dst).map_err(Box::from));
+    match result {
+        Ok(backup) => Box::into_raw(Box::new(backup)),
+        Err(err) => {
+            let dst: &mut Connection = unsafe { ffi::as_mut(dst) };
+            dst.fail(err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Copies up to `pages` pages (all remaining ones if negative). Returns `1` if pages remain, `0`
+/// once the backup is complete and `-1` on failure (stored as the last error of the destination).
+#[no_mangle]
+extern "C" fn backup_step(backup: *mut Backup, pages: i32) -> i32 {
+    let backup: &mut Backup = unsafe { ffi::as_mut(backup) };
+    match backup.step(pages) {
+        Ok(StepResult::More) => 1,
+        Ok(StepResult::Done) => 0,
+        Err(err) => {
+            backup.dst().fail(Box::new(err));
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn backup_progress(backup: *mut Backup) -> *const JsonString {
+    let backup: &mut Backup = unsafe { ffi::as_mut(backup) };
+    let progress = serde_json::to_string(&backup.progress()).expect("serialize backup progress");
+    JsonString::new(progress).into_raw()
+}
+
+/// Releases the backup. Returns `0` if a step failed (stored as the last error of the
+/// destination), `1` otherwise.
+#[no_mangle]
+extern "C" fn backup_finish(backup: *mut Backup) -> i32 {
+    let backup = unsafe { ffi::take(backup) };
+    let dst = backup.dst;
+    match backup.finish() {
+        Ok(()) => 1,
+        Err(err) => {
+            let dst: &mut Connection = unsafe { ffi::as_mut(dst) };
+            dst.fail(Box::new(err));
+            0
+        }
+    }
+}
+
+/// Opens a BLOB value (a [BlobTarget], JSON) for incremental I/O. Returns null on failure; the
+/// error is stored as the last error of the connection. The handle must be closed via
+/// [blob_close] before the connection is dropped.
+#[no_mangle]
+extern "C" fn blob_open(conn: *mut Connection, ptr: *const u8, len: usize) -> *mut Blob {
+    let target = unsafe { ffi::payload(ptr, len) };
+    let c: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = c
+        .resume()
+        .and_then(|()| c.allow_sql())
+        .and_then(|()| serde_json::from_slice::<BlobTarget>(target).map_err(Box::from))
+        .and_then(|target| Blob::open(conn, &target).map_err(Box::from));
+    match result {
+        Ok(blob) => {
+            c.open_blobs += 1;
+            Box::into_raw(Box::new(blob))
+        }
+        Err(err) => {
+            c.fail(err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Points the BLOB handle to the value of the same column in another row (rowid as JSON). Returns
+/// `1` on success and `0` on failure (stored as the last error of the connection).
+#[no_mangle]
+extern "C" fn blob_reopen(blob: *mut Blob, ptr: *const u8, len: usize) -> i32 {
+    let blob: &mut Blob = unsafe { ffi::as_mut(blob) };
+    let rowid = unsafe { ffi::payload(ptr, len) };
+    let result = serde_json::from_slice::<i64>(rowid)
+        .map_err(Box::from)
+        .and_then(|rowid| blob.reopen(rowid).map_err(Box::from));
+    match result {
+        Ok(()) => 1,
+        Err(err) => {
+            blob.conn().fail(err);
+            0
+        }
+    }
+}
+
+/// The size of the BLOB in bytes.
+#[no_mangle]
+extern "C" fn blob_size(blob: *mut Blob) -> u32 {
+    let blob: &mut Blob = unsafe { ffi::as_mut(blob) };
+    blob.size()
+}
+
+/// Reads `len` bytes starting at `offset` into `ptr` (allocated via [ffi::alloc]). Returns `1` on
+/// success and `0` on failure (stored as the last error of the connection).
+#[no_mangle]
+extern "C" fn blob_read(blob: *mut Blob, offset: u32, ptr: *mut u8, len: usize) -> i32 {
+    let blob: &mut Blob = unsafe { ffi::as_mut(blob) };
+    let buf: &mut [u8] = if len == 0 {
+        &mut []
+    } else {
+        unsafe { std::slice::from_raw_parts_mut(ptr, len) }
+    };
+    match blob.read(offset, buf) {
+        Ok(()) => 1,
+        Err(err) => {
+            blob.conn().fail(Box::new(err));
+            0
+        }
+    }
+}
+
+/// Writes the `len` bytes at `ptr` starting at `offset` of the BLOB, which must have been opened
+/// as `writable`. Returns `1` on success and `0` on failure (stored as the last error of the
+/// connection).
+#[no_mangle]
+extern "C" fn blob_write(blob: *mut Blob, offset: u32, ptr: *const u8, len: usize) -> i32 {
+    let blob: &mut Blob = unsafe { ffi::as_mut(blob) };
+    let data = unsafe { ffi::payload(ptr, len) };
+    match blob.write(offset, data) {
+        Ok(()) => 1,
+        Err(err) => {
+            blob.conn().fail(Box::new(err));
+            0
+        }
+    }
+}
+
+/// Releases the BLOB handle. Returns `0` if it failed (stored as the last error of the
+/// connection), `1` otherwise.
+#[no_mangle]
+extern "C" fn blob_close(blob: *mut Blob) -> i32 {
+    let blob = unsafe { ffi::take(blob) };
+    let conn: &mut Connection = unsafe { ffi::as_mut(blob.conn) };
+    conn.open_blobs -= 1;
+    match blob.close() {
+        Ok(()) => 1,
+        Err(err) => {
+            conn.fail(Box::new(err));
+            0
+        }
+    }
+}
+
+/// Releases as much memory of the connection as possible: unused page cache memory and cached
+/// statements.
+#[no_mangle]
+extern "C" fn conn_release_memory(conn: *mut Connection) {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    if let Some(c) = &conn.conn {
+        c.flush_prepared_statement_cache();
+        unsafe { rusqlite::ffi::sqlite3_db_release_memory(c.handle()) };
+    }
+}
+
+/// Gathers statistics for the query planner according to the [analyze::AnalyzeOptions] (JSON).
+#[no_mangle]
+extern "C" fn conn_analyze(conn: *mut Connection, ptr: *const u8, len: usize) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let options = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| {
+            serde_json::from_slice::<analyze::AnalyzeOptions>(options).map_err(Box::from)
+        })
+        .and_then(|options| Ok(analyze::analyze(conn.conn(), &options)?));
+    conn.json_result(result)
+}
+
+/// Checks foreign key constraints and the integrity of the tables listed in the
+/// [constraints::CheckOptions] (JSON) without modifying anything.
+#[no_mangle]
+extern "C" fn conn_check_constraints(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let options = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| {
+            serde_json::from_slice::<constraints::CheckOptions>(options).map_err(Box::from)
+        })
+        .and_then(|options| Ok(constraints::check_constraints(conn.conn(), &options)?));
+    conn.json_result(result)
+}
+
+/// Describes the placeholders and result columns of the SQL (a JSON string) without executing it.
+#[no_mangle]
+extern "C" fn conn_parameter_info(
+    conn: *mut Connection,
+    ptr: *const u8,
+    len: usize,
+) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+
+    let sql = unsafe { ffi::payload(ptr, len) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .and_then(|()| serde_json::from_slice::<String>(sql).map_err(Box::from))
+        .and_then(|sql| Ok(meta::parameter_info(conn.conn(), &sql)?));
+    conn.json_result(result)
+}
+
+#[no_mangle]
+extern "C" fn conn_self_test(conn: *mut Connection) -> *const JsonString {
+    let conn: &mut Connection = unsafe { ffi::as_mut(conn) };
+    let result = conn
+        .resume()
+        .and_then(|()| conn.allow_sql())
+        .map(|()| self_test::self_test(conn.conn()));
+    conn.json_result(result)
+}
+
+impl Connection {
+    fn new(conn: rusqlite::Connection, namespace: Option<&'static Namespace>) -> Self {
+        Self {
+            conn: Some(conn),
+            last_error: None,
+            step_budget: StepBudget::default(),
+            limits: Limits::default(),
+            security: Security::default(),
+            profile: None,
+            auto_rollback: true,
+            namespace,
+            options: ConnectionOptions::default(),
+            tag: None,
+            statement: None,
+            queries: Registry::default(),
+            open_blobs: 0,
+            max_db_size: None,
+            row_policies: Box::default(),
+        }
+    }
+
+    /// The underlying database connection. Must only be called after [Connection::resume].
+    fn conn(&self) -> &rusqlite::Connection {
+        self.conn.as_ref().expect("connection is suspended")
+    }
+
+    fn in_transaction(&self) -> bool {
+        self.conn
+            .as_ref()
+            .map_or(false, |conn| !conn.is_autocommit())
+    }
+
+    fn suspend(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let namespace = self.namespace()?;
+        if self.in_transaction() {
+            return Err("cannot suspend a connection inside a transaction".into());
+        }
+        if self.open_blobs > 0 {
+            return Err("cannot suspend a connection with open BLOB handles".into());
+        }
+
+        if let Some(conn) = self.conn.take() {
+            tracing::debug!(vfs = namespace.vfs, "suspend connection");
+            conn.close().map_err(|(conn, err)| {
+                self.conn = Some(conn);
+                err
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reopens the connection if it is suspended. Called by every export before using the
+    /// connection, which also makes its tag the current one (see [tag]).
+    fn resume(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        tag::set_current(self.tag.clone());
+        self.statement = None;
+        if self.conn.is_some() {
+            return Ok(());
+        }
+
+        let namespace = self.namespace()?;
+        tracing::debug!(vfs = namespace.vfs, "resume connection");
+        let conn = open(namespace, &self.options)?;
+        self.step_budget.apply(&conn, &self.limits);
+        self.security.apply(&conn)?;
+        if self.max_db_size.is_some() {
+            limits::apply_max_db_size(&conn, self.max_db_size)?;
+        }
+        if let Some(profile) = self.profile {
+            profile.apply(&conn)?;
+        }
+        if !self.row_policies.is_empty() {
+            self.row_policies.apply(&conn)?;
+        }
+        self.conn = Some(conn);
+        Ok(())
+    }
+
+    /// Fails if the connection is restricted to registered queries, for calls taking SQL and all
+    /// other calls reading or changing rows, tables or the schema by other means.
+    fn allow_sql(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.security.registered_queries_only() {
+            return Err(QueryError::NotRegistered.into());
+        }
+        Ok(())
+    }
+
+    fn into_raw(self) -> *mut Self {
+        Box::into_raw(Box::new(self))
+    }
+
+    /// The page store namespace of the database, or an error for in-memory databases.
+    fn namespace(&self) -> Result<&'static Namespace, NoPageStore> {
+        self.namespace.ok_or(NoPageStore)
+    }
+
+    fn execute(&mut self, query: &Query) -> Result<(), Box<dyn std::error::Error>> {
+        let result = self.execute_statement(query);
+        statement_log::record(
+            &query.sql,
+            &query.params,
+            result.as_ref().err().map(|e| &**e),
+        );
+        result
+    }
+
+    fn execute_statement(&mut self, query: &Query) -> Result<(), Box<dyn std::error::Error>> {
+        let _span = tracing::info_span!(
+            "execute",
+            sql = %query.sql,
+            fingerprint = %fingerprint::fingerprint(&query.sql)
+        )
+        .entered();
+        self.statement = Some(query.sql.clone());
+        let mut stmt = self.conn().prepare(&query.sql())?;
+        query.validate(&stmt)?;
+
+        self.step_budget.reset();
+        if stmt.column_count() > 0 {
+            // Statements returning rows (e.g. `INSERT ... RETURNING id`) are stepped to completion
+            // with their rows discarded, as `execute` fails for them. Use `query` to get the rows.
+            let mut rows = stmt
+                .query(params_from_iter(&query.params))
+                .map_err(|err| self.step_budget.map_err(err))?;
+            while rows
+                .next()
+                .map_err(|err| self.step_budget.map_err(err))?
+                .is_some()
+            {}
+        } else {
+            stmt.execute(params_from_iter(&query.params))
+                .map_err(|err| self.step_budget.map_err(err))?;
+        }
+
+        self.refresh_cdc(&stmt, query)?;
+        Ok(())
+    }
+
+    /// Keeps recording all columns of the tables whose changes are recorded (see [cdc]) after the
+    /// statement altered a table. Must be called after each statement, on every execution path.
+    fn refresh_cdc(
+        &self,
+        stmt: &rusqlite::Statement<'_>,
+        query: &Query,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !stmt.readonly() && fingerprint::normalize(&query.sql).starts_with("alter table ") {
+            cdc::refresh(self.conn())?;
+        }
+        Ok(())
+    }
+
+    fn query(&mut self, query: &Query) -> Result<String, Box<dyn std::error::Error>> {
+        let result = match query.as_of {
+            Some(time) => self.query_as_of(query, time),
+            None => self.query_current(query),
+        };
+        statement_log::record(
+            &query.sql,
+            &query.params,
+            result.as_ref().err().map(|e| &**e),
+        );
+        result
+    }
+
+    /// Runs the query against the rows of tables with a history as of `time`.
+    fn query_as_of(
+        &mut self,
+        query: &Query,
+        time: i64,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let views = history::create_views(self.conn(), time)?;
+        let result = self.query_current(query);
+        let dropped = history::drop_views(self.conn(), &views);
+        let json = result?;
+        dropped?;
+        Ok(json)
+    }
+
+    /// Runs the query against the current rows.
+    fn query_current(&mut self, query: &Query) -> Result<String, Box<dyn std::error::Error>> {
+        let _span = tracing::info_span!(
+            "query",
+            sql = %query.sql,
+            fingerprint = %fingerprint::fingerprint(&query.sql)
+        )
+        .entered();
+        self.statement = Some(query.sql.clone());
+        let mut stmt = self.conn().prepare(&query.sql())?;
+        query.validate(&stmt)?;
+
+        if let Some(Explain::Full) = query.explain {
+            let opcodes = explain::explain(self.conn(), &query.sql(), &query.params)?;
+            return Ok(serde_json::to_string(&opcodes)?);
+        }
+
+        self.step_budget.reset();
+        if query.count_only {
+            let mut rows = stmt
+                .query(params_from_iter(&query.params))
+                .map_err(|err| self.step_budget.map_err(err))?;
+            let mut count = 0u64;
+            while rows
+                .next()
+                .map_err(|err| self.step_budget.map_err(err))?
+                .is_some()
+            {
+                count += 1;
+            }
+            drop(rows);
+            self.refresh_cdc(&stmt, query)?;
+            return Ok(count.to_string());
+        }
+
+        let columns = if query.meta {
+            Some(meta::columns(self.conn(), &query.sql())?)
+        } else {
+            None
+        };
+
+        let names = stmt
+            .column_names()
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let mut rows = stmt
+            .query(params_from_iter(&query.params))
+            .map_err(|err| self.step_budget.map_err(err))?;
+        let json = spill::collect(
+            &names,
+            &mut rows,
+            &self.step_budget,
+            self.limits.max_result_bytes,
+            query.spill,
+        )?;
+        drop(rows);
+        self.refresh_cdc(&stmt, query)?;
+
+        match columns {
+            Some(columns) => {
+                // The statement counters are only complete once all rows were read, so the meta
+                // block is serialized after the rows and both are joined afterwards.
+                let meta = serde_json::to_string(&meta::Meta {
+                    columns,
+                    status: meta::StatementCounters::of(&stmt),
+                })?;
+                Ok(format!(r#"{{"meta":{meta},"rows":{json}}}"#))
+            }
+            None => Ok(json),
+        }
+    }
+
+    /// Stores the error of a failed call as the last error. Adds the violations of failed foreign
+    /// key constraints and the host's message for failed page store calls, and rolls back the
+    /// open transaction, if any and if enabled.
+    fn fail(&mut self, mut err: Box<dyn std::error::Error>) {
+        if let Some(max) = self.max_db_size {
+            err = limits::map_full_error(err, max);
+        }
+        if is_foreign_key_error(err.as_ref()) {
+            if let Some(conn) = &self.conn {
+                match error::foreign_key_violations(conn, None) {
+                    Ok(violations) if !violations.is_empty() => {
+                        err = Box::new(ForeignKeyViolations {
+                            error: err,
+                            violations,
+                        })
+                    }
+                    Ok(_) => {}
+                    Err(check_err) => {
+                        tracing::warn!(error = %check_err, "foreign key check failed")
+                    }
+                }
+            }
+        }
+
+        if let Some(message) = vfs::take_host_error() {
+            if is_io_error(err.as_ref()) {
+                err = Box::new(HostError {
+                    error: err,
+                    message,
+                });
+            }
+        }
+
+        if self.auto_rollback && self.in_transaction() {
+            match self.conn().execute_batch("ROLLBACK") {
+                Ok(()) => err = Box::new(TransactionRolledBack { error: err }),
+                Err(rollback_err) => {
+                    tracing::warn!(error = %rollback_err, "automatic rollback failed")
+                }
+            }
+        }
+        self.last_error = Some(err);
+    }
+
+    /// Runs `f` while holding a write lock on the database, so that no other connection writes
+    /// pages meanwhile.
+    fn with_write_lock<T, E>(
+        &mut self,
+        f: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, Box<dyn std::error::Error>>
+    where
+        E: std::error::Error + 'static,
+    {
+        self.resume()?;
+        self.conn().execute_batch("BEGIN IMMEDIATE")?;
+        let result = f();
+        self.conn().execute_batch("COMMIT")?;
+        Ok(result?)
+    }
+
+    /// Serializes a successful `result` to JSON, or stores the error as the last error and
+    /// returns null.
+    fn json_result<T: Serialize>(
+        &mut self,
+        result: Result<T, Box<dyn std::error::Error>>,
+    ) -> *const JsonString {
+        let value = match result {
+            Ok(value) => value,
+            Err(err) => {
+                self.fail(err);
+                return std::ptr::null();
+            }
+        };
+        match serde_json::to_string(&value) {
+            Ok(json) => JsonString::new(json).into_raw(),
+            Err(err) => {
+                self.fail(Box::new(err));
+                std::ptr::null()
+            }
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn set_write_batching(enabled: i32) {
+    vfs::set_write_batching(enabled != 0);
+}
+
+#[no_mangle]
+extern "C" fn pending_writes() -> *const JsonString {
+    let pending = serde_json::to_string(&vfs::pending_writes()).expect("serialize pending writes");
+    JsonString::new(pending).into_raw()
+}
+
+/// Writes all pending pages to the host. Returns `1` on success, `0` if a write failed, in which
+/// case the writes stay pending.
+#[no_mangle]
+extern "C" fn flush() -> i32 {
+    match vfs::flush() {
+        Ok(()) => 1,
+        Err(err) => {
+            tracing::error!(error = %err, "flush failed");
+            0
+        }
+    }
+}
+
+/// Sets the [d1::ErrorStyle] (JSON string, `native` or `d1`) of error reports. Returns `1` on
+/// success, `0` if the style is invalid.
+#[no_mangle]
+extern "C" fn set_error_style(ptr: *const u8, len: usize) -> i32 {
+    let style = unsafe { ffi::payload(ptr, len) };
+    match serde_json::from_slice::<d1::ErrorStyle>(style) {
+        Ok(style) => {
+            d1::set_style(style);
+            1
+        }
+        Err(err) => {
+            tracing::warn!(error = %err, "invalid error style");
+            0
+        }
+    }
+}
+
+/// Sets how page store calls failing with a transient error are retried according to the
+/// [retry::RetryPolicy] (JSON). Returns `1` on success, `0` if the policy is invalid.
+#[no_mangle]
+extern "C" fn set_retry_policy(ptr: *const u8, len: usize) -> i32 {
+    let policy = unsafe { ffi::payload(ptr, len) };
+    match serde_json::from_slice::<retry::RetryPolicy>(policy) {
+        Ok(policy) => {
+            retry::set_policy(policy);
+            1
+        }
+        Err(err) => {
+            tracing::warn!(error = %err, "invalid retry policy");
+            0
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn discard() {
+    vfs::discard();
+}
+
+#[no_mangle]
+extern "C" fn memory_stats() -> *const JsonString {
+    let stats = serde_json::to_string(&memory::memory_stats()).expect("serialize memory stats");
+    JsonString::new(stats).into_raw()
+}
+
+/// Releases memory not needed anymore after e.g. a burst of large queries, without closing any
+/// connection. See also [conn_release_memory].
+#[no_mangle]
+extern "C" fn release_memory() {
+    vfs::release_memory();
+}
+
+#[no_mangle]
+extern "C" fn set_soft_heap_limit(bytes: u32) {
+    memory::set_soft_heap_limit(bytes);
+}
+
+/// Enables or disables exporting spans (open, lock, read, write, query, ...) to the host via the
+/// `export_span` import.
+#[no_mangle]
+extern "C" fn set_span_export(enabled: i32) {
+    trace::set_export(enabled != 0);
+}
+
+/// Keeps the last `capacity` statements of all connections in a journal, to be read via
+/// [debug_statement_log]. `0` disables the journal.
+#[no_mangle]
+extern "C" fn set_statement_log(capacity: u32) {
+    statement_log::set_capacity(capacity as usize);
+}
+
+/// Returns the statements in the journal (see [set_statement_log]), oldest first.
+#[no_mangle]
+extern "C" fn debug_statement_log() -> *const JsonString {
+    let entries = serde_json::to_string(&statement_log::entries()).expect("serialize entries");
+    JsonString::new(entries).into_raw()
+}
+
+/// Reports statements running longer than `ms` milliseconds to the host's `slow_query` import.
+/// `0` disables the slow-query log.
+#[no_mangle]
+extern "C" fn set_slow_query_threshold(ms: u32) {
+    slow_query::set_threshold(ms);
+}
+
+#[no_mangle]
+extern "C" fn set_replication(enabled: i32) {
+    vfs::set_replication(enabled != 0);
+}
+
+/// Enables or disables recording which pages of the durable database each commit changes (see
+/// [changes]).
+#[no_mangle]
+extern "C" fn set_change_tracking(enabled: i32) {
+    changes::set_enabled(enabled != 0);
+}
+
+/// Applies a delta emitted by a primary instance. Returns `1` if it was applied, `0` if it was
+/// skipped because the page store is already at the same or a later commit and `-1` if the delta
+/// is invalid.
+#[no_mangle]
+extern "C" fn apply_delta(ptr: *const u8, len: usize) -> i32 {
+    let data = unsafe { ffi::payload(ptr, len) };
+    match replication::Delta::decode(data).and_then(|delta| vfs::apply_delta(&delta)) {
+        Ok(true) => 1,
+        Ok(false) => 0,
+        Err(err) => {
+            tracing::warn!(error = %err, "apply_delta failed");
+            -1
+        }
+    }
+}
+
+/// Returns the [fingerprint::Fingerprint] of the SQL (JSON string), or null if the payload is
+/// invalid.
+#[no_mangle]
+extern "C" fn sql_fingerprint(ptr: *const u8, len: usize) -> *const JsonString {
+    let sql = unsafe { ffi::payload(ptr, len) };
+    match serde_json::from_slice::<String>(sql) {
+        Ok(sql) => {
+            let fingerprint = fingerprint::Fingerprint::of(&sql);
+            let json = serde_json::to_string(&fingerprint).expect("serialize fingerprint");
+            JsonString::new(json).into_raw()
+        }
+        Err(err) => {
+            tracing::warn!(error = %err, "invalid sql_fingerprint payload");
+            std::ptr::null()
+        }
+    }
+}
+
+/// Runs the conformance test of the host's page store (see [conformance]) against the namespace
+/// `ns`, which must be empty and not be used by any connection. Returns the
+/// [conformance::ConformanceReport], or null if there is no such namespace.
+#[no_mangle]
+extern "C" fn run_conformance(ns: u32) -> *const JsonString {
+    match Namespace::by_id(ns) {
+        Some(ns) => {
+            let report = serde_json::to_string(&conformance::run(ns)).expect("serialize report");
+            JsonString::new(report).into_raw()
+        }
+        None => {
+            tracing::warn!(ns, "run_conformance of unknown namespace");
+            std::ptr::null()
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn locks_debug() -> *const JsonString {
+    let locks = serde_json::to_string(&vfs::locks_debug()).expect("serialize locks");
+    JsonString::new(locks).into_raw()
+}
+
+struct NamedRows<'a> {
+    names: Vec<String>,
+    rows: RefCell<Rows<'a>>,
+}
+
+impl<'a> Serialize for NamedRows<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeSeq;
+
+        let mut rows = self.rows.borrow_mut();
+        let mut seq = serializer.serialize_seq(None)?;
+        while let Some(row) = rows
+            .next()
+            .map_err(|err| serde::ser::Error::custom(format!("failed to get next row: {err}")))?
+        {
+            let row = NamedRow {
+                names: &self.names,
+                row,
+            };
+            seq.serialize_element(&row)?;
+        }
+        seq.end()
+    }
+}
+
+struct NamedRow<'a> {
+    names: &'a [String],
+    row: &'a Row<'a>,
+}
+
+impl<'a> Serialize for NamedRow<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use rusqlite::types::ValueRef;
+        use serde::ser::SerializeMap;
+
+        // The JSON type is chosen per cell from the value's storage class, as the declared column
+        // type doesn't determine it (e.g. `ANY` columns of STRICT tables, or any column of a
+        // non-STRICT table).
+        let mut map = serializer.serialize_map(Some(self.names.len()))?;
+        for i in 0..self.names.len() {
+            let val = self.row.get_ref_unwrap(i);
+            match val {
+                ValueRef::Null => map.serialize_entry(&self.names[i], &JsonValue::Null)?,
+                ValueRef::Integer(v) => map.serialize_entry(&self.names[i], &v)?,
+                ValueRef::Real(v) => map.serialize_entry(&self.names[i], &v)?,
+                ValueRef::Text(v) => {
+                    let s = String::from_utf8_lossy(v);
+                    map.serialize_entry(&self.names[i], &s)?
+                }
+                ValueRef::Blob(v) => map.serialize_entry(&self.names[i], &v)?,
+            }
+        }
+        map.end()
     }
 }
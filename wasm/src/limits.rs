@@ -0,0 +1,149 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rusqlite::ErrorCode;
+
+/// Number of VM instructions between two invocations of the progress handler.
+const PROGRESS_INTERVAL: i32 = 1000;
+
+/// SQLite's default `max_page_count`, used if no maximum database size is set.
+const DEFAULT_MAX_PAGE_COUNT: i64 = 1073741823;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// The maximum number of VM steps (opcodes) a single statement may execute. The limit is
+    /// enforced with a granularity of 1000 steps.
+    pub max_vm_steps: Option<u64>,
+    /// The maximum size of a query result (in bytes of JSON). Larger results fail, or are spilled
+    /// if the query enables `spill`.
+    pub max_result_bytes: Option<usize>,
+}
+
+/// Counts the VM steps of the currently executing statement via a progress handler and
+/// interrupts the statement once the limit is exceeded.
+#[derive(Default)]
+pub struct StepBudget {
+    steps: Arc<AtomicU64>,
+    max: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum LimitError {
+    VmSteps { max: u64 },
+    ResultSize { max: usize },
+    DatabaseSize { max: u64, error: rusqlite::Error },
+}
+
+/// Limits the size of the database to `max` bytes (rounded down to whole pages) via
+/// `PRAGMA max_page_count`, or removes the limit. SQLite doesn't lower the limit below the current
+/// size of the database, but no more pages can be added.
+pub fn apply_max_db_size(
+    conn: &rusqlite::Connection,
+    max: Option<u64>,
+) -> Result<(), rusqlite::Error> {
+    let max_page_count = match max {
+        Some(max) => {
+            let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+            (max / page_size as u64).max(1) as i64
+        }
+        None => DEFAULT_MAX_PAGE_COUNT,
+    };
+    conn.query_row(
+        &format!("PRAGMA max_page_count = {max_page_count}"),
+        [],
+        |_| Ok(()),
+    )
+}
+
+/// Replaces a `SQLITE_FULL` error (the database reached its `max_page_count`) with a
+/// [LimitError::DatabaseSize].
+pub fn map_full_error(err: Box<dyn Error>, max: u64) -> Box<dyn Error> {
+    let is_full = matches!(
+        err.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(failure, _)) if failure.code == ErrorCode::DiskFull
+    );
+    if !is_full {
+        return err;
+    }
+    match err.downcast::<rusqlite::Error>() {
+        Ok(error) => Box::new(LimitError::DatabaseSize { max, error: *error }),
+        Err(err) => err,
+    }
+}
+
+impl StepBudget {
+    pub fn apply(&mut self, conn: &rusqlite::Connection, limits: &Limits) {
+        self.max = limits.max_vm_steps;
+        match self.max {
+            Some(max) => {
+                let steps = self.steps.clone();
+                conn.progress_handler(
+                    PROGRESS_INTERVAL,
+                    Some(move || {
+                        let interval = PROGRESS_INTERVAL as u64;
+                        steps.fetch_add(interval, Ordering::Relaxed) + interval >= max
+                    }),
+                );
+            }
+            None => conn.progress_handler(0, None::<fn() -> bool>),
+        }
+    }
+
+    /// Must be called before each statement, as the budget applies per statement.
+    pub fn reset(&self) {
+        self.steps.store(0, Ordering::Relaxed);
+    }
+
+    /// Replaces the (generic) interrupted error with a more precise one if the statement failed
+    /// because it exceeded its budget.
+    pub fn map_err<E>(&self, err: E) -> Box<dyn std::error::Error>
+    where
+        E: std::error::Error + 'static,
+    {
+        match self.max {
+            Some(max) if self.steps.load(Ordering::Relaxed) >= max => {
+                Box::new(LimitError::VmSteps { max })
+            }
+            _ => Box::new(err),
+        }
+    }
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::VmSteps { max } => {
+                write!(f, "statement interrupted after exceeding {max} VM steps")
+            }
+            LimitError::ResultSize { max } => {
+                write!(f, "query result exceeds {max} bytes")
+            }
+            LimitError::DatabaseSize { max, .. } => {
+                write!(f, "database is full (maximum size of {max} bytes reached)")
+            }
+        }
+    }
+}
+
+impl LimitError {
+    /// A stable identifier of the error for the host to match on.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LimitError::VmSteps { .. } => "vm_steps",
+            LimitError::ResultSize { .. } => "result_size",
+            LimitError::DatabaseSize { .. } => "database_full",
+        }
+    }
+}
+
+impl std::error::Error for LimitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LimitError::DatabaseSize { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use rusqlite::ffi;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct MemoryStats {
+    /// Bytes currently allocated by SQLite.
+    pub sqlite_used: i64,
+    /// The maximum of `sqlite_used` since the module was instantiated.
+    pub sqlite_highwater: i64,
+    /// Bytes of the above used by the page caches of all connections.
+    pub page_cache: i64,
+    /// Size of the module's linear memory, which never shrinks.
+    pub heap_size: Option<usize>,
+    /// The current soft heap limit (`0` if there is none).
+    pub soft_heap_limit: i64,
+}
+
+pub fn memory_stats() -> MemoryStats {
+    let mut page_cache = 0;
+    let mut page_cache_highwater = 0;
+    unsafe {
+        ffi::sqlite3_status64(
+            ffi::SQLITE_STATUS_PAGECACHE_OVERFLOW,
+            &mut page_cache,
+            &mut page_cache_highwater,
+            0,
+        );
+    }
+
+    MemoryStats {
+        sqlite_used: unsafe { ffi::sqlite3_memory_used() },
+        sqlite_highwater: unsafe { ffi::sqlite3_memory_highwater(0) },
+        page_cache,
+        heap_size: heap_size(),
+        // A negative limit only queries the current one.
+        soft_heap_limit: unsafe { ffi::sqlite3_soft_heap_limit64(-1) },
+    }
+}
+
+/// Sets the soft heap limit, which makes SQLite release page cache memory before allocating more
+/// once the limit is reached. `0` removes the limit.
+pub fn set_soft_heap_limit(bytes: u32) {
+    unsafe { ffi::sqlite3_soft_heap_limit64(bytes.into()) };
+}
+
+#[cfg(target_arch = "wasm32")]
+fn heap_size() -> Option<usize> {
+    const WASM_PAGE_SIZE: usize = 65536;
+    Some(core::arch::wasm32::memory_size(0) * WASM_PAGE_SIZE)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn heap_size() -> Option<usize> {
+    None
+}
@@ -0,0 +1,116 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+use rusqlite::ffi;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Meta {
+    pub columns: Vec<ColumnMeta>,
+    pub status: StatementCounters,
+}
+
+/// Counters of the executed statement (`sqlite3_stmt_status`), e.g. to alert on queries that
+/// start doing full table scans as the data grows.
+#[derive(Debug, Serialize)]
+pub struct StatementCounters {
+    /// The number of steps in full table scans (a high value hints at a missing index).
+    pub fullscan_step: i32,
+    /// The number of sort operations.
+    pub sort: i32,
+    /// The number of rows inserted into automatic (transient) indexes.
+    pub autoindex: i32,
+    /// The number of VM steps.
+    pub vm_step: i32,
+}
+
+impl StatementCounters {
+    pub fn of(stmt: &rusqlite::Statement<'_>) -> Self {
+        use rusqlite::StatementStatus;
+
+        StatementCounters {
+            fullscan_step: stmt.get_status(StatementStatus::FullscanStep),
+            sort: stmt.get_status(StatementStatus::Sort),
+            autoindex: stmt.get_status(StatementStatus::AutoIndex),
+            vm_step: stmt.get_status(StatementStatus::VmStep),
+        }
+    }
+}
+
+/// Describes where a result column originates from. `table` and `origin` are `None` for
+/// expressions that don't directly refer to a table column.
+#[derive(Debug, Serialize)]
+pub struct ColumnMeta {
+    pub name: String,
+    pub database: Option<String>,
+    pub table: Option<String>,
+    pub origin: Option<String>,
+    pub decltype: Option<String>,
+}
+
+/// Describes the placeholders and result columns of a statement without executing it.
+#[derive(Debug, Serialize)]
+pub struct ParameterInfo {
+    pub count: usize,
+    /// The name of each placeholder including its prefix (e.g. `:id`), `None` for anonymous (`?`)
+    /// placeholders.
+    pub names: Vec<Option<String>>,
+    pub columns: Vec<ColumnMeta>,
+}
+
+/// Prepares `sql` to describe its placeholders and result columns.
+pub fn parameter_info(
+    conn: &rusqlite::Connection,
+    sql: &str,
+) -> Result<ParameterInfo, rusqlite::Error> {
+    let stmt = conn.prepare(sql)?;
+    let count = stmt.parameter_count();
+    let names = (1..=count)
+        .map(|i| stmt.parameter_name(i).map(String::from))
+        .collect();
+
+    Ok(ParameterInfo {
+        count,
+        names,
+        columns: columns(conn, sql)?,
+    })
+}
+
+/// Collects the column metadata of `sql`. rusqlite doesn't expose the origin of columns, so the
+/// statement is prepared a second time through the raw SQLite API (requires SQLite to be compiled
+/// with `SQLITE_ENABLE_COLUMN_METADATA`).
+pub fn columns(conn: &rusqlite::Connection, sql: &str) -> Result<Vec<ColumnMeta>, rusqlite::Error> {
+    let sql = CString::new(sql)?;
+
+    unsafe {
+        let db = conn.handle();
+        let mut stmt = std::ptr::null_mut();
+        let rc = ffi::sqlite3_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, std::ptr::null_mut());
+        if rc != ffi::SQLITE_OK {
+            let message = to_string(ffi::sqlite3_errmsg(db));
+            return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), message));
+        }
+
+        let count = ffi::sqlite3_column_count(stmt);
+        let columns = (0..count)
+            .map(|i| ColumnMeta {
+                name: to_string(ffi::sqlite3_column_name(stmt, i)).unwrap_or_default(),
+                database: to_string(ffi::sqlite3_column_database_name(stmt, i)),
+                table: to_string(ffi::sqlite3_column_table_name(stmt, i)),
+                origin: to_string(ffi::sqlite3_column_origin_name(stmt, i)),
+                decltype: to_string(ffi::sqlite3_column_decltype(stmt, i)),
+            })
+            .collect();
+        ffi::sqlite3_finalize(stmt);
+
+        Ok(columns)
+    }
+}
+
+unsafe fn to_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+    }
+}
@@ -0,0 +1,155 @@
+use std::cell::RefCell;
+use std::io;
+
+use sqlite_vfs::{register, RegisterError};
+
+use crate::vfs::{PageVerification, Reconciliation};
+use crate::PagesVfs;
+
+/// A [PagesVfs] registered under its own name, which routes its pages to its own namespace of the
+/// host's page store.
+#[derive(Debug)]
+pub struct Namespace {
+    pub vfs: &'static str,
+    pub id: u32,
+    pub page_size: usize,
+}
+
+/// All registered VFSs. The first one is the default VFS.
+pub static NAMESPACES: [Namespace; 4] = [
+    // The durable database.
+    Namespace {
+        vfs: "cfdo",
+        id: 0,
+        page_size: 4096,
+    },
+    // An ephemeral cache database. Its rows are small and frequently rewritten, so smaller pages
+    // keep the amount of data written to the host per change low.
+    Namespace {
+        vfs: "cfdo-cache",
+        id: 1,
+        page_size: 1024,
+    },
+    // Scratch space for results spilled by queries exceeding `Limits::max_result_bytes`. Its
+    // content is discarded on start, so hosts can back it with a temporary file.
+    Namespace {
+        vfs: "cfdo-spill",
+        id: 2,
+        page_size: 4096,
+    },
+    // The destination of `conn_salvage`, which copies what is left of a corrupted database into
+    // it. Only written to when salvaging, which requires it to be empty.
+    Namespace {
+        vfs: "cfdo-salvage",
+        id: 3,
+        page_size: 4096,
+    },
+];
+
+/// Namespaces starting at this id hold branches of a database (see [crate::fork]), each registered
+/// as VFS `cfdo-branch-<id>` once used.
+pub const FIRST_BRANCH: u32 = 16;
+const BRANCH_PREFIX: &str = "cfdo-branch-";
+
+thread_local! {
+    /// The branch namespaces registered so far. Leaked, as registered VFSs are never unregistered.
+    static BRANCHES: RefCell<Vec<&'static Namespace>> = RefCell::new(Vec::new());
+}
+
+/// Calls the associated function `$f` of the [PagesVfs] with the namespace's page size.
+macro_rules! with_page_size {
+    ($page_size:expr, $f:ident($($arg:expr),*)) => {
+        match $page_size {
+            1024 => PagesVfs::<1024>::$f($($arg),*),
+            4096 => PagesVfs::<4096>::$f($($arg),*),
+            page_size => unreachable!("unsupported page size {page_size}"),
+        }
+    };
+}
+
+impl Namespace {
+    pub fn main() -> &'static Namespace {
+        &NAMESPACES[0]
+    }
+
+    pub fn spill() -> &'static Namespace {
+        &NAMESPACES[2]
+    }
+
+    pub fn salvage() -> &'static Namespace {
+        &NAMESPACES[3]
+    }
+
+    pub fn find(vfs: &str) -> Option<&'static Namespace> {
+        if let Some(ns) = NAMESPACES.iter().find(|ns| ns.vfs == vfs) {
+            return Some(ns);
+        }
+        let id = vfs.strip_prefix(BRANCH_PREFIX)?.parse().ok()?;
+        Self::branch(id).ok()
+    }
+
+    pub fn by_id(id: u32) -> Option<&'static Namespace> {
+        NAMESPACES.iter().find(|ns| ns.id == id).or_else(|| {
+            BRANCHES.with(|branches| branches.borrow().iter().find(|ns| ns.id == id).copied())
+        })
+    }
+
+    /// The branch namespace `id` (see [FIRST_BRANCH]), registering its VFS on first use. Branches
+    /// have the page size of the durable database.
+    pub fn branch(id: u32) -> Result<&'static Namespace, Box<dyn std::error::Error>> {
+        if id < FIRST_BRANCH {
+            return Err(format!("branch namespaces start at {FIRST_BRANCH}, got {id}").into());
+        }
+        if let Some(ns) = Self::by_id(id) {
+            return Ok(ns);
+        }
+
+        let ns: &'static Namespace = Box::leak(Box::new(Namespace {
+            vfs: Box::leak(format!("{BRANCH_PREFIX}{id}").into_boxed_str()),
+            id,
+            page_size: Self::main().page_size,
+        }));
+        if let Err(err) = ns.register(false) {
+            let reason = match err {
+                RegisterError::Nul(_) => "nul byte in name".to_string(),
+                RegisterError::Register(code) => format!("error code {code}"),
+            };
+            return Err(format!("failed to register vfs `{}`: {reason}", ns.vfs).into());
+        }
+        BRANCHES.with(|branches| branches.borrow_mut().push(ns));
+        Ok(ns)
+    }
+
+    pub fn register(&self, as_default: bool) -> Result<(), RegisterError> {
+        match self.page_size {
+            1024 => register(self.vfs, PagesVfs::<1024>::new(self.id), as_default),
+            4096 => register(self.vfs, PagesVfs::<4096>::new(self.id), as_default),
+            page_size => unreachable!("unsupported page size {page_size}"),
+        }
+    }
+
+    pub fn page_count(&self) -> usize {
+        with_page_size!(self.page_size, page_count(self.id))
+    }
+
+    pub fn commit_counter(&self) -> Result<Option<u32>, io::Error> {
+        with_page_size!(self.page_size, commit_counter(self.id))
+    }
+
+    pub fn stored_page_size(&self) -> Result<Option<usize>, io::Error> {
+        with_page_size!(self.page_size, stored_page_size(self.id))
+    }
+
+    pub fn reconcile_storage(&self) -> Result<Reconciliation, io::Error> {
+        with_page_size!(self.page_size, reconcile_storage(self.id))
+    }
+
+    pub fn verify_pages(&self) -> Result<PageVerification, io::Error> {
+        with_page_size!(self.page_size, verify_pages(self.id))
+    }
+
+    /// Reads the pages ahead of SQLite asking for them, see [crate::vfs::prefetch].
+    pub fn prefetch(&self, ixs: &[u32]) -> Result<usize, io::Error> {
+        crate::vfs::prefetch(self.id, self.page_size, ixs)
+    }
+}
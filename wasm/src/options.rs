@@ -0,0 +1,110 @@
+use rusqlite::OpenFlags;
+
+use crate::freelist::AutoVacuum;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionOptions {
+    /// Open the database read-only.
+    pub read_only: bool,
+    /// Create the database if it doesn't exist yet. If disabled, opening a database that doesn't
+    /// exist fails.
+    pub create: bool,
+    /// Open the database using an URI filename (e.g. `file:main.db?mode=ro`) instead of `main.db`.
+    pub uri: Option<String>,
+    /// The registered VFS (and thus page store namespace) to open the database with, e.g.
+    /// `cfdo-cache`. Defaults to `cfdo`.
+    pub vfs: Option<String>,
+    /// Enforce foreign key constraints (`PRAGMA foreign_keys`). Enabled by default.
+    pub foreign_keys: bool,
+    /// Let triggers fire other triggers, including themselves, and fire delete triggers for rows
+    /// removed by `REPLACE` conflict resolution (`PRAGMA recursive_triggers`).
+    pub recursive_triggers: bool,
+    /// Allow reading and writing raw database pages through the `sqlite_dbpage` virtual table.
+    /// Writes bypass all of SQLite's consistency checks and can corrupt the database, so this is
+    /// only meant for backup and repair tooling. Disabled by default.
+    pub dangerous_raw_pages: bool,
+    /// SQL executed after opening the database (and after reopening a suspended connection), e.g.
+    /// `PRAGMA cache_size = -8000` or `PRAGMA temp_store = MEMORY`. Must not change the
+    /// `journal_mode`.
+    pub on_open_sql: Vec<String>,
+    /// Whether commits wait for the host to persist the written pages (`PRAGMA synchronous`).
+    /// Defaults to `full`.
+    pub synchronous: Synchronous,
+    /// How long a write waits for other connections of the same page store to release the write
+    /// lock (in milliseconds), sleeping via the host's `conn_sleep` in between. Waiting writers
+    /// get the lock in the order they asked for it. Defaults to `0`, which fails with
+    /// `SQLITE_BUSY` right away.
+    pub busy_timeout_ms: u32,
+    /// Allow opening databases with a UTF-16 text encoding (e.g. files imported into the page
+    /// store), which are rejected by default. Such databases can be converted to UTF-8 via
+    /// `conn_salvage`, which copies them into a fresh database.
+    pub allow_utf16: bool,
+    /// Whether free pages are deleted from the page store (`PRAGMA auto_vacuum`): `full` at every
+    /// commit, `incremental` via `conn_reclaim_free_pages`. Can only be set when the database is
+    /// created, so it is ignored for existing databases. Defaults to `none`.
+    pub auto_vacuum: Option<AutoVacuum>,
+}
+
+/// How `PRAGMA synchronous` maps onto the host's durability. SQLite only calls the VFS's sync
+/// (and thus the host's `storage_sync`) if it is not `off`. As the journal is kept in memory,
+/// `normal`, `full` and `extra` all sync once per commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    /// Never calls `storage_sync`; the host's storage transaction is committed once the write
+    /// lock is released, and it is up to the host when buffered writes are persisted. Trades
+    /// durability of the latest commits for latency.
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
+}
+
+impl ConnectionOptions {
+    pub fn open_flags(&self) -> OpenFlags {
+        let mut flags = OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        if self.read_only {
+            flags |= OpenFlags::SQLITE_OPEN_READ_ONLY;
+        } else {
+            flags |= OpenFlags::SQLITE_OPEN_READ_WRITE;
+            if self.create {
+                flags |= OpenFlags::SQLITE_OPEN_CREATE;
+            }
+        }
+        if self.uri.is_some() {
+            flags |= OpenFlags::SQLITE_OPEN_URI;
+        }
+        flags
+    }
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            create: true,
+            uri: None,
+            vfs: None,
+            foreign_keys: true,
+            recursive_triggers: false,
+            dangerous_raw_pages: false,
+            on_open_sql: Vec::new(),
+            synchronous: Synchronous::Full,
+            busy_timeout_ms: 0,
+            allow_utf16: false,
+            auto_vacuum: None,
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Transactional outbox: events are written to `_wasm_sqlite_outbox` in the same transaction as
+//! the changes they describe, and dispatched afterwards by claiming them for a lease. Claimed
+//! events are redelivered once their lease expires without being acknowledged, e.g. because the
+//! isolate was evicted while publishing them.
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use crate::history::NOW;
+
+/// The table backing the outbox. Payloads are stored as JSON text.
+const TABLE: &str = "_wasm_sqlite_outbox";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutboxPush {
+    pub topic: String,
+    pub payloads: Vec<JsonValue>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutboxClaim {
+    /// The maximum number of events to claim.
+    pub batch: u32,
+    /// For how long the events are claimed, in milliseconds.
+    pub lease_ms: u32,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutboxAck {
+    pub ids: Vec<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutboxEvent {
+    /// Increases with every written event.
+    pub id: i64,
+    pub topic: String,
+    pub payload: JsonValue,
+    /// How often the event has been claimed, including this time.
+    pub attempts: u32,
+}
+
+/// Writes the payloads to the outbox in a single savepoint, so that they are part of the current
+/// transaction (if any). Returns the number of written events.
+pub fn push(conn: &Connection, push: &OutboxPush) -> Result<usize, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    conn.execute_batch("SAVEPOINT outbox_push")?;
+    match push_payloads(conn, push) {
+        Ok(count) => {
+            conn.execute_batch("RELEASE outbox_push")?;
+            Ok(count)
+        }
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TO outbox_push; RELEASE outbox_push")
+                .ok();
+            Err(err)
+        }
+    }
+}
+
+fn push_payloads(
+    conn: &Connection,
+    push: &OutboxPush,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "INSERT INTO {TABLE} (topic, payload) VALUES (?, ?)"
+    ))?;
+    for payload in &push.payloads {
+        stmt.execute(params![push.topic, serde_json::to_string(payload)?])?;
+    }
+    Ok(push.payloads.len())
+}
+
+/// Claims and returns up to `batch` of the oldest events that are neither acknowledged nor
+/// claimed by an unexpired lease. Claiming happens in a single statement, so an event is never
+/// claimed twice at the same time.
+pub fn claim(
+    conn: &Connection,
+    claim: &OutboxClaim,
+) -> Result<Vec<OutboxEvent>, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    let mut stmt = conn.prepare_cached(&format!(
+        "UPDATE {TABLE} SET leased_until = {NOW} + ?2, attempts = attempts + 1 WHERE id IN \
+         (SELECT id FROM {TABLE} WHERE leased_until IS NULL OR leased_until <= {NOW} \
+          ORDER BY id LIMIT ?1) \
+         RETURNING id, topic, payload, attempts"
+    ))?;
+    let mut rows = stmt.query(params![claim.batch, claim.lease_ms])?;
+
+    let mut events = Vec::new();
+    while let Some(row) = rows.next()? {
+        let payload: String = row.get(2)?;
+        events.push(OutboxEvent {
+            id: row.get(0)?,
+            topic: row.get(1)?,
+            payload: serde_json::from_str(&payload)?,
+            attempts: row.get(3)?,
+        });
+    }
+    // The order of the rows returned by `RETURNING` is unspecified.
+    events.sort_by_key(|event| event.id);
+    Ok(events)
+}
+
+/// Removes the dispatched events from the outbox. Returns the number of removed events, which is
+/// less than the number of ids if some of them were already acknowledged.
+pub fn ack(conn: &Connection, ack: &OutboxAck) -> Result<usize, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    conn.execute_batch("SAVEPOINT outbox_ack")?;
+    match delete_ids(conn, &ack.ids) {
+        Ok(count) => {
+            conn.execute_batch("RELEASE outbox_ack")?;
+            Ok(count)
+        }
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TO outbox_ack; RELEASE outbox_ack")
+                .ok();
+            Err(err.into())
+        }
+    }
+}
+
+fn delete_ids(conn: &Connection, ids: &[i64]) -> Result<usize, rusqlite::Error> {
+    let mut stmt = conn.prepare_cached(&format!("DELETE FROM {TABLE} WHERE id = ?"))?;
+    let mut count = 0;
+    for id in ids {
+        count += stmt.execute([id])?;
+    }
+    Ok(count)
+}
+
+fn create_table(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.prepare_cached(&format!(
+        "CREATE TABLE IF NOT EXISTS {TABLE} (\
+         id INTEGER PRIMARY KEY AUTOINCREMENT, topic TEXT NOT NULL, payload TEXT NOT NULL, \
+         attempts INTEGER NOT NULL DEFAULT 0, leased_until INTEGER)"
+    ))?
+    .execute([])?;
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+/// The role of each page of the database, to understand which tables and indexes the pages in the
+/// page store belong to.
+#[derive(Debug, Serialize)]
+pub struct PageMap {
+    pub page_count: u32,
+    /// Ordered by page index (as used by the page store, i.e. 0-based).
+    pub pages: Vec<PageEntry>,
+    /// The number of pages per table or index, largest first.
+    pub objects: Vec<ObjectPages>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageEntry {
+    pub index: u32,
+    pub role: PageRole,
+    /// The table or index the page belongs to. `None` for free pages.
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageRole {
+    /// The first page, containing the database header and the root of `sqlite_schema`.
+    Header,
+    /// The root page of a table's or index's B-tree.
+    Root,
+    Interior,
+    Leaf,
+    /// Overflow pages of values that don't fit into their B-tree page.
+    Overflow,
+    /// Pages not part of any B-tree (pages on the freelist, or pointer-map pages if auto-vacuum
+    /// is enabled).
+    Free,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectPages {
+    pub name: String,
+    pub pages: u32,
+}
+
+/// Walks the B-trees of all tables and indexes (via the `dbstat` virtual table).
+pub fn page_map(conn: &rusqlite::Connection) -> Result<PageMap, rusqlite::Error> {
+    let page_count: u32 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+
+    let mut stmt =
+        conn.prepare("SELECT pageno, name, path, pagetype FROM dbstat('main') ORDER BY pageno")?;
+    let mut pages = stmt
+        .query_map([], |row| {
+            let pageno: u32 = row.get(0)?;
+            let path: String = row.get(2)?;
+            let pagetype: String = row.get(3)?;
+            let role = match pagetype.as_str() {
+                _ if pageno == 1 => PageRole::Header,
+                "overflow" => PageRole::Overflow,
+                _ if path == "/" => PageRole::Root,
+                "internal" => PageRole::Interior,
+                _ => PageRole::Leaf,
+            };
+            Ok((pageno - 1, (role, row.get::<_, String>(1)?)))
+        })?
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+    let mut objects = BTreeMap::<&str, u32>::new();
+    for (_, name) in pages.values() {
+        *objects.entry(name).or_default() += 1;
+    }
+    let mut objects = objects
+        .into_iter()
+        .map(|(name, pages)| ObjectPages {
+            name: name.to_string(),
+            pages,
+        })
+        .collect::<Vec<_>>();
+    objects.sort_by(|a, b| b.pages.cmp(&a.pages));
+
+    let pages = (0..page_count)
+        .map(|index| match pages.remove(&index) {
+            Some((role, name)) => PageEntry {
+                index,
+                role,
+                name: Some(name),
+            },
+            None => PageEntry {
+                index,
+                role: PageRole::Free,
+                name: None,
+            },
+        })
+        .collect();
+
+    Ok(PageMap {
+        page_count,
+        pages,
+        objects,
+    })
+}
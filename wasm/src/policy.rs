@@ -0,0 +1,273 @@
+//! Row-level security: a predicate per table that all statements of a connection are subject to
+//! (see `conn_set_row_policy`), so that multi-tenant deployments sharing a database can't leak
+//! rows of other tenants because of a missing `WHERE` clause.
+//!
+//! Each table with a policy is shadowed by a temporary view of the same name, which only contains
+//! the rows matching the predicate, and whose `INSTEAD OF` triggers write through to the table
+//! (rejecting rows not matching the predicate). An authorizer denies any other access to the
+//! table, e.g. via `main.<table>`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::os::raw::c_int;
+
+use rusqlite::{ffi, Connection, OptionalExtension};
+
+use crate::copy::quote;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RowPolicy {
+    pub table: String,
+    /// An SQL expression over the columns of the table, e.g. `tenant_id = 42`. Rows not matching
+    /// it can neither be read nor written. `None` removes the policy.
+    pub predicate: Option<String>,
+}
+
+/// The row policies of a connection, keyed by table name.
+#[derive(Default)]
+pub struct RowPolicies {
+    policies: RefCell<BTreeMap<String, String>>,
+    /// Set while the views and triggers are (re)created, which access the tables directly.
+    installing: Cell<bool>,
+}
+
+const TRIGGERS: [&str; 3] = ["policy_insert", "policy_update", "policy_delete"];
+
+impl RowPolicies {
+    pub fn is_empty(&self) -> bool {
+        self.policies.borrow().is_empty()
+    }
+
+    /// Sets (or removes) the policy of a table and applies it to `conn`.
+    pub fn set(
+        &self,
+        conn: &Connection,
+        policy: RowPolicy,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let predicate = match policy.predicate {
+            Some(predicate) => predicate,
+            None => {
+                let existing = self
+                    .policies
+                    .borrow()
+                    .keys()
+                    .find(|table| table.eq_ignore_ascii_case(&policy.table))
+                    .cloned();
+                if let Some(table) = existing {
+                    self.installing(|| uninstall(conn, &table))?;
+                    self.policies.borrow_mut().remove(&table);
+                }
+                return Ok(());
+            }
+        };
+
+        let table = conn
+            .query_row(
+                "SELECT name FROM main.sqlite_master
+                 WHERE type = 'table' AND name = ? COLLATE NOCASE",
+                [&policy.table],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+            .ok_or_else(|| format!("no such table: {}", policy.table))?;
+        self.installing(|| install(conn, &table, &predicate))?;
+        self.policies.borrow_mut().insert(table, predicate);
+        crate::security::authorize_row_policies(conn, self)?;
+        Ok(())
+    }
+
+    /// Creates the views and triggers of all policies, e.g. after reopening a suspended
+    /// connection.
+    pub fn apply(&self, conn: &Connection) -> Result<(), rusqlite::Error> {
+        for (table, predicate) in self.policies.borrow().iter() {
+            self.installing(|| install(conn, table, predicate))?;
+        }
+        crate::security::authorize_row_policies(conn, self)
+    }
+
+    fn installing<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.installing.set(true);
+        let result = f();
+        self.installing.set(false);
+        result
+    }
+
+    /// Whether the access reported to the authorizer is allowed. `accessor` is the innermost view
+    /// or trigger responsible for the access.
+    pub fn authorize(
+        &self,
+        action: c_int,
+        arg1: Option<&str>,
+        arg2: Option<&str>,
+        database: Option<&str>,
+        accessor: Option<&str>,
+    ) -> bool {
+        if self.installing.get() {
+            return true;
+        }
+
+        let policies = self.policies.borrow();
+        let policy_table = |name: Option<&str>| {
+            name.and_then(|name| {
+                policies
+                    .keys()
+                    .find(|table| table.eq_ignore_ascii_case(name))
+            })
+        };
+        let is_trigger_of = |table: &str, name: &str| {
+            TRIGGERS
+                .iter()
+                .any(|suffix| trigger_name(table, suffix).eq_ignore_ascii_case(name))
+        };
+        let is_policy_trigger = |name: Option<&str>| {
+            name.map_or(false, |name| {
+                policies.keys().any(|table| is_trigger_of(table, name))
+            })
+        };
+
+        match action {
+            ffi::SQLITE_READ | ffi::SQLITE_INSERT | ffi::SQLITE_UPDATE | ffi::SQLITE_DELETE => {
+                let table = match policy_table(arg1) {
+                    Some(table) if database == Some("main") => table,
+                    _ => return true,
+                };
+                // Only the view and its triggers may access the table.
+                accessor.map_or(false, |accessor| {
+                    accessor.eq_ignore_ascii_case(table) || is_trigger_of(table, accessor)
+                })
+            }
+            // Neither the views nor their triggers may be replaced, and the tables not renamed.
+            ffi::SQLITE_DROP_TEMP_VIEW => policy_table(arg1).is_none(),
+            // A trigger named after the table would be treated as the view accessing the table.
+            ffi::SQLITE_CREATE_TRIGGER | ffi::SQLITE_CREATE_TEMP_TRIGGER => {
+                !is_policy_trigger(arg1) && policy_table(arg1).is_none()
+            }
+            ffi::SQLITE_DROP_TRIGGER | ffi::SQLITE_DROP_TEMP_TRIGGER => !is_policy_trigger(arg1),
+            ffi::SQLITE_ALTER_TABLE => policy_table(arg2).is_none(),
+            _ => true,
+        }
+    }
+}
+
+fn trigger_name(table: &str, suffix: &str) -> String {
+    format!("{table}_{suffix}")
+}
+
+fn install(conn: &Connection, table: &str, predicate: &str) -> Result<(), rusqlite::Error> {
+    let columns = conn
+        .prepare("SELECT name, dflt_value, pk FROM pragma_table_info(?, 'main')")?
+        .query_map([table], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, i64>(2)? > 0,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let list = |f: fn(&str, Option<&str>) -> String, sep: &str| {
+        columns
+            .iter()
+            .map(|(name, default, _)| f(&quote(name), default.as_deref()))
+            .collect::<Vec<_>>()
+            .join(sep)
+    };
+    let names = list(|name, _| name.to_string(), ", ");
+    // Columns omitted from an insert into a view are `NULL`, so fall back to their defaults.
+    let inserted = list(
+        |name, default| match default {
+            Some(default) => format!("coalesce(new.{name}, {default}) AS {name}"),
+            None => format!("new.{name} AS {name}"),
+        },
+        ", ",
+    );
+    let updated = list(|name, _| format!("new.{name} AS {name}"), ", ");
+    let assignments = list(|name, _| format!("{name} = new.{name}"), ", ");
+    let values = list(
+        |name, default| match default {
+            Some(default) => format!("coalesce(new.{name}, {default})"),
+            None => format!("new.{name}"),
+        },
+        ", ",
+    );
+    // Identify the old row by its primary key, or by all its values (and then only one of
+    // identical rows) if it has none.
+    let old_row = if columns.iter().any(|(_, _, pk)| *pk) {
+        columns
+            .iter()
+            .filter(|(_, _, pk)| *pk)
+            .map(|(name, _, _)| format!("{0} IS old.{0}", quote(name)))
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    } else {
+        format!(
+            "rowid = (SELECT rowid FROM main.{} WHERE {} LIMIT 1)",
+            quote(table),
+            list(|name, _| format!("{name} IS old.{name}"), " AND ")
+        )
+    };
+    // Fails unless the row (as the columns of a subquery) matches the predicate.
+    let check = |row: &str| {
+        format!(
+            "SELECT RAISE(ABORT, {}) WHERE NOT EXISTS (
+               SELECT 1 FROM (SELECT {row}) AS {} WHERE ({predicate})
+             )",
+            literal(&format!("row violates the row policy of {table}")),
+            quote(table),
+        )
+    };
+
+    let result = conn.execute_batch(&format!(
+        "SAVEPOINT row_policy;
+         {drop}
+         CREATE TEMP VIEW {view} AS SELECT * FROM main.{view} WHERE ({predicate});
+         CREATE TEMP TRIGGER {insert} INSTEAD OF INSERT ON {view} BEGIN
+           {check_insert};
+           INSERT INTO main.{view} ({names}) VALUES ({values});
+         END;
+         CREATE TEMP TRIGGER {update} INSTEAD OF UPDATE ON {view} BEGIN
+           {check_update};
+           UPDATE main.{view} SET {assignments} WHERE {old_row};
+         END;
+         CREATE TEMP TRIGGER {delete} INSTEAD OF DELETE ON {view} BEGIN
+           DELETE FROM main.{view} WHERE {old_row};
+         END;
+         RELEASE row_policy;",
+        drop = drop_sql(table),
+        view = quote(table),
+        insert = quote(&trigger_name(table, TRIGGERS[0])),
+        update = quote(&trigger_name(table, TRIGGERS[1])),
+        delete = quote(&trigger_name(table, TRIGGERS[2])),
+        check_insert = check(&inserted),
+        check_update = check(&updated),
+    ));
+    if result.is_err() {
+        conn.execute_batch("ROLLBACK TO row_policy; RELEASE row_policy")
+            .ok();
+    }
+    result
+}
+
+fn uninstall(conn: &Connection, table: &str) -> Result<(), rusqlite::Error> {
+    conn.execute_batch(&drop_sql(table))
+}
+
+fn drop_sql(table: &str) -> String {
+    let mut sql = TRIGGERS
+        .iter()
+        .map(|suffix| {
+            format!(
+                "DROP TRIGGER IF EXISTS temp.{};",
+                quote(&trigger_name(table, suffix))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    sql.push_str(&format!("\nDROP VIEW IF EXISTS temp.{};", quote(table)));
+    sql
+}
+
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
@@ -0,0 +1,41 @@
+use serde::Serialize;
+
+use crate::namespace::Namespace;
+
+#[derive(Debug, Serialize)]
+pub struct PrewarmReport {
+    /// The tables and indexes whose root pages were loaded.
+    pub objects: usize,
+    /// The pages read from the host in the batched read (pages SQLite already cached are skipped).
+    pub pages: usize,
+}
+
+/// Loads the schema and then the root pages of the given tables and their indexes (of all tables if
+/// `tables` is empty) in a single batched host read, so that the first queries after a cold start
+/// don't need a round trip to the host for each of them.
+pub fn prewarm(
+    conn: &rusqlite::Connection,
+    namespace: &Namespace,
+    tables: &[String],
+) -> Result<PrewarmReport, Box<dyn std::error::Error>> {
+    let _span = tracing::debug_span!("prewarm", tables = tables.len()).entered();
+
+    // Reading the schema loads its pages into SQLite's page cache.
+    let mut stmt = conn.prepare(
+        "SELECT rootpage FROM sqlite_schema
+         WHERE rootpage > 0 AND (?1 = '[]' OR tbl_name IN (SELECT value FROM json_each(?1)))",
+    )?;
+    let roots = stmt
+        .query_map([serde_json::to_string(tables)?], |row| row.get::<_, u32>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Page numbers are 1-based, page store indices 0-based.
+    let ixs = roots.iter().map(|pgno| pgno - 1).collect::<Vec<_>>();
+    let pages = namespace.prefetch(&ixs)?;
+    tracing::debug!(objects = roots.len(), pages, "prewarmed");
+
+    Ok(PrewarmReport {
+        objects: roots.len(),
+        pages,
+    })
+}
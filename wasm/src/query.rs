@@ -0,0 +1,263 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+use rusqlite::Statement;
+use serde_json::Value as JsonValue;
+
+use crate::explain::Explain;
+
+/// The newest version of the query payload understood by this module. Payloads without a `v`
+/// field are version 1, which only differs from version 2 by the missing field.
+pub const PAYLOAD_VERSION: u32 = 2;
+
+/// The versioned envelope around a [Query] (`{"v": 2, "sql": ..., ...}`).
+#[derive(serde::Deserialize)]
+struct Envelope {
+    #[serde(default = "v1")]
+    v: u32,
+    #[serde(flatten)]
+    query: Query,
+}
+
+fn v1() -> u32 {
+    1
+}
+
+#[derive(serde::Deserialize)]
+pub struct Query {
+    pub sql: String,
+    pub params: Vec<JsonValue>,
+    /// Requires the number of params to match the number of placeholders, and rejects inline
+    /// string literals in the SQL if params are provided (a hint that values were concatenated
+    /// into the SQL instead of bound).
+    #[serde(default)]
+    pub strict: bool,
+    /// Return the rows together with metadata about the result (`{"meta": ..., "rows": ...}`).
+    #[serde(default)]
+    pub meta: bool,
+    /// Rejects the statement before it is executed if it could modify the database (as reported
+    /// by `sqlite3_stmt_readonly`), e.g. for code paths that should only ever read.
+    #[serde(default)]
+    pub readonly: bool,
+    /// Spill the rows into a scratch database if the result exceeds `Limits::max_result_bytes`,
+    /// and return a cursor to drain them (see [crate::spill]) instead of failing.
+    #[serde(default)]
+    pub spill: bool,
+    /// Only return the number of rows (a JSON number). The rows are stepped through without
+    /// reading or serializing their values, e.g. for existence or size checks. `meta` and `spill`
+    /// are ignored.
+    #[serde(default)]
+    pub count_only: bool,
+    /// Return the `EXPLAIN` output of the statement instead of running it (see
+    /// [crate::explain]).
+    #[serde(default)]
+    pub explain: Option<Explain>,
+    /// Run the query against the rows of tables with a history (see [crate::history]) as of this
+    /// time (unix epoch milliseconds) instead of their current rows.
+    #[serde(default)]
+    pub as_of: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum QueryError {
+    ParamCount {
+        expected: usize,
+        actual: usize,
+    },
+    InlineLiteral {
+        offset: usize,
+    },
+    UnsupportedVersion {
+        version: u32,
+    },
+    NotReadOnly,
+    /// SQL passed directly on a connection restricted to registered queries.
+    NotRegistered,
+    UnknownQuery {
+        name: String,
+    },
+}
+
+impl Query {
+    /// Parses a query payload of any supported version.
+    pub fn from_json(json: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let envelope: Envelope = serde_json::from_slice(json)?;
+        if envelope.v == 0 || envelope.v > PAYLOAD_VERSION {
+            return Err(QueryError::UnsupportedVersion {
+                version: envelope.v,
+            }
+            .into());
+        }
+        Ok(envelope.query)
+    }
+
+    /// The SQL to prepare. Numbered placeholders used as a table (`FROM ?1` or `JOIN ?1`) whose
+    /// param is a JSON array are turned into `json_each(?1)`, so that e.g.
+    /// `SELECT * FROM items WHERE id IN (SELECT value FROM ?1)` selects the array's elements.
+    pub fn sql(&self) -> Cow<'_, str> {
+        let mut sql = String::new();
+        let mut last = 0;
+        for (range, index) in find_table_params(&self.sql) {
+            let param = index.checked_sub(1).and_then(|i| self.params.get(i));
+            if !matches!(param, Some(JsonValue::Array(_))) {
+                continue;
+            }
+
+            sql.push_str(&self.sql[last..range.start]);
+            sql.push_str("json_each(");
+            sql.push_str(&self.sql[range.clone()]);
+            sql.push(')');
+            last = range.end;
+        }
+
+        if last == 0 {
+            Cow::Borrowed(&self.sql)
+        } else {
+            sql.push_str(&self.sql[last..]);
+            Cow::Owned(sql)
+        }
+    }
+
+    /// Validates the query against its prepared statement before it is executed.
+    pub fn validate(&self, stmt: &Statement<'_>) -> Result<(), QueryError> {
+        if self.readonly && !stmt.readonly() {
+            return Err(QueryError::NotReadOnly);
+        }
+
+        if !self.strict {
+            return Ok(());
+        }
+
+        let expected = stmt.parameter_count();
+        if expected != self.params.len() {
+            return Err(QueryError::ParamCount {
+                expected,
+                actual: self.params.len(),
+            });
+        }
+
+        if !self.params.is_empty() {
+            if let Some(offset) = find_string_literal(&self.sql) {
+                return Err(QueryError::InlineLiteral { offset });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the byte offset of the first string (or blob) literal in `sql`, ignoring quoted
+/// identifiers and comments.
+fn find_string_literal(sql: &str) -> Option<usize> {
+    let bytes = sql.as_bytes();
+    let skip_to = |from: usize, end: &[u8]| {
+        bytes[from..]
+            .windows(end.len())
+            .position(|w| w == end)
+            .map_or(bytes.len(), |pos| from + pos + end.len())
+    };
+
+    let mut i = 0;
+    while i < bytes.len() {
+        i = match (bytes[i], bytes.get(i + 1)) {
+            (b'\'', _) => return Some(i),
+            (b'"', _) => skip_to(i + 1, b"\""),
+            (b'`', _) => skip_to(i + 1, b"`"),
+            (b'[', _) => skip_to(i + 1, b"]"),
+            (b'-', Some(b'-')) => skip_to(i + 2, b"\n"),
+            (b'/', Some(b'*')) => skip_to(i + 2, b"*/"),
+            _ => i + 1,
+        };
+    }
+
+    None
+}
+
+/// Finds numbered placeholders used as a table (directly following `FROM` or `JOIN`), ignoring
+/// literals, quoted identifiers and comments. Returns their byte ranges and (1-based) indexes.
+fn find_table_params(sql: &str) -> Vec<(Range<usize>, usize)> {
+    let bytes = sql.as_bytes();
+    let skip_to = |from: usize, end: &[u8]| {
+        bytes[from..]
+            .windows(end.len())
+            .position(|w| w == end)
+            .map_or(bytes.len(), |pos| from + pos + end.len())
+    };
+
+    let mut params = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        i = match (bytes[i], bytes.get(i + 1)) {
+            (b'\'', _) => skip_to(i + 1, b"'"),
+            (b'"', _) => skip_to(i + 1, b"\""),
+            (b'`', _) => skip_to(i + 1, b"`"),
+            (b'[', _) => skip_to(i + 1, b"]"),
+            (b'-', Some(b'-')) => skip_to(i + 2, b"\n"),
+            (b'/', Some(b'*')) => skip_to(i + 2, b"*/"),
+            (b'?', Some(b'0'..=b'9')) => {
+                let end = bytes[i + 1..]
+                    .iter()
+                    .position(|b| !b.is_ascii_digit())
+                    .map_or(bytes.len(), |pos| i + 1 + pos);
+                let keyword = sql[..i]
+                    .trim_end()
+                    .rsplit(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .next()
+                    .unwrap_or_default();
+                if keyword.eq_ignore_ascii_case("from") || keyword.eq_ignore_ascii_case("join") {
+                    if let Ok(index) = sql[i + 1..end].parse() {
+                        params.push((i..end, index));
+                    }
+                }
+                end
+            }
+            _ => i + 1,
+        };
+    }
+
+    params
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::ParamCount { expected, actual } => write!(
+                f,
+                "query has {expected} placeholders, but {actual} params were provided"
+            ),
+            QueryError::InlineLiteral { offset } => write!(
+                f,
+                "query contains a string literal at byte offset {offset} even though params were \
+                 provided; bind the value as a param instead"
+            ),
+            QueryError::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported query payload version {version} (supported: 1 to {PAYLOAD_VERSION})"
+            ),
+            QueryError::NotReadOnly => {
+                f.write_str("query is expected to be read-only, but could modify the database")
+            }
+            QueryError::NotRegistered => {
+                f.write_str("only registered queries can be run on this connection")
+            }
+            QueryError::UnknownQuery { name } => write!(f, "no query registered as `{name}`"),
+        }
+    }
+}
+
+impl QueryError {
+    /// A stable identifier of the error for the host to match on.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            QueryError::ParamCount { .. } => "param_count",
+            QueryError::InlineLiteral { .. } => "inline_literal",
+            QueryError::UnsupportedVersion { .. } => "unsupported_version",
+            QueryError::NotReadOnly => "not_read_only",
+            QueryError::NotRegistered => "not_registered",
+            QueryError::UnknownQuery { .. } => "unknown_query",
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
@@ -0,0 +1,94 @@
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+/// The table backing all queues. Payloads are stored as JSON text.
+const TABLE: &str = "_wasm_sqlite_queue";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueuePush {
+    pub queue: String,
+    pub payloads: Vec<JsonValue>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueuePop {
+    pub queue: String,
+    pub limit: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueMessage {
+    /// Increases with every pushed message (across all queues).
+    pub id: i64,
+    pub payload: JsonValue,
+}
+
+/// Appends the payloads to the queue in a single savepoint. Returns the number of pushed messages.
+pub fn push(conn: &Connection, push: &QueuePush) -> Result<usize, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    conn.execute_batch("SAVEPOINT queue_push")?;
+    match push_payloads(conn, push) {
+        Ok(count) => {
+            conn.execute_batch("RELEASE queue_push")?;
+            Ok(count)
+        }
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TO queue_push; RELEASE queue_push")
+                .ok();
+            Err(err)
+        }
+    }
+}
+
+fn push_payloads(conn: &Connection, push: &QueuePush) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "INSERT INTO {TABLE} (queue, payload) VALUES (?, ?)"
+    ))?;
+    for payload in &push.payloads {
+        stmt.execute(params![push.queue, serde_json::to_string(payload)?])?;
+    }
+    Ok(push.payloads.len())
+}
+
+/// Removes and returns up to `limit` of the oldest messages of the queue. Removing and returning
+/// happens in a single statement, so a message is never returned twice.
+pub fn pop_batch(
+    conn: &Connection,
+    pop: &QueuePop,
+) -> Result<Vec<QueueMessage>, Box<dyn std::error::Error>> {
+    create_table(conn)?;
+    let mut stmt = conn.prepare_cached(&format!(
+        "DELETE FROM {TABLE} WHERE id IN \
+         (SELECT id FROM {TABLE} WHERE queue = ?1 ORDER BY id LIMIT ?2) \
+         RETURNING id, payload"
+    ))?;
+    let mut rows = stmt.query(params![pop.queue, pop.limit])?;
+
+    let mut messages = Vec::new();
+    while let Some(row) = rows.next()? {
+        let payload: String = row.get(1)?;
+        messages.push(QueueMessage {
+            id: row.get(0)?,
+            payload: serde_json::from_str(&payload)?,
+        });
+    }
+    // The order of the rows returned by `RETURNING` is unspecified.
+    messages.sort_by_key(|message| message.id);
+    Ok(messages)
+}
+
+fn create_table(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.prepare_cached(&format!(
+        "CREATE TABLE IF NOT EXISTS {TABLE} \
+         (id INTEGER PRIMARY KEY AUTOINCREMENT, queue TEXT NOT NULL, payload TEXT NOT NULL)"
+    ))?
+    .execute([])?;
+    conn.prepare_cached(&format!(
+        "CREATE INDEX IF NOT EXISTS {TABLE}_queue ON {TABLE} (queue, id)"
+    ))?
+    .execute([])?;
+    Ok(())
+}
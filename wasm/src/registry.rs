@@ -0,0 +1,55 @@
+//! Named queries registered up front (see `conn_register_query`) and run by name (`conn_run`).
+//! Connections restricted to them (`Security::registered_queries_only`) reject any other SQL, so
+//! that semi-trusted code can only run the statements the host registered.
+
+use std::collections::HashMap;
+
+use serde_json::Value as JsonValue;
+
+use crate::query::{Query, QueryError};
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Registration {
+    pub name: String,
+    pub sql: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Run {
+    pub name: String,
+    #[serde(default)]
+    pub params: Vec<JsonValue>,
+}
+
+#[derive(Default)]
+pub struct Registry {
+    queries: HashMap<String, String>,
+}
+
+impl Registry {
+    /// Registers (or replaces) the query `name`.
+    pub fn register(&mut self, registration: Registration) {
+        self.queries.insert(registration.name, registration.sql);
+    }
+
+    /// The registered query `run` refers to, with its params.
+    pub fn query(&self, run: Run) -> Result<Query, QueryError> {
+        let sql = self
+            .queries
+            .get(&run.name)
+            .ok_or(QueryError::UnknownQuery { name: run.name })?;
+        Ok(Query {
+            sql: sql.clone(),
+            params: run.params,
+            strict: false,
+            meta: false,
+            readonly: false,
+            spill: false,
+            count_only: false,
+            explain: None,
+            as_of: None,
+        })
+    }
+}
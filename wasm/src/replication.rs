@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+
+/// The pages changed by a committed transaction, emitted by a primary instance (see
+/// [crate::vfs::set_replication]) and applied by followers via [crate::vfs::apply_delta].
+///
+/// Encoded as a sequence of little-endian u32s: namespace, commit counter (the change counter
+/// from the database header), database size in pages and the number of pages, followed by each
+/// page's index, length and bytes.
+#[derive(Debug)]
+pub struct Delta {
+    pub namespace: u32,
+    pub commit_counter: u32,
+    pub page_count: u32,
+    pub pages: BTreeMap<u32, Vec<u8>>,
+}
+
+impl Delta {
+    pub fn encode(&self) -> Vec<u8> {
+        let len = 16
+            + self
+                .pages
+                .values()
+                .map(|data| 8 + data.len())
+                .sum::<usize>();
+        let mut buf = Vec::with_capacity(len);
+        for n in [
+            self.namespace,
+            self.commit_counter,
+            self.page_count,
+            self.pages.len() as u32,
+        ] {
+            buf.extend_from_slice(&n.to_le_bytes());
+        }
+        for (ix, data) in &self.pages {
+            buf.extend_from_slice(&ix.to_le_bytes());
+            buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            buf.extend_from_slice(data);
+        }
+        buf
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self, io::Error> {
+        let mut delta = Delta {
+            namespace: take_u32(&mut data)?,
+            commit_counter: take_u32(&mut data)?,
+            page_count: take_u32(&mut data)?,
+            pages: BTreeMap::new(),
+        };
+        let len = take_u32(&mut data)?;
+        for _ in 0..len {
+            let ix = take_u32(&mut data)?;
+            let page_len = take_u32(&mut data)? as usize;
+            if data.len() < page_len {
+                return Err(truncated());
+            }
+            let (page, rest) = data.split_at(page_len);
+            delta.pages.insert(ix, page.to_vec());
+            data = rest;
+        }
+        if !data.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("delta has {} trailing bytes", data.len()),
+            ));
+        }
+        Ok(delta)
+    }
+}
+
+fn take_u32(data: &mut &[u8]) -> Result<u32, io::Error> {
+    if data.len() < 4 {
+        return Err(truncated());
+    }
+    let (n, rest) = data.split_at(4);
+    *data = rest;
+    Ok(u32::from_le_bytes(n.try_into().unwrap()))
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(ErrorKind::UnexpectedEof, "delta is truncated")
+}
@@ -0,0 +1,62 @@
+use std::cell::Cell;
+
+use serde::Deserialize;
+
+/// Returned by the host's `get_page`, `put_page` and `storage_sync` if the call failed, but might
+/// succeed if retried (e.g. the backing store is rate limiting or had a network hiccup).
+pub const TRANSIENT_ERROR: u32 = 3;
+
+thread_local! {
+    static POLICY: Cell<RetryPolicy> = Cell::new(RetryPolicy::default());
+}
+
+/// How often and how fast page store calls failing with a transient error are retried, before the
+/// error is surfaced to SQLite (as `SQLITE_IOERR`).
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// The maximum number of calls (including the first one). `1` disables retries.
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled for each further retry.
+    pub initial_backoff_ms: u32,
+    pub max_backoff_ms: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff_ms: 10,
+            max_backoff_ms: 1000,
+        }
+    }
+}
+
+pub fn set_policy(policy: RetryPolicy) {
+    POLICY.with(|p| p.set(policy));
+}
+
+/// Calls `f` (a host import returning a status) until it doesn't fail with [TRANSIENT_ERROR]
+/// anymore or the attempts are exhausted, sleeping (via `conn_sleep`) between the attempts.
+/// Returns the last status.
+pub fn with_retry(op: &'static str, mut f: impl FnMut() -> u32) -> u32 {
+    let policy = POLICY.with(Cell::get);
+    let mut backoff = policy.initial_backoff_ms;
+    let mut attempt = 1;
+    loop {
+        let status = f();
+        if status != TRANSIENT_ERROR || attempt >= policy.max_attempts {
+            return status;
+        }
+
+        tracing::warn!(
+            op,
+            attempt,
+            backoff_ms = backoff,
+            "transient host error, retrying"
+        );
+        unsafe { crate::conn_sleep(backoff.max(1)) };
+        backoff = backoff.saturating_mul(2).min(policy.max_backoff_ms);
+        attempt += 1;
+    }
+}
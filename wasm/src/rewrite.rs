@@ -0,0 +1,23 @@
+//! Rewriting of the SQL passed to `conn_execute` and `conn_query` by the host before it is
+//! prepared (see the host's `rewrite_sql`), e.g. to route statements to shadow tables, add
+//! soft-delete filters or act on comment-based hints.
+
+use crate::query::Query;
+
+/// Returned by the host's `rewrite_sql` if the SQL is to be used as is.
+const UNCHANGED: u32 = u32::MAX;
+
+/// Replaces the SQL of the query with the host's rewrite of it, if any.
+pub fn rewrite(mut query: Query) -> Result<Query, Box<dyn std::error::Error>> {
+    let len = unsafe { crate::rewrite_sql(query.sql.as_ptr(), query.sql.len() as u32) };
+    if len == UNCHANGED {
+        return Ok(query);
+    }
+
+    let mut sql = vec![0u8; len as usize];
+    unsafe { crate::rewritten_sql(sql.as_mut_ptr(), len) };
+    let sql = String::from_utf8(sql)?;
+    tracing::debug!(from = %query.sql, to = %sql, "rewrite_sql");
+    query.sql = sql;
+    Ok(query)
+}
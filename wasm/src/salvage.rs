@@ -0,0 +1,326 @@
+//! Salvaging of corrupted databases ("database disk image is malformed") into the `cfdo-salvage`
+//! page store namespace.
+//!
+//! The bundled SQLite predates the recovery extension (`sqlite3_recover`), so this follows the
+//! same idea through SQL: the schema is recreated in a fresh database, and the rows of each table
+//! are copied by scanning its B-tree in rowid order. When the scan runs into a corrupted page, it
+//! seeks past it with growing gaps and continues with the rows behind it, so a single corrupted
+//! page only loses the rows stored on it (and possibly a few next to it).
+//!
+//! As the salvaged database is created with the UTF-8 text encoding, this also converts UTF-16
+//! databases.
+
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, ErrorCode, OptionalExtension, Statement};
+use serde::Serialize;
+
+use crate::copy::quote;
+use crate::namespace::Namespace;
+use crate::options::ConnectionOptions;
+
+#[derive(Debug, Default, Serialize)]
+pub struct SalvageReport {
+    pub tables: Vec<TableSalvage>,
+    /// Schema objects that could not be recreated in the salvaged database.
+    pub failed: Vec<FailedObject>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableSalvage {
+    pub name: String,
+    /// The number of rows copied into the salvaged database.
+    pub rows: u64,
+    /// Whether rows had to be skipped because of corrupted pages.
+    pub corrupted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedObject {
+    pub name: String,
+    pub error: String,
+}
+
+/// Copies as much of the database of `src` as possible into the (empty) `cfdo-salvage`
+/// namespace. Corrupted pages are skipped; any other error (e.g. the host failing to read a page)
+/// aborts the salvage.
+pub fn salvage(src: &rusqlite::Connection) -> Result<SalvageReport, Box<dyn std::error::Error>> {
+    let namespace = Namespace::salvage();
+    let _span = tracing::info_span!("salvage", vfs = namespace.vfs).entered();
+    if namespace.page_count() > 0 {
+        return Err(format!(
+            "the page store namespace of vfs `{}` must be empty to salvage into it",
+            namespace.vfs
+        )
+        .into());
+    }
+
+    let objects = {
+        let mut stmt = src.prepare(
+            "SELECT type, name, sql FROM sqlite_schema WHERE sql IS NOT NULL ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(SchemaObject {
+                kind: row.get(0)?,
+                name: row.get(1)?,
+                sql: row.get(2)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+
+    let dst = crate::open(namespace, &ConnectionOptions::default())?;
+    dst.execute_batch("BEGIN")?;
+    let mut report = SalvageReport::default();
+
+    // Tables first (virtual tables create their shadow tables, which are then filled like any
+    // other table), then their rows, and only then indexes and triggers, so that triggers don't
+    // fire for the copied rows.
+    let mut tables = Vec::new();
+    for object in objects.iter().filter(|o| o.kind == "table") {
+        if object.name.starts_with("sqlite_") {
+            continue;
+        }
+        if table_exists(&dst, &object.name)? {
+            tables.push(object);
+            continue;
+        }
+        match dst.execute_batch(&object.sql) {
+            Ok(()) if object.sql.starts_with("CREATE VIRTUAL TABLE") => {}
+            Ok(()) => tables.push(object),
+            Err(err) => report.failed.push(object.failed(err)),
+        }
+    }
+
+    for object in tables {
+        report.tables.push(salvage_table(src, &dst, &object.name)?);
+    }
+    if table_exists(&dst, "sqlite_sequence")? {
+        dst.execute_batch("DELETE FROM sqlite_sequence")?;
+        report
+            .tables
+            .push(salvage_table(src, &dst, "sqlite_sequence")?);
+    }
+
+    for object in objects.iter().filter(|o| o.kind != "table") {
+        if let Err(err) = dst.execute_batch(&object.sql) {
+            report.failed.push(object.failed(err));
+        }
+    }
+
+    dst.execute_batch("COMMIT")?;
+    tracing::info!(
+        tables = report.tables.len(),
+        failed = report.failed.len(),
+        "salvage complete"
+    );
+    Ok(report)
+}
+
+struct SchemaObject {
+    kind: String,
+    name: String,
+    sql: String,
+}
+
+impl SchemaObject {
+    fn failed(&self, err: rusqlite::Error) -> FailedObject {
+        tracing::warn!(name = %self.name, error = %err, "failed to recreate schema object");
+        FailedObject {
+            name: self.name.clone(),
+            error: err.to_string(),
+        }
+    }
+}
+
+fn table_exists(conn: &rusqlite::Connection, table: &str) -> Result<bool, rusqlite::Error> {
+    conn.query_row(
+        "SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = ?",
+        [table],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|row| row.is_some())
+}
+
+fn salvage_table(
+    src: &rusqlite::Connection,
+    dst: &rusqlite::Connection,
+    table: &str,
+) -> Result<TableSalvage, Box<dyn std::error::Error>> {
+    let mut report = TableSalvage {
+        name: table.to_string(),
+        rows: 0,
+        corrupted: false,
+    };
+
+    // Generated columns are recomputed by the salvaged database.
+    let mut columns = Vec::new();
+    let mut rowid_alias = false;
+    let result = (|| -> Result<(), rusqlite::Error> {
+        let mut stmt = src.prepare(&format!("PRAGMA table_xinfo({})", quote(table)))?;
+        let mut rows = stmt.query([])?;
+        let mut pk_count = 0;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get("name")?;
+            let decl_type: String = row.get("type")?;
+            let pk: i64 = row.get("pk")?;
+            let hidden: i64 = row.get("hidden")?;
+            if pk > 0 {
+                pk_count += 1;
+                rowid_alias = decl_type.eq_ignore_ascii_case("INTEGER");
+            }
+            if hidden == 0 {
+                columns.push(quote(&name));
+            }
+        }
+        rowid_alias &= pk_count == 1;
+        Ok(())
+    })();
+    if let Err(err) = result {
+        return skip_corrupted(report, err);
+    }
+
+    let has_rowid = src
+        .prepare(&format!("SELECT rowid FROM {} LIMIT 0", quote(table)))
+        .is_ok();
+    let rowid = has_rowid && !rowid_alias;
+
+    let insert_columns = if rowid {
+        std::iter::once("rowid".to_string())
+            .chain(columns.iter().cloned())
+            .collect::<Vec<_>>()
+    } else {
+        columns.clone()
+    };
+    let mut insert = dst.prepare(&format!(
+        "INSERT OR REPLACE INTO {} ({}) VALUES ({})",
+        quote(table),
+        insert_columns.join(", "),
+        vec!["?"; insert_columns.len()].join(", ")
+    ))?;
+
+    if !has_rowid {
+        // Without a rowid there is no way to seek past a corrupted page, so the scan stops at
+        // the first one.
+        let mut select = src.prepare(&format!(
+            "SELECT {} FROM {}",
+            columns.join(", "),
+            quote(table)
+        ))?;
+        return match copy_rows(&mut select, None, &mut insert, false, &mut report.rows) {
+            Ok(()) => Ok(report),
+            Err((_, err)) => skip_corrupted(report, err),
+        };
+    }
+
+    let mut select = src.prepare(&format!(
+        "SELECT rowid, {} FROM {} WHERE rowid > ? ORDER BY rowid",
+        columns.join(", "),
+        quote(table)
+    ))?;
+    let mut seek = src.prepare(&format!(
+        "SELECT rowid FROM {} WHERE rowid > ? ORDER BY rowid LIMIT 1",
+        quote(table)
+    ))?;
+
+    let mut after = i64::MIN;
+    loop {
+        match copy_rows(
+            &mut select,
+            Some(after),
+            &mut insert,
+            rowid,
+            &mut report.rows,
+        ) {
+            Ok(()) => break,
+            Err((last, err)) => {
+                if !is_corrupt(&err) {
+                    return Err(err.into());
+                }
+                tracing::warn!(table, after = last, error = %err, "skipping corrupted rows");
+                report.corrupted = true;
+                let last = last.unwrap_or(after);
+                match seek_past(&mut seek, last)? {
+                    Some(next) => after = next - 1,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Copies the rows returned by `select` into `insert`, counting them in `count`. For rowid tables,
+/// `select` starts after the rowid `after` and its first column is the rowid, which is only
+/// inserted if `insert_rowid` is set. Fails with the rowid of the last copied row (if any).
+fn copy_rows(
+    select: &mut Statement<'_>,
+    after: Option<i64>,
+    insert: &mut Statement<'_>,
+    insert_rowid: bool,
+    count: &mut u64,
+) -> Result<(), (Option<i64>, rusqlite::Error)> {
+    let has_rowid = after.is_some();
+    let column_count = select.column_count();
+    let mut last = None;
+    let mut rows = select
+        .query(params_from_iter(after))
+        .map_err(|err| (last, err))?;
+    loop {
+        let row = match rows.next() {
+            Ok(Some(row)) => row,
+            Ok(None) => return Ok(()),
+            Err(err) => return Err((last, err)),
+        };
+        let skip = usize::from(has_rowid && !insert_rowid);
+        let values = (skip..column_count)
+            .map(|i| row.get::<_, Value>(i))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| (last, err))?;
+        insert
+            .execute(params_from_iter(values))
+            .map_err(|err| (last, err))?;
+        if has_rowid {
+            last = Some(row.get(0).map_err(|err| (last, err))?);
+        }
+        *count += 1;
+    }
+}
+
+/// Finds the first readable rowid behind a corrupted page following `last`, by seeking with
+/// doubling gaps until the seek no longer lands on a corrupted page.
+fn seek_past(seek: &mut Statement<'_>, last: i64) -> Result<Option<i64>, rusqlite::Error> {
+    let mut gap = 1i64;
+    while let Some(from) = last.checked_add(gap) {
+        match seek.query_row([from], |row| row.get(0)).optional() {
+            Ok(next) => return Ok(next),
+            Err(err) if is_corrupt(&err) => {}
+            Err(err) => return Err(err),
+        }
+        match gap.checked_mul(2) {
+            Some(next_gap) => gap = next_gap,
+            None => break,
+        }
+    }
+    Ok(None)
+}
+
+fn skip_corrupted(
+    mut report: TableSalvage,
+    err: rusqlite::Error,
+) -> Result<TableSalvage, Box<dyn std::error::Error>> {
+    if !is_corrupt(&err) {
+        return Err(err.into());
+    }
+    tracing::warn!(table = %report.name, error = %err, "skipping corrupted table");
+    report.corrupted = true;
+    Ok(report)
+}
+
+fn is_corrupt(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(err, _) if err.code == ErrorCode::DatabaseCorrupt
+    )
+}
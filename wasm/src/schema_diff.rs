@@ -0,0 +1,474 @@
+//! Declarative schema management: compares the live schema with the desired one (given as
+//! `CREATE` statements) and returns the statements migrating the live schema to it (see [diff]).
+//! Tables get new columns appended via `ALTER TABLE ... ADD COLUMN` where possible, and are
+//! rebuilt otherwise (create a new table, copy the rows of the common columns, drop the old table
+//! and rename the new one, see https://www.sqlite.org/lang_altertable.html#otheralter). Changed
+//! indexes, views and triggers are dropped and recreated.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::cdc;
+use crate::copy::quote;
+use crate::error::foreign_key_violations;
+use crate::history::columns;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaDiffOptions {
+    /// The desired schema, as `CREATE` statements.
+    pub schema: String,
+    /// Apply the statements in a single transaction.
+    #[serde(default)]
+    pub apply: bool,
+    /// Drop the objects that only exist in the live schema as well.
+    #[serde(default)]
+    pub drop: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchemaDiff {
+    /// The statements migrating the live schema to the desired one, in order.
+    pub statements: Vec<String>,
+    /// The objects that only exist in the live schema (e.g. the history tables of
+    /// `enableHistory`), which are only dropped with `drop`.
+    pub extra: Vec<String>,
+    pub applied: bool,
+}
+
+/// An object of `sqlite_master`.
+struct Object {
+    kind: String,
+    name: String,
+    table: String,
+    sql: String,
+}
+
+impl Object {
+    /// The normalized SQL of the object after its name, to compare it regardless of how the name
+    /// is written (`ALTER TABLE ... RENAME TO` quotes it, for example).
+    fn definition(&self) -> String {
+        let sql = normalize(&self.sql);
+        let upper = sql.to_ascii_uppercase();
+        let kind = format!(" {} ", self.kind.to_ascii_uppercase());
+        let mut start = match upper.find(&kind) {
+            Some(ix) => ix + kind.len(),
+            None => return sql,
+        };
+        if upper[start..].starts_with("IF NOT EXISTS ") {
+            start += "IF NOT EXISTS ".len();
+        }
+        match upper[start..].find(&self.name.to_ascii_uppercase()) {
+            Some(ix) => sql[start + ix + self.name.len()..]
+                .trim_start_matches(['"', '`', ']'])
+                .to_string(),
+            None => sql,
+        }
+    }
+
+    fn drop_statement(&self) -> String {
+        format!("DROP {} {}", self.kind.to_uppercase(), quote(&self.name))
+    }
+}
+
+pub fn diff(conn: &Connection, options: &SchemaDiffOptions) -> Result<SchemaDiff, Box<dyn Error>> {
+    let desired_conn = crate::open_memory()?;
+    desired_conn
+        .execute_batch(&options.schema)
+        .map_err(|err| format!("invalid desired schema: {err}"))?;
+    let desired = objects(&desired_conn)?;
+    let live = objects(conn)?;
+
+    let key = |name: &str| name.to_lowercase();
+    let live_by_name = live
+        .iter()
+        .map(|object| (key(&object.name), object))
+        .collect::<HashMap<_, _>>();
+    let desired_names = desired
+        .iter()
+        .map(|object| key(&object.name))
+        .collect::<HashSet<_>>();
+    for object in &desired {
+        if let Some(existing) = live_by_name.get(&key(&object.name)) {
+            if existing.kind != object.kind {
+                return Err(format!(
+                    "`{}` is a {} in the live schema, but a {} in the desired one",
+                    object.name, existing.kind, object.kind
+                )
+                .into());
+            }
+        }
+    }
+
+    // Statements dropping objects go first, and indexes, views and triggers are created last, as
+    // they depend on the tables.
+    let mut drops = Vec::new();
+    let mut tables = Vec::new();
+    let mut creates = Vec::new();
+    let mut rebuilt = HashSet::new();
+
+    for object in desired.iter().filter(|object| object.kind == "table") {
+        let existing = match live_by_name.get(&key(&object.name)) {
+            Some(existing) => existing,
+            None => {
+                tables.push(object.sql.clone());
+                continue;
+            }
+        };
+        if existing.definition() == object.definition() {
+            continue;
+        }
+        if is_virtual(&existing.sql) || is_virtual(&object.sql) {
+            return Err(format!("cannot migrate virtual table `{}`", object.name).into());
+        }
+
+        match added_columns(&existing.sql, &object.sql) {
+            Some(added) => {
+                for column in added {
+                    tables.push(format!(
+                        "ALTER TABLE {} ADD COLUMN {column}",
+                        quote(&object.name)
+                    ));
+                }
+            }
+            None => {
+                tables.extend(rebuild(conn, &desired_conn, object)?);
+                rebuilt.insert(key(&object.name));
+            }
+        }
+    }
+
+    for object in desired.iter().filter(|object| object.kind != "table") {
+        // Indexes and triggers are dropped together with their table.
+        let dropped_with_table = object.kind != "view" && rebuilt.contains(&key(&object.table));
+        match live_by_name.get(&key(&object.name)) {
+            Some(_) if dropped_with_table => {}
+            Some(existing) if existing.definition() == object.definition() => continue,
+            Some(existing) => drops.push(existing.drop_statement()),
+            None => {}
+        }
+        creates.push(object.sql.clone());
+    }
+
+    let extra = live
+        .iter()
+        .filter(|object| !desired_names.contains(&key(&object.name)))
+        .collect::<Vec<_>>();
+    let extra_tables = extra
+        .iter()
+        .filter(|object| object.kind == "table")
+        .map(|object| key(&object.name))
+        .collect::<HashSet<_>>();
+    for object in &extra {
+        let on_table = object.kind == "index" || object.kind == "trigger";
+        if on_table && rebuilt.contains(&key(&object.table)) {
+            // Keep them, unless dropped, by recreating them on the rebuilt table.
+            if !options.drop {
+                creates.push(object.sql.clone());
+            }
+        } else if options.drop && !(on_table && extra_tables.contains(&key(&object.table))) {
+            drops.push(object.drop_statement());
+        }
+    }
+
+    let statements = drops
+        .into_iter()
+        .chain(tables)
+        .chain(creates)
+        .collect::<Vec<_>>();
+    let applied = options.apply && !statements.is_empty();
+    if applied {
+        apply(conn, &statements)?;
+    }
+
+    Ok(SchemaDiff {
+        statements,
+        extra: extra.iter().map(|object| object.name.clone()).collect(),
+        applied,
+    })
+}
+
+/// Reads the objects of the schema in the order they were created, except for internal ones
+/// (SQLite's, the module's and the shadow tables of virtual tables).
+fn objects(conn: &Connection) -> Result<Vec<Object>, rusqlite::Error> {
+    let objects = conn
+        .prepare(
+            "SELECT type, name, tbl_name, sql FROM sqlite_master
+             WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\'
+               AND name NOT LIKE '\\_wasm\\_sqlite\\_%' ESCAPE '\\'
+             ORDER BY rowid",
+        )?
+        .query_map([], |row| {
+            Ok(Object {
+                kind: row.get(0)?,
+                name: row.get(1)?,
+                table: row.get(2)?,
+                sql: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let virtual_tables = objects
+        .iter()
+        .filter(|object| is_virtual(&object.sql))
+        .map(|object| format!("{}_", object.name.to_lowercase()))
+        .collect::<Vec<_>>();
+    Ok(objects
+        .into_iter()
+        .filter(|object| {
+            let name = object.name.to_lowercase();
+            object.kind != "table"
+                || is_virtual(&object.sql)
+                || !virtual_tables.iter().any(|prefix| name.starts_with(prefix))
+        })
+        .collect())
+}
+
+fn is_virtual(sql: &str) -> bool {
+    normalize(sql).starts_with("CREATE VIRTUAL TABLE")
+}
+
+/// The statements rebuilding the table with its desired definition, keeping the rows of the
+/// columns it has before and after.
+fn rebuild(
+    conn: &Connection,
+    desired_conn: &Connection,
+    table: &Object,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let (open, _, _) = split_definition(&table.sql).ok_or_else(|| {
+        format!(
+            "cannot migrate table `{}` defined without columns",
+            table.name
+        )
+    })?;
+    let name = quote(&table.name);
+    let new_name = quote(&format!("_wasm_sqlite_new_{}", table.name));
+
+    let live_columns = columns(conn, &table.name)?
+        .into_iter()
+        .map(|column| column.to_lowercase())
+        .collect::<HashSet<_>>();
+    let common = columns(desired_conn, &table.name)?
+        .into_iter()
+        .filter(|column| live_columns.contains(&column.to_lowercase()))
+        .map(|column| quote(&column))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut statements = vec![format!("CREATE TABLE {new_name} {}", &table.sql[open..])];
+    if !common.is_empty() {
+        statements.push(format!(
+            "INSERT INTO {new_name} ({common}) SELECT {common} FROM {name}"
+        ));
+    }
+    statements.push(format!("DROP TABLE {name}"));
+    statements.push(format!("ALTER TABLE {new_name} RENAME TO {name}"));
+    Ok(statements)
+}
+
+/// The definitions of the columns appended to the table, if that is the only difference and they
+/// can be added via `ALTER TABLE ... ADD COLUMN`.
+fn added_columns(live_sql: &str, desired_sql: &str) -> Option<Vec<String>> {
+    let (_, live, live_options) = split_definition(live_sql)?;
+    let (_, desired, desired_options) = split_definition(desired_sql)?;
+    if desired.len() <= live.len() || normalize(live_options) != normalize(desired_options) {
+        return None;
+    }
+    let unchanged = live
+        .iter()
+        .zip(&desired)
+        .all(|(live, desired)| normalize(live) == normalize(desired));
+    if !unchanged {
+        return None;
+    }
+
+    let added = &desired[live.len()..];
+    let addable = added.iter().all(|column| {
+        let column = normalize(column);
+        let is_constraint = ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+            .iter()
+            .any(|keyword| column.split(' ').next() == Some(keyword));
+        // See the restrictions of https://www.sqlite.org/lang_altertable.html#altertabaddcol.
+        let has_default = column.contains(" DEFAULT ");
+        let is_restricted = ["PRIMARY KEY", "UNIQUE", " AS(", "CURRENT_", "DEFAULT("]
+            .iter()
+            .any(|restricted| column.contains(restricted))
+            || (column.contains("NOT NULL") && !has_default)
+            || (column.contains("REFERENCES") && has_default);
+        !is_constraint && !is_restricted
+    });
+    addable.then(|| {
+        added
+            .iter()
+            .map(|column| column.trim().to_string())
+            .collect()
+    })
+}
+
+/// Splits a `CREATE TABLE` statement into the offset of the opening parenthesis of its
+/// definition, the column definitions and table constraints within it, and the table options
+/// after it (e.g. `WITHOUT ROWID`). Returns `None` for tables defined via `AS SELECT`.
+fn split_definition(sql: &str) -> Option<(usize, Vec<&str>, &str)> {
+    let mut open = None;
+    let mut items = Vec::new();
+    let mut item_start = 0;
+    let mut depth = 0;
+    let mut chars = sql.char_indices().peekable();
+    while let Some((ix, c)) = chars.next() {
+        match c {
+            '\'' | '"' | '`' | '[' => {
+                let end = if c == '[' { ']' } else { c };
+                for (_, c) in chars.by_ref() {
+                    if c == end {
+                        break;
+                    }
+                }
+            }
+            '-' if matches!(chars.peek(), Some((_, '-'))) => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut prev = ' ';
+                for (_, c) in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '(' => {
+                depth += 1;
+                if depth == 1 {
+                    open = Some(ix);
+                    item_start = ix + 1;
+                }
+            }
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    items.push(&sql[item_start..ix]);
+                    return Some((open?, items, &sql[ix + 1..]));
+                }
+            }
+            ',' if depth == 1 => {
+                items.push(&sql[item_start..ix]);
+                item_start = ix + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Normalizes SQL for comparisons: comments are removed, whitespace is collapsed (and removed
+/// around parentheses and commas), and everything but quoted text is uppercased.
+fn normalize(sql: &str) -> String {
+    let mut normalized = String::with_capacity(sql.len());
+    let mut space = false;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                space = true;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+                space = true;
+            }
+            c if c.is_whitespace() => space = true,
+            '(' | ')' | ',' => {
+                normalized.push(c);
+                space = false;
+            }
+            c => {
+                if space && !normalized.is_empty() && !normalized.ends_with(['(', ')', ',']) {
+                    normalized.push(' ');
+                }
+                space = false;
+                if let '\'' | '"' | '`' | '[' = c {
+                    let end = if c == '[' { ']' } else { c };
+                    normalized.push(c);
+                    for c in chars.by_ref() {
+                        normalized.push(c);
+                        if c == end {
+                            break;
+                        }
+                    }
+                } else {
+                    normalized.push(c.to_ascii_uppercase());
+                }
+            }
+        }
+    }
+    normalized
+}
+
+/// Applies the statements in a single transaction, with foreign keys disabled (which is only
+/// possible outside of a transaction) so that dropping a table doesn't delete rows referencing
+/// it. Rejects the changes if they violate foreign keys.
+fn apply(conn: &Connection, statements: &[String]) -> Result<(), Box<dyn Error>> {
+    if !conn.is_autocommit() {
+        return Err("schema changes cannot be applied within a transaction".into());
+    }
+
+    let foreign_keys: bool = conn.pragma_query_value(None, "foreign_keys", |row| row.get(0))?;
+    conn.pragma_update(None, "foreign_keys", &false)?;
+    // Keeps views and triggers referencing a rebuilt table from failing its renaming.
+    conn.pragma_update(None, "legacy_alter_table", &true)?;
+    let result = apply_statements(conn, statements);
+    conn.pragma_update(None, "legacy_alter_table", &false)?;
+    conn.pragma_update(None, "foreign_keys", &foreign_keys)?;
+    result
+}
+
+fn apply_statements(conn: &Connection, statements: &[String]) -> Result<(), Box<dyn Error>> {
+    conn.execute_batch("SAVEPOINT schema_diff")?;
+    let result = statements
+        .iter()
+        .try_for_each(|statement| conn.execute_batch(statement))
+        .map_err(Box::<dyn Error>::from)
+        // Record the changed columns of tables whose changes are recorded (see [cdc]).
+        .and_then(|()| cdc::refresh(conn))
+        .and_then(|()| {
+            let violations = foreign_key_violations(conn, None)?;
+            match violations.first() {
+                Some(violation) => Err(format!(
+                    "schema changes violate foreign keys, e.g. of row {:?} of table `{}`",
+                    violation.rowid, violation.table
+                )
+                .into()),
+                None => Ok(()),
+            }
+        });
+    match result {
+        Ok(()) => {
+            conn.execute_batch("RELEASE schema_diff")?;
+            Ok(())
+        }
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TO schema_diff; RELEASE schema_diff")
+                .ok();
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+
+use rusqlite::ffi;
+
+use crate::policy::RowPolicies;
+
+/// Hardening options for connections that execute untrusted SQL. Options that are not set keep
+/// their current value.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Security {
+    /// Whether SQL functions and virtual tables used by the schema (views, triggers, ...) are
+    /// trusted. Disable to prevent hostile schemas from invoking functions with side effects.
+    pub trusted_schema: Option<bool>,
+    /// Enables `SQLITE_DBCONFIG_DEFENSIVE`, which disables language features that allow to
+    /// deliberately corrupt the database file (e.g. writing to the schema table).
+    pub defensive: Option<bool>,
+    /// Only allow running queries registered via `conn_register_query` (through `conn_run`);
+    /// passing SQL directly, or any other call reading or changing rows, tables or the schema
+    /// (e.g. `conn_upsert` or `conn_kv_set`), fails with kind `not_registered`. Can't be disabled
+    /// again, and no more queries can be registered once enabled.
+    pub registered_queries_only: Option<bool>,
+}
+
+impl Security {
+    pub fn apply(&self, conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+        if let Some(trusted_schema) = self.trusted_schema {
+            conn.pragma_update(None, "trusted_schema", &trusted_schema)?;
+        }
+
+        if let Some(defensive) = self.defensive {
+            let code = unsafe {
+                ffi::sqlite3_db_config(
+                    conn.handle(),
+                    ffi::SQLITE_DBCONFIG_DEFENSIVE,
+                    c_int::from(defensive),
+                    std::ptr::null_mut::<c_int>(),
+                )
+            };
+            if code != ffi::SQLITE_OK {
+                return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(code), None));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Overwrites the options set in `other`.
+    pub fn merge(&mut self, other: Security) {
+        self.trusted_schema = other.trusted_schema.or(self.trusted_schema);
+        self.defensive = other.defensive.or(self.defensive);
+        self.registered_queries_only = other
+            .registered_queries_only
+            .or(self.registered_queries_only);
+    }
+
+    /// Whether only registered queries can be run.
+    pub fn registered_queries_only(&self) -> bool {
+        self.registered_queries_only == Some(true)
+    }
+}
+
+/// Installs an authorizer that denies any access to the `sqlite_dbpage` virtual table, which
+/// exposes (and allows to overwrite) the raw pages of the database file.
+pub fn deny_raw_pages(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    set_authorizer(conn, std::ptr::null())
+}
+
+/// Like [deny_raw_pages], but additionally enforces the row `policies` (see [crate::policy]),
+/// which must outlive the connection.
+pub fn authorize_row_policies(
+    conn: &rusqlite::Connection,
+    policies: &RowPolicies,
+) -> Result<(), rusqlite::Error> {
+    set_authorizer(conn, policies)
+}
+
+fn set_authorizer(
+    conn: &rusqlite::Connection,
+    policies: *const RowPolicies,
+) -> Result<(), rusqlite::Error> {
+    let code = unsafe {
+        ffi::sqlite3_set_authorizer(conn.handle(), Some(authorize), policies as *mut c_void)
+    };
+    if code != ffi::SQLITE_OK {
+        return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(code), None));
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn authorize(
+    policies: *mut c_void,
+    action: c_int,
+    table: *const c_char,
+    arg2: *const c_char,
+    database: *const c_char,
+    accessor: *const c_char,
+) -> c_int {
+    if let Some(policies) = unsafe { (policies as *const RowPolicies).as_ref() } {
+        let arg = |arg: *const c_char| {
+            (!arg.is_null())
+                .then(|| unsafe { CStr::from_ptr(arg) }.to_str().ok())
+                .flatten()
+        };
+        if !policies.authorize(action, arg(table), arg(arg2), arg(database), arg(accessor)) {
+            return ffi::SQLITE_DENY;
+        }
+    }
+
+    let is_table_access = matches!(
+        action,
+        ffi::SQLITE_READ | ffi::SQLITE_INSERT | ffi::SQLITE_UPDATE | ffi::SQLITE_DELETE
+    );
+    if is_table_access
+        && !table.is_null()
+        && unsafe { CStr::from_ptr(table) }
+            .to_bytes()
+            .eq_ignore_ascii_case(b"sqlite_dbpage")
+    {
+        ffi::SQLITE_DENY
+    } else {
+        ffi::SQLITE_OK
+    }
+}
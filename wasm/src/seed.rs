@@ -0,0 +1,37 @@
+//! A seed database embedded into the module at build time (`SEED=path/to/seed.db ./build.sh`,
+//! which enables the `seed` feature), so that applications ship with their schema and reference
+//! data. It is written to the page store of the main database when it is opened while empty.
+
+use crate::namespace::Namespace;
+
+/// The seed database, from the path in `WASM_SQLITE_SEED` (set by `build.sh`).
+static SEED: &[u8] = include_bytes!(env!("WASM_SQLITE_SEED"));
+
+/// Writes the seed database into the page store of the main database, unless it already has
+/// pages. Returns whether it did.
+pub fn write_if_empty(namespace: &Namespace) -> Result<bool, Box<dyn std::error::Error>> {
+    if namespace.id != Namespace::main().id || namespace.page_count() > 0 {
+        return Ok(false);
+    }
+
+    if SEED.len() < 100 || &SEED[..16] != b"SQLite format 3\0" {
+        return Err("embedded seed is not a SQLite database".into());
+    }
+    // The page size is stored as a big-endian u16, with 1 representing 65536.
+    let page_size = match u16::from_be_bytes([SEED[16], SEED[17]]) {
+        1 => 65536,
+        page_size => page_size as usize,
+    };
+    if page_size != namespace.page_size {
+        return Err(format!(
+            "embedded seed has a page size of {page_size} bytes, but the page store of vfs `{}` \
+             only supports a page size of {} bytes",
+            namespace.vfs, namespace.page_size
+        )
+        .into());
+    }
+
+    let pages = crate::vfs::write_image(namespace.id, namespace.page_size, SEED)?;
+    tracing::info!(pages, "wrote embedded seed database");
+    Ok(true)
+}
@@ -0,0 +1,85 @@
+use rusqlite::params;
+use serde::Serialize;
+
+const TABLE: &str = "_wasm_sqlite_self_test";
+
+#[derive(Debug, Serialize)]
+pub struct SelfTestReport {
+    pub ok: bool,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+/// Writes a value to a scratch table, reads it back, drops the table again and runs a
+/// `quick_check`. All writes are committed, so that they actually round-trip through the page
+/// store. Stops at the first failing check.
+pub fn self_test(conn: &rusqlite::Connection) -> SelfTestReport {
+    let value = rand::random::<u64>().to_string();
+    let mut report = SelfTestReport {
+        ok: true,
+        checks: Vec::new(),
+    };
+
+    let checks: [(&'static str, &dyn Fn() -> Result<(), String>); 4] = [
+        ("write", &|| {
+            conn.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {TABLE} (value TEXT NOT NULL)"
+            ))
+            .map_err(|err| err.to_string())?;
+            conn.execute(
+                &format!("INSERT INTO {TABLE} (value) VALUES (?)"),
+                params![value],
+            )
+            .map_err(|err| err.to_string())?;
+            Ok(())
+        }),
+        ("read", &|| {
+            let read: String = conn
+                .query_row(
+                    &format!("SELECT value FROM {TABLE} WHERE value = ?"),
+                    params![value],
+                    |row| row.get(0),
+                )
+                .map_err(|err| err.to_string())?;
+            if read != value {
+                return Err(format!("read `{read}`, expected `{value}`"));
+            }
+            Ok(())
+        }),
+        ("drop", &|| {
+            conn.execute_batch(&format!("DROP TABLE {TABLE}"))
+                .map_err(|err| err.to_string())
+        }),
+        ("quick_check", &|| {
+            let result: String = conn
+                .query_row("PRAGMA quick_check", [], |row| row.get(0))
+                .map_err(|err| err.to_string())?;
+            if result != "ok" {
+                return Err(result);
+            }
+            Ok(())
+        }),
+    ];
+
+    for (name, check) in checks {
+        let result = check();
+        let ok = result.is_ok();
+        report.checks.push(SelfTestCheck {
+            name,
+            ok,
+            error: result.err(),
+        });
+        if !ok {
+            report.ok = false;
+            break;
+        }
+    }
+
+    report
+}
@@ -0,0 +1,75 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::clock::Instant;
+use crate::fingerprint::fingerprint;
+use crate::tag;
+use crate::vfs;
+
+thread_local! {
+    /// Statements running longer than this are reported to the host. `None` if disabled.
+    static THRESHOLD: Cell<Option<Duration>> = Cell::new(None);
+}
+
+#[derive(Serialize)]
+struct SlowQuery<'a> {
+    /// The SQL as passed in (with placeholders, bound values are never reported).
+    sql: &'a str,
+    /// The shape of the SQL, to aggregate slow queries by (see [crate::fingerprint]).
+    fingerprint: String,
+    duration_ms: u64,
+    /// The pages read from the page store (page cache misses).
+    pages_read: u64,
+    /// The pages written to the page store.
+    pages_written: u64,
+    /// The tag of the connection (see `conn_set_tag`).
+    tag: Option<&'a str>,
+}
+
+/// Sets the threshold above which statements are reported to the host's `slow_query` import.
+/// `0` disables the slow-query log.
+pub fn set_threshold(ms: u32) {
+    let threshold = (ms > 0).then(|| Duration::from_millis(ms.into()));
+    THRESHOLD.with(|t| t.set(threshold));
+}
+
+/// Runs `f` and reports it as a slow query of `sql` if it exceeds the threshold.
+pub fn measure<T>(sql: &str, f: impl FnOnce() -> T) -> T {
+    let threshold = match THRESHOLD.with(Cell::get) {
+        Some(threshold) => threshold,
+        None => return f(),
+    };
+
+    let io_before = vfs::page_io();
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    if elapsed < threshold {
+        return result;
+    }
+
+    let io_after = vfs::page_io();
+    let tag = tag::current();
+    let fingerprint = fingerprint(sql);
+    tracing::warn!(
+        sql,
+        fingerprint = %fingerprint,
+        duration_ms = elapsed.as_millis() as u64,
+        tag = tag.as_deref(),
+        "slow query"
+    );
+    let json = serde_json::to_vec(&SlowQuery {
+        sql,
+        fingerprint,
+        duration_ms: elapsed.as_millis() as u64,
+        pages_read: io_after.reads - io_before.reads,
+        pages_written: io_after.writes - io_before.writes,
+        tag: tag.as_deref(),
+    })
+    .expect("failed to serialize slow query");
+    unsafe { crate::slow_query(json.as_ptr(), json.len() as u32) };
+
+    result
+}
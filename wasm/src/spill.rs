@@ -0,0 +1,170 @@
+//! Spilling of results that exceed `Limits::max_result_bytes` into a scratch database, from which
+//! the host drains them in chunks via a cursor instead of receiving them all at once.
+
+use std::cell::{Cell, RefCell};
+
+use rusqlite::{params, OpenFlags, Rows};
+use serde::Serialize;
+
+use crate::limits::{LimitError, StepBudget};
+use crate::namespace::Namespace;
+use crate::NamedRow;
+
+thread_local! {
+    /// The connection to the scratch database, opened on first use.
+    static SPILL: RefCell<Option<rusqlite::Connection>> = RefCell::new(None);
+    static NEXT_CURSOR: Cell<u32> = Cell::new(1);
+}
+
+/// A result that has been spilled into the scratch database.
+#[derive(Serialize)]
+pub struct Cursor {
+    pub cursor: u32,
+    /// The total number of rows of the result.
+    pub count: u64,
+}
+
+/// Serializes all `rows` into a JSON array. If the array would exceed `max_bytes`, the rows are
+/// spilled into the scratch database and the returned JSON is a [Cursor] instead, or the query
+/// fails if `spill` is disabled.
+pub fn collect(
+    names: &[String],
+    rows: &mut Rows<'_>,
+    budget: &StepBudget,
+    max_bytes: Option<usize>,
+    spill: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut json = Vec::new();
+    let mut size = 2; // `[]`
+    while let Some(row) = rows.next().map_err(|err| budget.map_err(err))? {
+        let row = serde_json::to_string(&NamedRow { names, row })?;
+        size += row.len() + usize::from(!json.is_empty());
+        json.push(row);
+
+        match max_bytes {
+            Some(max) if size > max => {
+                if !spill {
+                    return Err(Box::new(LimitError::ResultSize { max }));
+                }
+                let cursor = spill_rows(json, rows, names, budget)?;
+                return Ok(serde_json::to_string(&cursor)?);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(format!("[{}]", json.join(",")))
+}
+
+fn spill_rows(
+    json: Vec<String>,
+    rows: &mut Rows<'_>,
+    names: &[String],
+    budget: &StepBudget,
+) -> Result<Cursor, Box<dyn std::error::Error>> {
+    let cursor = NEXT_CURSOR.with(|next| {
+        let cursor = next.get();
+        next.set(cursor.wrapping_add(1).max(1));
+        cursor
+    });
+    let _span = tracing::debug_span!("spill", cursor).entered();
+
+    with_spill(|conn| {
+        conn.execute_batch("BEGIN")?;
+        let result = (|| -> Result<Cursor, Box<dyn std::error::Error>> {
+            let mut insert =
+                conn.prepare_cached("INSERT INTO rows (cursor, json) VALUES (?1, ?2)")?;
+            let mut count = 0;
+            for row in json {
+                insert.execute(params![cursor, row])?;
+                count += 1;
+            }
+            while let Some(row) = rows.next().map_err(|err| budget.map_err(err))? {
+                let row = serde_json::to_string(&NamedRow { names, row })?;
+                insert.execute(params![cursor, row])?;
+                count += 1;
+            }
+            Ok(Cursor { cursor, count })
+        })();
+        match result {
+            Ok(cursor) => {
+                conn.execute_batch("COMMIT")?;
+                Ok(cursor)
+            }
+            Err(err) => {
+                // Without a rollback journal (see [open]), a ROLLBACK is undefined, so the
+                // rows spilled so far are deleted instead.
+                conn.prepare_cached("DELETE FROM rows WHERE cursor = ?1")
+                    .and_then(|mut delete| delete.execute([cursor]))
+                    .ok();
+                conn.execute_batch("COMMIT").ok();
+                Err(err)
+            }
+        }
+    })
+}
+
+/// Removes and returns (as a JSON array) the next `max_rows` rows of the cursor. Returns an empty
+/// array once the cursor is drained.
+pub fn next(cursor: u32, max_rows: u32) -> Result<String, Box<dyn std::error::Error>> {
+    with_spill(|conn| {
+        let mut select = conn.prepare_cached(
+            "SELECT rowid, json FROM rows WHERE cursor = ?1 ORDER BY rowid LIMIT ?2",
+        )?;
+        let mut last = None;
+        let mut json = Vec::new();
+        let mut rows = select.query(params![cursor, max_rows])?;
+        while let Some(row) = rows.next()? {
+            last = Some(row.get::<_, i64>(0)?);
+            json.push(row.get::<_, String>(1)?);
+        }
+        drop(rows);
+
+        if let Some(last) = last {
+            conn.prepare_cached("DELETE FROM rows WHERE cursor = ?1 AND rowid <= ?2")?
+                .execute(params![cursor, last])?;
+        }
+        Ok(format!("[{}]", json.join(",")))
+    })
+}
+
+/// Discards the remaining rows of the cursor.
+pub fn close(cursor: u32) -> Result<(), Box<dyn std::error::Error>> {
+    with_spill(|conn| {
+        conn.prepare_cached("DELETE FROM rows WHERE cursor = ?1")?
+            .execute([cursor])?;
+        Ok(())
+    })
+}
+
+fn with_spill<T>(
+    f: impl FnOnce(&rusqlite::Connection) -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    SPILL.with(|spill| {
+        let mut spill = spill.borrow_mut();
+        if spill.is_none() {
+            *spill = Some(open()?);
+        }
+        f(spill.as_ref().unwrap())
+    })
+}
+
+fn open() -> Result<rusqlite::Connection, rusqlite::Error> {
+    let conn = rusqlite::Connection::open_with_flags_and_vfs(
+        "spill.db",
+        OpenFlags::SQLITE_OPEN_READ_WRITE
+            | OpenFlags::SQLITE_OPEN_CREATE
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        Namespace::spill().vfs,
+    )?;
+    // The scratch database doesn't need to survive a crash, and cursors of a previous instance
+    // cannot be drained anymore.
+    conn.execute_batch(
+        "PRAGMA journal_mode = OFF;
+        PRAGMA synchronous = OFF;
+        CREATE TABLE IF NOT EXISTS rows (cursor INTEGER NOT NULL, json TEXT NOT NULL);
+        CREATE INDEX IF NOT EXISTS rows_cursor ON rows (cursor);
+        DELETE FROM rows;",
+    )?;
+    Ok(conn)
+}
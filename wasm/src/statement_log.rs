@@ -0,0 +1,88 @@
+//! An opt-in journal of the last statements run by any connection (see `set_statement_log`), e.g.
+//! to reconstruct the sequence of writes that led to a corrupted database. Bound values are only
+//! recorded as a hash keyed with a random key of the instance, so that the journal can be shared
+//! without leaking data: the hashes can't be brute-forced without the key, but statements of the
+//! same instance with the same values can still be matched up.
+
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use crate::tag;
+
+thread_local! {
+    static LOG: RefCell<VecDeque<Entry>> = RefCell::new(VecDeque::new());
+    /// The number of statements kept. `0` if the journal is disabled.
+    static CAPACITY: Cell<usize> = Cell::new(0);
+    static NEXT_SEQ: Cell<u64> = Cell::new(1);
+    /// The random key of the SipHash the bound values are hashed with.
+    static PARAMS_KEY: RandomState = RandomState::new();
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    /// Increases with every recorded statement, so that gaps reveal evicted entries.
+    pub seq: u64,
+    pub sql: String,
+    /// A keyed hash of the bound values (16 hex digits), `None` if there were none.
+    pub params_hash: Option<String>,
+    /// When the statement finished, by the host's monotonic clock (see `monotonic_millis`).
+    pub at_ms: f64,
+    /// The tag of the connection (see `conn_set_tag`).
+    pub tag: Option<String>,
+    /// The error the statement failed with, if any.
+    pub error: Option<String>,
+}
+
+/// Keeps the last `capacity` statements. `0` disables the journal and drops its entries.
+pub fn set_capacity(capacity: usize) {
+    CAPACITY.with(|c| c.set(capacity));
+    LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        while log.len() > capacity {
+            log.pop_front();
+        }
+        if capacity == 0 {
+            log.shrink_to_fit();
+        }
+    });
+}
+
+/// Records a statement that ran, if the journal is enabled.
+pub fn record(sql: &str, params: &[JsonValue], error: Option<&dyn std::error::Error>) {
+    let capacity = CAPACITY.with(Cell::get);
+    if capacity == 0 {
+        return;
+    }
+
+    let params_hash = (!params.is_empty()).then(|| {
+        let json = serde_json::to_vec(params).expect("serialize params");
+        let mut hasher = PARAMS_KEY.with(RandomState::build_hasher);
+        hasher.write(&json);
+        format!("{:016x}", hasher.finish())
+    });
+    let entry = Entry {
+        seq: NEXT_SEQ.with(|seq| seq.replace(seq.get() + 1)),
+        sql: sql.to_string(),
+        params_hash,
+        at_ms: unsafe { crate::monotonic_millis() },
+        tag: tag::current().map(|tag| tag.to_string()),
+        error: error.map(|err| err.to_string()),
+    };
+    LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        if log.len() >= capacity {
+            log.pop_front();
+        }
+        log.push_back(entry);
+    });
+}
+
+/// The recorded statements, oldest first.
+pub fn entries() -> Vec<Entry> {
+    LOG.with(|log| log.borrow().iter().cloned().collect())
+}
@@ -0,0 +1,20 @@
+//! Tags identifying the caller of a connection (e.g. a pool slot or tenant), which are attached to
+//! exported spans, slow queries, lock diagnostics and errors, so hosts running several connections
+//! can attribute them.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+thread_local! {
+    /// The tag of the connection the current export operates on.
+    static CURRENT: RefCell<Option<Rc<str>>> = RefCell::new(None);
+}
+
+/// Makes `tag` the tag of everything happening until the next call.
+pub fn set_current(tag: Option<Rc<str>>) {
+    CURRENT.with(|current| *current.borrow_mut() = tag);
+}
+
+pub fn current() -> Option<Rc<str>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
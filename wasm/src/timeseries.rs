@@ -0,0 +1,179 @@
+//! Helpers for time-series data, with timestamps as unix epoch seconds.
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params, params_from_iter, Connection};
+use serde::Serialize;
+
+use crate::copy::quote;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Downsample {
+    pub table: String,
+    /// The column containing the timestamp (unix epoch seconds).
+    pub time_column: String,
+    /// The columns to aggregate.
+    pub value_columns: Vec<String>,
+    /// Columns identifying a series (e.g. a metric name), aggregated separately.
+    #[serde(default)]
+    pub group_columns: Vec<String>,
+    #[serde(default)]
+    pub aggregate: Aggregate,
+    /// The bucket size in seconds.
+    pub interval: i64,
+    /// Only rows older than this timestamp are compacted.
+    pub before: i64,
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    #[default]
+    Avg,
+    Sum,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DownsampleReport {
+    /// The number of rows replaced by aggregates.
+    pub deleted: usize,
+    /// The number of aggregated rows inserted.
+    pub inserted: usize,
+}
+
+/// Registers the `time_bucket(interval, ts)` SQL function, which returns the start of the bucket
+/// `ts` falls into. The interval is either a number of seconds or a string with a unit suffix
+/// (`30s`, `5m`, `1h` or `1d`).
+pub fn register(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("time_bucket", 2, flags, |ctx| {
+        let interval = match ctx.get_raw(0) {
+            ValueRef::Integer(seconds) => Some(seconds),
+            ValueRef::Text(text) => std::str::from_utf8(text).ok().and_then(parse_interval),
+            _ => None,
+        }
+        .filter(|interval| *interval > 0)
+        .ok_or_else(|| rusqlite::Error::UserFunctionError("invalid time_bucket interval".into()))?;
+        let ts: Option<i64> = match ctx.get_raw(1) {
+            ValueRef::Null => None,
+            ValueRef::Real(ts) => Some(ts.floor() as i64),
+            _ => Some(ctx.get(1)?),
+        };
+        Ok(ts.map(|ts| time_bucket(interval, ts)))
+    })?;
+    Ok(())
+}
+
+fn time_bucket(interval: i64, ts: i64) -> i64 {
+    ts.div_euclid(interval) * interval
+}
+
+fn parse_interval(interval: &str) -> Option<i64> {
+    let unit = interval.chars().last()?;
+    let value = &interval[..interval.len() - unit.len_utf8()];
+    let factor = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return interval.parse().ok(),
+    };
+    value.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+/// Replaces the rows older than `before` with one aggregated row per bucket (and series), all
+/// inside a savepoint. Columns not listed are left `NULL` in the aggregated rows.
+pub fn downsample(
+    conn: &Connection,
+    downsample: &Downsample,
+) -> Result<DownsampleReport, Box<dyn std::error::Error>> {
+    if downsample.interval <= 0 {
+        return Err("downsample interval must be positive".into());
+    }
+
+    conn.execute_batch("SAVEPOINT ts_downsample")?;
+    match downsample_rows(conn, downsample) {
+        Ok(report) => {
+            conn.execute_batch("RELEASE ts_downsample")?;
+            Ok(report)
+        }
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TO ts_downsample; RELEASE ts_downsample")
+                .ok();
+            Err(err)
+        }
+    }
+}
+
+fn downsample_rows(
+    conn: &Connection,
+    downsample: &Downsample,
+) -> Result<DownsampleReport, Box<dyn std::error::Error>> {
+    let table = quote(&downsample.table);
+    let time = quote(&downsample.time_column);
+    let aggregate = match downsample.aggregate {
+        Aggregate::Avg => "avg",
+        Aggregate::Sum => "sum",
+        Aggregate::Min => "min",
+        Aggregate::Max => "max",
+    };
+    let groups = downsample
+        .group_columns
+        .iter()
+        .map(|column| quote(column))
+        .collect::<Vec<_>>();
+    let values = downsample
+        .value_columns
+        .iter()
+        .map(|column| quote(column))
+        .collect::<Vec<_>>();
+
+    // The aggregates are read into memory first, as the rows they are computed from are deleted
+    // before inserting them.
+    let mut select = vec![format!("time_bucket(?1, {time})")];
+    select.extend(groups.iter().cloned());
+    select.extend(values.iter().map(|value| format!("{aggregate}({value})")));
+    let mut group_by = vec!["1".to_string()];
+    group_by.extend(groups.iter().cloned());
+    let mut aggregates = Vec::new();
+    {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM {table} WHERE {time} < ?2 GROUP BY {}",
+            select.join(", "),
+            group_by.join(", ")
+        ))?;
+        let mut rows = stmt.query(params![downsample.interval, downsample.before])?;
+        while let Some(row) = rows.next()? {
+            aggregates.push(
+                (0..select.len())
+                    .map(|i| row.get::<_, Value>(i))
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+        }
+    }
+
+    let deleted = conn.execute(
+        &format!("DELETE FROM {table} WHERE {time} < ?"),
+        [downsample.before],
+    )?;
+
+    let mut columns = vec![time];
+    columns.extend(groups);
+    columns.extend(values);
+    let mut insert = conn.prepare(&format!(
+        "INSERT INTO {table} ({}) VALUES ({})",
+        columns.join(", "),
+        vec!["?"; columns.len()].join(", ")
+    ))?;
+    for aggregate in &aggregates {
+        insert.execute(params_from_iter(aggregate))?;
+    }
+
+    Ok(DownsampleReport {
+        deleted,
+        inserted: aggregates.len(),
+    })
+}
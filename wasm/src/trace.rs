@@ -0,0 +1,134 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::filter::{filter_fn, LevelFilter};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, Registry};
+
+use crate::clock::Instant;
+use crate::tag;
+
+thread_local! {
+    static EXPORT: Cell<bool> = Cell::new(false);
+}
+
+/// Installs the global subscriber, which logs to stderr (at the level set via `RUST_LOG`, `warn`
+/// by default) and exports closed spans to the host (see [set_export]).
+pub fn init() {
+    let level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|level| level.parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::WARN);
+    let subscriber = Registry::default()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(level),
+        )
+        .with(HostExporter.with_filter(filter_fn(|meta| meta.is_span() && EXPORT.with(Cell::get))));
+    tracing::subscriber::set_global_default(subscriber).ok();
+}
+
+/// Enables or disables exporting spans to the host via the `export_span` import.
+pub fn set_export(enabled: bool) {
+    EXPORT.with(|export| export.set(enabled));
+    tracing::callsite::rebuild_interest_cache();
+}
+
+/// Sends each closed span as JSON to the host.
+struct HostExporter;
+
+struct SpanData {
+    start: Instant,
+    tag: Option<Rc<str>>,
+    fields: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+struct ExportedSpan<'a> {
+    name: &'static str,
+    target: &'static str,
+    parent: Option<&'static str>,
+    duration_us: u64,
+    /// The tag of the connection the span belongs to (see `conn_set_tag`).
+    tag: Option<&'a str>,
+    fields: &'a Map<String, Value>,
+}
+
+impl<S> Layer<S> for HostExporter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Map::new();
+            attrs.record(&mut JsonVisitor(&mut fields));
+            span.extensions_mut().insert(SpanData {
+                start: Instant::now(),
+                tag: tag::current(),
+                fields,
+            });
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                values.record(&mut JsonVisitor(&mut data.fields));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let data = match span.extensions_mut().remove::<SpanData>() {
+            Some(data) => data,
+            None => return,
+        };
+
+        let json = serde_json::to_vec(&ExportedSpan {
+            name: span.name(),
+            target: span.metadata().target(),
+            parent: span.parent().map(|parent| parent.name()),
+            duration_us: data.start.elapsed().as_micros() as u64,
+            tag: data.tag.as_deref(),
+            fields: &data.fields,
+        })
+        .expect("failed to serialize span");
+        unsafe { crate::export_span(json.as_ptr(), json.len() as u32) };
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl<'a> Visit for JsonVisitor<'a> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
@@ -0,0 +1,42 @@
+/// Curated pragma settings for typical workloads on top of the page store.
+///
+/// Some settings are the same for all profiles because of how the page store works:
+/// - `temp_store = MEMORY`, as the VFS only supports the main database file (no temp files).
+/// - `mmap_size = 0`, as the VFS doesn't support memory-mapped I/O (setting it would be a no-op
+///   anyway, it just documents that).
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// Keeps the page cache small (256 KiB) for isolates close to their memory limit. Every cache
+    /// miss is a round trip to the host, so reads get slower.
+    LowMemory,
+    /// A large page cache (8 MiB) to avoid round trips to the host for hot pages.
+    ReadHeavy,
+    /// A medium page cache (2 MiB) and `synchronous = OFF`, so that the host's storage transaction
+    /// is only committed once the write lock is released instead of on every sync.
+    WriteHeavy,
+}
+
+impl Profile {
+    pub fn pragmas(self) -> [(&'static str, &'static str); 5] {
+        let (cache_size, synchronous, analysis_limit) = match self {
+            Profile::LowMemory => ("-256", "NORMAL", "100"),
+            Profile::ReadHeavy => ("-8192", "NORMAL", "1000"),
+            Profile::WriteHeavy => ("-2048", "OFF", "400"),
+        };
+        [
+            ("cache_size", cache_size),
+            ("temp_store", "MEMORY"),
+            ("mmap_size", "0"),
+            ("synchronous", synchronous),
+            ("analysis_limit", analysis_limit),
+        ]
+    }
+
+    pub fn apply(self, conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+        for (name, value) in self.pragmas() {
+            conn.execute_batch(&format!("PRAGMA {name} = {value}"))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use std::error::Error;
+
+use rusqlite::{params_from_iter, Connection};
+use serde::Serialize;
+use serde_json::{Map, Value as JsonValue};
+
+use crate::copy::quote;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Upsert {
+    pub table: String,
+    /// The rows to insert or update, as objects of column names to values.
+    pub rows: Vec<Map<String, JsonValue>>,
+    /// The columns of the unique index (or primary key) that identifies existing rows.
+    pub conflict_columns: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpsertReport {
+    /// The number of rows inserted or updated.
+    pub rows: usize,
+}
+
+/// Inserts each row, or updates the existing row with the same `conflict_columns` values
+/// (`INSERT ... ON CONFLICT DO UPDATE`). All rows are written inside a savepoint, so that a failure
+/// leaves the table untouched.
+pub fn upsert(conn: &Connection, upsert: &Upsert) -> Result<UpsertReport, Box<dyn Error>> {
+    if upsert.conflict_columns.is_empty() {
+        return Err("upsert requires at least one conflict column".into());
+    }
+
+    conn.execute_batch("SAVEPOINT upsert")?;
+    match upsert_rows(conn, upsert) {
+        Ok(report) => {
+            conn.execute_batch("RELEASE upsert")?;
+            Ok(report)
+        }
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TO upsert; RELEASE upsert")
+                .ok();
+            Err(err)
+        }
+    }
+}
+
+fn upsert_rows(conn: &Connection, upsert: &Upsert) -> Result<UpsertReport, Box<dyn Error>> {
+    let mut report = UpsertReport { rows: 0 };
+    for row in &upsert.rows {
+        // Rows don't need to have the same columns, so the statement is built per row and reused
+        // via the statement cache for rows with the same columns.
+        let mut stmt = conn.prepare_cached(&upsert_sql(
+            &upsert.table,
+            row.keys().map(String::as_str),
+            &upsert.conflict_columns,
+        ))?;
+        stmt.execute(params_from_iter(row.values()))?;
+        report.rows += 1;
+    }
+    Ok(report)
+}
+
+fn upsert_sql<'a>(
+    table: &str,
+    columns: impl Iterator<Item = &'a str>,
+    conflict_columns: &[String],
+) -> String {
+    let columns = columns.collect::<Vec<_>>();
+    let updates = columns
+        .iter()
+        .filter(|column| !conflict_columns.iter().any(|c| c == *column))
+        .map(|column| format!("{0} = excluded.{0}", quote(column)))
+        .collect::<Vec<_>>();
+
+    format!(
+        "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT ({}) DO {}",
+        quote(table),
+        columns
+            .iter()
+            .map(|column| quote(column))
+            .collect::<Vec<_>>()
+            .join(", "),
+        vec!["?"; columns.len()].join(", "),
+        conflict_columns
+            .iter()
+            .map(|column| quote(column))
+            .collect::<Vec<_>>()
+            .join(", "),
+        if updates.is_empty() {
+            "NOTHING".to_string()
+        } else {
+            format!("UPDATE SET {}", updates.join(", "))
+        }
+    )
+}
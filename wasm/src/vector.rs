@@ -0,0 +1,152 @@
+//! Brute-force vector similarity search for small data sets (`vector` feature). Embeddings are
+//! stored as blobs of little-endian `f32`s.
+
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+use crate::copy::quote;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VecInsert {
+    /// The table storing the embeddings (created if it doesn't exist yet).
+    pub table: String,
+    /// The rowid to store the embedding for, e.g. the id of the row the embedding belongs to.
+    /// Replaces an existing embedding with the same id.
+    pub id: i64,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VecSearch {
+    pub table: String,
+    pub embedding: Vec<f32>,
+    /// The number of nearest neighbors to return.
+    pub k: u32,
+    #[serde(default)]
+    pub metric: Metric,
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    #[default]
+    Cosine,
+    L2,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Neighbor {
+    pub id: i64,
+    pub distance: f64,
+}
+
+/// Registers the `vec_distance_cosine(a, b)` and `vec_distance_l2(a, b)` SQL functions.
+pub fn register(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("vec_distance_cosine", 2, flags, |ctx| {
+        distance(ctx, cosine_distance)
+    })?;
+    conn.create_scalar_function("vec_distance_l2", 2, flags, |ctx| {
+        distance(ctx, l2_distance)
+    })?;
+    Ok(())
+}
+
+pub fn insert(conn: &Connection, insert: &VecInsert) -> Result<(), rusqlite::Error> {
+    create_table(conn, &insert.table)?;
+    conn.prepare_cached(&format!(
+        "INSERT OR REPLACE INTO {} (id, embedding) VALUES (?, ?)",
+        quote(&insert.table)
+    ))?
+    .execute(params![insert.id, to_blob(&insert.embedding)])?;
+    Ok(())
+}
+
+/// Returns the `k` nearest neighbors of the embedding, nearest first. Scans all embeddings of the
+/// table, so it is only suited for small data sets.
+pub fn search(conn: &Connection, search: &VecSearch) -> Result<Vec<Neighbor>, rusqlite::Error> {
+    create_table(conn, &search.table)?;
+    let function = match search.metric {
+        Metric::Cosine => "vec_distance_cosine",
+        Metric::L2 => "vec_distance_l2",
+    };
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT id, {function}(embedding, ?) AS distance FROM {} ORDER BY distance LIMIT ?",
+        quote(&search.table)
+    ))?;
+    let neighbors = stmt.query_map(params![to_blob(&search.embedding), search.k], |row| {
+        Ok(Neighbor {
+            id: row.get(0)?,
+            distance: row.get(1)?,
+        })
+    })?;
+    neighbors.collect()
+}
+
+fn create_table(conn: &Connection, table: &str) -> Result<(), rusqlite::Error> {
+    conn.prepare_cached(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, embedding BLOB NOT NULL)",
+        quote(table)
+    ))?
+    .execute([])?;
+    Ok(())
+}
+
+fn distance(
+    ctx: &Context<'_>,
+    f: fn(&[f32], &[f32]) -> f64,
+) -> Result<Option<f64>, rusqlite::Error> {
+    let (a, b) = match (
+        ctx.get::<Option<Vec<u8>>>(0)?,
+        ctx.get::<Option<Vec<u8>>>(1)?,
+    ) {
+        (Some(a), Some(b)) => (from_blob(&a), from_blob(&b)),
+        _ => return Ok(None),
+    };
+    if a.len() != b.len() {
+        return Err(rusqlite::Error::UserFunctionError(
+            format!(
+                "embeddings have different dimensions ({} and {})",
+                a.len(),
+                b.len()
+            )
+            .into(),
+        ));
+    }
+    Ok(Some(f(&a, &b)))
+}
+
+fn cosine_distance(a: &[f32], b: &[f32]) -> f64 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f64, 0.0f64, 0.0f64);
+    for (a, b) in a.iter().zip(b) {
+        let (a, b) = (f64::from(*a), f64::from(*b));
+        dot += a * b;
+        norm_a += a * a;
+        norm_b += b * b;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 1.0;
+    }
+    1.0 - dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+fn l2_distance(a: &[f32], b: &[f32]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (f64::from(*a) - f64::from(*b)).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+fn to_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
@@ -0,0 +1,65 @@
+//! The schema version (`PRAGMA user_version`) and application id (`PRAGMA application_id`) in
+//! the database header, e.g. for hosts to negotiate schema migrations.
+
+use rusqlite::Connection;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct DbVersion {
+    pub user_version: i32,
+    pub application_id: i32,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetDbVersion {
+    pub user_version: Option<i32>,
+    pub application_id: Option<i32>,
+    /// Only set the versions if the current `user_version` is this one, e.g. so that only one of
+    /// several instances records a migration it ran.
+    pub expected_user_version: Option<i32>,
+}
+
+pub fn get(conn: &Connection) -> Result<DbVersion, rusqlite::Error> {
+    Ok(DbVersion {
+        user_version: conn.pragma_query_value(None, "user_version", |row| row.get(0))?,
+        application_id: conn.pragma_query_value(None, "application_id", |row| row.get(0))?,
+    })
+}
+
+/// Sets the given versions in a single savepoint. Returns the versions afterwards.
+pub fn set(conn: &Connection, set: &SetDbVersion) -> Result<DbVersion, Box<dyn std::error::Error>> {
+    conn.execute_batch("SAVEPOINT set_db_version")?;
+    match set_versions(conn, set) {
+        Ok(version) => {
+            conn.execute_batch("RELEASE set_db_version")?;
+            Ok(version)
+        }
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TO set_db_version; RELEASE set_db_version")
+                .ok();
+            Err(err)
+        }
+    }
+}
+
+fn set_versions(
+    conn: &Connection,
+    set: &SetDbVersion,
+) -> Result<DbVersion, Box<dyn std::error::Error>> {
+    if let Some(expected) = set.expected_user_version {
+        let current = get(conn)?.user_version;
+        if current != expected {
+            return Err(
+                format!("user_version is {current}, but expected it to be {expected}").into(),
+            );
+        }
+    }
+    if let Some(user_version) = set.user_version {
+        conn.pragma_update(None, "user_version", &user_version)?;
+    }
+    if let Some(application_id) = set.application_id {
+        conn.pragma_update(None, "application_id", &application_id)?;
+    }
+    Ok(get(conn)?)
+}
@@ -1,11 +1,464 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::io::{self, ErrorKind};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-use sqlite_vfs::{LockKind, OpenKind, OpenOptions, Vfs};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlite_vfs::{LockKind, OpenAccess, OpenKind, OpenOptions, Vfs};
+use tracing::{debug, debug_span, error, trace, trace_span, warn};
 
+use crate::clock::Instant;
+use crate::namespace::Namespace;
+use crate::replication::Delta;
+use crate::{changes, retry, tag};
+
+/// Returned by the host's `get_page` if the page was read.
+pub const GET_PAGE_OK: u32 = 0;
+/// Returned by the host's `get_page` if the page does not exist in the page store.
+pub const GET_PAGE_NOT_FOUND: u32 = 1;
+/// Returned by the host's `get_page` if reading the page failed (e.g. the storage is unavailable).
+/// The host provides the error message via `host_error_message`. Transient errors are retried (see
+/// [crate::retry]).
+const GET_PAGE_ERROR: u32 = 2;
+/// Returned by the host's `put_page` if the page was written.
+pub const PUT_PAGE_OK: u32 = 0;
+/// Returned by the host's `storage_sync` once all pages written so far are persisted.
+pub const STORAGE_SYNC_OK: u32 = 0;
+
+thread_local! {
+    /// SHA-256 hashes of the pages last read from or written to the host, used to skip writing
+    /// pages whose content didn't change (SQLite frequently rewrites unchanged pages). A collision
+    /// would drop a write, hence a cryptographic hash. This assumes that the page store is only
+    /// modified through this module. Keyed by namespace and page index.
+    static PAGE_HASHES: RefCell<HashMap<(u32, u32), [u8; 32]>> = RefCell::new(HashMap::new());
+
+    /// Page writes not flushed to the host yet, per namespace. `None` if write batching is
+    /// disabled.
+    static WRITE_BATCH: RefCell<Option<BTreeMap<u32, WriteBatch>>> = RefCell::new(None);
+
+    /// Diagnostics about the locks held (or waited for) by each database handle.
+    static LOCK_HOLDERS: RefCell<BTreeMap<u64, LockHolder>> = RefCell::new(BTreeMap::new());
+
+    /// Whether committed page changes are emitted to the host as [Delta]s.
+    static REPLICATION: Cell<bool> = Cell::new(false);
+
+    /// The number of pages read and written by SQLite so far (see [page_io]).
+    static PAGE_IO: Cell<PageIo> = Cell::new(PageIo::default());
+
+    /// The message of the last failed page store call (see [take_host_error]).
+    static HOST_ERROR: RefCell<Option<String>> = RefCell::new(None);
+
+    /// Pages read from the host ahead of SQLite asking for them (see [prefetch]), keyed by
+    /// namespace and page index. `None` for pages that don't exist. Each page is handed to SQLite
+    /// once, which then keeps it in its own page cache.
+    static PREFETCHED: RefCell<HashMap<(u32, u32), Option<Vec<u8>>>> = RefCell::new(HashMap::new());
+
+    /// Bytes added to (or removed from) the page store by the current storage transaction, per
+    /// namespace. Reported to the host once the transaction commits (see [report_storage_deltas]).
+    static STORAGE_DELTAS: RefCell<BTreeMap<u32, i64>> = RefCell::new(BTreeMap::new());
+}
+
+static NEXT_HANDLE_ID: AtomicU64 = AtomicU64::new(1);
+
+struct LockHolder {
+    lock: LockKind,
+    since: Instant,
+    requested: Option<LockKind>,
+    failed_attempts: u32,
+    waiting_since: Option<Instant>,
+    /// The tag of the connection that last used the handle (see `conn_set_tag`).
+    tag: Option<Rc<str>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LockDebug {
+    pub handle: u64,
+    pub lock: String,
+    pub held_ms: u64,
+    /// The lock the handle unsuccessfully tried to acquire most recently.
+    pub requested: Option<String>,
+    pub failed_attempts: u32,
+    pub waiting_ms: Option<u64>,
+    pub tag: Option<String>,
+}
+
+/// Passed to the host's `on_lock_blocked` import when a handle fails to acquire a lock because of
+/// another handle.
+#[derive(Serialize)]
+struct LockBlocked<'a> {
+    /// The lock that couldn't be acquired, e.g. `Reserved` to start writing.
+    kind: String,
+    handle: u64,
+    tag: Option<&'a str>,
+    /// The handle holding the write lock (or first in line for it), if any; `None` if only
+    /// readers are in the way.
+    holder: Option<u64>,
+    holder_tag: Option<&'a str>,
+    holder_lock: Option<String>,
+    /// How long the holder has held its lock.
+    holder_held_ms: Option<u64>,
+}
+
+/// Lists all database handles that currently hold or wait for a lock, including how long they
+/// have been doing so.
+pub fn locks_debug() -> Vec<LockDebug> {
+    LOCK_HOLDERS.with(|holders| {
+        holders
+            .borrow()
+            .iter()
+            .map(|(handle, holder)| LockDebug {
+                handle: *handle,
+                lock: format!("{:?}", holder.lock),
+                held_ms: holder.since.elapsed().as_millis() as u64,
+                requested: holder.requested.map(|lock| format!("{lock:?}")),
+                failed_attempts: holder.failed_attempts,
+                waiting_ms: holder
+                    .waiting_since
+                    .map(|since| since.elapsed().as_millis() as u64),
+                tag: holder.tag.as_deref().map(String::from),
+            })
+            .collect()
+    })
+}
+
+#[derive(Default)]
+struct WriteBatch {
+    pages: BTreeMap<u32, Vec<u8>>,
+    /// The page count the host's page store is truncated to when flushed.
+    truncate: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PendingWrites {
+    pub namespace: u32,
+    pub pages: Vec<u32>,
+    pub truncate: Option<u32>,
+}
+
+/// Enables or disables write batching. While enabled, page writes and deletes are kept in memory
+/// until [flush] is called. Disabling it flushes all pending writes.
+pub fn set_write_batching(enabled: bool) {
+    if enabled {
+        WRITE_BATCH.with(|batches| batches.borrow_mut().get_or_insert_with(BTreeMap::new));
+    } else {
+        if let Err(err) = flush() {
+            error!(error = %err, "failed to flush pending writes, keeping write batching enabled");
+            return;
+        }
+        WRITE_BATCH.with(|batches| batches.borrow_mut().take());
+    }
+}
+
+/// Lists the pending writes of each namespace that has any.
+pub fn pending_writes() -> Vec<PendingWrites> {
+    WRITE_BATCH.with(|batches| {
+        batches
+            .borrow()
+            .iter()
+            .flatten()
+            .filter(|(_, batch)| !batch.pages.is_empty() || batch.truncate.is_some())
+            .map(|(namespace, batch)| PendingWrites {
+                namespace: *namespace,
+                pages: batch.pages.keys().copied().collect(),
+                truncate: batch.truncate,
+            })
+            .collect()
+    })
+}
+
+/// Writes all pending pages (of all namespaces) to the host. Must not be called while a
+/// transaction is writing, as it would persist its partial state. If a write fails, the host's
+/// storage transaction is rolled back and the writes stay pending.
+pub fn flush() -> Result<(), io::Error> {
+    let batches = WRITE_BATCH.with(|batches| batches.borrow_mut().as_mut().map(std::mem::take));
+    let batches = match batches {
+        Some(batches) => batches,
+        None => return Ok(()),
+    };
+
+    if batches
+        .values()
+        .all(|batch| batch.pages.is_empty() && batch.truncate.is_none())
+    {
+        return Ok(());
+    }
+
+    let token = unsafe { crate::txn_begin() };
+    let written = batches.iter().try_for_each(|(namespace, batch)| {
+        if let Some(truncate) = batch.truncate {
+            let page_count = unsafe { crate::page_count(*namespace) };
+            host_del_pages(*namespace, truncate, page_count);
+        }
+        batch
+            .pages
+            .iter()
+            .try_for_each(|(ix, data)| host_put_page(*namespace, *ix, data))
+    });
+    match written {
+        Ok(()) => {
+            unsafe { crate::txn_commit(token) };
+            report_storage_deltas();
+            batches
+                .keys()
+                .try_for_each(|namespace| host_storage_sync(*namespace))
+        }
+        Err(err) => {
+            unsafe { crate::txn_rollback(token) };
+            discard_storage_deltas();
+            WRITE_BATCH.with(|pending| *pending.borrow_mut() = Some(batches));
+            Err(err)
+        }
+    }
+}
+
+/// Drops all pending writes. SQLite notices the reverted change counter in the database header
+/// and discards its own page cache on the next read.
+pub fn discard() {
+    WRITE_BATCH.with(|batches| {
+        if let Some(batches) = batches.borrow_mut().as_mut() {
+            batches.clear();
+        }
+    });
+}
+
+/// Drops the hashes of all known pages and the prefetched pages not read yet (and the memory they
+/// occupy). Until pages are read again, writes of unchanged pages are no longer skipped.
+pub fn release_memory() {
+    PREFETCHED.with(|prefetched| {
+        let mut prefetched = prefetched.borrow_mut();
+        prefetched.clear();
+        prefetched.shrink_to_fit();
+    });
+    PAGE_HASHES.with(|hashes| {
+        let mut hashes = hashes.borrow_mut();
+        hashes.clear();
+        hashes.shrink_to_fit();
+    });
+}
+
+/// Page reads and writes requested by SQLite (i.e. page cache misses and dirty pages written
+/// back), across all namespaces.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PageIo {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// The number of pages read and written since the module was instantiated. Take the difference of
+/// two calls to get the pages touched in between.
+pub fn page_io() -> PageIo {
+    PAGE_IO.with(Cell::get)
+}
+
+/// Enables or disables emitting the pages changed by each committed transaction to the host's
+/// `emit_delta`, for followers to apply them via [apply_delta].
+pub fn set_replication(enabled: bool) {
+    REPLICATION.with(|replication| replication.set(enabled));
+}
+
+/// Writes the pages of a [Delta] emitted by a primary into the page store (truncating it to the
+/// delta's database size). Returns `false` without applying the delta if the page store is already
+/// at the same or a later commit. Must not be called while a transaction is in progress.
+pub fn apply_delta(delta: &Delta) -> Result<bool, io::Error> {
+    let header = delta.pages.get(&0).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            "delta does not contain the header page",
+        )
+    })?;
+    let namespace = delta.namespace;
+
+    let mut current = vec![0u8; header.len()];
+    let status = retry::with_retry("get_page", || unsafe {
+        crate::get_page(namespace, 0, current.as_mut_ptr(), current.len() as u32)
+    });
+    if status != GET_PAGE_OK && status != GET_PAGE_NOT_FOUND {
+        return Err(host_error(format!(
+            "failed to read header page of namespace {namespace}"
+        )));
+    }
+    if status == GET_PAGE_OK && read_u32(&current, 24) >= delta.commit_counter {
+        debug!(
+            commit = delta.commit_counter,
+            "apply_delta (stale, skipped)"
+        );
+        return Ok(false);
+    }
+
+    let token = unsafe { crate::txn_begin() };
+    let written = delta
+        .pages
+        .iter()
+        .try_for_each(|(ix, data)| host_put_page(namespace, *ix, data));
+    if let Err(err) = written {
+        unsafe { crate::txn_rollback(token) };
+        discard_storage_deltas();
+        return Err(err);
+    }
+    let page_count = unsafe { crate::page_count(namespace) };
+    host_del_pages(namespace, delta.page_count, page_count);
+    unsafe { crate::txn_commit(token) };
+    report_storage_deltas();
+
+    debug!(
+        commit = delta.commit_counter,
+        pages = delta.pages.len(),
+        "apply_delta"
+    );
+    Ok(true)
+}
+
+/// Writes a database image into the (empty) page store of the namespace in a single storage
+/// transaction. Returns the number of written pages.
+pub fn write_image(namespace: u32, page_size: usize, image: &[u8]) -> Result<usize, io::Error> {
+    if image.len() % page_size != 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("image size is not a multiple of the page size of {page_size} bytes"),
+        ));
+    }
+
+    let token = unsafe { crate::txn_begin() };
+    let written = image
+        .chunks(page_size)
+        .enumerate()
+        .try_for_each(|(ix, data)| host_put_page(namespace, ix as u32, data));
+    if let Err(err) = written {
+        unsafe { crate::txn_rollback(token) };
+        discard_storage_deltas();
+        return Err(err);
+    }
+    unsafe { crate::txn_commit(token) };
+    report_storage_deltas();
+
+    let pages = image.len() / page_size;
+    debug!(namespace, pages, "write_image");
+    Ok(pages)
+}
+
+/// Reads the given pages of the namespace from the host in a single `get_pages` call and keeps
+/// them until SQLite reads them. Pages already prefetched or pending in the write batch are
+/// skipped. Returns the number of pages read.
+pub fn prefetch(namespace: u32, page_size: usize, ixs: &[u32]) -> Result<usize, io::Error> {
+    let mut ixs = ixs
+        .iter()
+        .copied()
+        .filter(|ix| {
+            let prefetched =
+                PREFETCHED.with(|prefetched| prefetched.borrow().contains_key(&(namespace, *ix)));
+            let pending = WRITE_BATCH.with(|batches| {
+                let batches = batches.borrow();
+                batches
+                    .as_ref()
+                    .and_then(|batches| batches.get(&namespace))
+                    .map_or(false, |batch| {
+                        batch.pages.contains_key(ix) || batch.truncate.is_some()
+                    })
+            });
+            !prefetched && !pending
+        })
+        .collect::<Vec<_>>();
+    ixs.sort_unstable();
+    ixs.dedup();
+    if ixs.is_empty() {
+        return Ok(0);
+    }
+
+    let _span = debug_span!("prefetch", ns = namespace, pages = ixs.len()).entered();
+    let mut data = vec![0u8; ixs.len() * page_size];
+    let mut found = vec![0u8; ixs.len()];
+    let status = retry::with_retry("get_pages", || unsafe {
+        crate::get_pages(
+            namespace,
+            ixs.as_ptr(),
+            ixs.len() as u32,
+            data.as_mut_ptr(),
+            page_size as u32,
+            found.as_mut_ptr(),
+        )
+    });
+    match status {
+        GET_PAGE_OK => {}
+        GET_PAGE_ERROR | retry::TRANSIENT_ERROR => {
+            return Err(host_error(format!(
+                "failed to read {} pages of namespace {namespace}",
+                ixs.len()
+            )))
+        }
+        status => {
+            return Err(io::Error::new(
+                ErrorKind::Other,
+                format!("host returned unknown status {status} for get_pages"),
+            ))
+        }
+    }
+
+    PREFETCHED.with(|prefetched| {
+        let mut prefetched = prefetched.borrow_mut();
+        for ((ix, page), found) in ixs.iter().zip(data.chunks_exact(page_size)).zip(found) {
+            let page = (found != 0).then(|| page.to_vec());
+            if let Some(page) = &page {
+                PAGE_HASHES.with(|hashes| {
+                    hashes
+                        .borrow_mut()
+                        .insert((namespace, *ix), page_hash(page))
+                });
+            }
+            prefetched.insert((namespace, *ix), page);
+        }
+    });
+    Ok(ixs.len())
+}
+
+/// Takes the message of the last failed page store call, if any. Clears it.
+pub fn take_host_error() -> Option<String> {
+    HOST_ERROR.with(|err| err.borrow_mut().take())
+}
+
+/// Reads a page of a namespace that doesn't hold a database (see [crate::changes]).
+pub fn get_meta_page(
+    namespace: u32,
+    ix: u32,
+) -> Result<Option<[u8; changes::PAGE_SIZE]>, io::Error> {
+    Connection::<{ changes::PAGE_SIZE }>::try_get_page(namespace, ix)
+}
+
+/// Writes a page of a namespace that doesn't hold a database, as part of the current storage
+/// transaction (or write batch).
+pub fn put_meta_page(
+    namespace: u32,
+    ix: u32,
+    data: &[u8; changes::PAGE_SIZE],
+) -> Result<(), io::Error> {
+    Connection::<{ changes::PAGE_SIZE }>::put_page(namespace, ix, data)
+}
+
+/// Fetches the message of the failed page store call from the host and turns it into an error.
+pub fn host_error(context: String) -> io::Error {
+    let mut buf = vec![0u8; 256];
+    let len = unsafe { crate::host_error_message(buf.as_mut_ptr(), buf.len() as u32) } as usize;
+    if len > buf.len() {
+        buf.resize(len, 0);
+        unsafe { crate::host_error_message(buf.as_mut_ptr(), len as u32) };
+    }
+    buf.truncate(len);
+    let message = String::from_utf8_lossy(&buf).into_owned();
+
+    error!(%message, "{context}");
+    HOST_ERROR.with(|err| *err.borrow_mut() = Some(message.clone()));
+    io::Error::new(ErrorKind::Other, format!("{context}: {message}"))
+}
+
+/// A VFS storing the database in the host's page store. Each instance routes its pages to its own
+/// namespace, so that several instances can be registered (under different names) to serve
+/// independent databases from a single module.
 #[derive(Default)]
 pub struct PagesVfs<const PAGE_SIZE: usize> {
+    namespace: u32,
     lock_state: Arc<Mutex<LockState>>,
 }
 
@@ -13,11 +466,94 @@ pub struct PagesVfs<const PAGE_SIZE: usize> {
 struct LockState {
     read: usize,
     write: Option<bool>,
+    /// The handle holding the write lock.
+    writer: Option<u64>,
+    /// Handles waiting for the write lock, in the order they first asked for it. The write lock is
+    /// only handed to the first one, so that writers retrying via the busy handler get it in FIFO
+    /// order instead of whoever happens to retry first.
+    write_queue: VecDeque<QueuedWriter>,
+}
+
+#[derive(Debug)]
+struct QueuedWriter {
+    handle: u64,
+    last_attempt: Instant,
+}
+
+/// Queued writers that didn't retry for this long (e.g. because their busy handler gave up while
+/// they keep a read transaction open) lose their place in the write queue.
+const WRITE_QUEUE_STALE_AFTER: Duration = Duration::from_secs(1);
+
+impl LockState {
+    /// Whether `handle` may take the write lock, which requires it to be free and no other handle
+    /// to be waiting for it for longer. Otherwise, `handle` is queued (or keeps its place).
+    fn may_write(&mut self, handle: u64) -> bool {
+        self.write_queue.retain(|queued| {
+            queued.handle == handle || queued.last_attempt.elapsed() < WRITE_QUEUE_STALE_AFTER
+        });
+        let is_next = self
+            .write_queue
+            .front()
+            .map_or(true, |queued| queued.handle == handle);
+        if self.write.is_none() && is_next {
+            self.leave_write_queue(handle);
+            return true;
+        }
+
+        let now = Instant::now();
+        match self
+            .write_queue
+            .iter_mut()
+            .find(|queued| queued.handle == handle)
+        {
+            Some(queued) => queued.last_attempt = now,
+            None => self.write_queue.push_back(QueuedWriter {
+                handle,
+                last_attempt: now,
+            }),
+        }
+        false
+    }
+
+    fn leave_write_queue(&mut self, handle: u64) {
+        self.write_queue.retain(|queued| queued.handle != handle);
+    }
+
+    /// The handle `handle` most likely waits for: the one holding the write lock, or else the
+    /// writer queued before it. `None` if it only waits for readers.
+    fn blocker(&self, handle: u64) -> Option<u64> {
+        match self.writer {
+            Some(writer) if writer == handle => None,
+            Some(writer) => Some(writer),
+            None => self
+                .write_queue
+                .front()
+                .map(|queued| queued.handle)
+                .filter(|queued| *queued != handle),
+        }
+    }
 }
 
 pub struct Connection<const PAGE_SIZE: usize> {
+    id: u64,
+    namespace: u32,
     lock_state: Arc<Mutex<LockState>>,
     lock: LockKind,
+    /// Token of the host's storage transaction the current SQLite transaction's page writes are
+    /// part of.
+    txn: Option<u32>,
+    /// Pages passed to the host as part of the storage transaction. Their hashes (see
+    /// [PAGE_HASHES]) are forgotten if it is rolled back, as the host discards their content.
+    written: BTreeSet<u32>,
+    /// Pages written by the current transaction, if replication is enabled.
+    delta: BTreeMap<u32, Vec<u8>>,
+    /// Pages written by the current transaction, if they are tracked (see [crate::changes]).
+    changed: BTreeSet<u32>,
+    /// The file change counter of the header written by the current transaction.
+    counter: Option<u32>,
+    /// The database size from the header (see [Connection::header_page_count]) read while holding
+    /// the shared lock. Only another lock transition can change it, so it is cleared on each.
+    header_page_count: Cell<Option<Option<usize>>>,
 }
 
 impl<const PAGE_SIZE: usize> Vfs for PagesVfs<PAGE_SIZE> {
@@ -40,9 +576,27 @@ impl<const PAGE_SIZE: usize> Vfs for PagesVfs<PAGE_SIZE> {
             ));
         }
 
+        // Opening without create access requires the database to exist already.
+        if matches!(opts.access, OpenAccess::Read | OpenAccess::Write)
+            && Connection::<PAGE_SIZE>::page_count(self.namespace) == 0
+        {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                "database does not exist (and create access was not requested)",
+            ));
+        }
+
         Ok(Connection {
+            id: NEXT_HANDLE_ID.fetch_add(1, Ordering::Relaxed),
+            namespace: self.namespace,
             lock_state: self.lock_state.clone(),
             lock: LockKind::None,
+            txn: None,
+            written: BTreeSet::new(),
+            delta: BTreeMap::new(),
+            changed: BTreeSet::new(),
+            counter: None,
+            header_page_count: Cell::new(None),
         })
     }
 
@@ -53,7 +607,7 @@ impl<const PAGE_SIZE: usize> Vfs for PagesVfs<PAGE_SIZE> {
     }
 
     fn exists(&self, db: &str) -> Result<bool, std::io::Error> {
-        Ok(db == "main.db" && Connection::<PAGE_SIZE>::page_count() > 0)
+        Ok(db == "main.db" && Connection::<PAGE_SIZE>::page_count(self.namespace) > 0)
     }
 
     fn temporary_name(&self) -> String {
@@ -71,30 +625,212 @@ impl<const PAGE_SIZE: usize> Vfs for PagesVfs<PAGE_SIZE> {
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct Reconciliation {
+    pub page_count: usize,
+    pub header_page_count: Option<usize>,
+    pub deleted: Vec<u32>,
+}
+
+impl<const PAGE_SIZE: usize> PagesVfs<PAGE_SIZE> {
+    pub fn new(namespace: u32) -> Self {
+        Self {
+            namespace,
+            lock_state: Default::default(),
+        }
+    }
+
+    /// The number of pages in the page store of the given namespace.
+    pub fn page_count(namespace: u32) -> usize {
+        Connection::<PAGE_SIZE>::page_count(namespace)
+    }
+
+    /// The file change counter of the database in the page store, which SQLite increments with
+    /// every commit. Returns `None` for new databases.
+    pub fn commit_counter(namespace: u32) -> Result<Option<u32>, io::Error> {
+        let header = Connection::<PAGE_SIZE>::try_get_page(namespace, 0)?;
+        Ok(header.map(|header| read_u32(&header, 24)))
+    }
+
+    /// The page size the database in the page store was created with. Returns `None` for new
+    /// databases.
+    pub fn stored_page_size(namespace: u32) -> Result<Option<usize>, io::Error> {
+        if Connection::<PAGE_SIZE>::page_count(namespace) == 0 {
+            return Ok(None);
+        }
+
+        let header = Connection::<PAGE_SIZE>::get_page(namespace, 0)?;
+        if &header[..16] != b"SQLite format 3\0" {
+            return Ok(None);
+        }
+
+        // The page size is stored as a big-endian u16, with 1 representing 65536.
+        match u16::from_be_bytes([header[16], header[17]]) {
+            1 => Ok(Some(65536)),
+            page_size => Ok(Some(page_size as usize)),
+        }
+    }
+
+    /// Compares the host's page count with the database size from the header. Trailing pages the
+    /// header doesn't know about are deleted. Pages the header expects but the host doesn't have
+    /// cannot be repaired and result in an error listing their indexes.
+    ///
+    /// Must only be called while holding a write lock (e.g. inside `BEGIN IMMEDIATE`).
+    pub fn reconcile_storage(namespace: u32) -> Result<Reconciliation, io::Error> {
+        let page_count = Connection::<PAGE_SIZE>::page_count(namespace);
+        let header_page_count = Connection::<PAGE_SIZE>::header_page_count(namespace)?;
+        let mut reconciliation = Reconciliation {
+            page_count,
+            header_page_count,
+            deleted: Vec::new(),
+        };
+
+        let expected = match header_page_count {
+            Some(expected) => expected,
+            None => return Ok(reconciliation),
+        };
+
+        if page_count < expected {
+            let missing = (page_count..expected)
+                .map(|ix| ix.to_string())
+                .collect::<Vec<_>>();
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "page store is missing pages [{}]; database header expects {expected} pages, \
+                     but host reports {page_count}",
+                    missing.join(", ")
+                ),
+            ));
+        }
+
+        if page_count > expected {
+            warn!(
+                from = expected,
+                to = page_count,
+                "reconcile: delete trailing pages"
+            );
+            Connection::<PAGE_SIZE>::del_pages(namespace, expected as u32, page_count as u32);
+            reconciliation.deleted = (expected as u32..page_count as u32).collect();
+        }
+
+        Ok(reconciliation)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageVerification {
+    pub page_count: usize,
+    pub header_page_count: Option<usize>,
+    pub freelist_count: usize,
+    /// Pages in use by the database, but missing from the page store.
+    pub missing: Vec<u32>,
+    /// Free pages missing from the page store (harmless, they are read as zero pages).
+    pub missing_free: Vec<u32>,
+    /// Pages in the page store beyond the database size from the header.
+    pub orphaned: Vec<u32>,
+}
+
+impl<const PAGE_SIZE: usize> PagesVfs<PAGE_SIZE> {
+    /// Reads every page of the database from the host and reports pages that are missing or that
+    /// exist beyond the end of the database.
+    pub fn verify_pages(namespace: u32) -> Result<PageVerification, io::Error> {
+        let page_count = Connection::<PAGE_SIZE>::page_count(namespace);
+        let header_page_count = Connection::<PAGE_SIZE>::header_page_count(namespace)?;
+        let freelist = Connection::<PAGE_SIZE>::freelist(namespace)?;
+        let mut verification = PageVerification {
+            page_count,
+            header_page_count,
+            freelist_count: freelist.len(),
+            missing: Vec::new(),
+            missing_free: Vec::new(),
+            orphaned: Vec::new(),
+        };
+
+        let db_page_count = header_page_count.unwrap_or(page_count);
+        for ix in 0..db_page_count {
+            if Connection::<PAGE_SIZE>::try_get_page(namespace, ix as u32)?.is_none() {
+                if freelist.contains(&ix) {
+                    verification.missing_free.push(ix as u32);
+                } else {
+                    verification.missing.push(ix as u32);
+                }
+            }
+        }
+        for ix in db_page_count..page_count {
+            if Connection::<PAGE_SIZE>::try_get_page(namespace, ix as u32)?.is_some() {
+                verification.orphaned.push(ix as u32);
+            }
+        }
+
+        Ok(verification)
+    }
+}
+
 impl<const PAGE_SIZE: usize> sqlite_vfs::DatabaseHandle for Connection<PAGE_SIZE> {
     type WalIndex = sqlite_vfs::WalDisabled;
 
     fn size(&self) -> Result<u64, io::Error> {
-        let size = Self::page_count() * PAGE_SIZE;
-        eprintln!("size={size}");
+        // The host's page count can be stale (e.g. when host writes race), so prefer the database
+        // size from the header. While writing, pages beyond the size in the header are written
+        // before the header itself is updated, so fall back to the host's page count in that case.
+        // The header is read once per shared lock instead of on each call.
+        let header_page_count = match self.lock {
+            LockKind::None => Self::header_page_count(self.namespace)?,
+            LockKind::Shared => match self.header_page_count.get() {
+                Some(header_page_count) => header_page_count,
+                None => {
+                    let header_page_count = Self::header_page_count(self.namespace)?;
+                    self.header_page_count.set(Some(header_page_count));
+                    header_page_count
+                }
+            },
+            _ => None,
+        };
+        let page_count = header_page_count.unwrap_or_else(|| Self::page_count(self.namespace));
+        let size = page_count * PAGE_SIZE;
+        trace!(size, "size");
         Ok(size as u64)
     }
 
     fn read_exact_at(&mut self, buf: &mut [u8], offset: u64) -> Result<(), io::Error> {
         let index = offset as usize / PAGE_SIZE;
         let offset = offset as usize % PAGE_SIZE;
+        let _span = trace_span!("read", ns = self.namespace, index).entered();
+        PAGE_IO.with(|io| {
+            io.set(PageIo {
+                reads: io.get().reads + 1,
+                ..io.get()
+            })
+        });
 
-        let data = Self::get_page(index as u32);
+        let data = match Self::try_get_page(self.namespace, index as u32)? {
+            Some(data) => data,
+            // Pages that are free or beyond the end of the database can legitimately be absent
+            // from the page store.
+            None if Self::is_free_page(self.namespace, index)? => {
+                trace!(index, "read (missing, free page)");
+                [0u8; PAGE_SIZE]
+            }
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "page {index} is missing from the page store and is not on the freelist"
+                    ),
+                ))
+            }
+        };
         if data.len() < buf.len() + offset {
-            eprintln!(
-                "read {} < {} -> UnexpectedEof",
-                data.len(),
-                buf.len() + offset
+            warn!(
+                len = data.len(),
+                expected = buf.len() + offset,
+                "read -> UnexpectedEof"
             );
             return Err(ErrorKind::UnexpectedEof.into());
         }
 
-        eprintln!("read index={} len={} offset={}", index, buf.len(), offset);
+        trace!(index, len = buf.len(), offset, "read");
         buf.copy_from_slice(&data[offset..offset + buf.len()]);
 
         Ok(())
@@ -102,6 +838,7 @@ impl<const PAGE_SIZE: usize> sqlite_vfs::DatabaseHandle for Connection<PAGE_SIZE
 
     fn write_all_at(&mut self, buf: &[u8], offset: u64) -> Result<(), io::Error> {
         if offset as usize % PAGE_SIZE > 0 {
+            self.rollback_txn();
             return Err(io::Error::new(
                 ErrorKind::Other,
                 "unexpected write across page boundaries",
@@ -109,7 +846,15 @@ impl<const PAGE_SIZE: usize> sqlite_vfs::DatabaseHandle for Connection<PAGE_SIZE
         }
 
         let index = offset as usize / PAGE_SIZE;
+        let _span = trace_span!("write", ns = self.namespace, index).entered();
+        PAGE_IO.with(|io| {
+            io.set(PageIo {
+                writes: io.get().writes + 1,
+                ..io.get()
+            })
+        });
         let page = buf.try_into().map_err(|_| {
+            self.rollback_txn();
             io::Error::new(
                 ErrorKind::Other,
                 format!(
@@ -119,38 +864,81 @@ impl<const PAGE_SIZE: usize> sqlite_vfs::DatabaseHandle for Connection<PAGE_SIZE
                 ),
             )
         })?;
-        eprintln!("write index={} len={}", index, buf.len());
-        Self::put_page(index as u32, page);
+        let hash = page_hash(page);
+        if !Self::is_pending(self.namespace, index as u32)
+            && PAGE_HASHES
+                .with(|hashes| hashes.borrow().get(&(self.namespace, index as u32)) == Some(&hash))
+        {
+            trace!(index, len = buf.len(), "write (unchanged, skipped)");
+            return Ok(());
+        }
+
+        trace!(index, len = buf.len(), "write");
+        self.begin_txn();
+        if self.txn.is_some() {
+            self.written.insert(index as u32);
+        }
+        if let Err(err) = Self::put_page(self.namespace, index as u32, page) {
+            self.rollback_txn();
+            return Err(err);
+        }
+        if REPLICATION.with(Cell::get) {
+            self.delta.insert(index as u32, page.to_vec());
+        }
+        if changes::is_tracked(self.namespace) {
+            self.changed.insert(index as u32);
+            if index == 0 {
+                self.counter = Some(read_u32(page, 24));
+            }
+        }
 
         Ok(())
     }
 
     fn sync(&mut self, _data_only: bool) -> Result<(), io::Error> {
-        // Everything is directly written to storage, so no extra steps necessary to sync.
+        // Commit the host's storage transaction and let the host persist buffered page writes
+        // before SQLite considers the transaction durable. With write batching enabled, the pages
+        // haven't been passed to the host yet; they are synced once flushed.
+        self.commit_txn();
+        if WRITE_BATCH.with(|batches| batches.borrow().is_none()) {
+            host_storage_sync(self.namespace)?;
+        }
         Ok(())
     }
 
     fn set_len(&mut self, size: u64) -> Result<(), io::Error> {
-        eprintln!("set_len={size}");
+        debug!(size, "set_len");
 
         let mut page_count = size as usize / PAGE_SIZE;
         if size as usize % PAGE_SIZE > 0 {
             page_count += 1;
         }
 
-        let current_page_count = Self::page_count();
+        let current_page_count = Self::page_count(self.namespace);
         if page_count > 0 && page_count < current_page_count {
-            for i in (page_count..current_page_count).into_iter().rev() {
-                Self::del_page(i as u32);
-            }
+            self.begin_txn();
+            Self::del_pages(self.namespace, page_count as u32, current_page_count as u32);
         }
 
         Ok(())
     }
 
     fn lock(&mut self, lock: LockKind) -> Result<bool, io::Error> {
+        // Without `PRAGMA synchronous`, SQLite doesn't sync, so commit the host's storage
+        // transaction at the latest when the write lock is released.
+        if lock <= LockKind::Shared {
+            self.commit_txn();
+        }
+        self.header_page_count.set(None);
+
+        let _span =
+            debug_span!("lock", ns = self.namespace, from = ?self.lock, to = ?lock).entered();
         let ok = Self::lock(self, lock);
-        // eprintln!("locked = {}", ok);
+        trace!(ok, "lock");
+        self.record_lock(lock, ok);
+        if !ok {
+            self.notify_blocked(lock);
+        }
         Ok(ok)
     }
 
@@ -168,39 +956,296 @@ impl<const PAGE_SIZE: usize> sqlite_vfs::DatabaseHandle for Connection<PAGE_SIZE
 
     fn set_chunk_size(&self, chunk_size: usize) -> Result<(), io::Error> {
         if chunk_size != PAGE_SIZE {
-            eprintln!("set_chunk_size={chunk_size} (rejected)");
+            warn!(chunk_size, "set_chunk_size (rejected)");
             Err(io::Error::new(
                 ErrorKind::Other,
                 "changing chunk size is not allowed",
             ))
         } else {
-            eprintln!("set_chunk_size={chunk_size}");
+            debug!(chunk_size, "set_chunk_size");
             Ok(())
         }
     }
 }
 
 impl<const PAGE_SIZE: usize> Connection<PAGE_SIZE> {
-    fn get_page(ix: u32) -> [u8; PAGE_SIZE] {
+    fn get_page(namespace: u32, ix: u32) -> Result<[u8; PAGE_SIZE], io::Error> {
+        Ok(Self::try_get_page(namespace, ix)?.unwrap_or([0u8; PAGE_SIZE]))
+    }
+
+    /// Reads the page from the host. Returns `None` if the page does not exist in the page store,
+    /// and an error if the host failed to read it.
+    fn try_get_page(namespace: u32, ix: u32) -> Result<Option<[u8; PAGE_SIZE]>, io::Error> {
+        let pending = WRITE_BATCH.with(|batches| {
+            let batches = batches.borrow();
+            let batch = batches.as_ref()?.get(&namespace)?;
+            if let Some(data) = batch.pages.get(&ix) {
+                return Some(Some(data.as_slice().try_into().unwrap()));
+            }
+            if matches!(batch.truncate, Some(truncate) if ix >= truncate) {
+                return Some(None);
+            }
+            None
+        });
+        if let Some(page) = pending {
+            return Ok(page);
+        }
+
+        let prefetched =
+            PREFETCHED.with(|prefetched| prefetched.borrow_mut().remove(&(namespace, ix)));
+        if let Some(page) = prefetched {
+            trace!(ns = namespace, ix, "read prefetched page");
+            return Ok(page.map(|data| data.as_slice().try_into().unwrap()));
+        }
+
         let mut data = [0u8; PAGE_SIZE];
-        unsafe { crate::get_page(ix, data.as_mut_ptr()) };
-        data
+        let status = retry::with_retry("get_page", || unsafe {
+            crate::get_page(namespace, ix, data.as_mut_ptr(), PAGE_SIZE as u32)
+        });
+        match status {
+            GET_PAGE_OK => {}
+            GET_PAGE_NOT_FOUND => return Ok(None),
+            GET_PAGE_ERROR | retry::TRANSIENT_ERROR => {
+                return Err(host_error(format!(
+                    "failed to read page {ix} of namespace {namespace}"
+                )))
+            }
+            status => {
+                return Err(io::Error::new(
+                    ErrorKind::Other,
+                    format!("host returned unknown status {status} for page {ix}"),
+                ))
+            }
+        }
+
+        PAGE_HASHES.with(|hashes| {
+            hashes
+                .borrow_mut()
+                .insert((namespace, ix), page_hash(&data))
+        });
+        Ok(Some(data))
     }
 
-    fn put_page(ix: u32, data: &[u8; PAGE_SIZE]) {
-        unsafe {
-            crate::put_page(ix, data.as_ptr());
+    fn put_page(namespace: u32, ix: u32, data: &[u8; PAGE_SIZE]) -> Result<(), io::Error> {
+        let batched = WRITE_BATCH.with(|batches| match batches.borrow_mut().as_mut() {
+            Some(batches) => {
+                let batch = batches.entry(namespace).or_default();
+                batch.pages.insert(ix, data.to_vec());
+                true
+            }
+            None => false,
+        });
+        if !batched {
+            host_put_page(namespace, ix, data)?;
         }
+        Ok(())
     }
 
-    fn del_page(ix: u32) {
-        unsafe {
-            crate::del_page(ix);
+    /// Deletes the pages `from_ix..to_ix`, which are the last pages of the page store.
+    fn del_pages(namespace: u32, from_ix: u32, to_ix: u32) {
+        // Pages are only ever deleted from the end, so a delete is a truncation of the page store.
+        let batched = WRITE_BATCH.with(|batches| match batches.borrow_mut().as_mut() {
+            Some(batches) => {
+                let batch = batches.entry(namespace).or_default();
+                batch.pages.retain(|ix, _| *ix < from_ix);
+                batch.truncate = Some(
+                    batch
+                        .truncate
+                        .map_or(from_ix, |truncate| truncate.min(from_ix)),
+                );
+                true
+            }
+            None => false,
+        });
+        if !batched {
+            host_del_pages(namespace, from_ix, to_ix);
+        }
+    }
+
+    /// Starts a storage transaction on the host unless one is already in progress. Skipped if
+    /// write batching is enabled, as [flush] wraps the writes into a transaction instead.
+    fn begin_txn(&mut self) {
+        if self.txn.is_some() || WRITE_BATCH.with(|batches| batches.borrow().is_some()) {
+            return;
+        }
+
+        let token = unsafe { crate::txn_begin() };
+        debug!(token, "txn_begin");
+        self.txn = Some(token);
+    }
+
+    fn commit_txn(&mut self) {
+        self.written.clear();
+        self.record_changes();
+        if let Some(token) = self.txn.take() {
+            debug!(token, "txn_commit");
+            unsafe { crate::txn_commit(token) };
         }
+        report_storage_deltas();
+        self.emit_delta();
     }
 
-    fn page_count() -> usize {
-        unsafe { crate::page_count() as usize }
+    /// Emits the pages written by the committed transaction to the host.
+    fn emit_delta(&mut self) {
+        if self.delta.is_empty() {
+            return;
+        }
+
+        let pages = std::mem::take(&mut self.delta);
+        let header = match pages.get(&0) {
+            Some(header) => header.clone(),
+            None => match Self::get_page(self.namespace, 0) {
+                Ok(header) => header.to_vec(),
+                Err(err) => {
+                    error!(error = %err, "emit_delta: failed to read header page, delta dropped");
+                    return;
+                }
+            },
+        };
+        let delta = Delta {
+            namespace: self.namespace,
+            commit_counter: read_u32(&header, 24),
+            page_count: read_u32(&header, 28),
+            pages,
+        };
+        debug!(
+            commit = delta.commit_counter,
+            pages = delta.pages.len(),
+            "emit_delta"
+        );
+        let data = delta.encode();
+        unsafe { crate::emit_delta(data.as_ptr(), data.len() as u32) };
+    }
+
+    /// Records the pages changed by the transaction in the change map, as part of its storage
+    /// transaction. Failing to do so only makes the next commit start tracking anew.
+    fn record_changes(&mut self) {
+        let pages = std::mem::take(&mut self.changed);
+        let counter = match self.counter.take() {
+            Some(counter) if !pages.is_empty() => counter,
+            _ => return,
+        };
+        if let Err(err) = changes::record(counter, &pages) {
+            error!(error = %err, counter, "failed to record changed pages");
+        }
+    }
+
+    fn rollback_txn(&mut self) {
+        let namespace = self.namespace;
+        let written = std::mem::take(&mut self.written);
+        PAGE_HASHES.with(|hashes| {
+            let mut hashes = hashes.borrow_mut();
+            for ix in &written {
+                hashes.remove(&(namespace, *ix));
+            }
+        });
+        PREFETCHED.with(|prefetched| {
+            let mut prefetched = prefetched.borrow_mut();
+            for ix in &written {
+                prefetched.remove(&(namespace, *ix));
+            }
+        });
+        self.delta.clear();
+        self.changed.clear();
+        self.counter = None;
+        if let Some(token) = self.txn.take() {
+            debug!(token, "txn_rollback");
+            unsafe { crate::txn_rollback(token) };
+        }
+        discard_storage_deltas();
+    }
+
+    fn is_pending(namespace: u32, ix: u32) -> bool {
+        WRITE_BATCH.with(|batches| {
+            batches
+                .borrow()
+                .as_ref()
+                .and_then(|batches| batches.get(&namespace))
+                .map_or(false, |batch| batch.pages.contains_key(&ix))
+        })
+    }
+
+    fn page_count(namespace: u32) -> usize {
+        let page_count = unsafe { crate::page_count(namespace) as usize };
+        WRITE_BATCH.with(|batches| {
+            match batches
+                .borrow()
+                .as_ref()
+                .and_then(|batches| batches.get(&namespace))
+            {
+                Some(batch) => {
+                    let page_count = batch
+                        .truncate
+                        .map_or(page_count, |truncate| page_count.min(truncate as usize));
+                    match batch.pages.keys().next_back() {
+                        Some(last) => page_count.max(*last as usize + 1),
+                        None => page_count,
+                    }
+                }
+                None => page_count,
+            }
+        })
+    }
+
+    /// The database size in pages as stored in the database header. Returns `None` if there is no
+    /// header yet, or if the size in the header is not valid (SQLite only considers it valid if
+    /// the change counter matches the version-valid-for number).
+    fn header_page_count(namespace: u32) -> Result<Option<usize>, io::Error> {
+        if Self::page_count(namespace) == 0 {
+            return Ok(None);
+        }
+
+        let header = Self::get_page(namespace, 0)?;
+        let change_counter = read_u32(&header, 24);
+        let version_valid_for = read_u32(&header, 92);
+        if change_counter != version_valid_for {
+            return Ok(None);
+        }
+
+        let page_count = read_u32(&header, 28);
+        Ok((page_count > 0).then(|| page_count as usize))
+    }
+
+    /// Collects the indexes of all pages on the freelist (both trunk and leaf pages).
+    fn freelist(namespace: u32) -> Result<HashSet<usize>, io::Error> {
+        let header = Self::get_page(namespace, 0)?;
+        let mut trunk = read_u32(&header, 32);
+        let mut pages = HashSet::with_capacity(read_u32(&header, 36) as usize);
+
+        // Page numbers on the freelist are 1-based, page indexes 0-based.
+        while trunk > 0 {
+            let ix = trunk as usize - 1;
+            if !pages.insert(ix) {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("freelist contains a loop at page {ix}"),
+                ));
+            }
+
+            let data = Self::try_get_page(namespace, ix as u32)?.ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("freelist trunk page {ix} is missing from the page store"),
+                )
+            })?;
+            let leaf_count = read_u32(&data, 4) as usize;
+            for leaf in (0..leaf_count.min(PAGE_SIZE / 4 - 2)).map(|i| read_u32(&data, 8 + i * 4)) {
+                if leaf > 0 {
+                    pages.insert(leaf as usize - 1);
+                }
+            }
+
+            trunk = read_u32(&data, 0);
+        }
+
+        Ok(pages)
+    }
+
+    fn is_free_page(namespace: u32, ix: usize) -> Result<bool, io::Error> {
+        match Self::header_page_count(namespace)? {
+            Some(page_count) if ix >= page_count => Ok(true),
+            _ => Ok(Self::freelist(namespace)?.contains(&ix)),
+        }
     }
 
     fn lock(&mut self, to: LockKind) -> bool {
@@ -210,10 +1255,7 @@ impl<const PAGE_SIZE: usize> Connection<PAGE_SIZE> {
 
         let mut lock_state = self.lock_state.lock().unwrap();
 
-        // eprintln!(
-        //     "lock state={:?} from={:?} to={:?}",
-        //     lock_state, self.lock, to
-        // );
+        trace!(state = ?lock_state, "lock");
 
         // The following locking implementation is probably not sound (wouldn't be surprised if it
         // potentially dead-locks), but suffice for the experiment.
@@ -224,7 +1266,9 @@ impl<const PAGE_SIZE: usize> Connection<PAGE_SIZE> {
                     lock_state.read -= 1;
                 } else if self.lock > LockKind::Shared {
                     lock_state.write = None;
+                    lock_state.writer = None;
                 }
+                lock_state.leave_write_queue(self.id);
                 self.lock = LockKind::None;
                 true
             }
@@ -237,13 +1281,14 @@ impl<const PAGE_SIZE: usize> Connection<PAGE_SIZE> {
                 lock_state.read += 1;
                 if self.lock > LockKind::Shared {
                     lock_state.write = None;
+                    lock_state.writer = None;
                 }
                 self.lock = LockKind::Shared;
                 true
             }
 
             LockKind::Reserved => {
-                if lock_state.write.is_some() || self.lock != LockKind::Shared {
+                if self.lock != LockKind::Shared || !lock_state.may_write(self.id) {
                     return false;
                 }
 
@@ -251,6 +1296,7 @@ impl<const PAGE_SIZE: usize> Connection<PAGE_SIZE> {
                     lock_state.read -= 1;
                 }
                 lock_state.write = Some(false);
+                lock_state.writer = Some(self.id);
                 self.lock = LockKind::Reserved;
                 true
             }
@@ -261,7 +1307,7 @@ impl<const PAGE_SIZE: usize> Connection<PAGE_SIZE> {
             }
 
             LockKind::Exclusive => {
-                if lock_state.write.is_some() && self.lock <= LockKind::Shared {
+                if self.lock <= LockKind::Shared && !lock_state.may_write(self.id) {
                     return false;
                 }
 
@@ -270,6 +1316,7 @@ impl<const PAGE_SIZE: usize> Connection<PAGE_SIZE> {
                 }
 
                 lock_state.write = Some(true);
+                lock_state.writer = Some(self.id);
                 if lock_state.read == 0 {
                     self.lock = LockKind::Exclusive;
                     true
@@ -281,6 +1328,73 @@ impl<const PAGE_SIZE: usize> Connection<PAGE_SIZE> {
         }
     }
 
+    /// Updates the lock diagnostics after a lock (or unlock) attempt.
+    fn record_lock(&self, requested: LockKind, ok: bool) {
+        LOCK_HOLDERS.with(|holders| {
+            let mut holders = holders.borrow_mut();
+            if ok && self.lock == LockKind::None {
+                holders.remove(&self.id);
+                return;
+            }
+
+            let now = Instant::now();
+            let holder = holders.entry(self.id).or_insert_with(|| LockHolder {
+                lock: self.lock,
+                since: now,
+                requested: None,
+                failed_attempts: 0,
+                waiting_since: None,
+                tag: None,
+            });
+            holder.tag = tag::current();
+            if holder.lock != self.lock {
+                holder.lock = self.lock;
+                holder.since = now;
+            }
+            if ok {
+                holder.requested = None;
+                holder.failed_attempts = 0;
+                holder.waiting_since = None;
+            } else {
+                holder.requested = Some(requested);
+                holder.failed_attempts += 1;
+                holder.waiting_since.get_or_insert(now);
+            }
+        });
+    }
+
+    /// Tells the host's `on_lock_blocked` import about the first failed attempt to acquire
+    /// `requested` (retries, e.g. by the busy handler, are not reported again).
+    fn notify_blocked(&self, requested: LockKind) {
+        let blocker = self.lock_state.lock().unwrap().blocker(self.id);
+        LOCK_HOLDERS.with(|holders| {
+            let holders = holders.borrow();
+            if holders.get(&self.id).map(|holder| holder.failed_attempts) != Some(1) {
+                return;
+            }
+
+            let holder = blocker.and_then(|blocker| holders.get(&blocker));
+            let tag = tag::current();
+            let blocked = LockBlocked {
+                kind: format!("{requested:?}"),
+                handle: self.id,
+                tag: tag.as_deref(),
+                holder: blocker,
+                holder_tag: holder.and_then(|holder| holder.tag.as_deref()),
+                holder_lock: holder.map(|holder| format!("{:?}", holder.lock)),
+                holder_held_ms: holder.map(|holder| holder.since.elapsed().as_millis() as u64),
+            };
+            debug!(
+                kind = %blocked.kind,
+                holder = blocked.holder,
+                holder_tag = blocked.holder_tag,
+                "lock blocked"
+            );
+            let json = serde_json::to_vec(&blocked).expect("failed to serialize blocked lock");
+            unsafe { crate::on_lock_blocked(json.as_ptr(), json.len() as u32) };
+        });
+    }
+
     fn reserved(&self) -> bool {
         if self.lock > LockKind::Shared {
             return true;
@@ -293,8 +1407,88 @@ impl<const PAGE_SIZE: usize> Connection<PAGE_SIZE> {
 
 impl<const PAGE_SIZE: usize> Drop for Connection<PAGE_SIZE> {
     fn drop(&mut self) {
+        // A storage transaction still open at this point never made it to a commit.
+        self.rollback_txn();
         if self.lock != LockKind::None {
             self.lock(LockKind::None);
         }
+        LOCK_HOLDERS.with(|holders| holders.borrow_mut().remove(&self.id));
     }
 }
+
+fn host_put_page(namespace: u32, ix: u32, data: &[u8]) -> Result<(), io::Error> {
+    // Known pages exist in the page store; only ask the host for its page count otherwise.
+    let added = !PAGE_HASHES.with(|hashes| hashes.borrow().contains_key(&(namespace, ix)))
+        && ix >= unsafe { crate::page_count(namespace) };
+    let status = retry::with_retry("put_page", || unsafe {
+        crate::put_page(namespace, ix, data.as_ptr(), data.len() as u32)
+    });
+    if status != PUT_PAGE_OK {
+        return Err(host_error(format!(
+            "failed to write page {ix} of namespace {namespace}"
+        )));
+    }
+    PAGE_HASHES.with(|hashes| hashes.borrow_mut().insert((namespace, ix), page_hash(data)));
+    PREFETCHED.with(|prefetched| prefetched.borrow_mut().remove(&(namespace, ix)));
+    if added {
+        add_storage_delta(namespace, data.len() as i64);
+    }
+    Ok(())
+}
+
+fn host_storage_sync(namespace: u32) -> Result<(), io::Error> {
+    let _span = debug_span!("storage_sync", ns = namespace).entered();
+    let status = retry::with_retry("storage_sync", || unsafe { crate::storage_sync(namespace) });
+    if status != STORAGE_SYNC_OK {
+        return Err(host_error(format!("failed to sync namespace {namespace}")));
+    }
+    Ok(())
+}
+
+/// Deletes the pages `from_ix..to_ix` in a single host call.
+fn host_del_pages(namespace: u32, from_ix: u32, to_ix: u32) {
+    if from_ix >= to_ix {
+        return;
+    }
+
+    let _span = debug_span!("del_pages", ns = namespace, from_ix, to_ix).entered();
+    unsafe {
+        crate::del_pages(namespace, from_ix, to_ix);
+    }
+    let deleted = |(ns, ix): &(u32, u32)| *ns == namespace && (from_ix..to_ix).contains(ix);
+    PAGE_HASHES.with(|hashes| hashes.borrow_mut().retain(|key, _| !deleted(key)));
+    PREFETCHED.with(|prefetched| prefetched.borrow_mut().retain(|key, _| !deleted(key)));
+    if let Some(ns) = Namespace::by_id(namespace) {
+        add_storage_delta(
+            namespace,
+            -(ns.page_size as i64) * i64::from(to_ix - from_ix),
+        );
+    }
+}
+
+fn add_storage_delta(namespace: u32, bytes: i64) {
+    STORAGE_DELTAS.with(|deltas| *deltas.borrow_mut().entry(namespace).or_default() += bytes);
+}
+
+/// Reports the bytes added to (or removed from) each namespace by the committed storage
+/// transaction to the host's `on_storage_delta`, e.g. for per-tenant billing.
+fn report_storage_deltas() {
+    let deltas = STORAGE_DELTAS.with(|deltas| std::mem::take(&mut *deltas.borrow_mut()));
+    for (namespace, bytes) in deltas {
+        if bytes != 0 {
+            unsafe { crate::on_storage_delta(namespace, bytes as f64) };
+        }
+    }
+}
+
+fn discard_storage_deltas() {
+    STORAGE_DELTAS.with(|deltas| deltas.borrow_mut().clear());
+}
+
+fn page_hash(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+type Error = Box<dyn std::error::Error>;
+
+const HOSTS: [&str; 3] = ["wasmtime", "deno", "node"];
+
+fn main() -> Result<(), Error> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("e2e") => e2e(args.collect()),
+        Some("bench") => bench(),
+        Some("miri") => miri(),
+        _ => {
+            eprintln!("Usage: cargo xtask e2e [wasmtime|deno|node]...");
+            eprintln!("       cargo xtask bench");
+            eprintln!("       cargo xtask miri");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Builds the wasm module and runs the example hosts in `examples/` against it. Runs all hosts if
+/// none are explicitly selected.
+fn e2e(hosts: Vec<String>) -> Result<(), Error> {
+    let hosts = if hosts.is_empty() {
+        HOSTS.iter().map(|h| h.to_string()).collect()
+    } else {
+        hosts
+    };
+    if let Some(host) = hosts.iter().find(|h| !HOSTS.contains(&h.as_str())) {
+        return Err(format!("unknown host `{host}`; expected one of {HOSTS:?}").into());
+    }
+
+    let root = project_root();
+
+    // The JS hosts use the asyncified module in `dist/`, which `npm run build` creates (including
+    // the cargo build). The wasmtime host uses the plain cargo output.
+    if hosts.iter().any(|h| h != "wasmtime") {
+        run(Command::new("npm")
+            .args(["run", "build"])
+            .current_dir(&root))?;
+    } else {
+        run(Command::new("make")
+            .arg("build")
+            .current_dir(root.join("wasm")))?;
+    }
+
+    for host in &hosts {
+        eprintln!("Running e2e example for {host}");
+        match host.as_str() {
+            "wasmtime" => run(Command::new(env!("CARGO"))
+                .args(["run", "--release", "--manifest-path"])
+                .arg(root.join("examples/wasmtime/Cargo.toml"))
+                .arg("--")
+                .arg(root.join("wasm/target/wasm32-wasi/release/wasm_sqlite.wasm"))
+                .current_dir(&root))?,
+            "deno" => run(Command::new("deno")
+                .args([
+                    "run",
+                    "--allow-read",
+                    "--import-map=examples/deno/import_map.json",
+                    "examples/deno/main.ts",
+                ])
+                .current_dir(&root))?,
+            "node" => run(Command::new("node")
+                .args([
+                    "--experimental-loader=./examples/node/wasm-loader.mjs",
+                    "examples/node/main.mjs",
+                ])
+                .current_dir(&root))?,
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the native criterion benchmarks of the VFS, and the wasmtime harness against the wasm
+/// module.
+fn bench() -> Result<(), Error> {
+    let root = project_root();
+
+    run(Command::new(env!("CARGO"))
+        .args(["bench", "--package", "wasm-sqlite-bench"])
+        .current_dir(root.join("wasm")))?;
+
+    run(Command::new("make")
+        .arg("build")
+        .current_dir(root.join("wasm")))?;
+    run(Command::new(env!("CARGO"))
+        .args(["run", "--release", "--manifest-path"])
+        .arg(root.join("examples/wasmtime/Cargo.toml"))
+        .arg("--")
+        .arg(root.join("wasm/target/wasm32-wasi/release/wasm_sqlite.wasm"))
+        .arg("--bench")
+        .current_dir(&root))?;
+
+    Ok(())
+}
+
+/// Runs the ownership flows of the memory shared with the host (`wasm/examples/ffi.rs`) under
+/// Miri (requires a nightly toolchain with the `miri` component).
+fn miri() -> Result<(), Error> {
+    run(Command::new("cargo")
+        .args(["+nightly", "miri", "run", "--example", "ffi"])
+        .current_dir(project_root().join("wasm")))
+}
+
+fn run(cmd: &mut Command) -> Result<(), Error> {
+    let status = cmd.status()?;
+    if !status.success() {
+        return Err(format!("command {cmd:?} failed with {status}").into());
+    }
+    Ok(())
+}
+
+fn project_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .ancestors()
+        .nth(2)
+        .unwrap()
+        .to_path_buf()
+}